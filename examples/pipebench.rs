@@ -0,0 +1,19 @@
+use std::process::exit;
+
+use anyhow::Result;
+
+use redlox::{bench_compile, bench_scanner, bench_vm};
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: pipebench [path]");
+        exit(1);
+    }
+    let text = std::fs::read_to_string(&args[1])?;
+    println!("{}", bench_scanner(text.clone())?);
+    println!("{}", bench_compile(text.clone())?);
+    println!("{}", bench_vm(text)?);
+
+    Ok(())
+}
@@ -11,7 +11,7 @@ fn main() -> Result<()> {
         exit(1);
     }
     let text = std::fs::read_to_string(&args[1])?;
-    bench_scanner(text)?;
+    println!("{}", bench_scanner(text)?);
 
     Ok(())
 }
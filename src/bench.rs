@@ -0,0 +1,103 @@
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+pub struct Benchmark {
+    pub stage: &'static str,
+    pub tokens: usize,
+    pub bytes: usize,
+    pub elapsed: Duration,
+    pub env: Environment,
+}
+
+pub struct Environment {
+    pub version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub profile: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+impl Benchmark {
+    pub(crate) fn new(
+        stage: &'static str,
+        tokens: usize,
+        bytes: usize,
+        elapsed: Duration,
+    ) -> Self {
+        Benchmark {
+            stage,
+            tokens,
+            bytes,
+            elapsed,
+            env: Environment::capture(),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn tokens_per_sec(&self) -> f64 {
+        self.tokens as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for Benchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "== {} ==", self.stage)?;
+        writeln!(f, "{}", self.env)?;
+        writeln!(f, "tokens:  {}", self.tokens)?;
+        writeln!(f, "bytes:   {}", self.bytes)?;
+        writeln!(f, "time:    {:.6}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "tok/s:   {:.0}", self.tokens_per_sec())?;
+        write!(f, "bytes/s: {:.0}", self.bytes_per_sec())
+    }
+}
+
+impl Environment {
+    fn capture() -> Self {
+        // Every feature that changes what the vm does while it runs.
+        let features = [
+            ("trace_execution", cfg!(feature = "trace_execution")),
+            ("trace_stack", cfg!(feature = "trace_stack")),
+            ("print_code", cfg!(feature = "print_code")),
+            ("stress_gc", cfg!(feature = "stress_gc")),
+            ("jit", cfg!(feature = "jit")),
+            ("profiling", cfg!(feature = "profiling")),
+            ("check_stack", cfg!(feature = "check_stack")),
+            ("log", cfg!(feature = "log")),
+            ("tracing", cfg!(feature = "tracing")),
+        ];
+        let features = features
+            .into_iter()
+            .filter_map(|(name, on)| on.then_some(name))
+            .collect();
+        Environment {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+            features,
+        }
+    }
+}
+
+impl Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "redlox {} {}-{} ({}) features: [{}]",
+            self.version,
+            self.arch,
+            self.os,
+            self.profile,
+            self.features.join(", ")
+        )
+    }
+}
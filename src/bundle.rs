@@ -0,0 +1,65 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+
+// A bundle is the runtime executable, then the compiled program, then the
+// program's length (u64, little-endian), then this marker.
+const MARKER: &[u8; 8] = b"RLOXBNDL";
+const TRAILER_LEN: u64 = 16;
+
+/// Write `output`: a copy of the `runtime` executable (normally the
+/// interpreter itself) with `program`, from [`Vm::compile`], appended. When
+/// run, the copy finds the program with [`bundled_program`] and runs it.
+///
+/// [`Vm::compile`]: crate::Vm::compile
+pub fn bundle(program: &[u8], runtime: &Path, output: &Path) -> Result<()> {
+    let mut bytes = fs::read(runtime)?;
+    // Bundling from a bundle replaces its program rather than stacking.
+    if let Some(start) = program_start(&bytes) {
+        bytes.truncate(start);
+    }
+    bytes.extend_from_slice(program);
+    bytes.extend_from_slice(&(program.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(MARKER);
+    fs::write(output, bytes)?;
+    fs::set_permissions(output, fs::metadata(runtime)?.permissions())?;
+    Ok(())
+}
+
+/// The program appended to `exe` by [`bundle`], if there is one.
+pub fn bundled_program(exe: &Path) -> Result<Option<Vec<u8>>> {
+    let mut file = File::open(exe)?;
+    let size = file.metadata()?.len();
+    if size < TRAILER_LEN {
+        return Ok(None);
+    }
+    let mut tail = [0; TRAILER_LEN as usize];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut tail)?;
+    if &tail[8..] != MARKER {
+        return Ok(None);
+    }
+    let len = u64::from_le_bytes(tail[..8].try_into()?);
+    if len > size - TRAILER_LEN {
+        bail!("corrupt bundle");
+    }
+    let mut program = vec![0; len as usize];
+    file.seek(SeekFrom::Start(size - TRAILER_LEN - len))?;
+    file.read_exact(&mut program)?;
+    Ok(Some(program))
+}
+
+// Where the program starts in a bundle's bytes.
+fn program_start(bytes: &[u8]) -> Option<usize> {
+    let (rest, marker) = bytes.split_at_checked(bytes.len().checked_sub(8)?)?;
+    if marker != MARKER {
+        return None;
+    }
+    let (rest, len) = rest.split_at_checked(rest.len().checked_sub(8)?)?;
+    let len = u64::from_le_bytes(len.try_into().ok()?) as usize;
+    rest.len().checked_sub(len)
+}
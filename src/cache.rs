@@ -0,0 +1,71 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{fnv1a, program::Program, vm::Heap};
+
+/// Compiled scripts saved on disk by [`Vm::interpret_cached`], one file
+/// for each source and set of compile options.
+///
+/// [`Vm::interpret_cached`]: crate::Vm::interpret_cached
+pub struct BytecodeCache {
+    dir: PathBuf,
+}
+
+impl BytecodeCache {
+    /// A cache in `$XDG_CACHE_HOME/redlox` (or `~/.cache/redlox`), if either
+    /// location is known.
+    pub fn new() -> Option<Self> {
+        let base = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => Path::new(&std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(BytecodeCache::in_dir(base.join("redlox")))
+    }
+
+    pub fn in_dir<P: Into<PathBuf>>(dir: P) -> Self {
+        BytecodeCache { dir: dir.into() }
+    }
+
+    pub(crate) fn load(
+        &self,
+        source: &str,
+        key: &[u8],
+        heap: &mut Heap,
+    ) -> Option<Program> {
+        let bytes = fs::read(self.path(source, key)).ok()?;
+        let header = BytecodeCache::header(source, key);
+        let (saved, program) = bytes.split_at_checked(header.len())?;
+        if saved != header {
+            return None;
+        }
+        Program::deserialize(program, heap).ok()
+    }
+
+    // The whole source and the options it was compiled with, since files
+    // are only named by their hashes.
+    fn header(source: &str, key: &[u8]) -> Vec<u8> {
+        let mut header = (source.len() as u64).to_le_bytes().to_vec();
+        header.extend_from_slice(source.as_bytes());
+        header.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        header.extend_from_slice(key);
+        header
+    }
+
+    fn path(&self, source: &str, key: &[u8]) -> PathBuf {
+        let source = fnv1a(source.as_bytes());
+        self.dir.join(format!("{:016x}-{:016x}.loxc", source, fnv1a(key)))
+    }
+
+    // Failing to save is harmless; the script just gets compiled next time.
+    pub(crate) fn store(&self, source: &str, key: &[u8], program: &Program) {
+        let mut bytes = BytecodeCache::header(source, key);
+        bytes.extend(program.serialize());
+        let path = self.path(source, key);
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let _ = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&tmp, bytes))
+            .and_then(|_| fs::rename(&tmp, &path));
+    }
+}
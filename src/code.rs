@@ -1,13 +1,35 @@
-use std::fmt::Display;
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    rc::Rc,
+};
 
 use anyhow::{bail, Result};
 
-use crate::Value;
+use crate::{
+    message::{Diagnostic, Message},
+    program::{Reader, Writer},
+    vm::{Heap, LoxFunction, LoxString},
+    Stderr, Value,
+};
+
+#[cfg(test)]
+mod test;
 
 #[allow(non_upper_case_globals)]
 #[allow(non_snake_case)]
 pub(crate) mod Op {
-    pub(super) fn name(op: u8) -> &'static str {
+    // The generic form of a quickened opcode.
+    pub(crate) fn generic(op: u8) -> u8 {
+        match op {
+            AddNumber | AddString => Add,
+            CallNative => Call,
+            op => op,
+        }
+    }
+
+    pub(crate) fn name(op: u8) -> &'static str {
         match op {
             Nil => "NIL",
             True => "TRUE",
@@ -24,6 +46,17 @@ pub(crate) mod Op {
             Subtract => "SUBTRACT",
             Multiply => "MULTIPLY",
             Divide => "DIVIDE",
+            AddNumber => "ADDNUMBER",
+            AddString => "ADDSTRING",
+            Zero => "ZERO",
+            One => "ONE",
+            EmptyString => "EMPTYSTRING",
+            Power => "POWER",
+            Stringify => "STRINGIFY",
+            GetIndex => "GETINDEX",
+            SetIndex => "SETINDEX",
+            Range => "RANGE",
+            CheckNumber => "CHECKNUMBER",
             Nop => "NOP",
             Constant => "CONSTANT",
             PopN => "POPN",
@@ -37,6 +70,11 @@ pub(crate) mod Op {
             Loop => "LOOP",
             Extend => "EXTEND",
             Call => "CALL",
+            Int => "INT",
+            CallNative => "CALLNATIVE",
+            List => "LIST",
+            Map => "MAP",
+            IterNext => "ITERNEXT",
             _ => "(unknown)",
         }
     }
@@ -57,6 +95,27 @@ pub(crate) mod Op {
     pub const Subtract: u8 = 12;
     pub const Multiply: u8 = 13;
     pub const Divide: u8 = 14;
+    // Quickened forms, which the vm rewrites generic opcodes into once it
+    // has seen their operands, and back again if the operands change.
+    pub const AddNumber: u8 = 15;
+    pub const AddString: u8 = 16;
+    // Common constants, which need no constant table entry.
+    pub const Zero: u8 = 17;
+    pub const One: u8 = 18;
+    pub const EmptyString: u8 = 19;
+    pub const Power: u8 = 20;
+    // Replaces the value on top of the stack with its text, as `print`
+    // shows it.
+    pub const Stringify: u8 = 21;
+    // `list[index]`, and `list[index] = value`, which leaves the value.
+    pub const GetIndex: u8 = 22;
+    pub const SetIndex: u8 = 23;
+    // Checks that the two values on top of the stack, the ends of a range,
+    // are numbers.
+    pub const Range: u8 = 24;
+    // Checks that the value on top of the stack, the target of a `++` or
+    // `--`, is a number.
+    pub const CheckNumber: u8 = 25;
     pub const Nop: u8 = 127;
     // One-argument opcodes
     pub const Constant: u8 = 128;
@@ -71,12 +130,58 @@ pub(crate) mod Op {
     pub const Loop: u8 = 137;
     pub const Extend: u8 = 138;
     pub const Call: u8 = 139;
+    // A small whole number, given by the operand.
+    pub const Int: u8 = 140;
+    // Quickened Call, for a site that last called a native function.
+    pub const CallNative: u8 = 141;
+    // Makes a list of the top `operand` values.
+    pub const List: u8 = 142;
+    // Makes a map of the top `operand` pairs of keys and values.
+    pub const Map: u8 = 143;
+    // Steps a for-in loop over the list or map in local slot `operand`,
+    // pushing the next item (or nil) and whether there was one.
+    pub const IterNext: u8 = 144;
 }
 
+// The code is in Cells so that the vm can quicken instructions while it is
+// running them.
 pub(crate) struct Chunk {
-    code: Vec<Bytecode>,
+    code: Vec<Cell<Bytecode>>,
     constants: Vec<Value>,
     line_map: LineMap,
+    // The text of the script the chunk was compiled from, if the vm keeps
+    // it for error messages.
+    source: Option<Rc<str>>,
+    // While compiling: the offsets of jumps not yet patched, and where
+    // Extends have been inserted to widen jumps, in order.
+    pending: Vec<usize>,
+    widened: Vec<usize>,
+    // While compiling: the index of each number and string constant, so
+    // that repeats of one share a slot.
+    pool: HashMap<Pooled, u32>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Pooled {
+    Number(u64),
+    String(Box<str>),
+}
+
+// A position in a chunk being compiled, which stays valid when a jump before
+// it is widened.
+#[derive(Copy, Clone)]
+pub(crate) struct Label {
+    offset: usize,
+    // How many widenings had happened when the label was made.
+    epoch: usize,
+}
+
+// A patched jump or loop, for re-laying out jumps when one is widened.
+struct Span {
+    start: usize,
+    len: usize,
+    target: usize,
+    backward: bool,
 }
 
 pub(crate) struct InstIter<'a> {
@@ -107,93 +212,88 @@ impl Chunk {
             code: Vec::new(),
             constants: Vec::new(),
             line_map: LineMap::new(),
+            source: None,
+            pending: Vec::new(),
+            widened: Vec::new(),
+            pool: HashMap::new(),
         }
     }
 
     pub(crate) fn add_constant(&mut self, value: Value) -> Result<u32> {
+        let key = match &value {
+            // By bits, so that 0 and -0 stay apart.
+            Value::Number(n) => Some(Pooled::Number(n.to_bits())),
+            Value::String(s) => Some(Pooled::String((**s.borrow()).clone())),
+            _ => None,
+        };
+        if let Some(&idx) = key.as_ref().and_then(|key| self.pool.get(key)) {
+            return Ok(idx);
+        }
         let idx = self.constants.len();
         if idx >= Chunk::MAX_CONSTS {
-            bail!("too many constants in one chunk")
+            bail!(Diagnostic::new(Message::TooManyConstants, &[]))
         }
         self.constants.push(value);
-        Ok(idx as u32)
-    }
-
-    pub(crate) fn disassemble<T: Display>(&self, name: &str, sym_names: &[T]) {
-        println!("== {name} ==");
-        let mut offset = 0;
-        for inst in self.instructions(offset) {
-            print!("{:4} ", self.get_line(offset));
-            self.disassemble_instruction(inst, offset, sym_names);
-            offset += inst.len;
+        if let Some(key) = key {
+            self.pool.insert(key, idx as u32);
         }
+        Ok(idx as u32)
     }
 
-    fn disassemble_const(&self, arg: u32) {
-        Chunk::disassemble_op_arg(Op::Constant, arg);
-        if arg as usize >= self.constants.len() {
-            println!("(out of range)");
-        } else {
-            println!("{}", self.constants[arg as usize]);
-        }
+    // Lists the chunk, followed by the chunks of any functions among its
+    // constants, each indented one level further than the chunk it is in.
+    pub(crate) fn disassemble<T: Display>(
+        &self,
+        name: &str,
+        sym_names: &[T],
+    ) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = self.write_listing(&mut out, name, sym_names, 0);
+        out
     }
 
+    // The listing line for one instruction, without the source line.
     pub(crate) fn disassemble_instruction<T: Display>(
         &self,
         inst: Instruction,
         offset: usize,
         sym_names: &[T],
-    ) {
-        print!("{:04} ", offset);
-        match inst.opcode {
-            op if op < Op::Constant => {
-                println!("{}", Op::name(op));
-            }
-            Op::Constant => {
-                // Show the value of the constant
-                self.disassemble_const(inst.operand);
-            }
-            Op::DefineGlobal | Op::GetGlobal | Op::SetGlobal => {
-                // Show the name of the symbol
-                self.disassemble_sym(inst.opcode, inst.operand, sym_names);
-            }
-            Op::JumpIfFalse | Op::Jump => {
-                // Convert the offset argument to an address
-                Chunk::disassemble_op_arg(
-                    inst.opcode,
-                    (offset + 2 + inst.operand as usize) as u32,
-                );
-            }
-            Op::Loop => {
-                // Convert the offset argument to an address
-                Chunk::disassemble_op_arg(
-                    Op::Loop,
-                    (offset + inst.len - inst.operand as usize) as u32,
-                );
-            }
-            _ => {
-                Chunk::disassemble_op_arg(inst.opcode, inst.operand);
-                println!();
-            }
-        }
+    ) -> String {
+        let mut out = String::new();
+        let _ = self.write_instruction(&mut out, inst, offset, sym_names);
+        out
     }
 
-    fn disassemble_op_arg(op: Opcode, arg: u32) {
-        print!("{:10} {:08} ", Op::name(op), arg);
+    pub(crate) fn constants(&self) -> &[Value] {
+        &self.constants
     }
 
-    fn disassemble_sym<T: Display>(
-        &self,
-        op: Opcode,
-        arg: u32,
-        sym_names: &[T],
-    ) {
-        Chunk::disassemble_op_arg(op, arg);
-        if arg as usize >= sym_names.len() {
-            println!("(out of range)");
-        } else {
-            println!("{}", sym_names[arg as usize]);
+    pub(crate) fn deserialize(
+        r: &mut Reader,
+        heap: &mut Heap,
+    ) -> Result<Chunk> {
+        let mut chunk = Chunk::new();
+        for _ in 0..r.u32()? {
+            chunk.code.push(Cell::new(r.u16()?));
+            chunk.line_map.lines.push(r.u32()?);
+        }
+        for _ in 0..r.u32()? {
+            let value = match r.u8()? {
+                0 => Value::Nil,
+                1 => Value::Boolean(r.u8()? != 0),
+                2 => Value::Number(f64::from_bits(r.u64()?)),
+                3 => Value::String(heap.alloc(LoxString::new(r.str()?))),
+                4 => {
+                    let func = LoxFunction::deserialize(r, heap)?;
+                    Value::Function(heap.alloc(func))
+                }
+                tag => bail!("bad constant tag {}", tag),
+            };
+            chunk.constants.push(value);
         }
+        chunk.validate(r.symbols)?;
+        Ok(chunk)
     }
 
     pub(crate) fn get_constant(&self, idx: u32) -> Value {
@@ -205,7 +305,7 @@ impl Chunk {
         let mut inst = Instruction::default();
         let mut idx = offset;
         loop {
-            let bytes = self.code[idx].to_be_bytes();
+            let bytes = self.code[idx].get().to_be_bytes();
             inst.opcode = bytes[0];
             inst.operand |= bytes[1] as u32;
             if inst.opcode != Op::Extend {
@@ -225,7 +325,7 @@ impl Chunk {
         self.line_map.get_line(offset)
     }
 
-    pub(crate) fn instructions(&self, offset: usize) -> InstIter {
+    pub(crate) fn instructions(&self, offset: usize) -> InstIter<'_> {
         InstIter {
             chunk: self,
             offset,
@@ -240,29 +340,464 @@ impl Chunk {
         self.line_map.new_line(line);
     }
 
-    pub(crate) fn patch_jump(&mut self, offset: usize, delta: u16) {
-        let code = u16::from_be_bytes([
-            (self.code[offset] >> 8) as u8,
-            (delta >> 8) as u8,
-        ]);
-        self.code[offset] = code;
-        let code = u16::from_be_bytes([
-            (self.code[offset + 1] >> 8) as u8,
-            (delta & 0xff) as u8,
-        ]);
-        self.code[offset + 1] = code;
+    // The current position of the end of the chunk.
+    pub(crate) fn label(&self) -> Label {
+        Label {
+            offset: self.code.len(),
+            epoch: self.widened.len(),
+        }
+    }
+
+    // Finished jumps and loops, with the targets their operands encode.
+    fn spans(&self) -> Vec<Span> {
+        let mut spans = Vec::new();
+        let mut offset = 0;
+        for inst in self.instructions(0) {
+            let start = offset;
+            offset += inst.len;
+            let delta = inst.operand as usize;
+            let (target, backward) = match inst.opcode {
+                Op::Jump | Op::JumpIfFalse => (offset + delta, false),
+                Op::Loop => (offset - delta, true),
+                _ => continue,
+            };
+            if !self.pending.contains(&start) {
+                spans.push(Span {
+                    start,
+                    len: inst.len,
+                    target,
+                    backward,
+                });
+            }
+        }
+        spans
+    }
+
+    // Points the jump written at `label` to the end of the chunk. If that
+    // is too far for its operand, it gets another Extend, moving everything
+    // after it along; any jumps that then need more room are widened the
+    // same way, until every jump fits.
+    pub(crate) fn patch_jump(&mut self, label: Label) {
+        let origin = self.resolve(label);
+        let span = Span {
+            start: origin,
+            len: 2,
+            target: self.code.len(),
+            backward: false,
+        };
+        if span.fits() {
+            self.pending.retain(|&offset| offset != origin);
+            self.set_operand(&span);
+            return;
+        }
+
+        // Collected while the jump is still pending, so that its placeholder
+        // operand isn't taken for a target.
+        let mut spans = self.spans();
+        self.pending.retain(|&offset| offset != origin);
+        spans.push(span);
+        while let Some(at) = spans
+            .iter()
+            .find(|span| !span.fits())
+            .map(|span| span.start)
+        {
+            let word = u16::from_be_bytes([Op::Extend, 0]);
+            self.code.insert(at, Cell::new(word));
+            self.line_map.lines.insert(at, self.line_map.lines[at]);
+            self.widened.push(at);
+            for offset in &mut self.pending {
+                if *offset > at {
+                    *offset += 1;
+                }
+            }
+            for span in &mut spans {
+                match span.start {
+                    start if start > at => span.start += 1,
+                    start if start == at => span.len += 1,
+                    _ => (),
+                }
+                // A jump to the widened instruction still lands on its start.
+                if span.target > at {
+                    span.target += 1;
+                }
+            }
+        }
+        for span in &spans {
+            self.set_operand(span);
+        }
+    }
+
+    #[cfg_attr(not(feature = "print_code"), allow(dead_code))]
+    pub(crate) fn print_disassembly<T: Display>(
+        &self,
+        w: &Stderr,
+        name: &str,
+        sym_names: &[T],
+    ) {
+        let listing = self.disassemble(name, sym_names);
+        let _ = w.borrow_mut().write_all(listing.as_bytes());
+    }
+
+    #[cfg_attr(not(feature = "trace_execution"), allow(dead_code))]
+    pub(crate) fn print_instruction<T: Display>(
+        &self,
+        w: &Stderr,
+        inst: Instruction,
+        offset: usize,
+        sym_names: &[T],
+    ) {
+        let line = self.disassemble_instruction(inst, offset, sym_names);
+        let _ = w.borrow_mut().write_all(line.as_bytes());
     }
 
     fn push_op(&mut self, op: Opcode, arg: u8) {
         let code = u16::from_be_bytes([op, arg]);
-        self.code.push(code);
+        self.code.push(Cell::new(code));
         self.line_map.add_op();
     }
 
-    pub(crate) fn write_jump(&mut self, op: Opcode) -> usize {
-        let offset = self.code.len();
+    // Where `label` is now, after any widening since it was made.
+    fn resolve(&self, label: Label) -> usize {
+        let widened = &self.widened[label.epoch..];
+        label.offset + widened.iter().filter(|&&at| at < label.offset).count()
+    }
+
+    // Keep the script's text with this chunk and those of the functions in
+    // it, so that runtime errors can quote it.
+    pub(crate) fn set_source(&mut self, source: Rc<str>) {
+        for constant in &self.constants {
+            if let Value::Function(func) = constant {
+                func.borrow_mut().chunk.set_source(source.clone());
+            }
+        }
+        self.source = Some(source);
+    }
+
+    pub(crate) fn source_line(&self, line: u32) -> Option<&str> {
+        crate::source_line(self.source.as_deref()?, line)
+    }
+
+    // Replace the opcode of the (unextended) instruction at `offset`.
+    pub(crate) fn quicken(&self, offset: usize, op: Opcode) {
+        let [_, arg] = self.code[offset].get().to_be_bytes();
+        self.code[offset].set(u16::from_be_bytes([op, arg]));
+    }
+
+    pub(crate) fn serialize(&self, w: &mut Writer) {
+        w.u32(self.code.len() as u32);
+        // Quickening depends on what the vm has seen, so save generic code.
+        for (code, &line) in self.code.iter().zip(&self.line_map.lines) {
+            let [op, arg] = code.get().to_be_bytes();
+            w.u16(u16::from_be_bytes([Op::generic(op), arg]));
+            w.u32(line);
+        }
+        w.u32(self.constants.len() as u32);
+        for constant in &self.constants {
+            match constant {
+                Value::Nil => w.u8(0),
+                Value::Boolean(v) => {
+                    w.u8(1);
+                    w.u8(*v as u8);
+                }
+                Value::Number(v) => {
+                    w.u8(2);
+                    w.u64(v.to_bits());
+                }
+                Value::String(v) => {
+                    w.u8(3);
+                    w.str(&v.borrow());
+                }
+                Value::Function(v) => {
+                    w.u8(4);
+                    v.borrow().serialize(w);
+                }
+                Value::Builtin(_) | Value::List(_) | Value::Map(_) => {
+                    unreachable!("natives, lists and maps are never constants")
+                }
+            }
+        }
+    }
+
+    // Replaces the symbol id of each global opcode, here and in the functions
+    // among the constants, with `ids[id]`. A new id has to fit in as many
+    // bytes as the old one, since widening would move jump targets.
+    pub(crate) fn relink(&self, ids: &[u32]) -> Result<()> {
+        let mut offset = 0;
+        for inst in self.instructions(0) {
+            if let Op::DefineGlobal | Op::GetGlobal | Op::SetGlobal =
+                inst.opcode
+            {
+                let id = ids[inst.operand as usize];
+                if inst.len < 4 && id >> (8 * inst.len) != 0 {
+                    bail!("too many globals to run this program");
+                }
+                for (i, cell) in self.code[offset..offset + inst.len]
+                    .iter()
+                    .rev()
+                    .enumerate()
+                {
+                    let [op, _] = cell.get().to_be_bytes();
+                    let arg = (id >> (8 * i)) as u8;
+                    cell.set(u16::from_be_bytes([op, arg]));
+                }
+            }
+            offset += inst.len;
+        }
+        for constant in &self.constants {
+            if let Value::Function(f) = constant {
+                f.borrow().chunk.relink(ids)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that a deserialized chunk can't send the vm out of bounds.
+    fn validate(&self, symbols: usize) -> Result<()> {
+        let mut offset = 0;
+        for inst in self.instructions(0) {
+            let next = offset + inst.len;
+            let in_range = match inst.opcode {
+                Op::Constant => (inst.operand as usize) < self.constants.len(),
+                Op::Jump | Op::JumpIfFalse => {
+                    next + inst.operand as usize <= self.code.len()
+                }
+                Op::Loop => inst.operand as usize <= next,
+                Op::DefineGlobal | Op::GetGlobal | Op::SetGlobal => {
+                    (inst.operand as usize) < symbols
+                }
+                Op::Extend => false,
+                _ => true,
+            };
+            if !in_range {
+                bail!("bad operand at offset {}", offset);
+            }
+            offset = next;
+        }
+        Ok(())
+    }
+
+    // The stack height, relative to the frame base, before each instruction
+    // (indexed by offset; u32::MAX where no reachable instruction starts).
+    // Fails if any instruction could take the stack below the frame, use a
+    // local slot that doesn't exist yet, or be reached with two different
+    // heights, none of which the compiler should ever emit.
+    pub(crate) fn stack_depths(&self, arity: usize) -> Result<Vec<u32>> {
+        let mut starts = vec![false; self.code.len()];
+        let mut offset = 0;
+        for inst in self.instructions(0) {
+            starts[offset] = true;
+            offset += inst.len;
+        }
+
+        let mut depths = vec![u32::MAX; self.code.len()];
+        let mut work = vec![(0, arity as u32 + 1)];
+        while let Some((offset, depth)) = work.pop() {
+            if offset >= self.code.len() || !starts[offset] {
+                bail!("jump to offset {} is not an instruction", offset);
+            }
+            match depths[offset] {
+                u32::MAX => depths[offset] = depth,
+                seen if seen == depth => continue,
+                seen => bail!(
+                    "stack height at offset {} is {} or {}",
+                    offset,
+                    seen,
+                    depth
+                ),
+            }
+            let inst = self.get_instruction(offset);
+            let next = offset + inst.len;
+            let operand = inst.operand;
+            let (pops, pushes) = match inst.opcode {
+                Op::Nil | Op::True | Op::False | Op::Constant => (0, 1),
+                Op::Zero | Op::One | Op::EmptyString | Op::Int => (0, 1),
+                Op::GetGlobal => (0, 1),
+                Op::GetLocal | Op::SetLocal | Op::IterNext
+                    if operand >= depth =>
+                {
+                    bail!("no local slot {} at offset {}", operand, offset)
+                }
+                Op::GetLocal => (0, 1),
+                Op::SetLocal | Op::SetGlobal => (1, 1),
+                Op::Pop | Op::Print | Op::DefineGlobal => (1, 0),
+                Op::List => (operand, 1),
+                Op::Map => (2 * operand, 1),
+                Op::IterNext => (0, 2),
+                Op::GetIndex => (2, 1),
+                Op::SetIndex => (3, 1),
+                Op::Range => (2, 2),
+                Op::PopN => (operand, 0),
+                Op::Not | Op::Negate | Op::CheckNumber | Op::Stringify => {
+                    (1, 1)
+                }
+                Op::Equal
+                | Op::Greater
+                | Op::Less
+                | Op::Add
+                | Op::AddNumber
+                | Op::AddString
+                | Op::Subtract
+                | Op::Multiply
+                | Op::Divide
+                | Op::Power => (2, 1),
+                Op::Call | Op::CallNative => (operand + 1, 1),
+                Op::JumpIfFalse => (1, 1),
+                Op::Jump | Op::Loop | Op::Nop => (0, 0),
+                // The result, above the callee's slot.
+                Op::Return => (2, 2),
+                op => bail!("unexpected opcode {} at offset {}", op, offset),
+            };
+            // Nothing may pop the frame's own slot 0 except Return.
+            if pops >= depth && inst.opcode != Op::Return || pops > depth {
+                bail!("stack underflow at offset {}", offset);
+            }
+            let depth = depth - pops + pushes;
+            match inst.opcode {
+                Op::Return => (),
+                Op::Jump => work.push((next + operand as usize, depth)),
+                Op::JumpIfFalse => {
+                    work.push((next, depth));
+                    work.push((next + operand as usize, depth));
+                }
+                Op::Loop => match next.checked_sub(operand as usize) {
+                    Some(target) => work.push((target, depth)),
+                    None => bail!("loop before start at offset {}", offset),
+                },
+                _ => work.push((next, depth)),
+            }
+        }
+        Ok(depths)
+    }
+
+    // Writes the distance to a span's target across its operand bytes.
+    fn set_operand(&self, span: &Span) {
+        let delta = span.delta();
+        for (i, code) in self.code[span.start..][..span.len].iter().enumerate()
+        {
+            let [op, _] = code.get().to_be_bytes();
+            let byte = (delta >> (8 * (span.len - 1 - i))) as u8;
+            code.set(u16::from_be_bytes([op, byte]));
+        }
+    }
+
+    fn write_instruction<T: Display>(
+        &self,
+        w: &mut impl fmt::Write,
+        inst: Instruction,
+        offset: usize,
+        sym_names: &[T],
+    ) -> fmt::Result {
+        write!(w, "{:04} ", offset)?;
+        let arg = inst.operand;
+        let next = offset + inst.len;
+        match inst.opcode {
+            op if op < Op::Constant => writeln!(w, "{}", Op::name(op)),
+            Op::Constant => {
+                Chunk::write_operand(w, inst.opcode, arg)?;
+                // Show the value of the constant
+                match self.constants.get(arg as usize) {
+                    Some(value) => writeln!(w, " {}", value),
+                    None => writeln!(w, " (out of range)"),
+                }
+            }
+            Op::DefineGlobal | Op::GetGlobal | Op::SetGlobal => {
+                Chunk::write_operand(w, inst.opcode, arg)?;
+                // Show the name of the symbol
+                match sym_names.get(arg as usize) {
+                    Some(name) => writeln!(w, " {}", name),
+                    None => writeln!(w, " (out of range)"),
+                }
+            }
+            Op::JumpIfFalse | Op::Jump => {
+                // Convert the offset argument to an address
+                let dest = next + arg as usize;
+                Chunk::write_operand(w, inst.opcode, dest as u32)?;
+                writeln!(w)
+            }
+            Op::Loop => {
+                // Convert the offset argument to an address
+                let dest = next.wrapping_sub(arg as usize);
+                Chunk::write_operand(w, inst.opcode, dest as u32)?;
+                writeln!(w)
+            }
+            _ => {
+                Chunk::write_operand(w, inst.opcode, arg)?;
+                writeln!(w)
+            }
+        }
+    }
+
+    pub(crate) fn write_jump(&mut self, op: Opcode) -> Label {
+        let label = self.label();
+        self.pending.push(label.offset);
         self.write_op_arg(op, 0xfff);
-        offset
+        label
+    }
+
+    fn write_listing<T: Display>(
+        &self,
+        w: &mut impl fmt::Write,
+        name: &str,
+        sym_names: &[T],
+        depth: usize,
+    ) -> fmt::Result {
+        let indent = depth * 2;
+        writeln!(w, "{:indent$}== {name} ==", "")?;
+        let mut offset = 0;
+        for inst in self.instructions(offset) {
+            write!(w, "{:indent$}{:4} ", "", self.get_line(offset))?;
+            self.write_instruction(w, inst, offset, sym_names)?;
+            offset += inst.len;
+        }
+        self.write_constant_counts(w, indent)?;
+        for constant in &self.constants {
+            if let Value::Function(func) = constant {
+                let func = func.borrow();
+                func.chunk.write_listing(
+                    w,
+                    func.name(),
+                    sym_names,
+                    depth + 1,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // A line summing up the constant table, like "constants: 2 numbers, 1
+    // string", for spotting chunks whose tables have grown large.
+    fn write_constant_counts(
+        &self,
+        w: &mut impl fmt::Write,
+        indent: usize,
+    ) -> fmt::Result {
+        if self.constants.is_empty() {
+            return Ok(());
+        }
+        let mut counts = BTreeMap::new();
+        for constant in &self.constants {
+            *counts.entry(constant.type_name()).or_insert(0) += 1;
+        }
+        let counts: Vec<_> = counts
+            .into_iter()
+            .map(|(ty, n)| match n {
+                1 => format!("1 {}", ty),
+                n => format!("{} {}s", n, ty),
+            })
+            .collect();
+        writeln!(w, "{:indent$}constants: {}", "", counts.join(", "))
+    }
+
+    // Writes a Loop back to `dest`, with as many Extends as it takes.
+    pub(crate) fn write_loop(&mut self, dest: Label) {
+        let dest = self.resolve(dest);
+        // The Loop's own length counts towards the distance back.
+        let delta = |len: usize| self.code.len() + len - dest;
+        let mut len = 1;
+        while len < 4 && delta(len) >> (8 * len) != 0 {
+            len += 1;
+        }
+        self.write_op_arg(Op::Loop, delta(len) as u32);
     }
 
     pub(crate) fn write_op(&mut self, op: Opcode) {
@@ -270,6 +805,14 @@ impl Chunk {
         self.push_op(op, 0);
     }
 
+    // Writes `op` as though it were on `line`, leaving the line of the ops
+    // after it as it was.
+    pub(crate) fn write_op_at(&mut self, op: Opcode, line: u32) {
+        let current = std::mem::replace(&mut self.line_map.current, line);
+        self.write_op(op);
+        self.line_map.current = current;
+    }
+
     pub(crate) fn write_op_arg(&mut self, op: Opcode, arg: u32) {
         assert!(op >= Op::Constant);
         if arg > 0xff {
@@ -281,6 +824,36 @@ impl Chunk {
         }
         self.push_op(op, arg as u8);
     }
+
+    pub(crate) fn write_op_arg_at(&mut self, op: Opcode, arg: u32, line: u32) {
+        let current = std::mem::replace(&mut self.line_map.current, line);
+        self.write_op_arg(op, arg);
+        self.line_map.current = current;
+    }
+
+    fn write_operand(
+        w: &mut impl fmt::Write,
+        op: Opcode,
+        arg: u32,
+    ) -> fmt::Result {
+        write!(w, "{:10} {:08}", Op::name(op), arg)
+    }
+}
+
+impl Span {
+    fn delta(&self) -> usize {
+        let end = self.start + self.len;
+        if self.backward {
+            end - self.target
+        } else {
+            self.target - end
+        }
+    }
+
+    // Whether the operand has room for the distance to the target.
+    fn fits(&self) -> bool {
+        self.len >= 4 || self.delta() >> (8 * self.len) == 0
+    }
 }
 
 impl Default for Chunk {
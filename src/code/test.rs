@@ -0,0 +1,236 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{Chunk, Op};
+use crate::{
+    vm::{Heap, LoxFunction, LoxString},
+    Value, Vm,
+};
+
+#[test]
+fn listing() {
+    let mut chunk = Chunk::default();
+    let idx = chunk.add_constant(Value::Number(2.5)).unwrap();
+    chunk.write_op_arg(Op::Constant, idx);
+    chunk.write_op_arg(Op::DefineGlobal, 1);
+    chunk.new_line(2);
+    chunk.write_op_arg(Op::GetLocal, 3);
+    chunk.write_op(Op::Return);
+
+    let names = ["a", "b"];
+    assert_eq!(
+        chunk.disassemble("test", &names),
+        "== test ==\n\
+         \x20  1 0000 CONSTANT   00000000 2.5\n\
+         \x20  1 0001 DEFINEGLOBAL 00000001 b\n\
+         \x20  2 0002 GETLOCAL   00000003\n\
+         \x20  2 0003 RETURN\n\
+         constants: 1 number\n"
+    );
+}
+
+#[test]
+fn jump_addresses() {
+    let mut chunk = Chunk::default();
+    chunk.write_op(Op::True);
+    let jump = chunk.write_jump(Op::JumpIfFalse);
+    chunk.write_op(Op::Pop);
+    chunk.patch_jump(jump);
+    chunk.write_op_arg(Op::Loop, 5);
+    chunk.write_op(Op::Return);
+
+    let listing: Vec<_> = chunk
+        .instructions(0)
+        .scan(0, |offset, inst| {
+            let line = chunk.disassemble_instruction(inst, *offset, &[""]);
+            *offset += inst.len();
+            Some(line)
+        })
+        .collect();
+    assert_eq!(
+        listing,
+        [
+            "0000 TRUE\n",
+            "0001 JUMPIFFALSE 00000004\n",
+            "0003 POP\n",
+            "0004 LOOP       00000000\n",
+            "0005 RETURN\n",
+        ]
+    );
+}
+
+#[test]
+fn extended_operands() {
+    let mut chunk = Chunk::default();
+    chunk.write_op_arg(Op::GetLocal, 0x1234);
+    chunk.write_op(Op::Nil);
+
+    assert_eq!(
+        chunk.disassemble::<&str>("test", &[]),
+        "== test ==\n\
+         \x20  1 0000 GETLOCAL   00004660\n\
+         \x20  1 0002 NIL\n"
+    );
+}
+
+#[test]
+fn out_of_range_operands() {
+    let mut chunk = Chunk::default();
+    chunk.write_op_arg(Op::Constant, 3);
+    chunk.write_op_arg(Op::GetGlobal, 3);
+
+    assert_eq!(
+        chunk.disassemble::<&str>("test", &[]),
+        "== test ==\n\
+         \x20  1 0000 CONSTANT   00000003 (out of range)\n\
+         \x20  1 0001 GETGLOBAL  00000003 (out of range)\n"
+    );
+}
+
+#[test]
+fn nested_functions() {
+    let mut heap = Heap::new();
+    let mut inner = LoxFunction::new("outer.inner");
+    inner.chunk.write_op(Op::Nil);
+    inner.chunk.write_op(Op::Return);
+    let mut outer = LoxFunction::new("outer");
+    let idx = outer
+        .chunk
+        .add_constant(Value::Function(heap.alloc(inner)))
+        .unwrap();
+    outer.chunk.write_op_arg(Op::Constant, idx);
+    outer.chunk.write_op(Op::Return);
+    let mut script = Chunk::default();
+    let s = Value::String(heap.alloc(LoxString::new("s")));
+    script.add_constant(s).unwrap();
+    script
+        .add_constant(Value::Function(heap.alloc(outer)))
+        .unwrap();
+    script.write_op_arg(Op::Constant, 1);
+
+    assert_eq!(
+        script.disassemble::<&str>("<script>", &[]),
+        "== <script> ==\n\
+         \x20  1 0000 CONSTANT   00000001 <fn outer>\n\
+         constants: 1 function, 1 string\n\
+         \x20 == outer ==\n\
+         \x20    1 0000 CONSTANT   00000000 <fn outer.inner>\n\
+         \x20    1 0001 RETURN\n\
+         \x20 constants: 1 function\n\
+         \x20   == outer.inner ==\n\
+         \x20      1 0000 NIL\n\
+         \x20      1 0001 RETURN\n"
+    );
+}
+
+#[test]
+fn constants_are_pooled() {
+    let mut heap = Heap::new();
+    let mut chunk = Chunk::default();
+    let mut add = |value| chunk.add_constant(value).unwrap();
+    let a = Value::String(heap.alloc(LoxString::new("a")));
+    let other_a = Value::String(heap.alloc(LoxString::new("a")));
+    assert_eq!(add(Value::Number(2.5)), 0);
+    assert_eq!(add(a), 1);
+    assert_eq!(add(Value::Number(2.5)), 0);
+    assert_eq!(add(other_a), 1);
+    assert_eq!(add(Value::Number(-0.0)), 2);
+    assert_eq!(add(Value::Number(0.0)), 3);
+    let f = Value::Function(heap.alloc(LoxFunction::new("f")));
+    assert_eq!(add(f.clone()), 4);
+    assert_eq!(add(f), 5);
+}
+
+#[test]
+fn vm_writes_to_stdout() {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(stdout.clone(), stderr.clone());
+
+    assert!(vm.disassemble("print 1;".to_string()));
+    assert_eq!(
+        String::from_utf8(stdout.borrow().to_vec()).unwrap(),
+        "== <script> ==\n\
+         \x20  1 0000 ONE\n\
+         \x20  1 0001 PRINT\n\
+         \x20  1 0002 NIL\n\
+         \x20  1 0003 RETURN\n"
+    );
+
+    stdout.borrow_mut().clear();
+    assert!(!vm.disassemble("print;".to_string()));
+    assert!(stdout.borrow().is_empty());
+    assert!(!stderr.borrow().is_empty());
+}
+
+// Each jump's offset, with the offset it lands on.
+fn jump_targets(chunk: &Chunk) -> Vec<(usize, usize)> {
+    let mut targets = Vec::new();
+    let mut offset = 0;
+    for inst in chunk.instructions(0) {
+        let next = offset + inst.len();
+        let delta = inst.operand() as usize;
+        match inst.opcode() {
+            Op::Jump | Op::JumpIfFalse => targets.push((offset, next + delta)),
+            Op::Loop => targets.push((offset, next - delta)),
+            _ => (),
+        }
+        offset = next;
+    }
+    targets
+}
+
+#[test]
+fn widened_jumps_cascade() {
+    // Like if/else: the first jump ends up spanning the second, and only
+    // just fits until the second is widened.
+    let mut chunk = Chunk::default();
+    let loop_start = chunk.label();
+    let first = chunk.write_jump(Op::JumpIfFalse);
+    for _ in 0..0xfffd {
+        chunk.write_op(Op::Nop);
+    }
+    let second = chunk.write_jump(Op::Jump);
+    chunk.patch_jump(first);
+    chunk.write_op(Op::Nop);
+    chunk.write_loop(loop_start);
+    for _ in 0..0x10000 {
+        chunk.write_op(Op::Nop);
+    }
+    chunk.patch_jump(second);
+    chunk.write_op(Op::Return);
+
+    // Both jumps now have a second Extend, and the Loop moved with them.
+    let second_at = 3 + 0xfffd;
+    let after_second = second_at + 3;
+    let loop_at = after_second + 1;
+    let end = loop_at + 3 + 0x10000;
+    assert_eq!(
+        jump_targets(&chunk),
+        [(0, after_second), (second_at, end), (loop_at, 0)]
+    );
+    assert_eq!(chunk.len(), end + 1);
+}
+
+#[test]
+fn loop_operand_width() {
+    let mut chunk = Chunk::default();
+    let start = chunk.label();
+    for _ in 0..0xff {
+        chunk.write_op(Op::Nop);
+    }
+    chunk.write_loop(start);
+    for _ in 0..0xff00 {
+        chunk.write_op(Op::Nop);
+    }
+    chunk.write_loop(start);
+
+    let first = 0xff;
+    let second = first + 2 + 0xff00;
+    assert_eq!(jump_targets(&chunk), [(first, 0), (second, 0)]);
+    let lens: Vec<_> = chunk
+        .instructions(first)
+        .filter(|inst| inst.opcode() == Op::Loop)
+        .map(|inst| inst.len())
+        .collect();
+    assert_eq!(lens, [2, 3]);
+}
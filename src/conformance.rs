@@ -0,0 +1,371 @@
+//! A conformance suite: Lox programs, each with the output it should give,
+//! that between them run every opcode, give operands of every width (with
+//! no, one and two `Extend` prefixes), and jump and loop over bodies too
+//! long for narrower operands. A fork or another backend can run them with
+//! [`run`] to check that it behaves as this crate's vm does.
+
+/// A program in the suite, and what running it should give.
+#[derive(Clone, Debug)]
+pub struct Case {
+    pub name: String,
+    pub source: String,
+    /// Everything the program prints.
+    pub stdout: String,
+    /// Part of the error the program stops with, if it should stop with
+    /// one; otherwise nothing should be written to stderr.
+    pub error: Option<String>,
+}
+
+impl Case {
+    fn new(name: &str, source: &str, stdout: &[&str]) -> Self {
+        Case {
+            name: name.to_string(),
+            source: source.to_string(),
+            stdout: stdout.iter().map(|line| format!("{}\n", line)).collect(),
+            error: None,
+        }
+    }
+
+    fn error(name: &str, source: &str, stdout: &[&str], error: &str) -> Self {
+        Case {
+            error: Some(error.to_string()),
+            ..Case::new(name, source, stdout)
+        }
+    }
+
+    /// Whether `stdout` and `stderr`, from running the program, are what
+    /// they should be; if not, why not.
+    pub fn check(&self, stdout: &str, stderr: &str) -> Result<(), String> {
+        if stdout != self.stdout {
+            return Err(format!(
+                "{}: expected stdout {:?}, got {:?}",
+                self.name, self.stdout, stdout
+            ));
+        }
+        match &self.error {
+            Some(error) if !stderr.contains(error.as_str()) => Err(format!(
+                "{}: expected an error with {:?}, got {:?}",
+                self.name, error, stderr
+            )),
+            None if !stderr.is_empty() => Err(format!(
+                "{}: expected no error, got {:?}",
+                self.name, stderr
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Runs each of `cases` with `run`, which takes a program's source and
+/// returns what it wrote to stdout and stderr, as
+/// [`testing::interpret`](crate::testing::interpret) does. Returns a
+/// message for each case that didn't give what it should.
+pub fn run<F>(cases: &[Case], mut run: F) -> Vec<String>
+where
+    F: FnMut(&str) -> (String, String),
+{
+    cases
+        .iter()
+        .filter_map(|case| {
+            let (stdout, stderr) = run(&case.source);
+            case.check(&stdout, &stderr).err()
+        })
+        .collect()
+}
+
+/// The suite, apart from [`max_consts_cases`].
+pub fn cases() -> Vec<Case> {
+    let mut cases = opcode_cases();
+    cases.extend(error_cases());
+    for n in [0x100, 0x101, 0x10000, 0x10001] {
+        cases.push(constants(n));
+    }
+    // Natives and earlier cases take some symbols, so these go well past
+    // each boundary.
+    for n in [0x120, 0x10020] {
+        cases.push(globals(n));
+    }
+    cases.push(locals(0x120));
+    // Each statement of a body is five instructions.
+    for statements in [60, 14000] {
+        cases.push(jumps(statements));
+        cases.push(loops(statements));
+    }
+    cases
+}
+
+/// Programs with as many constants in one chunk as there can be, and with
+/// one more, which fails to compile. They take tens of seconds, and over a
+/// gigabyte of memory, to compile, so they aren't among [`cases`].
+pub fn max_consts_cases() -> Vec<Case> {
+    const MAX_CONSTS: usize = 0xffffff;
+    let mut over = constants(MAX_CONSTS + 1);
+    over.stdout = String::new();
+    over.error = Some("too many constants in one chunk".to_string());
+    vec![constants(MAX_CONSTS), over]
+}
+
+fn opcode_cases() -> Vec<Case> {
+    vec![
+        Case::new(
+            "literals",
+            r#"
+            print nil; print true; print false;
+            print 0; print 1; print ""; print 42; print 2.5; print -0.5;
+            print "text";
+            "#,
+            &[
+                "nil", "true", "false", "0", "1", "", "42", "2.5", "-0.5",
+                "text",
+            ],
+        ),
+        Case::new(
+            "arithmetic",
+            r#"
+            print 7 - 2; print 3 * 4; print 7 / 2; print 2 ** 10;
+            print -(3); print 1 + 2; print "a" + "b";
+            var n = 0;
+            var s = "";
+            for (var i = 0; i < 3; i = i + 1) {
+              n = n + 2.5;
+              s = s + "ab";
+            }
+            print n; print s;
+            // An addition whose operands change type after it was
+            // quickened.
+            var x = 1;
+            for (var i = 0; i < 3; i = i + 1) {
+              if (i == 2) x = "x";
+              print x + x;
+            }
+            "#,
+            &[
+                "5", "12", "3.5", "1024", "-3", "3", "ab", "7.5", "ababab",
+                "2", "2", "xx",
+            ],
+        ),
+        Case::new(
+            "comparison",
+            r#"
+            print 1 < 2; print 2 < 1; print 2 > 1; print 1 <= 1;
+            print 1 >= 2; print 1 == 1; print 1 != 1; print "a" == "a";
+            print nil == false; print !nil; print !0;
+            "#,
+            &[
+                "true", "false", "true", "true", "false", "true", "false",
+                "true", "false", "true", "false",
+            ],
+        ),
+        Case::new(
+            "variables",
+            r#"
+            var g = "global";
+            print g;
+            g = "set";
+            {
+              var a = 1;
+              var b = 2;
+              a = a + b;
+              print a;
+              print b;
+            }
+            print g;
+            "#,
+            &["global", "3", "2", "set"],
+        ),
+        Case::new(
+            "control_flow",
+            r#"
+            if (true) print "then"; else print "else";
+            if (nil) print "then"; else print "else";
+            print nil or "or";
+            print 1 and 2;
+            print false and 1;
+            var i = 0;
+            while (i < 3) i = i + 1;
+            print i;
+            for (var j = 0; j < 10; j = j + 1) {
+              if (j == 1) continue;
+              if (j == 3) break;
+              print j;
+            }
+            "#,
+            &["then", "else", "or", "2", "false", "3", "0", "2"],
+        ),
+        Case::new(
+            "functions",
+            r#"
+            fun add(a, b) { return a + b; }
+            print add(1, 2);
+            fun none() {}
+            print none();
+            fun fib(n) {
+              if (n < 2) return n;
+              return fib(n - 1) + fib(n - 2);
+            }
+            print fib(10);
+            print add;
+            "#,
+            &["3", "nil", "55", "<fn add>"],
+        ),
+        Case::new(
+            "natives",
+            r#"
+            // A call site that has called a native is quickened.
+            for (var i = 0; i < 3; i = i + 1) print len("abc") + i;
+            print clock() >= 0;
+            print len;
+            "#,
+            &["3", "4", "5", "true", "<native fn>"],
+        ),
+        Case::new(
+            "collections",
+            r#"
+            var l = [1, "two", [3]];
+            print l;
+            print l[1];
+            l[0] = 10;
+            print l[0] + l[2][0];
+            var m = {"a": 1, 2: "b"};
+            print m;
+            print m["a"];
+            m["c"] = 3;
+            print m["c"];
+            print m["missing"];
+            for (var x in l) print x;
+            for (var k in m) print k;
+            for (var i in 0..2) print i;
+            for (var i in 1..=2) print i;
+            print "${l[0]} and ${m}";
+            "#,
+            &[
+                "[1, two, [3]]",
+                "two",
+                "13",
+                "{a: 1, 2: b}",
+                "1",
+                "3",
+                "nil",
+                "10",
+                "two",
+                "[3]",
+                "a",
+                "2",
+                "c",
+                "0",
+                "1",
+                "1",
+                "2",
+                "10 and {a: 1, 2: b, c: 3}",
+            ],
+        ),
+    ]
+}
+
+// Each prints something first, so that output before an error is checked
+// too.
+fn error_cases() -> Vec<Case> {
+    let errors = [
+        ("negate", "-nil;", "operand must be a number"),
+        ("increment", "var s = \"s\";\ns++;", "operand must be a number"),
+        ("add", "nil + 1;", "operands must be numbers or strings"),
+        ("compare", "nil < 1;", "operands must be numbers"),
+        ("call", "nil();", "can only call functions"),
+        (
+            "arity",
+            "fun f(a) {}\nf();",
+            "expected 1 arguments but got 0",
+        ),
+        ("get_global", "print nope;", "undefined variable 'nope'"),
+        ("set_global", "nope = 1;", "undefined variable 'nope'"),
+        ("index", "[1][1];", "index 1 is out of bounds"),
+        ("map_key", "var m = {};\nm[nil] = 1;", "map keys must be"),
+        ("iterate", "for (var x in 1) {}", "can only loop over lists"),
+        (
+            "range",
+            "for (var x in 0..nil) {}",
+            "range endpoints must be",
+        ),
+    ];
+    errors
+        .iter()
+        .map(|(name, source, error)| {
+            let name = format!("error_{}", name);
+            let source =
+                format!("print \"before\";\n{}\nprint \"after\";", source);
+            Case::error(&name, &source, &["before"], error)
+        })
+        .collect()
+}
+
+// `n` different constants, so that the last has index `n - 1`.
+fn constants(n: usize) -> Case {
+    let mut source = String::from("var x;\n");
+    for i in 0..n {
+        source.push_str(&format!("x = {}.5;\n", i));
+    }
+    source.push_str("print x;\n");
+    let last = format!("{}.5", n - 1);
+    Case::new(&format!("constants_{:#x}", n), &source, &[&last])
+}
+
+fn globals(n: usize) -> Case {
+    let mut source = String::new();
+    for i in 0..n {
+        source.push_str(&format!("var g{} = {};\n", i, i % 200));
+    }
+    let last = format!("g{}", n - 1);
+    source.push_str(&format!("{last} = {last} + 1;\nprint {last};\n"));
+    let last = ((n - 1) % 200 + 1).to_string();
+    Case::new(&format!("globals_{:#x}", n), &source, &[&last])
+}
+
+fn locals(n: usize) -> Case {
+    let mut source = String::from("{\n");
+    for i in 0..n {
+        source.push_str(&format!("var l{} = {};\n", i, i % 200));
+    }
+    let last = format!("l{}", n - 1);
+    source.push_str(&format!("{last} = {last} + 1;\nprint {last};\n}}\n"));
+    let last = ((n - 1) % 200 + 1).to_string();
+    Case::new(&format!("locals_{:#x}", n), &source, &[&last])
+}
+
+fn body(statements: usize) -> String {
+    "n = n + 1;\n".repeat(statements)
+}
+
+// Forward jumps, over a then branch and an else branch, each of which
+// runs once.
+fn jumps(statements: usize) -> Case {
+    let body = body(statements);
+    let source = format!(
+        "var n = 0;\nfun f(c) {{\nif (c) {{\n{body}}} else {{\n{body}n = -n;\n}}\n}}\n\
+         f(true);\nprint n;\nf(false);\nprint n;\n"
+    );
+    let (once, twice) = (statements as i64, -2 * statements as i64);
+    Case::new(
+        &format!("jumps_{}", statements),
+        &source,
+        &[&once.to_string(), &twice.to_string()],
+    )
+}
+
+// Backward jumps, with `continue` and `break` from the end of the body.
+fn loops(statements: usize) -> Case {
+    let body = body(statements);
+    let source = format!(
+        "var n = 0;\nfor (var i = 0; i < 3; i = i + 1) {{\n{body}}}\nprint n;\n\
+         while (true) {{\n{body}if (n > {}) break;\ncontinue;\n}}\nprint n;\n",
+        4 * statements
+    );
+    let total = 3 * statements;
+    Case::new(
+        &format!("loops_{}", statements),
+        &source,
+        &[&total.to_string(), &(total + 2 * statements).to_string()],
+    )
+}
+
+#[cfg(test)]
+mod test;
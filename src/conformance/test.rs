@@ -0,0 +1,70 @@
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+use super::{cases, max_consts_cases, run};
+use crate::{code::Op, testing::interpret, vm::LoxFunction, Value, Vm};
+
+#[test]
+fn suite() {
+    let mismatches = run(&cases(), interpret);
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+#[test]
+#[ignore = "needs over a gigabyte of memory"]
+fn max_consts() {
+    let mismatches = run(&max_consts_cases(), interpret);
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+// Each opcode run, with the lengths of the instructions it was run in: an
+// instruction one word longer has one Extend before it.
+fn collect_ops(func: &LoxFunction, ops: &mut BTreeSet<(&'static str, usize)>) {
+    for inst in func.chunk.instructions(0) {
+        ops.insert((Op::name(inst.opcode()), inst.len()));
+    }
+    for value in func.chunk.constants() {
+        if let Value::Function(f) = value {
+            collect_ops(&f.borrow(), ops);
+        }
+    }
+}
+
+// The opcodes seen after running each case, so that the quickened forms
+// its instructions were rewritten to are among them.
+#[test]
+fn every_opcode() {
+    let mut seen = BTreeSet::new();
+    for case in cases() {
+        let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut vm = Vm::new(out.clone(), out);
+        let script = vm.compile_script(case.source).unwrap();
+        let _ = vm.run_script(&script);
+        let func = vm.script_function(&script).unwrap();
+        collect_ops(&func.borrow(), &mut seen);
+    }
+    let all: BTreeSet<_> = (0..=255u8)
+        .map(Op::name)
+        .filter(|&name| name != "(unknown)")
+        // Nop is never compiled; Extend is counted in an instruction's
+        // length.
+        .filter(|&name| name != "NOP" && name != "EXTEND")
+        .collect();
+    let names: BTreeSet<_> = seen.iter().map(|&(name, _)| name).collect();
+    let missing: Vec<_> = all.difference(&names).collect();
+    assert!(missing.is_empty(), "not run: {:?}", missing);
+    for name in ["CONSTANT", "DEFINEGLOBAL", "GETGLOBAL", "SETGLOBAL", "LOOP"] {
+        for len in 1..=3 {
+            assert!(seen.contains(&(name, len)), "{} of length {}", name, len);
+        }
+    }
+    // Forward jumps are written with one Extend, to have room for their
+    // targets.
+    for name in ["JUMPIFFALSE", "JUMP"] {
+        for len in 2..=3 {
+            assert!(seen.contains(&(name, len)), "{} of length {}", name, len);
+        }
+    }
+    for name in ["GETLOCAL", "SETLOCAL"] {
+        assert!(seen.contains(&(name, 2)), "{} with an Extend", name);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::{self, Display};
+
+use crate::Value;
+
+/// A value passed between a host program and a vm, by
+/// [`Vm::define_global`], [`Vm::get_global`], and natives added with
+/// [`Vm::register_native`].
+///
+/// [`Vm::define_global`]: crate::Vm::define_global
+/// [`Vm::get_global`]: crate::Vm::get_global
+/// [`Vm::register_native`]: crate::Vm::register_native
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostValue {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    /// A Lox or native function, by name. Functions can be read but not
+    /// defined from the host.
+    Function(String),
+    /// A copy of a list. Where a list is inside itself, the copy has nil
+    /// instead.
+    List(Vec<HostValue>),
+    /// A copy of a map's entries, in order, with nil for a map inside
+    /// itself as for lists. Keys other than strings and numbers are
+    /// dropped when it's passed to a vm.
+    Map(Vec<(HostValue, HostValue)>),
+}
+
+impl HostValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            HostValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            HostValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            HostValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, HostValue::Nil)
+    }
+
+    pub fn as_list(&self) -> Option<&[HostValue]> {
+        match self {
+            HostValue::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(HostValue, HostValue)]> {
+        match self {
+            HostValue::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    // Strings, functions, lists and maps live on a vm's heap; this copies
+    // them out.
+    pub(crate) fn from_value(value: &Value) -> Self {
+        HostValue::copy(value, &mut Vec::new())
+    }
+
+    // `outer` holds the addresses of the lists and maps that `value` is
+    // inside of.
+    fn copy(value: &Value, outer: &mut Vec<usize>) -> Self {
+        match value {
+            Value::Nil => HostValue::Nil,
+            Value::Boolean(b) => HostValue::Bool(*b),
+            Value::Number(n) => HostValue::Number(*n),
+            Value::String(s) => HostValue::String(s.borrow().to_string()),
+            Value::Function(f) => {
+                HostValue::Function(f.borrow().name().to_string())
+            }
+            Value::Builtin(f) => {
+                HostValue::Function(f.borrow().name().to_string())
+            }
+            Value::List(list) if outer.contains(&list.addr()) => HostValue::Nil,
+            Value::List(list) => {
+                outer.push(list.addr());
+                let mut items = Vec::new();
+                for item in &list.borrow().items {
+                    items.push(HostValue::copy(item, outer));
+                }
+                outer.pop();
+                HostValue::List(items)
+            }
+            Value::Map(map) if outer.contains(&map.addr()) => HostValue::Nil,
+            Value::Map(map) => {
+                outer.push(map.addr());
+                let mut entries = Vec::new();
+                for (key, value) in map.borrow().entries() {
+                    let key = HostValue::copy(key, outer);
+                    entries.push((key, HostValue::copy(value, outer)));
+                }
+                outer.pop();
+                HostValue::Map(entries)
+            }
+        }
+    }
+}
+
+impl Display for HostValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostValue::Nil => write!(f, "nil"),
+            HostValue::Bool(b) => b.fmt(f),
+            HostValue::Number(n) => n.fmt(f),
+            HostValue::String(s) => s.fmt(f),
+            HostValue::Function(name) => write!(f, "<fn {}>", name),
+            HostValue::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt(f)?;
+                }
+                write!(f, "]")
+            }
+            HostValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<()> for HostValue {
+    fn from(_: ()) -> Self {
+        HostValue::Nil
+    }
+}
+
+impl From<bool> for HostValue {
+    fn from(value: bool) -> Self {
+        HostValue::Bool(value)
+    }
+}
+
+impl From<f64> for HostValue {
+    fn from(value: f64) -> Self {
+        HostValue::Number(value)
+    }
+}
+
+impl From<i32> for HostValue {
+    fn from(value: i32) -> Self {
+        HostValue::Number(value as f64)
+    }
+}
+
+impl From<&str> for HostValue {
+    fn from(value: &str) -> Self {
+        HostValue::String(value.to_string())
+    }
+}
+
+impl From<String> for HostValue {
+    fn from(value: String) -> Self {
+        HostValue::String(value)
+    }
+}
+
+impl<T: Into<HostValue>> From<Option<T>> for HostValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(HostValue::Nil, Into::into)
+    }
+}
+
+// Conversions out of a `HostValue` give it back if it's the wrong type.
+
+impl TryFrom<HostValue> for bool {
+    type Error = HostValue;
+
+    fn try_from(value: HostValue) -> Result<Self, Self::Error> {
+        value.as_bool().ok_or(value)
+    }
+}
+
+impl TryFrom<HostValue> for f64 {
+    type Error = HostValue;
+
+    fn try_from(value: HostValue) -> Result<Self, Self::Error> {
+        value.as_number().ok_or(value)
+    }
+}
+
+impl TryFrom<HostValue> for String {
+    type Error = HostValue;
+
+    fn try_from(value: HostValue) -> Result<Self, Self::Error> {
+        match value {
+            HostValue::String(s) => Ok(s),
+            value => Err(value),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+
+use crate::{
+    code::{Chunk, Op},
+    program::Program,
+    vm::{Heap, LoxFunction},
+    Value,
+};
+
+#[cfg(test)]
+mod test;
+
+/// A read-only copy of a compiled program, as returned by [`Vm::compile`],
+/// for tools that analyze or display bytecode.
+///
+/// [`Vm::compile`]: crate::Vm::compile
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgramView {
+    /// Every function in the program, starting with the top-level script.
+    pub functions: Vec<FunctionView>,
+    /// The global names that global opcodes' operands index into.
+    pub globals: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionView {
+    pub name: String,
+    pub arity: usize,
+    /// Where the function was declared; 0 for the script.
+    pub line: u32,
+    pub instructions: Vec<InstructionView>,
+    pub constants: Vec<ConstantView>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstructionView {
+    /// In code units, from the start of the function.
+    pub offset: usize,
+    /// The name the disassembler shows, e.g. "CONSTANT".
+    pub opcode: &'static str,
+    /// As encoded; jump operands are distances, not offsets.
+    pub operand: Option<u32>,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstantView {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    /// An index into [`ProgramView::functions`].
+    Function(usize),
+}
+
+/// Read a compiled program without running it.
+pub fn inspect(program: &[u8]) -> Result<ProgramView> {
+    let mut heap = Heap::new();
+    let program = Program::deserialize(program, &mut heap)?;
+    let mut view = ProgramView {
+        functions: Vec::new(),
+        globals: program.symbols().iter().map(|s| s.to_string()).collect(),
+    };
+    add_function(&mut view.functions, &program.script);
+    Ok(view)
+}
+
+// Adds `func`, then the functions it contains, returning its index.
+fn add_function(
+    functions: &mut Vec<FunctionView>,
+    func: &LoxFunction,
+) -> usize {
+    let idx = functions.len();
+    functions.push(FunctionView {
+        name: func.name().to_string(),
+        arity: func.arity,
+        line: func.line,
+        instructions: instructions(&func.chunk),
+        constants: Vec::new(),
+    });
+    let constants = func
+        .chunk
+        .constants()
+        .iter()
+        .map(|constant| match constant {
+            Value::Nil => ConstantView::Nil,
+            Value::Boolean(b) => ConstantView::Bool(*b),
+            Value::Number(n) => ConstantView::Number(*n),
+            Value::String(s) => ConstantView::String(s.borrow().to_string()),
+            Value::Function(f) => {
+                ConstantView::Function(add_function(functions, &f.borrow()))
+            }
+            Value::Builtin(_) | Value::List(_) | Value::Map(_) => {
+                unreachable!("natives, lists and maps are never saved")
+            }
+        })
+        .collect();
+    functions[idx].constants = constants;
+    idx
+}
+
+fn instructions(chunk: &Chunk) -> Vec<InstructionView> {
+    let mut offset = 0;
+    chunk
+        .instructions(0)
+        .map(|inst| {
+            let view = InstructionView {
+                offset,
+                opcode: Op::name(inst.opcode()),
+                operand: (inst.opcode() >= Op::Constant)
+                    .then(|| inst.operand()),
+                line: chunk.get_line(offset),
+            };
+            offset += inst.len();
+            view
+        })
+        .collect()
+}
@@ -0,0 +1,54 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{inspect, ConstantView, InstructionView};
+use crate::Vm;
+
+fn compile(source: &str) -> Vec<u8> {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    Vm::new(out.clone(), out)
+        .compile(source.to_string())
+        .unwrap()
+}
+
+#[test]
+fn functions_and_constants() {
+    let source = "var a = 2.5;\nfun f(x) {\n  print \"hi\"; }\n";
+    let view = inspect(&compile(source)).unwrap();
+    assert!(view.globals.contains(&"a".to_string()));
+    assert_eq!(view.functions.len(), 2);
+
+    let script = &view.functions[0];
+    assert_eq!((script.name.as_str(), script.arity), ("<script>", 0));
+    assert_eq!(script.constants[0], ConstantView::Number(2.5));
+    assert_eq!(script.constants[1], ConstantView::Function(1));
+    let define = &script.instructions[1];
+    assert_eq!(define.opcode, "DEFINEGLOBAL");
+    let sym = define.operand.unwrap() as usize;
+    assert_eq!(view.globals[sym], "a");
+
+    let f = &view.functions[1];
+    assert_eq!((f.name.as_str(), f.arity, f.line), ("f", 1, 2));
+    assert_eq!(f.constants, [ConstantView::String("hi".into())]);
+    assert_eq!(
+        f.instructions[..2],
+        [
+            InstructionView {
+                offset: 0,
+                opcode: "CONSTANT",
+                operand: Some(0),
+                line: 3,
+            },
+            InstructionView {
+                offset: 1,
+                opcode: "PRINT",
+                operand: None,
+                line: 3,
+            },
+        ]
+    );
+}
+
+#[test]
+fn bad_programs() {
+    assert!(inspect(b"not a program").is_err());
+}
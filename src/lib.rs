@@ -1,23 +1,43 @@
 use std::{
     cell::RefCell,
     fmt::{self, Display},
-    io::Write,
-    ops::Deref,
+    io::{BufRead, Write},
     rc::Rc,
 };
 
-use vm::{LoxFunction, LoxString, RustFunction};
+use vm::{LoxFunction, LoxList, LoxMap, LoxString, Obj, RustFunction};
 
-pub use parser::print_tokens;
-pub use parser::scanner::bench_scanner;
-pub use vm::Vm;
+pub use bench::{Benchmark, Environment};
+pub use bundle::{bundle, bundled_program};
+pub use cache::BytecodeCache;
+pub use host::HostValue;
+pub use inspect::{
+    inspect, ConstantView, FunctionView, InstructionView, ProgramView,
+};
+pub use message::{Catalog, Message};
+pub use parser::{ast, print_tokens};
+pub use parser::{bench_compile, scanner::bench_scanner};
+pub use vm::{
+    bench_vm, Clock, CompiledScript, Dialect, FlushPolicy, InterruptHandle,
+    NativeContext, RuntimeError, Safepoint, SafepointHook, Vm, VmOptions,
+};
+#[cfg(feature = "profiling")]
+pub use vm::{Site, Stats};
 
+mod bench;
+mod bundle;
+mod cache;
 mod code;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod host;
+mod inspect;
+mod message;
 mod parser;
+mod program;
+pub mod testing;
 mod vm;
 
-struct Obj<T>(Rc<RefCell<T>>);
-
 #[derive(Clone, PartialEq)]
 enum Value {
     Nil,
@@ -26,63 +46,60 @@ enum Value {
     String(Obj<LoxString>),
     Function(Obj<LoxFunction>),
     Builtin(Obj<RustFunction>),
+    List(Obj<LoxList>),
+    Map(Obj<LoxMap>),
 }
 
 pub type Stdout = Rc<RefCell<dyn Write>>;
 pub type Stderr = Rc<RefCell<dyn Write>>;
+pub type Stdin = Rc<RefCell<dyn BufRead>>;
 
-impl<T> Clone for Obj<T> {
-    fn clone(&self) -> Self {
-        Obj(self.0.clone())
-    }
+// The text of line number `line` (counting from 1) in `source`.
+fn source_line(source: &str, line: u32) -> Option<&str> {
+    let line = source.split('\n').nth((line as usize).checked_sub(1)?)?;
+    Some(line.trim_end_matches('\r'))
 }
 
-impl<T> Deref for Obj<T> {
-    type Target = Rc<RefCell<T>>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
 }
 
-impl<T> From<T> for Obj<T> {
-    fn from(value: T) -> Self {
-        Obj(Rc::new(RefCell::new(value)))
-    }
-}
-
-impl PartialEq for Obj<LoxFunction> {
-    fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(self, other)
-    }
-}
+impl Value {
+    const TRUE: Value = Value::Boolean(true);
+    const FALSE: Value = Value::Boolean(false);
 
-impl PartialEq for Obj<LoxString> {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+    // Whether it's a list or map, which can be changed in place.
+    fn is_container(&self) -> bool {
+        matches!(self, Value::List(_) | Value::Map(_))
     }
-}
 
-impl PartialEq for Obj<RustFunction> {
-    fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(self, other)
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Builtin(_) => "function",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
     }
 }
 
-impl Value {
-    const TRUE: Value = Value::Boolean(true);
-    const FALSE: Value = Value::Boolean(false);
-}
-
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(v) => v.fmt(f),
+            // Never locale-dependent, since Rust's formatting isn't.
             Value::Number(v) => v.fmt(f),
             Value::String(v) => v.borrow().fmt(f),
             Value::Function(v) => v.borrow().fmt(f),
             Value::Builtin(v) => v.borrow().fmt(f),
+            Value::List(v) => v.fmt(f),
+            Value::Map(v) => v.fmt(f),
         }
     }
 }
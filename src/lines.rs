@@ -0,0 +1,124 @@
+//! Reading the REPL's lines: with line editing, history and Tab completion
+//! when built with the `repl` feature, and straight from stdin otherwise.
+
+use anyhow::Result;
+
+use redlox::Vm;
+
+#[cfg(feature = "repl")]
+pub(crate) use editor::Lines;
+#[cfg(not(feature = "repl"))]
+pub(crate) use plain::Lines;
+
+#[cfg(feature = "repl")]
+mod editor {
+    use rustyline::completion::Completer;
+    use rustyline::error::ReadlineError;
+    use rustyline::highlight::Highlighter;
+    use rustyline::hint::Hinter;
+    use rustyline::history::DefaultHistory;
+    use rustyline::validate::Validator;
+    use rustyline::{Context, Editor, Helper};
+
+    use super::*;
+
+    pub(crate) struct Lines {
+        editor: Editor<Completions, DefaultHistory>,
+    }
+
+    // Tab completion, from the names the vm knew when the line was
+    // started.
+    struct Completions {
+        names: Vec<String>,
+    }
+
+    impl Lines {
+        pub(crate) fn new() -> Result<Self> {
+            Ok(Lines {
+                editor: Editor::new()?,
+            })
+        }
+
+        // The next line, or None at the end of input.
+        pub(crate) fn read(
+            &mut self,
+            vm: &Vm,
+            prompt: &str,
+        ) -> Result<Option<String>> {
+            let names = vm.completions("");
+            self.editor.set_helper(Some(Completions { names }));
+            let line = match self.editor.readline(prompt) {
+                // Ctrl-C at the prompt ends the REPL, as SIGINT does when
+                // the terminal isn't being edited.
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => {
+                    return Ok(None)
+                }
+                result => result?,
+            };
+            self.editor.add_history_entry(line.as_str())?;
+            Ok(Some(line))
+        }
+    }
+
+    impl Completer for Completions {
+        type Candidate = String;
+
+        fn complete(
+            &self,
+            line: &str,
+            pos: usize,
+            _: &Context<'_>,
+        ) -> rustyline::Result<(usize, Vec<String>)> {
+            let start = line[..pos]
+                .char_indices()
+                .rev()
+                .take_while(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+                .last()
+                .map_or(pos, |(i, _)| i);
+            let prefix = &line[start..pos];
+            let names =
+                self.names.iter().filter(|name| name.starts_with(prefix));
+            Ok((start, names.cloned().collect()))
+        }
+    }
+
+    impl Hinter for Completions {
+        type Hint = String;
+    }
+
+    impl Highlighter for Completions {}
+
+    impl Validator for Completions {}
+
+    impl Helper for Completions {}
+}
+
+#[cfg(not(feature = "repl"))]
+mod plain {
+    use std::io::{self, stdin, stdout, BufRead, StdinLock, Write};
+
+    use super::*;
+
+    pub(crate) struct Lines {
+        lines: io::Lines<StdinLock<'static>>,
+    }
+
+    impl Lines {
+        pub(crate) fn new() -> Result<Self> {
+            Ok(Lines {
+                lines: stdin().lock().lines(),
+            })
+        }
+
+        // The next line, or None at the end of input.
+        pub(crate) fn read(
+            &mut self,
+            _vm: &Vm,
+            prompt: &str,
+        ) -> Result<Option<String>> {
+            print!("{}", prompt);
+            stdout().flush()?;
+            Ok(self.lines.next().transpose()?)
+        }
+    }
+}
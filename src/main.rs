@@ -1,42 +1,220 @@
 use std::cell::RefCell;
-use std::io::{stdin, stdout, BufRead, Write};
 use std::process::exit;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::{env, io};
 
 use anyhow::Result;
 
-use redlox::Vm;
+use redlox::{
+    ast, bundle, bundled_program, BytecodeCache, Dialect, Vm, VmOptions,
+};
+
+mod lines;
+mod tutor;
 
 fn main() -> Result<()> {
     let stdout = Rc::new(RefCell::new(io::stdout()));
     let stderr = Rc::new(RefCell::new(io::stderr()));
-    let mut vm = Vm::new(stdout, stderr);
-    let args: Vec<String> = env::args().collect();
+    let exe = env::current_exe()?;
+    if let Some(program) = bundled_program(&exe)? {
+        return Vm::new(stdout, stderr).run_compiled(&program);
+    }
+    let mut args: Vec<String> = env::args().collect();
+    let use_cache = !take_flag(&mut args, "--no-cache");
+    let disassemble = take_flag(&mut args, "--disassemble");
+    let emit_ast = take_flag(&mut args, "--emit=ast");
+    let rename_locals = take_flag(&mut args, "--rename-locals");
+    let options = VmOptions {
+        dialect: match take_flag(&mut args, "--book") {
+            true => Dialect::Book,
+            false => Dialect::Extended,
+        },
+        ..Default::default()
+    };
+    #[cfg(feature = "js")]
+    let emit_js = take_flag(&mut args, "--emit=js");
+    #[cfg(not(feature = "js"))]
+    let emit_js = false;
     match args.len() {
-        1 => repl(&mut vm)?,
-        2 => {
+        1 => {
+            let options = VmOptions {
+                optional_semicolons: true,
+                show_source: true,
+                ..options
+            };
+            repl(&mut Vm::with_options(stdout, stderr, options))?
+        }
+        5 if args[1] == "bundle" || args[1] == "compile" => {
+            let (script, output) = match &args[2..] {
+                [script, flag, output] if flag == "-o" => (script, output),
+                [flag, output, script] if flag == "-o" => (script, output),
+                _ => usage(),
+            };
+            let source = std::fs::read_to_string(script)?;
+            let program = match Vm::with_options(stdout, stderr, options)
+                .compile(source)
+            {
+                Some(program) => program,
+                None => exit(65),
+            };
+            if args[1] == "bundle" {
+                bundle(&program, &exe, output.as_ref())?
+            } else {
+                std::fs::write(output, program)?
+            }
+        }
+        3 if args[1] == "run" => {
+            let program = std::fs::read(&args[2])?;
+            Vm::with_options(stdout, stderr, options).run_compiled(&program)?
+        }
+        5 if args[1] == "rename" => {
+            let source = std::fs::read_to_string(&args[4])?;
+            match ast::rename(&source, &args[2], &args[3], &options) {
+                Ok(renamed) => print!("{}", renamed),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(65);
+                }
+            }
+        }
+        3 if args[1] == "minify" => {
+            let source = std::fs::read_to_string(&args[2])?;
+            match ast::minify(&source, &options, rename_locals) {
+                Ok(minified) => print!("{}", minified),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(65);
+                }
+            }
+        }
+        4 if args[1] == "refs" => {
+            let (name, path) = (&args[2], &args[3]);
+            let source = std::fs::read_to_string(path)?;
+            let stmts = match ast::parse(source.clone(), &options) {
+                Ok(stmts) => stmts,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(65);
+                }
+            };
+            let index = ast::symbols(&source, &stmts);
+            let Some(symbol) = index.symbols.get(name) else {
+                eprintln!("no global named '{}'", name);
+                exit(1);
+            };
+            let spans = symbol.definitions.iter().map(|s| (s, "definition"));
+            let refs = symbol.references.iter().map(|s| (s, "reference"));
+            for (span, kind) in spans.chain(refs) {
+                let column = span.start
+                    - source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+                println!("{}:{}:{}: {}", path, span.line, column + 1, kind);
+            }
+        }
+        2 if args[1] == "tutor" => tutor::run()?,
+        2 if disassemble => {
             let source = std::fs::read_to_string(&args[1])?;
-            vm.interpret(source)?;
+            let mut vm = Vm::with_options(stdout, stderr, options);
+            if !vm.disassemble(source) {
+                exit(65);
+            }
         }
-        _ => {
-            eprintln!("Usage: rlox [path]");
-            exit(1);
+        2 if emit_ast || emit_js => {
+            let source = std::fs::read_to_string(&args[1])?;
+            match ast::parse(source, &options) {
+                #[cfg(feature = "js")]
+                Ok(stmts) if emit_js => print!("{}", ast::to_js(&stmts)),
+                Ok(stmts) => println!("{}", ast::to_json(&stmts)),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(65);
+                }
+            }
         }
+        2 => {
+            let source = std::fs::read_to_string(&args[1])?;
+            let options = VmOptions {
+                show_source: true,
+                ..options
+            };
+            let mut vm = Vm::with_options(stdout, stderr, options);
+            match BytecodeCache::new().filter(|_| use_cache) {
+                Some(cache) => vm.interpret_cached(source, &cache)?,
+                None => vm.interpret(source)?,
+            }
+        }
+        _ => usage(),
     }
     Ok(())
 }
 
+// Removes `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(idx) => {
+            args.remove(idx);
+            true
+        }
+        None => false,
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: rlox [--book] [--no-cache] [--disassemble] [path]");
+    eprintln!("       rlox [--book] --emit=ast <path>");
+    #[cfg(feature = "js")]
+    eprintln!("       rlox --emit=js <path>");
+    eprintln!("       rlox [--book] minify [--rename-locals] <path>");
+    eprintln!("       rlox [--book] refs <name> <path>");
+    eprintln!("       rlox [--book] rename <old> <new> <path>");
+    eprintln!("       rlox compile <path> -o <output>");
+    eprintln!("       rlox run <compiled>");
+    eprintln!("       rlox bundle <path> -o <output>");
+    eprintln!("       rlox tutor");
+    exit(1);
+}
+
+// Where the Ctrl-C handler finds the REPL's vm.
+#[cfg(unix)]
+static INTERRUPT: std::sync::OnceLock<redlox::InterruptHandle> =
+    std::sync::OnceLock::new();
+
+// Whether the REPL is running an entry, rather than waiting for one.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Makes Ctrl-C stop the entry being run, rather than the REPL. At the
+// prompt, it still ends the REPL.
+#[cfg(unix)]
+fn catch_interrupts(vm: &Vm) {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        if !RUNNING.load(Ordering::Relaxed) {
+            unsafe {
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+                libc::raise(libc::SIGINT);
+            }
+        } else if let Some(handle) = INTERRUPT.get() {
+            handle.interrupt();
+        }
+    }
+    let _ = INTERRUPT.set(vm.interrupt_handle());
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_interrupts(_vm: &Vm) {}
+
 fn repl(vm: &mut Vm) -> Result<()> {
-    let mut lines = stdin().lock().lines();
+    catch_interrupts(vm);
+    let mut lines = lines::Lines::new()?;
     let mut line_no = 1;
     let mut source: Vec<String> = Vec::new();
     loop {
-        print!("{:4}> ", line_no);
-        stdout().flush()?;
-        let mut line = match lines.next() {
+        let mut line = match lines.read(vm, &format!("{:4}> ", line_no))? {
             None => break,
-            Some(line) => line?,
+            Some(line) => line,
         };
         line_no += 1;
         if line.ends_with('\\') {
@@ -45,8 +223,24 @@ fn repl(vm: &mut Vm) -> Result<()> {
             continue;
         } else {
             source.push(line);
-            if let Err(e) = vm.interpret(source.join("\n")) {
-                eprintln!("{}", e)
+            let entry = source.join("\n");
+            // ":dis <code>" shows the bytecode for <code> instead of running it.
+            if let Some(code) = entry.strip_prefix(":dis ") {
+                vm.disassemble(code.to_string());
+            } else if let Some(prefix) = entry.strip_prefix(":complete ") {
+                println!("{}", vm.completions(prefix.trim()).join(" "));
+            } else if let Some(name) = entry.strip_prefix(":doc ") {
+                match vm.doc(name.trim()) {
+                    Some(doc) => println!("{}", doc),
+                    None => println!("no documentation for '{}'", name.trim()),
+                }
+            } else {
+                RUNNING.store(true, Ordering::Relaxed);
+                let result = vm.interpret_repl(entry);
+                RUNNING.store(false, Ordering::Relaxed);
+                if let Err(e) = result {
+                    eprintln!("{}", e)
+                }
             }
             source.clear();
         }
@@ -0,0 +1,578 @@
+//! The text of compile errors, warnings and runtime errors. Each kind of
+//! diagnostic has a [`Message`], whose name is its key and stays the same
+//! from release to release, and an English template; a [`Catalog`] gives
+//! other templates, to translate or reword them.
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+/// A kind of diagnostic. Its template is its English text, where `{0}`,
+/// `{1}` and so on stand for its arguments, such as the name of an
+/// undefined variable. The `[line N] Error at '...':` before a compile
+/// error, and `[line N]` before a runtime error, aren't part of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Message {
+    // Compile errors and warnings
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidEscape,
+    InvalidUnicodeEscape,
+    SourceTooLarge,
+    SourceTooManyLines,
+    TooManyConstants,
+    TooManyArguments,
+    TooManyParameters,
+    TooManyListItems,
+    TooManyMapEntries,
+    ExpressionTooDeep,
+    StatementTooDeep,
+    FunctionTooDeep,
+    ExpectExpression,
+    ExpectVariableName,
+    ExpectParameterName,
+    ExpectFunctionName,
+    AlreadyVariable,
+    AlreadyParameter,
+    AlreadyFunction,
+    AlreadyGlobal,
+    AlreadyImported,
+    ReadInOwnInitializer,
+    AssignUndeclared,
+    InvalidAssignmentTarget,
+    InvalidIncrementTarget,
+    AssignmentCount,
+    ExpectAssignmentEquals,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    ReturnFromTopLevel,
+    ReturnFromDefer,
+    NestedDefer,
+    DeferAsBody,
+    DefaultNotLast,
+    ExpectCase,
+    ExportNotTopLevel,
+    ImportNotTopLevel,
+    ExpectExported,
+    ExpectNamespace,
+    ExpectNamespaceSeparator,
+    ExpectImportName,
+    NotExported,
+    ExpectArgumentsEnd,
+    ExpectParameters,
+    ExpectParametersEnd,
+    ExpectFunctionBody,
+    ExpectGroupEnd,
+    ExpectIndexEnd,
+    ExpectListEnd,
+    ExpectMapColon,
+    ExpectMapEnd,
+    ExpectInterpolationEnd,
+    ExpectBlockEnd,
+    ExpectIfParen,
+    ExpectWhileParen,
+    ExpectConditionEnd,
+    ExpectForParen,
+    ExpectLoopConditionEnd,
+    ExpectLoopClausesEnd,
+    ExpectForInSequenceEnd,
+    ExpectSwitchParen,
+    ExpectSwitchSubjectEnd,
+    ExpectSwitchBody,
+    ExpectCaseColon,
+    ExpectCaseEnd,
+    ExpectSwitchEnd,
+    ExpectPrintParen,
+    ExpectPrintEnd,
+    ExpectSemicolonAfterValue,
+    ExpectSemicolonAfterExpression,
+    ExpectSemicolonAfterVariable,
+    ExpectSemicolonAfterAssignment,
+    ExpectSemicolonAfterReturn,
+    ExpectSemicolonAfterBreak,
+    ExpectSemicolonAfterContinue,
+    ExpectSemicolonAfterImport,
+    MaybeUnassigned,
+    ShadowsLocal,
+    ShadowsGlobalDeclared,
+    ShadowsGlobal,
+    // Runtime errors
+    UndefinedVariable,
+    ReadOnlyGlobal,
+    OperandNumber,
+    OperandsNumbers,
+    OperandsNumbersOrStrings,
+    OperandTypes,
+    NotCallable,
+    NotAFunction,
+    ArgumentCount,
+    CalleeArgumentCount,
+    ArgumentRange,
+    CalleeArgumentRange,
+    DeclaredOnLine,
+    CalleeDeclaredOnLine,
+    NotIndexable,
+    IndexNotNumber,
+    IndexNotWhole,
+    IndexOutOfBounds,
+    MapKeyType,
+    MapKeyNaN,
+    NotIterable,
+    ForInIndex,
+    RangeEndpoints,
+    ListTooLong,
+    StringTooLong,
+    StackOverflow,
+    OutOfMemory,
+    OutOfFuel,
+    Interrupted,
+    ForeignScript,
+    HostFunction,
+    HostFunctionArgument,
+    NativeReturnedFunction,
+    ArgumentNotNumber,
+    ArgumentNotString,
+    ArgumentNotFunction,
+    ArgumentNotMap,
+    ArgumentNotSized,
+    PositionNotWhole,
+    PositionOutOfBounds,
+    SubstringReversed,
+    DecimalsRange,
+    ReadInput,
+    AssertionFailed,
+    AssertionFailedWith,
+    UnknownOpcode,
+    InternalError,
+}
+
+impl Message {
+    /// Every kind, compile errors and warnings first.
+    pub const ALL: &'static [Message] = &[
+        Message::UnexpectedCharacter,
+        Message::UnterminatedString,
+        Message::InvalidEscape,
+        Message::InvalidUnicodeEscape,
+        Message::SourceTooLarge,
+        Message::SourceTooManyLines,
+        Message::TooManyConstants,
+        Message::TooManyArguments,
+        Message::TooManyParameters,
+        Message::TooManyListItems,
+        Message::TooManyMapEntries,
+        Message::ExpressionTooDeep,
+        Message::StatementTooDeep,
+        Message::FunctionTooDeep,
+        Message::ExpectExpression,
+        Message::ExpectVariableName,
+        Message::ExpectParameterName,
+        Message::ExpectFunctionName,
+        Message::AlreadyVariable,
+        Message::AlreadyParameter,
+        Message::AlreadyFunction,
+        Message::AlreadyGlobal,
+        Message::AlreadyImported,
+        Message::ReadInOwnInitializer,
+        Message::AssignUndeclared,
+        Message::InvalidAssignmentTarget,
+        Message::InvalidIncrementTarget,
+        Message::AssignmentCount,
+        Message::ExpectAssignmentEquals,
+        Message::BreakOutsideLoop,
+        Message::ContinueOutsideLoop,
+        Message::ReturnFromTopLevel,
+        Message::ReturnFromDefer,
+        Message::NestedDefer,
+        Message::DeferAsBody,
+        Message::DefaultNotLast,
+        Message::ExpectCase,
+        Message::ExportNotTopLevel,
+        Message::ImportNotTopLevel,
+        Message::ExpectExported,
+        Message::ExpectNamespace,
+        Message::ExpectNamespaceSeparator,
+        Message::ExpectImportName,
+        Message::NotExported,
+        Message::ExpectArgumentsEnd,
+        Message::ExpectParameters,
+        Message::ExpectParametersEnd,
+        Message::ExpectFunctionBody,
+        Message::ExpectGroupEnd,
+        Message::ExpectIndexEnd,
+        Message::ExpectListEnd,
+        Message::ExpectMapColon,
+        Message::ExpectMapEnd,
+        Message::ExpectInterpolationEnd,
+        Message::ExpectBlockEnd,
+        Message::ExpectIfParen,
+        Message::ExpectWhileParen,
+        Message::ExpectConditionEnd,
+        Message::ExpectForParen,
+        Message::ExpectLoopConditionEnd,
+        Message::ExpectLoopClausesEnd,
+        Message::ExpectForInSequenceEnd,
+        Message::ExpectSwitchParen,
+        Message::ExpectSwitchSubjectEnd,
+        Message::ExpectSwitchBody,
+        Message::ExpectCaseColon,
+        Message::ExpectCaseEnd,
+        Message::ExpectSwitchEnd,
+        Message::ExpectPrintParen,
+        Message::ExpectPrintEnd,
+        Message::ExpectSemicolonAfterValue,
+        Message::ExpectSemicolonAfterExpression,
+        Message::ExpectSemicolonAfterVariable,
+        Message::ExpectSemicolonAfterAssignment,
+        Message::ExpectSemicolonAfterReturn,
+        Message::ExpectSemicolonAfterBreak,
+        Message::ExpectSemicolonAfterContinue,
+        Message::ExpectSemicolonAfterImport,
+        Message::MaybeUnassigned,
+        Message::ShadowsLocal,
+        Message::ShadowsGlobalDeclared,
+        Message::ShadowsGlobal,
+        Message::UndefinedVariable,
+        Message::ReadOnlyGlobal,
+        Message::OperandNumber,
+        Message::OperandsNumbers,
+        Message::OperandsNumbersOrStrings,
+        Message::OperandTypes,
+        Message::NotCallable,
+        Message::NotAFunction,
+        Message::ArgumentCount,
+        Message::CalleeArgumentCount,
+        Message::ArgumentRange,
+        Message::CalleeArgumentRange,
+        Message::DeclaredOnLine,
+        Message::CalleeDeclaredOnLine,
+        Message::NotIndexable,
+        Message::IndexNotNumber,
+        Message::IndexNotWhole,
+        Message::IndexOutOfBounds,
+        Message::MapKeyType,
+        Message::MapKeyNaN,
+        Message::NotIterable,
+        Message::ForInIndex,
+        Message::RangeEndpoints,
+        Message::ListTooLong,
+        Message::StringTooLong,
+        Message::StackOverflow,
+        Message::OutOfMemory,
+        Message::OutOfFuel,
+        Message::Interrupted,
+        Message::ForeignScript,
+        Message::HostFunction,
+        Message::HostFunctionArgument,
+        Message::NativeReturnedFunction,
+        Message::ArgumentNotNumber,
+        Message::ArgumentNotString,
+        Message::ArgumentNotFunction,
+        Message::ArgumentNotMap,
+        Message::ArgumentNotSized,
+        Message::PositionNotWhole,
+        Message::PositionOutOfBounds,
+        Message::SubstringReversed,
+        Message::DecimalsRange,
+        Message::ReadInput,
+        Message::AssertionFailed,
+        Message::AssertionFailedWith,
+        Message::UnknownOpcode,
+        Message::InternalError,
+    ];
+
+    /// The name of the kind, such as "UndefinedVariable".
+    pub fn key(self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// The kind named `key`.
+    pub fn from_key(key: &str) -> Option<Message> {
+        Message::ALL.iter().copied().find(|m| m.key() == key)
+    }
+
+    /// How many arguments the kind's messages have.
+    pub fn arity(self) -> usize {
+        placeholders(self.template()).max().map_or(0, |n| n + 1)
+    }
+
+    /// The English template.
+    pub fn template(self) -> &'static str {
+        use Message::*;
+        match self {
+            UnexpectedCharacter => "unexpected character '{0}'",
+            UnterminatedString => "unterminated string",
+            InvalidEscape => "invalid escape sequence '\\{0}'",
+            InvalidUnicodeEscape => "invalid unicode escape",
+            SourceTooLarge => {
+                "source too large ({0} bytes, but the limit is {1})"
+            }
+            SourceTooManyLines => {
+                "source too large ({0} lines, but the limit is {1})"
+            }
+            TooManyConstants => "too many constants in one chunk",
+            TooManyArguments => "can't have more than 255 arguments",
+            TooManyParameters => "can't have more than 255 parameters",
+            TooManyListItems => "can't have more than 255 items in a list",
+            TooManyMapEntries => "can't have more than 255 entries in a map",
+            ExpressionTooDeep => "expression too deeply nested",
+            StatementTooDeep => "statement too deeply nested",
+            FunctionTooDeep => "function too deeply nested",
+            ExpectExpression => "expect expression",
+            ExpectVariableName => "expect variable name",
+            ExpectParameterName => "expect parameter name",
+            ExpectFunctionName => "expect function name",
+            AlreadyVariable => {
+                "already a variable with this name in this scope"
+            }
+            AlreadyParameter => {
+                "already a parameter with this name in this scope"
+            }
+            AlreadyFunction => {
+                "already a function with this name in this scope"
+            }
+            AlreadyGlobal => "already a global with this name",
+            AlreadyImported => "already imported a global with this name",
+            ReadInOwnInitializer => {
+                "can't read local variable in its own initializer"
+            }
+            AssignUndeclared => "can't assign to undeclared variable",
+            InvalidAssignmentTarget => "invalid assignment target",
+            InvalidIncrementTarget => "invalid increment target",
+            AssignmentCount => "expect {0} values to assign, not {1}",
+            ExpectAssignmentEquals => "expect '=' after variables",
+            BreakOutsideLoop => "'break' outside of loop",
+            ContinueOutsideLoop => "'continue' outside of loop",
+            ReturnFromTopLevel => "can't return from top-level code",
+            ReturnFromDefer => "can't return from 'defer'",
+            NestedDefer => "can't defer inside 'defer'",
+            DeferAsBody => "'defer' can't be the body of another statement",
+            DefaultNotLast => "default case must be the last case",
+            ExpectCase => "expect switch case",
+            ExportNotTopLevel => "can only export at the top level",
+            ImportNotTopLevel => "can only import at the top level",
+            ExpectExported => "expect 'var' or 'fun' after 'export'",
+            ExpectNamespace => "expect namespace after 'import'",
+            ExpectNamespaceSeparator => "expect '::' after namespace",
+            ExpectImportName => "expect name to import",
+            NotExported => "'{0}' isn't exported",
+            ExpectArgumentsEnd => "expect ')' after arguments",
+            ExpectParameters => "expect '(' after function name",
+            ExpectParametersEnd => "expect ')' after parameters",
+            ExpectFunctionBody => "expect '{' before function body",
+            ExpectGroupEnd => "expect ')' after expression",
+            ExpectIndexEnd => "expect ']' after index",
+            ExpectListEnd => "expect ']' after list items",
+            ExpectMapColon => "expect ':' after map key",
+            ExpectMapEnd => "expect '}' after map entries",
+            ExpectInterpolationEnd => "expect '}' after expression in string",
+            ExpectBlockEnd => "expect '}' after block",
+            ExpectIfParen => "expect '(' after 'if'",
+            ExpectWhileParen => "expect '(' after 'while'",
+            ExpectConditionEnd => "expect ')' after condition",
+            ExpectForParen => "expect '(' after for",
+            ExpectLoopConditionEnd => "expect ';' after loop condition",
+            ExpectLoopClausesEnd => "expect ')' after loop clauses",
+            ExpectForInSequenceEnd => "expect ')' after for-in sequence",
+            ExpectSwitchParen => "expect '(' after 'switch'",
+            ExpectSwitchSubjectEnd => "expect ')' after switch expression",
+            ExpectSwitchBody => "expect '{' before switch body",
+            ExpectCaseColon => "expect ':' after switch expression",
+            ExpectCaseEnd => "expect ';' after switch case",
+            ExpectSwitchEnd => "expect '}' after switch body",
+            ExpectPrintParen => "expect '(' after 'print'",
+            ExpectPrintEnd => "expect ')' after value",
+            ExpectSemicolonAfterValue => "expect ';' after value",
+            ExpectSemicolonAfterExpression => "expect ';' after expression",
+            ExpectSemicolonAfterVariable => {
+                "expect ';' after variable declaration"
+            }
+            ExpectSemicolonAfterAssignment => "expect ';' after assignment",
+            ExpectSemicolonAfterReturn => "expect ';' after return value",
+            ExpectSemicolonAfterBreak => "expect ';' after 'break'",
+            ExpectSemicolonAfterContinue => "expect ';' after 'continue'",
+            ExpectSemicolonAfterImport => "expect ';' after import",
+            MaybeUnassigned => {
+                "local variable may be read before it is assigned"
+            }
+            ShadowsLocal => "shadows a local declared on line {0}",
+            ShadowsGlobalDeclared => "shadows a global declared on line {0}",
+            ShadowsGlobal => "shadows a global",
+            UndefinedVariable => "undefined variable '{0}'",
+            ReadOnlyGlobal => "'{0}' is read-only in a restricted namespace",
+            OperandNumber => "operand must be a number",
+            OperandsNumbers => "operands must be numbers",
+            OperandsNumbersOrStrings => "operands must be numbers or strings",
+            OperandTypes => "{0}, got {1}",
+            NotCallable => "can only call functions or classes",
+            NotAFunction => "'{0}' is not a function",
+            ArgumentCount => "expected {0} arguments but got {1}",
+            CalleeArgumentCount => "'{0}' expected {1} arguments but got {2}",
+            ArgumentRange => "expected {0} to {1} arguments but got {2}",
+            CalleeArgumentRange => {
+                "'{0}' expected {1} to {2} arguments but got {3}"
+            }
+            DeclaredOnLine => "{0} (declared on line {1})",
+            CalleeDeclaredOnLine => "{0} ('{1}' declared on line {2})",
+            NotIndexable => "can only index lists and maps",
+            IndexNotNumber => "index must be a number",
+            IndexNotWhole => "index must be a whole number",
+            IndexOutOfBounds => {
+                "index {0} is out of bounds for a list of length {1}"
+            }
+            MapKeyType => "map keys must be strings or numbers",
+            MapKeyNaN => "map key can't be NaN",
+            NotIterable => "can only loop over lists and maps",
+            ForInIndex => "for-in index must be a number",
+            RangeEndpoints => "range endpoints must be numbers",
+            ListTooLong => "list too long ({0} items, but the limit is {1})",
+            StringTooLong => {
+                "string too long ({0} bytes, but the limit is {1})"
+            }
+            StackOverflow => "stack overflow",
+            OutOfMemory => "out of memory",
+            OutOfFuel => "out of fuel",
+            Interrupted => "interrupted",
+            ForeignScript => "script was compiled by another vm, or released",
+            HostFunction => "can't define function '{0}' from the host",
+            HostFunctionArgument => "can't pass function '{0}' from the host",
+            NativeReturnedFunction => {
+                "native function '{0}' can't return function '{1}'"
+            }
+            ArgumentNotNumber => {
+                "argument {0} to '{1}' must be a number, got {2}"
+            }
+            ArgumentNotString => {
+                "argument {0} to '{1}' must be a string, got {2}"
+            }
+            ArgumentNotFunction => "argument must be a function",
+            ArgumentNotMap => "argument must be a map",
+            ArgumentNotSized => "argument must be a string, list or map",
+            PositionNotWhole => "position must be a whole number",
+            PositionOutOfBounds => {
+                "position {0} is out of bounds for a string of length {1}"
+            }
+            SubstringReversed => "substring start {0} is after its end {1}",
+            DecimalsRange => "decimals must be a whole number from 0 to 100",
+            ReadInput => "can't read input: {0}",
+            AssertionFailed => "assertion failed",
+            AssertionFailedWith => "assertion failed: {0}",
+            UnknownOpcode => "unknown opcode {0}",
+            InternalError => "internal error: {0}",
+        }
+    }
+}
+
+/// Templates to use instead of the English ones, set on a vm with
+/// [`VmOptions::messages`](crate::VmOptions::messages). Kinds with no
+/// template here are left in English.
+#[derive(Clone, Debug, Default)]
+pub struct Catalog {
+    templates: HashMap<Message, String>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Catalog::default()
+    }
+
+    /// Use `template` for messages of kind `message`. It can use the kind's
+    /// arguments in any order, and leave some out, but it's an error to
+    /// use an argument the kind doesn't have.
+    pub fn set(
+        &mut self,
+        message: Message,
+        template: &str,
+    ) -> Result<(), String> {
+        let arity = message.arity();
+        if let Some(n) = placeholders(template).find(|&n| n >= arity) {
+            return Err(format!(
+                "{} has {} arguments, so it can't use {{{}}}",
+                message.key(),
+                arity,
+                n
+            ));
+        }
+        self.templates.insert(message, template.to_string());
+        Ok(())
+    }
+
+    /// A message of kind `message`, with `args` in place of the
+    /// placeholders in its template.
+    pub fn format(&self, message: Message, args: &[&dyn Display]) -> String {
+        let template = match self.templates.get(&message) {
+            Some(template) => template,
+            None => message.template(),
+        };
+        fill(template, args)
+    }
+}
+
+// A compile error found where there's no catalog to hand, such as in the
+// scanner, which the parser puts through its catalog when it reports it.
+// Elsewhere, it reads as the English message.
+#[derive(Debug)]
+pub(crate) struct Diagnostic {
+    message: Message,
+    args: Vec<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(message: Message, args: &[&dyn Display]) -> Self {
+        Diagnostic {
+            message,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    pub(crate) fn format(&self, catalog: &Catalog) -> String {
+        let args: Vec<&dyn Display> =
+            self.args.iter().map(|arg| arg as &dyn Display).collect();
+        catalog.format(self.message, &args)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.format(&Catalog::default()))
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+// The argument numbers of the placeholders in `template`. A brace not
+// around a number, as in "expect '{' before function body", is just text.
+fn placeholders(template: &str) -> impl Iterator<Item = usize> + '_ {
+    template.split('{').skip(1).filter_map(|rest| {
+        let (n, _) = rest.split_once('}')?;
+        n.parse().ok()
+    })
+}
+
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('{') {
+        text.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let arg = rest[1..].split_once('}').and_then(|(n, after)| {
+            Some((args.get(n.parse::<usize>().ok()?)?, after))
+        });
+        match arg {
+            Some((arg, after)) => {
+                text.push_str(&arg.to_string());
+                rest = after;
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,100 @@
+use std::{collections::HashSet, sync::Arc};
+
+use super::{Catalog, Message};
+use crate::{testing::interpret_with, VmOptions};
+
+#[test]
+fn keys() {
+    let keys: HashSet<_> = Message::ALL.iter().map(|m| m.key()).collect();
+    assert_eq!(keys.len(), Message::ALL.len());
+    for &message in Message::ALL {
+        assert_eq!(Message::from_key(&message.key()), Some(message));
+    }
+    assert_eq!(Message::UndefinedVariable.key(), "UndefinedVariable");
+    assert_eq!(Message::from_key("NoSuchMessage"), None);
+}
+
+#[test]
+fn templates() {
+    let catalog = Catalog::new();
+    assert_eq!(Message::IndexOutOfBounds.arity(), 2);
+    assert_eq!(Message::ExpectFunctionBody.arity(), 0);
+    assert_eq!(
+        catalog.format(Message::IndexOutOfBounds, &[&3, &2]),
+        "index 3 is out of bounds for a list of length 2"
+    );
+    assert_eq!(
+        catalog.format(Message::ExpectFunctionBody, &[]),
+        "expect '{' before function body"
+    );
+    assert_eq!(
+        catalog.format(Message::InvalidEscape, &[&'q']),
+        "invalid escape sequence '\\q'"
+    );
+}
+
+#[test]
+fn set() {
+    let mut catalog = Catalog::new();
+    let msg = Message::IndexOutOfBounds;
+    catalog.set(msg, "{1} items, no {0}").unwrap();
+    assert_eq!(catalog.format(msg, &[&3, &2]), "2 items, no 3");
+    catalog.set(msg, "out of bounds").unwrap();
+    assert_eq!(catalog.format(msg, &[&3, &2]), "out of bounds");
+    assert_eq!(
+        catalog.set(msg, "{2}").unwrap_err(),
+        "IndexOutOfBounds has 2 arguments, so it can't use {2}"
+    );
+    assert_eq!(catalog.format(msg, &[&3, &2]), "out of bounds");
+}
+
+fn reworded(source: &str, options: VmOptions) -> (String, String) {
+    let mut catalog = Catalog::new();
+    for (msg, template) in [
+        (Message::ExpectExpression, "Ausdruck erwartet"),
+        (Message::UnterminatedString, "Zeichenkette nicht beendet"),
+        (Message::ShadowsLocal, "verdeckt Zeile {0}"),
+        (Message::UndefinedVariable, "'{0}' ist nicht definiert"),
+        (Message::OperandTypes, "{0} (nicht {1})"),
+        (Message::OperandNumber, "Zahl erwartet"),
+        (Message::ArgumentNotString, "Argument {0} von {1}: {2}"),
+        (Message::OutOfFuel, "Treibstoff leer"),
+    ] {
+        catalog.set(msg, template).unwrap();
+    }
+    let options = VmOptions {
+        messages: Arc::new(catalog),
+        ..options
+    };
+    interpret_with(source, options)
+}
+
+#[test]
+fn compile_errors() {
+    let (_, err) = reworded("print ;", VmOptions::default());
+    assert_eq!(err, "[line 1] Error at ';': Ausdruck erwartet\n");
+    let (_, err) = reworded("print \"abc", VmOptions::default());
+    assert_eq!(err, "[line 1] Error: Zeichenkette nicht beendet\n");
+    let options = VmOptions {
+        warn_shadowing: true,
+        ..VmOptions::default()
+    };
+    let (_, err) = reworded("{ var a;\n{ var a; } }", options);
+    assert_eq!(err, "[line 2] Warning at 'a': verdeckt Zeile 1\n");
+}
+
+#[test]
+fn runtime_errors() {
+    let (_, err) = reworded("print nope;", VmOptions::default());
+    assert_eq!(err, "[line 1] 'nope' ist nicht definiert\n");
+    let options = VmOptions {
+        verbose_errors: true,
+        ..VmOptions::default()
+    };
+    let (_, err) = reworded("-nil;", options);
+    assert_eq!(err, "[line 1] Zahl erwartet (nicht nil)\n");
+    let (_, err) = reworded("len(1, 2);", VmOptions::default());
+    assert!(err.contains("expected 1 arguments but got 2"), "{}", err);
+    let (_, err) = reworded("split(1, \"\");", VmOptions::default());
+    assert_eq!(err, "[line 1] Argument 1 von split: number\n");
+}
@@ -1,17 +1,31 @@
-use std::{cell::RefCell, io, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    hint::black_box,
+    io,
+    ops::Range,
+    rc::Rc,
+    time::Instant,
+};
 
-use anyhow::Error;
+use anyhow::{bail, Error, Result};
 
 use crate::{
-    code::{Chunk, Op, Opcode},
-    vm::{LoxFunction, LoxString, Vm},
-    Stderr, Value,
+    code::{Chunk, Label, Op, Opcode},
+    message::{Diagnostic, Message},
+    vm::{LoxFunction, LoxString, Vm, VmOptions},
+    Benchmark, Dialect, Stderr, Value,
 };
-use scanner::{Scanner, Token, TokenType};
+use scanner::{Checkpoint, Scanner, Token, TokenType};
 use Prec::Precedence;
 
+pub mod ast;
 pub(super) mod scanner;
 
+#[cfg(test)]
+mod test;
+
 #[allow(non_upper_case_globals)]
 #[allow(non_snake_case)]
 mod Prec {
@@ -26,13 +40,15 @@ mod Prec {
     pub const Term: u32 = 6;
     pub const Factor: u32 = 7;
     pub const Unary: u32 = 8;
-    pub const Call: u32 = 9;
-    pub const Primary: u32 = 10;
+    // Above Unary, so that `-x ** 2` is `-(x ** 2)`.
+    pub const Power: u32 = 9;
+    pub const Call: u32 = 10;
 
     pub(crate) fn for_op_type(ty: TokenType) -> Precedence {
         match ty {
             TokenType::Minus | TokenType::Plus => Term,
             TokenType::Slash | TokenType::Star => Factor,
+            TokenType::StarStar => Power,
             TokenType::BangEqual | TokenType::EqualEqual => Equality,
             TokenType::Greater
             | TokenType::GreaterEqual
@@ -40,7 +56,7 @@ mod Prec {
             | TokenType::LessEqual => Comparison,
             TokenType::And => And,
             TokenType::Or => Or,
-            TokenType::LeftParen => Call,
+            TokenType::LeftParen | TokenType::LeftBracket => Call,
             _ => None,
         }
     }
@@ -48,27 +64,95 @@ mod Prec {
     pub type Precedence = u32;
 }
 
+// Whether `token` is a `++` or `--` after an operand, rather than one
+// starting the next statement on a new line. The syntax tree's parser goes
+// by this too.
+fn is_postfix_increment(token: Token, options: &VmOptions) -> bool {
+    matches!(token.ty(), TokenType::PlusPlus | TokenType::MinusMinus)
+        && !(options.optional_semicolons && token.newline())
+}
+
+// Whether `token`, after an operand, is a `--` that's a minus and a
+// negation, as in `a--b`, rather than a decrement: `next` starts another
+// operand on the same line. Book Lox has no `--`, so `a--b` stays `a - -b`.
+// The syntax tree's parser goes by this too.
+fn splits_decrement(token: Token, next: Token, options: &VmOptions) -> bool {
+    token.ty() == TokenType::MinusMinus
+        && is_postfix_increment(token, options)
+        && !(options.optional_semicolons && next.newline())
+        && matches!(
+            next.ty(),
+            TokenType::LeftParen
+                | TokenType::LeftBracket
+                | TokenType::Minus
+                | TokenType::MinusMinus
+                | TokenType::Bang
+                | TokenType::PlusPlus
+                | TokenType::Number
+                | TokenType::Identifier
+                | TokenType::String
+                | TokenType::InterpolationStart
+                | TokenType::Nil
+                | TokenType::True
+                | TokenType::False
+        )
+}
+
+// Whether a statement may end without a `;` before `token`: it's on a new
+// line, or closes the block or script. Never where `required`, as in a
+// `for` clause. The syntax tree's parser goes by this too.
+fn ends_statement(token: Token, options: &VmOptions, required: bool) -> bool {
+    options.optional_semicolons
+        && !required
+        && token.ty() != TokenType::Semicolon
+        && (token.newline()
+            || matches!(token.ty(), TokenType::RightBrace | TokenType::Eof))
+}
+
 struct Compiler {
     locals: Locals,
     function: LoxFunction,
+    // The defer statements in the scopes being compiled, oldest first.
+    defers: Vec<Deferred>,
+}
+
+// A defer statement, kept so it can be compiled again wherever its scope is
+// left.
+#[derive(Clone)]
+struct Deferred {
+    depth: i32,
+    // How many locals were declared before it.
+    locals: usize,
+    // Where the statement starts.
+    start: Checkpoint,
+    current: Token,
+    previous: Token,
 }
 
 #[derive(Debug)]
 struct Local {
     sym: u32,
     depth: i32,
+    // Where it was declared.
+    line: u32,
+    // False while there's a path here on which the local still has the
+    // nil it was declared with.
+    assigned: bool,
 }
 
 struct Locals {
     depth: i32,
     locals: Vec<Local>,
+    // Slots that can't be resolved, because the deferred statement being
+    // compiled comes before them.
+    hidden: Range<usize>,
 }
 
 #[derive(Copy, Clone)]
 struct LoopInfo {
     depth: i32,
-    loop_start: usize,
-    exit_jump: usize,
+    loop_start: Label,
+    break_jump: Label,
 }
 
 pub(crate) struct Parser {
@@ -77,30 +161,62 @@ pub(crate) struct Parser {
     current: Token,
     previous: Token,
     had_error: bool,
+    had_warning: bool,
     panic_mode: bool,
     compilers: Vec<Compiler>,
+    symbols: Vec<u32>,
+    // The symbol of each identifier's global in the script's namespace, by
+    // identifier id like `symbols`.
+    namespaced: Vec<u32>,
+    lookahead: VecDeque<Scanned>,
+    options: VmOptions,
+    // The globals declared so far, and the lines they were declared on.
+    globals: HashMap<u32, u32>,
+    // The global each imported name refers to, and the globals declared
+    // with `export`, which the vm hears about once the script compiles.
+    imports: HashMap<u32, u32>,
+    exports: Vec<u32>,
+    // In strict mode, assignments to globals not declared so far, which
+    // are errors unless the rest of the script declares them.
+    undeclared: Vec<(Token, u32)>,
+    // How many expressions, statements and functions the one being parsed
+    // is inside of.
+    depth: usize,
+    // Whether a script that is just an expression prints its value.
+    repl: bool,
+    // Whether a deferred statement is being compiled, and whether that's
+    // again, at a scope exit, rather than where it was written.
+    deferring: bool,
+    replaying: bool,
+    // Whether the next global declared is exported.
+    exporting: bool,
+    // Whether a ';' is needed even where optional semicolons would allow
+    // a newline, as between the clauses of a `for`.
+    semicolon_required: bool,
+}
+
+// Scan errors keep the line they were found on, since the scanner may have
+// moved past it by the time a buffered error is reported.
+type Scanned = std::result::Result<Token, (Error, u32)>;
+
+pub fn bench_compile(text: String) -> Result<Benchmark> {
+    let tokens = scanner::bench_scanner(text.clone())?.tokens;
+    let bytes = text.len();
+    let stderr = Rc::new(RefCell::new(io::stderr()));
+    let mut vm = Vm::new(Rc::new(RefCell::new(io::sink())), stderr.clone());
+    let mut parser = Parser::new(text, stderr);
+    let start = Instant::now();
+    let func = black_box(parser.parse(&mut vm, "<script>"));
+    let elapsed = start.elapsed();
+    if func.is_none() {
+        bail!("compile error");
+    }
+    Ok(Benchmark::new("compile", tokens, bytes, elapsed))
 }
 
 pub fn print_tokens(source: String) {
     let mut parser = Parser::new(source, Rc::new(RefCell::new(io::stderr())));
-    let mut line: u32 = 0;
-    loop {
-        parser.advance();
-        if parser.had_error {
-            break;
-        }
-        let token = parser.current;
-        if token.line() != line {
-            line = token.line();
-            print!("{:4} ", line);
-        } else {
-            print!("   | ");
-        }
-        println!("{:12} {}", token.ty(), parser.scanner.token_text(token));
-        if token.ty() == TokenType::Eof {
-            break;
-        }
-    }
+    parser.show_tokens();
 }
 
 impl Compiler {
@@ -108,6 +224,7 @@ impl Compiler {
         Compiler {
             locals: Locals::new(),
             function: LoxFunction::new(name),
+            defers: Vec::new(),
         }
     }
 }
@@ -119,11 +236,14 @@ impl Locals {
             locals: vec![Local {
                 depth: 0,
                 sym: u32::MAX,
+                line: 0,
+                assigned: true,
             }],
+            hidden: 0..0,
         }
     }
 
-    fn add(&mut self, sym: u32) -> bool {
+    fn add(&mut self, sym: u32, line: u32) -> bool {
         for local in self.locals.iter().rev() {
             if local.depth != -1 && local.depth < self.depth {
                 break;
@@ -132,10 +252,34 @@ impl Locals {
                 return false;
             }
         }
-        self.locals.push(Local { sym, depth: -1 });
+        self.locals.push(Local {
+            sym,
+            depth: -1,
+            line,
+            assigned: true,
+        });
         true
     }
 
+    fn assign(&mut self, slot: usize) {
+        if let Some(local) = self.locals.get_mut(slot) {
+            local.assigned = true;
+        }
+    }
+
+    // After a return, break or continue, nothing can be read on this path.
+    fn assign_all(&mut self) {
+        for local in &mut self.locals {
+            local.assigned = true;
+        }
+    }
+
+    // Which locals have been assigned on every path to here, to put back
+    // with `restore` after code that might not run.
+    fn assigned(&self) -> Vec<bool> {
+        self.locals.iter().map(|local| local.assigned).collect()
+    }
+
     fn begin_scope(&mut self) {
         self.depth += 1;
     }
@@ -153,9 +297,7 @@ impl Locals {
     fn end_scope(&mut self) -> usize {
         self.depth -= 1;
         let mut count = 0usize;
-        while !self.locals.is_empty()
-            && self.locals[self.locals.len() - 1].depth > self.depth
-        {
+        while self.locals.last().is_some_and(|l| l.depth > self.depth) {
             count += 1;
             self.locals.pop();
         }
@@ -166,26 +308,61 @@ impl Locals {
         self.locals.push(Local {
             sym: u32::MAX,
             depth: self.depth,
+            line: 0,
+            assigned: true,
         });
         self.locals.len() - 1
     }
 
-    fn mark_initialized(&mut self) {
-        let idx = self.locals.len() - 1;
-        self.locals[idx].depth = self.depth;
+    fn is_assigned(&self, slot: usize) -> bool {
+        self.locals.get(slot).is_none_or(|local| local.assigned)
+    }
+
+    fn line(&self, slot: usize) -> u32 {
+        self.locals.get(slot).map_or(0, |local| local.line)
+    }
+
+    // Marks the most recently added local as ready to use. Returns false if
+    // there is no such local waiting.
+    fn mark_initialized(&mut self) -> bool {
+        match self.locals.last_mut() {
+            Some(local) if local.depth == -1 => {
+                local.depth = self.depth;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Where two paths join: a local is assigned if it was on both.
+    fn merge(&mut self, other: &[bool]) {
+        for (local, &assigned) in self.locals.iter_mut().zip(other) {
+            local.assigned &= assigned;
+        }
     }
 
     fn resolve(&self, sym: u32) -> Option<(usize, bool)> {
-        let idx = self.locals.iter().rev().position(|local| local.sym == sym);
-        idx.map(|i| {
-            let slot = self.locals.len() - i - 1;
-            (slot, self.locals[slot].depth != -1)
-        })
+        let slot = (0..self.locals.len()).rev().find(|slot| {
+            self.locals[*slot].sym == sym && !self.hidden.contains(slot)
+        });
+        slot.map(|slot| (slot, self.locals[slot].depth != -1))
+    }
+
+    fn restore(&mut self, saved: &[bool]) {
+        for (local, &assigned) in self.locals.iter_mut().zip(saved) {
+            local.assigned = assigned;
+        }
     }
 
     fn top_level(&self) -> bool {
         self.depth == 0
     }
+
+    fn unassign_last(&mut self) {
+        if let Some(local) = self.locals.last_mut() {
+            local.assigned = false;
+        }
+    }
 }
 
 impl Default for Locals {
@@ -202,24 +379,50 @@ impl Parser {
             current: Token::default(),
             previous: Token::default(),
             had_error: false,
+            had_warning: false,
             panic_mode: false,
             compilers: Vec::new(),
+            symbols: Vec::new(),
+            namespaced: Vec::new(),
+            lookahead: VecDeque::new(),
+            options: VmOptions::default(),
+            globals: HashMap::new(),
+            imports: HashMap::new(),
+            exports: Vec::new(),
+            undeclared: Vec::new(),
+            depth: 0,
+            repl: false,
+            deferring: false,
+            replaying: false,
+            exporting: false,
+            semicolon_required: false,
+        }
+    }
+
+    // A parser for REPL entries.
+    pub(crate) fn repl(source: String, stderr: Stderr) -> Parser {
+        Parser {
+            repl: true,
+            ..Parser::new(source, stderr)
         }
     }
 
     fn advance(&mut self) {
         self.previous = self.current;
         loop {
-            match self.scanner.scan_token() {
+            match self.next_token() {
                 Ok(token) => {
                     self.current = token;
                     let line = self.current.line();
-                    if line != self.previous.line() {
+                    // There's no chunk when only listing tokens.
+                    if line != self.previous.line()
+                        && !self.compilers.is_empty()
+                    {
                         self.chunk().new_line(line);
                     }
                     break;
                 }
-                Err(e) => self.scan_error(e),
+                Err((e, line)) => self.scan_error(e, line),
             }
         }
     }
@@ -227,7 +430,9 @@ impl Parser {
     fn and(&mut self, vm: &mut Vm) {
         let end_jump = self.emit_jump(Op::JumpIfFalse);
         self.emit_op(Op::Pop);
+        let assigned = self.locals().assigned();
         self.parse_precedence(Prec::And, vm);
+        self.locals().restore(&assigned);
         self.patch_jump(end_jump);
     }
 
@@ -237,7 +442,7 @@ impl Parser {
             loop {
                 self.expression(vm);
                 if arg_count == 255 {
-                    self.error("can't have more than 255 arguments");
+                    self.error(Message::TooManyArguments);
                 }
                 arg_count += 1;
                 if !self.matches(TokenType::Comma) {
@@ -245,13 +450,12 @@ impl Parser {
                 }
             }
         }
-        self.consume(TokenType::RightParen, "expect ')' after arguments");
+        self.consume(TokenType::RightParen, Message::ExpectArgumentsEnd);
         arg_count & 0xff
     }
 
     fn arity(&mut self) -> &mut usize {
-        let idx = self.compilers.len() - 1;
-        &mut self.compilers[idx].function.arity
+        &mut self.compiler().function.arity
     }
 
     fn begin_scope(&mut self) {
@@ -260,29 +464,36 @@ impl Parser {
 
     fn binary(&mut self, vm: &mut Vm) {
         let operator_type = self.previous.ty();
-        self.parse_precedence(Prec::for_op_type(operator_type) + 1, vm);
+        let line = self.previous.line();
+        // `**` is right-associative, so the right operand can be another.
+        let precedence = match operator_type {
+            TokenType::StarStar => Prec::Power,
+            ty => Prec::for_op_type(ty) + 1,
+        };
+        self.parse_precedence(precedence, vm);
 
         match operator_type {
-            TokenType::Plus => self.emit_op(Op::Add),
-            TokenType::Minus => self.emit_op(Op::Subtract),
-            TokenType::Star => self.emit_op(Op::Multiply),
-            TokenType::Slash => self.emit_op(Op::Divide),
-            TokenType::EqualEqual => self.emit_op(Op::Equal),
-            TokenType::Less => self.emit_op(Op::Less),
-            TokenType::Greater => self.emit_op(Op::Greater),
+            TokenType::Plus => self.emit_op_at(Op::Add, line),
+            TokenType::Minus => self.emit_op_at(Op::Subtract, line),
+            TokenType::Star => self.emit_op_at(Op::Multiply, line),
+            TokenType::StarStar => self.emit_op_at(Op::Power, line),
+            TokenType::Slash => self.emit_op_at(Op::Divide, line),
+            TokenType::EqualEqual => self.emit_op_at(Op::Equal, line),
+            TokenType::Less => self.emit_op_at(Op::Less, line),
+            TokenType::Greater => self.emit_op_at(Op::Greater, line),
             TokenType::BangEqual => {
-                self.emit_op(Op::Equal);
+                self.emit_op_at(Op::Equal, line);
                 self.emit_op(Op::Not);
             }
             TokenType::GreaterEqual => {
-                self.emit_op(Op::Less);
+                self.emit_op_at(Op::Less, line);
                 self.emit_op(Op::Not);
             }
             TokenType::LessEqual => {
-                self.emit_op(Op::Greater);
+                self.emit_op_at(Op::Greater, line);
                 self.emit_op(Op::Not);
             }
-            _ => unreachable!(),
+            _ => self.internal_error("unexpected binary operator"),
         }
     }
 
@@ -291,23 +502,33 @@ impl Parser {
         {
             self.declaration(vm, loop_);
         }
-        self.consume(TokenType::RightBrace, "expect '}' after block");
+        self.consume(TokenType::RightBrace, Message::ExpectBlockEnd);
     }
 
-    fn break_statement(&mut self, loop_: Option<LoopInfo>) {
-        self.consume(TokenType::Semicolon, "expect ';' after 'break'");
-        if loop_.is_none() {
-            self.error("'break' outside of loop");
+    // Breaks loop back to a jump just before the loop, which is patched to
+    // go past it (and its else clause); the loop itself starts by jumping
+    // over that.
+    fn break_target(&mut self) -> Label {
+        let skip = self.emit_jump(Op::Jump);
+        let break_jump = self.emit_jump(Op::Jump);
+        self.patch_jump(skip);
+        break_jump
+    }
+
+    fn break_statement(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
+        self.consume_semicolon(Message::ExpectSemicolonAfterBreak);
+        let Some(loop_) = loop_ else {
+            self.error(Message::BreakOutsideLoop);
             return;
-        }
+        };
 
-        let loop_ = loop_.unwrap();
+        self.emit_defers(vm, loop_.depth);
         let n = self.locals().count_to_depth(loop_.depth);
         if n > 0 {
             self.emit_op_arg(Op::PopN, n as u32);
         }
-        self.emit_op(Op::False);
-        self.emit_loop(loop_.exit_jump);
+        self.emit_loop(loop_.break_jump);
+        self.locals().assign_all();
     }
 
     fn call(&mut self, vm: &mut Vm) {
@@ -319,12 +540,52 @@ impl Parser {
         self.current.ty() == ty
     }
 
+    // In strict mode, only declared variables can be assigned to. A global
+    // may be declared further on, so that is checked at the end.
+    fn check_assignment(
+        &mut self,
+        vm: &Vm,
+        name: Token,
+        op_set: Opcode,
+        arg: u32,
+    ) {
+        if self.options.strict
+            && !self.replaying
+            && op_set == Op::SetGlobal
+            && !self.globals.contains_key(&arg)
+            && !vm.has_global(arg)
+        {
+            self.undeclared.push((name, arg));
+        }
+    }
+
+    // Reports the assignments check_assignment put off whose globals the
+    // script never declared.
+    fn check_undeclared(&mut self, vm: &Vm) {
+        for (name, arg) in std::mem::take(&mut self.undeclared) {
+            if !self.globals.contains_key(&arg) && !vm.has_global(arg) {
+                self.panic_mode = false;
+                self.error_at(name, Message::AssignUndeclared);
+            }
+        }
+    }
+
     fn chunk(&mut self) -> &mut Chunk {
+        &mut self.compiler().function.chunk
+    }
+
+    // The function being compiled. There always is one while parsing; if
+    // not, that's reported, and a stand-in keeps the parser going.
+    fn compiler(&mut self) -> &mut Compiler {
+        if self.compilers.is_empty() {
+            self.internal_error("no function being compiled");
+            self.compilers.push(Compiler::new("<error>"));
+        }
         let idx = self.compilers.len() - 1;
-        &mut self.compilers[idx].function.chunk
+        &mut self.compilers[idx]
     }
 
-    fn consume(&mut self, ty: TokenType, msg: &str) {
+    fn consume(&mut self, ty: TokenType, msg: Message) {
         if self.current.ty() == ty {
             self.advance();
         } else {
@@ -332,30 +593,26 @@ impl Parser {
         }
     }
 
-    fn consume_or<F>(&mut self, ty: TokenType, msg: F)
-    where
-        F: Fn() -> String,
-    {
-        if self.current.ty() == ty {
-            self.advance();
-        } else {
-            self.error_at(self.current, &msg())
+    fn consume_semicolon(&mut self, msg: Message) {
+        if !self.implicit_semicolon() {
+            self.consume(TokenType::Semicolon, msg);
         }
     }
 
-    fn continue_statement(&mut self, loop_: Option<LoopInfo>) {
-        self.consume(TokenType::Semicolon, "expect ';' after 'continue'");
-        if loop_.is_none() {
-            self.error("'continue' outside of loop");
+    fn continue_statement(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
+        self.consume_semicolon(Message::ExpectSemicolonAfterContinue);
+        let Some(loop_) = loop_ else {
+            self.error(Message::ContinueOutsideLoop);
             return;
-        }
+        };
 
-        let loop_ = loop_.unwrap();
+        self.emit_defers(vm, loop_.depth);
         let n = self.locals().count_to_depth(loop_.depth);
         if n > 0 {
             self.emit_op_arg(Op::PopN, n as u32);
         }
         self.emit_loop(loop_.loop_start);
+        self.locals().assign_all();
     }
 
     fn declaration(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
@@ -363,6 +620,12 @@ impl Parser {
             self.fun_declaration(vm);
         } else if self.matches(TokenType::Var) {
             self.var_declaration(vm);
+        } else if self.matches(TokenType::Defer) {
+            self.defer_statement(vm);
+        } else if self.matches(TokenType::Export) {
+            self.export_declaration(vm);
+        } else if self.matches(TokenType::Import) {
+            self.import_declaration(vm);
         } else {
             self.statement(vm, loop_);
         }
@@ -372,44 +635,152 @@ impl Parser {
         }
     }
 
-    fn declare_variable(&mut self, vm: &mut Vm, syntax: &str) -> u32 {
-        self.consume_or(TokenType::Identifier, || {
-            format!("expect {} name", syntax)
-        });
-        let sym = vm.get_symbol(self.token_text());
-        if !self.locals().top_level() && !self.locals().add(sym) {
-            self.error_from(|| {
-                format!("already a {} with this name in this scope", syntax)
-            });
+    // The statement is only checked here; it's compiled to run wherever its
+    // scope is left.
+    fn defer_statement(&mut self, vm: &mut Vm) {
+        if self.deferring {
+            self.error(Message::NestedDefer);
+            return;
+        }
+        let deferred = Deferred {
+            depth: self.locals().depth,
+            locals: self.locals().locals.len(),
+            start: self.scanner.checkpoint(),
+            current: self.current,
+            previous: self.previous,
+        };
+
+        let chunk = std::mem::take(self.chunk());
+        // Which locals are assigned depends on where it ends up running.
+        let assigned = self.locals().assigned();
+        self.locals().assign_all();
+        self.deferring = true;
+        self.statement(vm, None);
+        self.deferring = false;
+        self.locals().restore(&assigned);
+        *self.chunk() = chunk;
+        let line = self.current.line();
+        self.chunk().new_line(line);
+
+        self.compiler().defers.push(deferred);
+    }
+
+    // `expect` is the error for a missing name, and `duplicate` for a
+    // local already declared in the same scope.
+    fn declare_variable(
+        &mut self,
+        vm: &mut Vm,
+        expect: Message,
+        duplicate: Message,
+    ) -> u32 {
+        self.consume(TokenType::Identifier, expect);
+        let mut sym = self.identifier(vm);
+        let line = self.previous.line();
+        if self.locals().top_level() {
+            if self.imports.contains_key(&sym) {
+                self.error(Message::AlreadyImported);
+            }
+            sym = self.global_symbol(vm, sym);
+            let redeclared =
+                self.globals.contains_key(&sym) || vm.has_global(sym);
+            self.globals.entry(sym).or_insert(line);
+            if redeclared && self.options.strict {
+                self.error(Message::AlreadyGlobal);
+            }
+            if std::mem::take(&mut self.exporting) {
+                self.exports.push(sym);
+            }
+        } else {
+            let shadowed = self.locals().resolve(sym).map(|(slot, _)| slot);
+            if !self.locals().add(sym, line) {
+                self.error(duplicate);
+            } else if self.options.warn_shadowing {
+                self.warn_shadowing(vm, sym, shadowed);
+            }
         }
         sym
     }
 
     fn emit_constant(&mut self, value: Value) {
+        // Common values have their own opcodes, and don't take up a slot in
+        // the constant table.
+        let op = match &value {
+            Value::Number(v) if v.to_bits() == 0 => Some(Op::Zero),
+            Value::Number(v) if *v == 1.0 => Some(Op::One),
+            Value::String(s) if s.borrow().is_empty() => Some(Op::EmptyString),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.emit_op(op);
+            return;
+        }
+        // So do small whole numbers, like loop bounds and steps, which
+        // fit in a single operand byte.
+        if let Value::Number(v) = value {
+            if v.fract() == 0.0 && (2.0..=255.0).contains(&v) {
+                self.chunk().write_op_arg(Op::Int, v as u32);
+                return;
+            }
+        }
         let chunk = self.chunk();
         let arg = match chunk.add_constant(value) {
             Ok(idx) => idx,
             Err(e) => {
-                self.error(&e.to_string());
+                let text = self.error_text(&e);
+                self.report_at(self.previous, &text);
                 return;
             }
         };
         chunk.write_op_arg(Op::Constant, arg);
     }
 
-    fn emit_jump(&mut self, op: Opcode) -> usize {
+    // Compiles the deferred statements of scopes deeper than `depth` again,
+    // newest first, for code that leaves those scopes.
+    fn emit_defers(&mut self, vm: &mut Vm, depth: i32) {
+        let defers: Vec<_> = self
+            .compiler()
+            .defers
+            .iter()
+            .rev()
+            .filter(|deferred| deferred.depth > depth)
+            .cloned()
+            .collect();
+        // After an error, the code is thrown away anyway.
+        if defers.is_empty() || self.had_error {
+            return;
+        }
+
+        let resume = self.scanner.checkpoint();
+        let (current, previous) = (self.current, self.previous);
+        let lookahead = std::mem::take(&mut self.lookahead);
+        let end = self.locals().locals.len();
+        let deferring = std::mem::replace(&mut self.deferring, true);
+        let replaying = std::mem::replace(&mut self.replaying, true);
+        for deferred in defers {
+            self.scanner.restore(deferred.start);
+            self.current = deferred.current;
+            self.previous = deferred.previous;
+            self.lookahead.clear();
+            self.chunk().new_line(deferred.current.line());
+            self.locals().hidden = deferred.locals..end;
+            self.statement(vm, None);
+        }
+        self.locals().hidden = 0..0;
+        self.deferring = deferring;
+        self.replaying = replaying;
+        self.scanner.restore(resume);
+        self.current = current;
+        self.previous = previous;
+        self.lookahead = lookahead;
+        self.chunk().new_line(current.line());
+    }
+
+    fn emit_jump(&mut self, op: Opcode) -> Label {
         self.chunk().write_jump(op)
     }
 
-    fn emit_loop(&mut self, dest: usize) {
-        let mut delta = self.chunk().len() - dest + 1;
-        if delta > 0xff {
-            delta += 1;
-        }
-        if delta > 0xffff {
-            self.error("loop body too large");
-        }
-        self.emit_op_arg(Op::Loop, delta as u32);
+    fn emit_loop(&mut self, dest: Label) {
+        self.chunk().write_loop(dest);
     }
 
     fn emit_op(&mut self, op: Opcode) {
@@ -420,7 +791,24 @@ impl Parser {
         self.chunk().write_op_arg(op, arg);
     }
 
-    fn end_scope(&mut self) {
+    // Ops are on the line of the current token, which is usually where
+    // their runtime errors belong. When an op stands for a token parsed
+    // earlier, such as a binary operator or the `[` of an index, these put
+    // it on that token's line instead.
+    fn emit_op_arg_at(&mut self, op: Opcode, arg: u32, line: u32) {
+        self.chunk().write_op_arg_at(op, arg, line);
+    }
+
+    fn emit_op_at(&mut self, op: Opcode, line: u32) {
+        self.chunk().write_op_at(op, line);
+    }
+
+    fn end_scope(&mut self, vm: &mut Vm) {
+        let depth = self.locals().depth;
+        self.emit_defers(vm, depth - 1);
+        self.compiler()
+            .defers
+            .retain(|deferred| deferred.depth < depth);
         let n = self.locals().end_scope() as u32;
         if n == 1 {
             self.emit_op(Op::Pop);
@@ -430,23 +818,44 @@ impl Parser {
         }
     }
 
-    fn error(&mut self, msg: &str) {
+    fn error(&mut self, msg: Message) {
         self.error_at(self.previous, msg);
     }
 
-    fn error_from<F>(&mut self, msg: F)
-    where
-        F: Fn() -> String,
-    {
-        self.error_at(self.previous, &msg());
+    fn error_with(&mut self, msg: Message, args: &[&dyn Display]) {
+        let text = self.message(msg, args);
+        self.report_at(self.previous, &text);
     }
 
-    fn error_at(&mut self, token: Token, msg: &str) {
-        let msg = match token.ty() {
-            TokenType::Eof => format!(" at end: {}", msg),
-            _ => format!(" at '{}': {}", self.scanner.token_text(token), msg),
-        };
-        self.report_error(token.line(), msg);
+    fn error_at(&mut self, token: Token, msg: Message) {
+        let text = self.message(msg, &[]);
+        self.report_at(token, &text);
+    }
+
+    // The text of a scanner or chunk error, through the catalog if it's
+    // one of the crate's own.
+    fn error_text(&self, err: &Error) -> String {
+        match err.downcast_ref::<Diagnostic>() {
+            Some(diagnostic) => diagnostic.format(&self.options.messages),
+            None => err.to_string(),
+        }
+    }
+
+    // `export var ...` or `export fun ...`, declaring a global that scripts
+    // in other namespaces can import.
+    fn export_declaration(&mut self, vm: &mut Vm) {
+        if !self.locals().top_level() {
+            self.error(Message::ExportNotTopLevel);
+        }
+        self.exporting = true;
+        if self.matches(TokenType::Fun) {
+            self.fun_declaration(vm);
+        } else if self.matches(TokenType::Var) {
+            self.var_declaration(vm);
+        } else {
+            self.error_at(self.current, Message::ExpectExported);
+        }
+        self.exporting = false;
     }
 
     fn expression(&mut self, vm: &mut Vm) {
@@ -454,46 +863,70 @@ impl Parser {
     }
 
     fn expression_statement(&mut self, vm: &mut Vm) {
+        if self.check(TokenType::Identifier)
+            && self.peek_next().ty() == TokenType::Comma
+        {
+            self.multiple_assignment(vm);
+            return;
+        }
+        // In the REPL, an entry that is only an expression prints its
+        // value, and doesn't need a ';'.
+        let bare = self.repl
+            && !self.deferring
+            && self.compilers.len() == 1
+            && self.chunk().len() == 0;
         self.expression(vm);
-        self.consume(TokenType::Semicolon, "expect ';' after expression");
-        self.emit_op(Op::Pop);
+        if !(bare && self.check(TokenType::Eof)) {
+            self.consume_semicolon(Message::ExpectSemicolonAfterExpression);
+        }
+        if bare && self.check(TokenType::Eof) {
+            self.emit_op(Op::Print);
+        } else {
+            self.emit_op(Op::Pop);
+        }
     }
 
-    fn for_statement(&mut self, vm: &mut Vm) {
+    fn for_statement(&mut self, vm: &mut Vm, outer: Option<LoopInfo>) {
         self.begin_scope();
 
-        self.consume(TokenType::LeftParen, "expect '(' after for");
+        self.consume(TokenType::LeftParen, Message::ExpectForParen);
         if self.matches(TokenType::Semicolon) {
             // no initializer
         } else if self.matches(TokenType::Var) {
+            if self.in_follows() {
+                self.for_in(vm, outer);
+                self.end_scope(vm);
+                return;
+            }
+            self.semicolon_required = true;
             self.var_declaration(vm);
+            self.semicolon_required = false;
         } else {
+            self.semicolon_required = true;
             self.expression_statement(vm);
+            self.semicolon_required = false;
         }
 
-        let mut loop_start = self.chunk().len();
+        let break_jump = self.break_target();
+        let mut loop_start = self.chunk().label();
         if self.matches(TokenType::Semicolon) {
             // no condition
             self.emit_op(Op::True);
         } else {
             self.expression(vm);
-            self.consume(
-                TokenType::Semicolon,
-                "expect ';' after loop condition",
-            );
+            self.consume(TokenType::Semicolon, Message::ExpectLoopConditionEnd);
         }
         let exit_jump = self.emit_jump(Op::JumpIfFalse);
         self.emit_op(Op::Pop);
+        // Nothing after the condition is sure to run.
+        let assigned = self.locals().assigned();
 
         if !self.matches(TokenType::RightParen) {
             let body_jump = self.emit_jump(Op::Jump);
-            let increment_start = self.chunk().len();
+            let increment_start = self.chunk().label();
             self.expression(vm);
             self.emit_op(Op::Pop);
-            self.consume(
-                TokenType::RightParen,
-                "expect ')' after loop clauses",
-            );
+            self.consume(TokenType::RightParen, Message::ExpectLoopClausesEnd);
             self.emit_loop(loop_start);
             loop_start = increment_start;
             self.patch_jump(body_jump);
@@ -502,73 +935,216 @@ impl Parser {
         let loop_ = Some(LoopInfo {
             depth: self.locals().depth,
             loop_start,
-            exit_jump,
+            break_jump,
         });
         self.statement(vm, loop_);
         self.emit_loop(loop_start);
 
         self.patch_jump(exit_jump);
         self.emit_op(Op::Pop);
+        self.loop_else(vm, outer);
+        self.patch_jump(break_jump);
+        self.locals().restore(&assigned);
 
-        self.end_scope();
+        self.end_scope(vm);
     }
 
-    fn fun_declaration(&mut self, vm: &mut Vm) {
-        let sym = self.declare_variable(vm, "function");
+    // `for (var name in sequence)` loops over the items of a list, or the
+    // keys of a map, using two hidden locals: the sequence and the index of
+    // the next item. Over a range, `start..end` or `start..=end`, they are
+    // the next number and the end.
+    fn for_in(&mut self, vm: &mut Vm, outer: Option<LoopInfo>) {
+        self.advance();
+        let sym = self.identifier(vm);
+        let line = self.previous.line();
+        self.advance();
 
-        if !self.locals().top_level() {
-            self.locals().mark_initialized();
+        let start_line = self.current.line();
+        self.expression(vm);
+        let inclusive = self.matches(TokenType::DotDotEqual);
+        let range = inclusive || self.matches(TokenType::DotDot);
+        if range {
+            let line = self.previous.line();
+            self.expression(vm);
+            self.emit_op_at(Op::Range, line);
+        } else {
+            self.emit_op_arg(Op::Int, 0);
         }
-
-        match self.parse(vm, &vm.get_sym_name(sym)) {
-            None => self.emit_op(Op::Nil),
-            Some(func) => self.emit_constant(Value::Function(func.into())),
+        let slot = self.locals().inject();
+        self.locals().inject();
+        self.consume(TokenType::RightParen, Message::ExpectForInSequenceEnd);
+
+        let break_jump = self.break_target();
+        let loop_start = self.chunk().label();
+        match range {
+            true => self.range_next(slot as u32, inclusive),
+            false => self.emit_op_arg_at(Op::IterNext, slot as u32, start_line),
         }
+        let exit_jump = self.emit_jump(Op::JumpIfFalse);
+        self.emit_op(Op::Pop);
+        let assigned = self.locals().assigned();
 
-        if self.locals().top_level() {
-            self.emit_op_arg(Op::DefineGlobal, sym);
-        }
+        let loop_ = Some(LoopInfo {
+            depth: self.locals().depth,
+            loop_start,
+            break_jump,
+        });
+        self.begin_scope();
+        self.locals().add(sym, line);
+        self.mark_initialized();
+        self.statement(vm, loop_);
+        self.end_scope(vm);
+        self.emit_loop(loop_start);
+
+        // The item and flag left by the last step.
+        self.patch_jump(exit_jump);
+        self.emit_op_arg(Op::PopN, 2);
+        self.loop_else(vm, outer);
+        self.patch_jump(break_jump);
+        self.locals().restore(&assigned);
+    }
+
+    fn fun_declaration(&mut self, vm: &mut Vm) {
+        self.nested(Message::FunctionTooDeep, |p| {
+            let doc = p.scanner.doc(p.previous);
+            let sym = p.declare_variable(
+                vm,
+                Message::ExpectFunctionName,
+                Message::AlreadyFunction,
+            );
+
+            // As the script wrote it, without any namespace.
+            let own = p.token_text().to_string();
+            if !p.locals().top_level() {
+                p.mark_initialized();
+            }
+
+            let name = match p.compilers.len() {
+                1 => own,
+                _ => format!("{}.{}", p.compiler().function.name(), own),
+            };
+            match p.parse(vm, &name) {
+                None => p.emit_op(Op::Nil),
+                Some(mut func) => {
+                    func.doc = doc;
+                    let func = vm.alloc(func);
+                    p.emit_constant(Value::Function(func))
+                }
+            }
+
+            if p.locals().top_level() {
+                p.emit_op_arg(Op::DefineGlobal, sym);
+            }
+        });
     }
 
     fn function(&mut self, vm: &mut Vm) {
         self.begin_scope();
 
-        self.consume(TokenType::LeftParen, "expect '(' after function name");
+        self.consume(TokenType::LeftParen, Message::ExpectParameters);
         if !self.check(TokenType::RightParen) {
             loop {
                 *self.arity() += 1;
                 if *self.arity() > 255 {
-                    self.error_at(
-                        self.current,
-                        "can't have more than 255 parameters",
-                    );
+                    self.error_at(self.current, Message::TooManyParameters);
                 }
-                self.declare_variable(vm, "parameter");
-                self.locals().mark_initialized();
+                self.declare_variable(
+                    vm,
+                    Message::ExpectParameterName,
+                    Message::AlreadyParameter,
+                );
+                self.mark_initialized();
                 if !self.matches(TokenType::Comma) {
                     break;
                 }
             }
         }
-        self.consume(TokenType::RightParen, "expect ')' after parameters");
+        self.consume(TokenType::RightParen, Message::ExpectParametersEnd);
 
-        self.consume(TokenType::LeftBrace, "expect '{' before function body");
+        self.consume(TokenType::LeftBrace, Message::ExpectFunctionBody);
         self.block(vm, None);
     }
 
+    fn get_variable(&mut self, op_get: Opcode, arg: u32) {
+        if op_get == Op::GetLocal
+            && !self.replaying
+            && !self.locals().is_assigned(arg as usize)
+        {
+            self.warning(Message::MaybeUnassigned, &[]);
+            // One warning per variable and path is enough.
+            self.locals().assign(arg as usize);
+        }
+        self.emit_op_arg(op_get, arg);
+    }
+
+    // The global a script refers to by `sym`: the one it imported by that
+    // name, or else its own, in its namespace if it has one.
+    fn global_symbol(&mut self, vm: &mut Vm, sym: u32) -> u32 {
+        if let Some(&imported) = self.imports.get(&sym) {
+            return imported;
+        }
+        let namespace = match &self.options.namespace {
+            Some(namespace) => namespace,
+            None => return sym,
+        };
+        // Cached by the identifier just read, if `sym` is its symbol.
+        let id = match self.previous.ident() {
+            Some(id) if self.symbols.get(id as usize) == Some(&sym) => {
+                Some(id as usize)
+            }
+            _ => None,
+        };
+        match id.and_then(|id| self.namespaced.get(id)) {
+            Some(&own) if own != u32::MAX => return own,
+            _ => (),
+        }
+        let name = format!("{}::{}", namespace, vm.get_sym_name(sym));
+        let own = vm.get_symbol(&name);
+        if let Some(id) = id {
+            if id >= self.namespaced.len() {
+                self.namespaced.resize(id + 1, u32::MAX);
+            }
+            self.namespaced[id] = own;
+        }
+        own
+    }
+
     fn grouping(&mut self, vm: &mut Vm) {
         self.expression(vm);
-        self.consume(TokenType::RightParen, "expect ')' after expression");
+        self.consume(TokenType::RightParen, Message::ExpectGroupEnd);
+    }
+
+    fn identifier(&mut self, vm: &mut Vm) -> u32 {
+        // Identifier ids from the scanner are dense, so each distinct name is
+        // interned with the vm only once per compile.
+        let id = match self.previous.ident() {
+            Some(id) => id as usize,
+            None => return vm.get_symbol(self.token_text()),
+        };
+        if id >= self.symbols.len() {
+            self.symbols.resize(id + 1, u32::MAX);
+        }
+        if self.symbols[id] == u32::MAX {
+            self.symbols[id] = vm.get_symbol(self.token_text());
+        }
+        self.symbols[id]
+    }
+
+    fn implicit_semicolon(&self) -> bool {
+        ends_statement(self.current, &self.options, self.semicolon_required)
     }
 
     fn if_statement(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
-        self.consume(TokenType::LeftParen, "expect '(' after 'if'");
+        self.consume(TokenType::LeftParen, Message::ExpectIfParen);
         self.expression(vm);
-        self.consume(TokenType::RightParen, "expect ')' after condition");
+        self.consume(TokenType::RightParen, Message::ExpectConditionEnd);
 
         let then_jump = self.emit_jump(Op::JumpIfFalse);
         self.emit_op(Op::Pop);
+        let assigned = self.locals().assigned();
         self.statement(vm, loop_);
+        let then_assigned = self.locals().assigned();
+        self.locals().restore(&assigned);
         let else_jump = self.emit_jump(Op::Jump);
         self.patch_jump(then_jump);
         self.emit_op(Op::Pop);
@@ -576,22 +1152,234 @@ impl Parser {
         if self.matches(TokenType::Else) {
             self.statement(vm, loop_);
         }
+        self.locals().merge(&then_assigned);
         self.patch_jump(else_jump);
     }
 
+    // `import ns::name;`, after which the script's `name` is the global
+    // `ns::name`, as long as that was exported.
+    fn import_declaration(&mut self, vm: &mut Vm) {
+        if !self.locals().top_level() {
+            self.error(Message::ImportNotTopLevel);
+        }
+        self.consume(TokenType::Identifier, Message::ExpectNamespace);
+        let namespace = self.token_text().to_string();
+        self.consume(TokenType::ColonColon, Message::ExpectNamespaceSeparator);
+        self.consume(TokenType::Identifier, Message::ExpectImportName);
+        let sym = self.identifier(vm);
+        let name = format!("{}::{}", namespace, vm.get_sym_name(sym));
+        let imported = vm.get_symbol(&name);
+        let own = self.global_symbol(vm, sym);
+        if !vm.is_exported(imported) {
+            self.error_with(Message::NotExported, &[&name]);
+        } else if self.globals.contains_key(&own) {
+            self.error(Message::AlreadyGlobal);
+        }
+        self.imports.insert(sym, imported);
+        self.consume_semicolon(Message::ExpectSemicolonAfterImport);
+    }
+
+    // After `for (var`, in the extended dialect: is this `name in`?
+    fn in_follows(&mut self) -> bool {
+        if self.options.dialect != Dialect::Extended
+            || !self.check(TokenType::Identifier)
+        {
+            return false;
+        }
+        let next = self.peek_next();
+        next.ty() == TokenType::Identifier
+            && self.scanner.token_text(next) == "in"
+    }
+
+    // `++x` or `--x`, which leaves the new value.
+    fn increment(&mut self, vm: &mut Vm) {
+        let op = self.previous;
+        if !self.matches(TokenType::Identifier) {
+            self.error_at(self.current, Message::InvalidIncrementTarget);
+            return;
+        }
+        let name = self.previous;
+        let variable = self.resolve_variable(vm);
+        self.increment_variable(vm, name, variable, op, true);
+        // `++f()` would be incrementing a call.
+        if self.check(TokenType::LeftParen) {
+            self.error_at(self.current, Message::InvalidIncrementTarget);
+        }
+    }
+
+    // Adds one to the variable `name`, or subtracts one if `op` is `--`,
+    // leaving the new value if `prefix` and otherwise the old one.
+    fn increment_variable(
+        &mut self,
+        vm: &Vm,
+        name: Token,
+        (op_set, op_get, arg): (Opcode, Opcode, u32),
+        op: Token,
+        prefix: bool,
+    ) {
+        self.check_assignment(vm, name, op_set, arg);
+        self.get_variable(op_get, arg);
+        self.emit_op_at(Op::CheckNumber, op.line());
+        if !prefix {
+            self.emit_op_arg(op_get, arg);
+        }
+        self.emit_op(Op::One);
+        match op.ty() {
+            TokenType::PlusPlus => self.emit_op_at(Op::Add, op.line()),
+            _ => self.emit_op_at(Op::Subtract, op.line()),
+        }
+        self.emit_op_arg(op_set, arg);
+        if !prefix {
+            self.emit_op(Op::Pop);
+        }
+        if op_set == Op::SetLocal {
+            self.locals().assign(arg as usize);
+        }
+    }
+
+    // `list[index]`, or an assignment to it.
+    fn index(&mut self, vm: &mut Vm, can_assign: bool) {
+        let line = self.previous.line();
+        self.expression(vm);
+        self.consume(TokenType::RightBracket, Message::ExpectIndexEnd);
+        if can_assign && self.matches(TokenType::Equal) {
+            self.expression(vm);
+            self.emit_op_at(Op::SetIndex, line);
+        } else {
+            self.emit_op_at(Op::GetIndex, line);
+        }
+    }
+
+    // The operator after an operand, taking a `--` that isn't a decrement
+    // as a minus before a negation.
+    fn infix_operator(&mut self) -> TokenType {
+        if self.splits_decrement() {
+            self.split_decrement();
+        }
+        self.current.ty()
+    }
+
+    // For states the compiler should never get into, reported like any
+    // other compile error rather than panicking.
+    fn internal_error(&mut self, msg: &str) {
+        self.error_with(Message::InternalError, &[&msg]);
+    }
+
+    // Pushes the text of the part of a string just scanned, unless it's
+    // empty, returning whether it did.
+    fn interpolated_text(&mut self, vm: &mut Vm) -> bool {
+        let text = self.scanner.string_value(self.previous);
+        if text.is_empty() {
+            return false;
+        }
+        self.emit_constant(Value::String(vm.alloc(LoxString::new(&text))));
+        true
+    }
+
+    // A string with `${...}` in it, as the concatenation of its parts, with
+    // the value of each expression as `print` would show it.
+    fn interpolation(&mut self, vm: &mut Vm) {
+        let mut joined = self.interpolated_text(vm);
+        loop {
+            self.expression(vm);
+            self.emit_op(Op::Stringify);
+            if joined {
+                self.emit_op(Op::Add);
+            }
+            joined = true;
+            let more = self.matches(TokenType::InterpolationMiddle);
+            if !more {
+                let msg = Message::ExpectInterpolationEnd;
+                self.consume(TokenType::InterpolationEnd, msg);
+                if self.previous.ty() != TokenType::InterpolationEnd {
+                    return;
+                }
+            }
+            if self.interpolated_text(vm) {
+                self.emit_op(Op::Add);
+            }
+            if !more {
+                return;
+            }
+        }
+    }
+
+    fn list(&mut self, vm: &mut Vm) {
+        let mut count: u32 = 0;
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                self.expression(vm);
+                if count == 255 {
+                    self.error(Message::TooManyListItems);
+                }
+                count += 1;
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBracket, Message::ExpectListEnd);
+        self.emit_op_arg(Op::List, count & 0xff);
+    }
+
     fn literal(&mut self) {
-        let op = match self.previous.ty() {
-            TokenType::Nil => Op::Nil,
-            TokenType::True => Op::True,
-            TokenType::False => Op::False,
-            _ => unreachable!(),
-        };
-        self.emit_op(op);
+        match self.previous.ty() {
+            TokenType::Nil => self.emit_op(Op::Nil),
+            TokenType::True => self.emit_op(Op::True),
+            TokenType::False => self.emit_op(Op::False),
+            _ => self.internal_error("unexpected literal"),
+        }
+    }
+
+    fn location(&self, token: Token) -> String {
+        match token.ty() {
+            TokenType::Eof => " at end".to_string(),
+            _ => format!(" at '{}'", self.scanner.token_text(token)),
+        }
     }
 
     fn locals(&mut self) -> &mut Locals {
-        let idx = self.compilers.len() - 1;
-        &mut self.compilers[idx].locals
+        &mut self.compiler().locals
+    }
+
+    // An else clause runs when its loop ends without a break.
+    fn loop_else(&mut self, vm: &mut Vm, outer: Option<LoopInfo>) {
+        if self.matches(TokenType::Else) {
+            self.statement(vm, outer);
+        }
+    }
+
+    fn mark_initialized(&mut self) {
+        // A declaration that failed leaves no local behind, and has already
+        // been reported.
+        if !self.locals().mark_initialized() && !self.had_error {
+            self.internal_error("no local to initialize");
+        }
+    }
+
+    // The text of `msg`, from the catalog if it has it.
+    fn message(&self, msg: Message, args: &[&dyn Display]) -> String {
+        self.options.messages.format(msg, args)
+    }
+
+    fn map(&mut self, vm: &mut Vm) {
+        let mut count: u32 = 0;
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                self.expression(vm);
+                self.consume(TokenType::Colon, Message::ExpectMapColon);
+                self.expression(vm);
+                if count == 255 {
+                    self.error(Message::TooManyMapEntries);
+                }
+                count += 1;
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBrace, Message::ExpectMapEnd);
+        self.emit_op_arg(Op::Map, count & 0xff);
     }
 
     fn matches(&mut self, ty: TokenType) -> bool {
@@ -603,9 +1391,76 @@ impl Parser {
         }
     }
 
+    // `a, b = x, y;` evaluates all of the values before assigning any of
+    // them, so it can swap variables without a temporary.
+    fn multiple_assignment(&mut self, vm: &mut Vm) {
+        let mut targets = Vec::new();
+        loop {
+            self.consume(TokenType::Identifier, Message::ExpectVariableName);
+            let name = self.previous;
+            let (op_set, _, arg) = self.resolve_variable(vm);
+            self.check_assignment(vm, name, op_set, arg);
+            targets.push((op_set, arg));
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::Equal, Message::ExpectAssignmentEquals);
+
+        let mut count = 0;
+        loop {
+            self.expression(vm);
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        if count != targets.len() {
+            let expected = targets.len();
+            self.error_with(Message::AssignmentCount, &[&expected, &count]);
+        }
+        self.consume_semicolon(Message::ExpectSemicolonAfterAssignment);
+
+        // The last value is on top of the stack.
+        for (op_set, arg) in targets.into_iter().rev() {
+            self.emit_op_arg(op_set, arg);
+            self.emit_op(Op::Pop);
+            if op_set == Op::SetLocal {
+                self.locals().assign(arg as usize);
+            }
+        }
+    }
+
+    // Runs `parse` one level deeper, unless that would pass the nesting
+    // limit. Then it reports an error instead, and skips a token so that
+    // whatever is parsing the enclosing levels still moves on.
+    fn nested<F>(&mut self, too_deep: Message, parse: F)
+    where
+        F: FnOnce(&mut Parser),
+    {
+        if self.depth >= self.options.max_nesting {
+            self.error_at(self.current, too_deep);
+            self.advance();
+            return;
+        }
+        self.depth += 1;
+        parse(self);
+        self.depth -= 1;
+    }
+
+    fn next_token(&mut self) -> Scanned {
+        match self.lookahead.pop_front() {
+            Some(scanned) => scanned,
+            None => self.scan(),
+        }
+    }
+
     fn number(&mut self) {
-        let value = self.token_text().parse::<f64>().unwrap();
-        self.emit_constant(Value::Number(value));
+        // Rust's parsing ignores the locale, as Lox source should.
+        match self.token_text().parse::<f64>() {
+            Ok(value) => self.emit_constant(Value::Number(value)),
+            Err(_) => self.internal_error("bad number literal"),
+        }
     }
 
     fn or(&mut self, vm: &mut Vm) {
@@ -613,17 +1468,48 @@ impl Parser {
         let end_jump = self.emit_jump(Op::Jump);
         self.patch_jump(else_jump);
         self.emit_op(Op::Pop);
+        let assigned = self.locals().assigned();
         self.parse_precedence(Prec::Or, vm);
+        self.locals().restore(&assigned);
         self.patch_jump(end_jump);
     }
 
+    // Whether compiling printed any warnings.
+    pub(crate) fn had_warning(&self) -> bool {
+        self.had_warning
+    }
+
     pub(crate) fn parse(
         &mut self,
         vm: &mut Vm,
         name: &str,
     ) -> Option<LoxFunction> {
+        #[cfg(feature = "log")]
+        let started = std::time::Instant::now();
+        if self.compilers.is_empty() {
+            #[cfg(feature = "log")]
+            log::trace!("compiling {} bytes", self.scanner.text().len());
+            self.options = vm.options().clone();
+            self.scanner.set_dialect(self.options.dialect);
+            if let Err(msg) = self.options.check_source(self.scanner.text()) {
+                // Without quoting the source, which may be one huge line.
+                let _ = writeln!(
+                    self.stderr.borrow_mut(),
+                    "[line 1] Error: {}",
+                    msg
+                );
+                return None;
+            }
+        }
         self.compilers.push(Compiler::new(name));
 
+        if name != "<script>" {
+            // The previous token is the function's name.
+            self.compiler().function.line = self.previous.line();
+            let line = self.current.line();
+            self.chunk().new_line(line);
+        }
+
         if name == "<script>" {
             self.advance();
             while !(self.matches(TokenType::Eof)) {
@@ -633,78 +1519,184 @@ impl Parser {
             self.function(vm);
         }
 
+        self.emit_defers(vm, -1);
         self.emit_op(Op::Nil);
         self.emit_op(Op::Return);
+        if self.compilers.len() == 1 {
+            self.check_undeclared(vm);
+        }
 
-        #[cfg(feature = "print_code")]
         if !self.had_error {
-            self.chunk().disassemble(name, vm.get_sym_names());
+            if let Err(e) = self.compiler().function.check_stack() {
+                self.internal_error(&e.to_string());
+            }
         }
 
-        let mut compiler = self.compilers.pop().unwrap();
+        // Nested functions are listed along with the script.
+        #[cfg(feature = "print_code")]
+        if !self.had_error && self.compilers.len() == 1 {
+            let trace = vm.trace_output();
+            self.chunk()
+                .print_disassembly(&trace, name, vm.get_sym_names());
+        }
+
+        let Some(mut compiler) = self.compilers.pop() else {
+            self.internal_error("no function being compiled");
+            return None;
+        };
+        if !self.compilers.is_empty() {
+            // The enclosing chunk missed any line changes in the body.
+            let line = self.current.line();
+            self.chunk().new_line(line);
+        } else if self.options.show_source && !self.had_error {
+            compiler
+                .function
+                .chunk
+                .set_source(self.scanner.text().into());
+        }
+        if self.compilers.is_empty() && !self.had_error {
+            for sym in self.exports.drain(..) {
+                vm.export(sym);
+            }
+        }
+        #[cfg(feature = "log")]
+        if self.compilers.is_empty() {
+            log::debug!(
+                "compiled {} bytes in {:?}{}",
+                self.scanner.text().len(),
+                started.elapsed(),
+                if self.had_error { ", with errors" } else { "" }
+            );
+        }
         (!self.had_error).then_some(std::mem::take(&mut compiler.function))
     }
 
     fn parse_precedence(&mut self, precedence: Precedence, vm: &mut Vm) {
-        self.advance();
+        self.nested(Message::ExpressionTooDeep, |p| {
+            // `--` not before a variable is two minus signs, as in `--(1)`.
+            if p.check(TokenType::MinusMinus)
+                && p.peek_next().ty() != TokenType::Identifier
+            {
+                p.split_decrement();
+            }
+            p.advance();
+
+            let can_assign = precedence <= Prec::Assignment;
+            match p.previous.ty() {
+                TokenType::LeftParen => p.grouping(vm),
+                TokenType::LeftBracket => p.list(vm),
+                TokenType::LeftBrace
+                    if p.options.dialect == Dialect::Extended =>
+                {
+                    p.map(vm)
+                }
+                TokenType::Minus | TokenType::Bang => p.unary(vm),
+                TokenType::PlusPlus | TokenType::MinusMinus => p.increment(vm),
+                TokenType::Number => p.number(),
+                TokenType::Identifier => p.variable(vm, can_assign),
+                TokenType::String => p.string(vm),
+                TokenType::InterpolationStart => p.interpolation(vm),
+                TokenType::Nil | TokenType::True | TokenType::False => {
+                    p.literal()
+                }
+                _ => {
+                    p.error(Message::ExpectExpression);
+                    return;
+                }
+            }
 
-        let can_assign = precedence <= Prec::Assignment;
-        match self.previous.ty() {
-            TokenType::LeftParen => self.grouping(vm),
-            TokenType::Minus | TokenType::Bang => self.unary(vm),
-            TokenType::Number => self.number(),
-            TokenType::Identifier => self.variable(vm, can_assign),
-            TokenType::String => self.string(vm),
-            TokenType::Nil | TokenType::True | TokenType::False => {
-                self.literal()
-            }
-            _ => {
-                self.error("expect expression");
-                return;
+            while precedence <= Prec::for_op_type(p.infix_operator()) {
+                p.advance();
+                match p.previous.ty() {
+                    TokenType::Minus
+                    | TokenType::Plus
+                    | TokenType::Slash
+                    | TokenType::Star
+                    | TokenType::StarStar
+                    | TokenType::EqualEqual
+                    | TokenType::BangEqual
+                    | TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual => p.binary(vm),
+                    TokenType::And => p.and(vm),
+                    TokenType::Or => p.or(vm),
+                    TokenType::LeftParen => p.call(vm),
+                    TokenType::LeftBracket => p.index(vm, can_assign),
+                    _ => p.internal_error("unexpected infix operator"),
+                }
             }
-        }
 
-        while precedence <= Prec::for_op_type(self.current.ty()) {
-            self.advance();
-            match self.previous.ty() {
-                TokenType::Minus
-                | TokenType::Plus
-                | TokenType::Slash
-                | TokenType::Star
-                | TokenType::EqualEqual
-                | TokenType::BangEqual
-                | TokenType::Greater
-                | TokenType::GreaterEqual
-                | TokenType::Less
-                | TokenType::LessEqual => self.binary(vm),
-                TokenType::And => self.and(vm),
-                TokenType::Or => self.or(vm),
-                TokenType::LeftParen => self.call(vm),
-                _ => unreachable!(),
+            // Any postfix `++` on a variable was taken by variable().
+            if is_postfix_increment(p.current, &p.options) {
+                p.error_at(p.current, Message::InvalidIncrementTarget);
             }
-        }
+            if can_assign && p.matches(TokenType::Equal) {
+                p.error(Message::InvalidAssignmentTarget);
+            }
+        });
+    }
 
-        if can_assign && self.matches(TokenType::Equal) {
-            self.error("invalid assignment target");
-        }
+    fn patch_jump(&mut self, origin: Label) {
+        self.chunk().patch_jump(origin);
     }
 
-    fn patch_jump(&mut self, origin: usize) {
-        // Forward jumps are always 2 ops
-        let delta = self.chunk().len() - origin - 2;
-        if delta > 0xffff {
-            self.error("too much code to jump over");
+    fn peek_next(&mut self) -> Token {
+        // The token after current; any scan errors before it stay buffered
+        // until advance() reaches them.
+        let buffered = self.lookahead.iter().find_map(|s| s.as_ref().ok());
+        if let Some(&token) = buffered {
+            return token;
+        }
+        loop {
+            let scanned = self.scan();
+            let token = scanned.as_ref().ok().copied();
+            self.lookahead.push_back(scanned);
+            if let Some(token) = token {
+                return token;
+            }
         }
-        self.chunk().patch_jump(origin, delta as u16);
     }
 
     fn print_statement(&mut self, vm: &mut Vm) {
-        self.expression(vm);
-        self.consume(TokenType::Semicolon, "expect ';' after value");
+        if self.options.strict {
+            self.consume(TokenType::LeftParen, Message::ExpectPrintParen);
+            self.expression(vm);
+            self.consume(TokenType::RightParen, Message::ExpectPrintEnd);
+        } else {
+            self.expression(vm);
+        }
+        self.consume_semicolon(Message::ExpectSemicolonAfterValue);
         self.emit_op(Op::Print);
     }
 
-    fn report_error(&mut self, line: u32, msg: String) {
+    // Steps a for-in loop over a range, as IterNext does over a list: pushes
+    // the number in `slot` and whether it's still before the end, in the
+    // slot after, then moves it on by one.
+    fn range_next(&mut self, slot: u32, inclusive: bool) {
+        self.emit_op_arg(Op::GetLocal, slot);
+        self.emit_op_arg(Op::GetLocal, slot);
+        self.emit_op_arg(Op::GetLocal, slot + 1);
+        if inclusive {
+            self.emit_op(Op::Greater);
+            self.emit_op(Op::Not);
+        } else {
+            self.emit_op(Op::Less);
+        }
+        self.emit_op_arg(Op::GetLocal, slot);
+        self.emit_op(Op::One);
+        self.emit_op(Op::Add);
+        self.emit_op_arg(Op::SetLocal, slot);
+        self.emit_op(Op::Pop);
+    }
+
+    fn report_at(&mut self, token: Token, msg: &str) {
+        let msg = format!("{}: {}", self.location(token), msg);
+        self.report_error(token.line(), msg, Some(token));
+    }
+
+    // `at` is the token the error is at, if it isn't a scan error.
+    fn report_error(&mut self, line: u32, msg: String, at: Option<Token>) {
         if self.panic_mode {
             return;
         }
@@ -712,24 +1704,86 @@ impl Parser {
         self.had_error = true;
         let _ =
             writeln!(self.stderr.borrow_mut(), "[line {}] Error{}", line, msg);
+        self.show_source(line, at);
+    }
+
+    // How to get and set the variable named by the previous token.
+    fn resolve_variable(&mut self, vm: &mut Vm) -> (Opcode, Opcode, u32) {
+        let sym = self.identifier(vm);
+        match self.locals().resolve(sym) {
+            None => {
+                let sym = self.global_symbol(vm, sym);
+                (Op::SetGlobal, Op::GetGlobal, sym)
+            }
+            Some((slot, is_initialized)) => {
+                if !is_initialized {
+                    self.error(Message::ReadInOwnInitializer);
+                }
+                (Op::SetLocal, Op::GetLocal, slot as u32)
+            }
+        }
     }
 
     fn return_statement(&mut self, vm: &mut Vm) {
         if self.compilers.len() == 1 {
-            self.error("can't return from top-level code");
+            self.error(Message::ReturnFromTopLevel);
+        } else if self.deferring {
+            self.error(Message::ReturnFromDefer);
         }
-        if self.matches(TokenType::Semicolon) {
+        if self.matches(TokenType::Semicolon) || self.implicit_semicolon() {
+            self.emit_defers(vm, -1);
             self.emit_op(Op::Nil);
             self.emit_op(Op::Return);
         } else {
             self.expression(vm);
-            self.consume(TokenType::Semicolon, "expect ';' after return value");
+            self.consume_semicolon(Message::ExpectSemicolonAfterReturn);
+            // The value sits above the locals while deferred statements
+            // run.
+            self.locals().begin_scope();
+            self.locals().inject();
+            self.emit_defers(vm, -1);
+            self.locals().end_scope();
             self.emit_op(Op::Return);
         }
+        self.locals().assign_all();
+    }
+
+    fn scan(&mut self) -> Scanned {
+        self.scanner
+            .scan_token()
+            .map_err(|e| (e, self.scanner.line()))
     }
 
-    fn scan_error(&mut self, err: Error) {
-        self.report_error(self.scanner.line(), format!(": {}", err));
+    fn scan_error(&mut self, err: Error, line: u32) {
+        let text = self.error_text(&err);
+        self.report_error(line, format!(": {}", text), None);
+    }
+
+    // Quotes the line a diagnostic is about, with carets under the token
+    // it is at, if the vm keeps source for error messages.
+    fn show_source(&self, line: u32, at: Option<Token>) {
+        if !self.options.show_source {
+            return;
+        }
+        let mut stderr = self.stderr.borrow_mut();
+        match at {
+            Some(token) => {
+                let (text, column, width) = self.scanner.token_line(token);
+                // Keep tabs, so the carets line up however they're shown.
+                let pad: String = text
+                    .chars()
+                    .take(column)
+                    .map(|c| if c == '\t' { c } else { ' ' })
+                    .collect();
+                let carets = "^".repeat(width);
+                let _ = writeln!(stderr, "    {}\n    {}{}", text, pad, carets);
+            }
+            None => {
+                if let Some(text) = self.scanner.line_text(line) {
+                    let _ = writeln!(stderr, "    {}", text);
+                }
+            }
+        }
     }
 
     fn show_tokens(&mut self) {
@@ -753,71 +1807,87 @@ impl Parser {
         }
     }
 
+    // Replaces a `--` at current with the two `-` it's made of.
+    fn split_decrement(&mut self) {
+        let (minus, negate) = self.current.split_minus();
+        self.current = minus;
+        self.lookahead.push_front(Ok(negate));
+    }
+
+    fn splits_decrement(&mut self) -> bool {
+        self.check(TokenType::MinusMinus)
+            && splits_decrement(self.current, self.peek_next(), &self.options)
+    }
+
     fn statement(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
-        if self.matches(TokenType::Print) {
-            self.print_statement(vm);
-        } else if self.matches(TokenType::For) {
-            self.for_statement(vm);
-        } else if self.matches(TokenType::If) {
-            self.if_statement(vm, loop_);
-        } else if self.matches(TokenType::Return) {
-            self.return_statement(vm);
-        } else if self.matches(TokenType::While) {
-            self.while_statement(vm);
-        } else if self.matches(TokenType::Break) {
-            self.break_statement(loop_);
-        } else if self.matches(TokenType::Continue) {
-            self.continue_statement(loop_);
-        } else if self.matches(TokenType::Switch) {
-            self.switch_statement(vm, loop_);
-        } else if self.matches(TokenType::LeftBrace) {
-            self.begin_scope();
-            self.block(vm, loop_);
-            self.end_scope();
-        } else {
-            self.expression_statement(vm);
-        }
+        self.nested(Message::StatementTooDeep, |p| {
+            if p.matches(TokenType::Print) {
+                p.print_statement(vm);
+            } else if p.matches(TokenType::For) {
+                p.for_statement(vm, loop_);
+            } else if p.matches(TokenType::If) {
+                p.if_statement(vm, loop_);
+            } else if p.matches(TokenType::Return) {
+                p.return_statement(vm);
+            } else if p.matches(TokenType::While) {
+                p.while_statement(vm, loop_);
+            } else if p.matches(TokenType::Break) {
+                p.break_statement(vm, loop_);
+            } else if p.matches(TokenType::Continue) {
+                p.continue_statement(vm, loop_);
+            } else if p.matches(TokenType::Switch) {
+                p.switch_statement(vm, loop_);
+            } else if p.matches(TokenType::Defer) {
+                p.error(Message::DeferAsBody);
+            } else if p.matches(TokenType::LeftBrace) {
+                p.begin_scope();
+                p.block(vm, loop_);
+                p.end_scope(vm);
+            } else {
+                p.expression_statement(vm);
+            }
+        });
     }
 
     fn string(&mut self, vm: &mut Vm) {
-        let raw = self.token_text();
-        let value =
-            Value::String(LoxString::new(&raw[1..raw.len() - 1]).into());
-        self.emit_constant(value);
+        let text = LoxString::new(&self.scanner.string_value(self.previous));
+        self.emit_constant(Value::String(vm.alloc(text)));
     }
 
     fn switch_case(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
         // TODO: begin scope to keep local count down?
         while !self.check(TokenType::Semicolon) && !self.check(TokenType::Eof) {
             self.statement(vm, loop_);
-            if self.previous.ty() == TokenType::Semicolon {
+            if self.previous.ty() == TokenType::Semicolon
+                || (self.options.optional_semicolons && self.current.newline())
+            {
                 return;
             }
         }
-        self.consume(TokenType::Semicolon, "expect ';' after switch case")
+        self.consume(TokenType::Semicolon, Message::ExpectCaseEnd)
     }
 
     fn switch_statement(&mut self, vm: &mut Vm, loop_: Option<LoopInfo>) {
         // To contain the synthesized local
         self.begin_scope();
 
-        self.consume(TokenType::LeftParen, "expect '(' after 'switch'");
+        self.consume(TokenType::LeftParen, Message::ExpectSwitchParen);
         let test_slot = self.locals().inject();
         self.expression(vm);
-        self.consume(
-            TokenType::RightParen,
-            "expect ')' after switch expression",
-        );
+        self.consume(TokenType::RightParen, Message::ExpectSwitchSubjectEnd);
 
-        self.consume(TokenType::LeftBrace, "expect '{' before switch body");
-        let mut patch_false: Option<usize> = None;
-        let mut patch_true: Vec<usize> = Vec::new();
+        self.consume(TokenType::LeftBrace, Message::ExpectSwitchBody);
+        // Only the subject and the first test are sure to be evaluated.
+        let assigned = self.locals().assigned();
+        let mut patch_false: Option<Label> = None;
+        let mut patch_true: Vec<Label> = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof)
         {
-            if let Some(jump) = patch_false {
+            if let Some(jump) = patch_false.take() {
                 self.patch_jump(jump);
                 self.emit_op(Op::Pop);
             }
+            self.locals().restore(&assigned);
             if self.matches(TokenType::Case) || self.matches(TokenType::Default)
             {
                 let default = self.previous.ty() == TokenType::Default;
@@ -828,30 +1898,34 @@ impl Parser {
                     patch_false = Some(self.emit_jump(Op::JumpIfFalse));
                     self.emit_op(Op::Pop);
                 }
-                self.consume(
-                    TokenType::Colon,
-                    "expect ':' after switch expression",
-                );
+                self.consume(TokenType::Colon, Message::ExpectCaseColon);
                 self.switch_case(vm, loop_);
                 if default {
                     if !self.check(TokenType::RightBrace) {
-                        self.error("default case must be the last case");
+                        self.error(Message::DefaultNotLast);
                     }
                     break;
                 } else if !self.check(TokenType::RightBrace) {
                     patch_true.push(self.emit_jump(Op::Jump));
                 }
             } else {
-                self.error("expect switch case");
+                self.error(Message::ExpectCase);
                 break;
             }
         }
-        self.consume(TokenType::RightBrace, "expect '}' after switch body");
+        self.consume(TokenType::RightBrace, Message::ExpectSwitchEnd);
+        // With no default, the last case's test can still fail.
+        if let Some(jump) = patch_false {
+            patch_true.push(self.emit_jump(Op::Jump));
+            self.patch_jump(jump);
+            self.emit_op(Op::Pop);
+        }
         for origin in patch_true {
             self.patch_jump(origin);
         }
+        self.locals().restore(&assigned);
 
-        self.end_scope();
+        self.end_scope(vm);
     }
 
     fn synchronize(&mut self) {
@@ -889,70 +1963,129 @@ impl Parser {
         match operator_type {
             TokenType::Minus => self.emit_op(Op::Negate),
             TokenType::Bang => self.emit_op(Op::Not),
-            _ => unreachable!(),
+            _ => self.internal_error("unexpected unary operator"),
         }
     }
 
     fn var_declaration(&mut self, vm: &mut Vm) {
-        let sym = self.declare_variable(vm, "variable");
+        let sym = self.declare_variable(
+            vm,
+            Message::ExpectVariableName,
+            Message::AlreadyVariable,
+        );
 
+        let start = self.chunk().len();
         if self.matches(TokenType::Equal) {
             self.expression(vm);
         } else {
             self.emit_op(Op::Nil);
         }
-        self.consume(
-            TokenType::Semicolon,
-            "expect ';' after variable declaration",
-        );
+        self.consume_semicolon(Message::ExpectSemicolonAfterVariable);
 
         if self.locals().top_level() {
             self.emit_op_arg(Op::DefineGlobal, sym);
         } else {
-            self.locals().mark_initialized();
+            self.mark_initialized();
+            // Starting out nil, explicitly or not, doesn't count as being
+            // assigned.
+            let nil = self.chunk().instructions(start).map(|i| i.opcode());
+            if nil.eq([Op::Nil]) {
+                self.locals().unassign_last();
+            }
         }
     }
 
     fn variable(&mut self, vm: &mut Vm, can_assign: bool) {
-        let sym = vm.get_symbol(self.token_text());
-        let (op_set, op_get, arg) = match self.locals().resolve(sym) {
-            None => (Op::SetGlobal, Op::GetGlobal, sym),
-            Some((slot, is_initialized)) => {
-                if !is_initialized {
-                    self.error(
-                        "can't read local variable in its own initializer",
-                    );
-                }
-                (Op::SetLocal, Op::GetLocal, slot as u32)
-            }
-        };
+        let name = self.previous;
+        let (op_set, op_get, arg) = self.resolve_variable(vm);
 
         if can_assign && self.matches(TokenType::Equal) {
+            self.check_assignment(vm, name, op_set, arg);
             self.expression(vm);
             self.emit_op_arg(op_set, arg);
+            if op_set == Op::SetLocal {
+                self.locals().assign(arg as usize);
+            }
+        } else if is_postfix_increment(self.current, &self.options)
+            && !self.splits_decrement()
+        {
+            self.advance();
+            let op = self.previous;
+            self.increment_variable(vm, name, (op_set, op_get, arg), op, false);
         } else {
-            self.emit_op_arg(op_get, arg);
+            self.get_variable(op_get, arg);
+        }
+    }
+
+    fn warning(&mut self, msg: Message, args: &[&dyn Display]) {
+        // Any warning was given when the statement was first compiled.
+        if self.replaying {
+            return;
+        }
+        if self.options.strict {
+            self.error_with(msg, args);
+        } else if !self.panic_mode {
+            self.had_warning = true;
+            let msg = self.message(msg, args);
+            let token = self.previous;
+            let _ = writeln!(
+                self.stderr.borrow_mut(),
+                "[line {}] Warning{}: {}",
+                token.line(),
+                self.location(token),
+                msg
+            );
+            self.show_source(token.line(), Some(token));
+        }
+    }
+
+    // Warns that the local just declared as `sym` hides another variable:
+    // the local in `shadowed`, or else a global.
+    fn warn_shadowing(
+        &mut self,
+        vm: &mut Vm,
+        sym: u32,
+        shadowed: Option<usize>,
+    ) {
+        let sym = self.global_symbol(vm, sym);
+        match (shadowed, self.globals.get(&sym)) {
+            (Some(slot), _) => {
+                let line = self.locals().line(slot);
+                self.warning(Message::ShadowsLocal, &[&line]);
+            }
+            (None, Some(&line)) => {
+                self.warning(Message::ShadowsGlobalDeclared, &[&line]);
+            }
+            (None, None) if vm.has_global(sym) => {
+                self.warning(Message::ShadowsGlobal, &[]);
+            }
+            (None, None) => (),
         }
     }
 
-    fn while_statement(&mut self, vm: &mut Vm) {
-        let loop_start = self.chunk().len();
-        self.consume(TokenType::LeftParen, "expect '(' after 'while'");
+    fn while_statement(&mut self, vm: &mut Vm, outer: Option<LoopInfo>) {
+        let break_jump = self.break_target();
+        let loop_start = self.chunk().label();
+        self.consume(TokenType::LeftParen, Message::ExpectWhileParen);
         self.expression(vm);
-        self.consume(TokenType::RightParen, "expect ')' after condition");
+        self.consume(TokenType::RightParen, Message::ExpectConditionEnd);
 
         let exit_jump = self.emit_jump(Op::JumpIfFalse);
         self.emit_op(Op::Pop);
+        let assigned = self.locals().assigned();
 
         let loop_ = Some(LoopInfo {
             depth: self.locals().depth,
             loop_start,
-            exit_jump,
+            break_jump,
         });
         self.statement(vm, loop_);
 
         self.emit_loop(loop_start);
         self.patch_jump(exit_jump);
         self.emit_op(Op::Pop);
+        self.loop_else(vm, outer);
+        self.patch_jump(break_jump);
+        self.locals().restore(&assigned);
     }
 }
@@ -0,0 +1,1160 @@
+//! A syntax tree for Lox source, for tools that want the shape of a script
+//! rather than its bytecode. The compiler doesn't use it: it still emits
+//! code in a single pass.
+//!
+//! So the grammar is written out twice, here and in the compiler, and a
+//! change to the syntax has to be made in both, a cost taken on to keep
+//! the compiler single-pass. Only the operator precedences (`Prec`), the
+//! checks for a postfix `++` or `--`, and where a `;` may be left out are
+//! shared. The tests parse the programs in `tests/lox`, which the vm's
+//! tests run, to catch the two drifting apart.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+use anyhow::{anyhow, Error, Result};
+use serde::Serialize;
+
+use super::scanner::{Scanner, Token, TokenType};
+use super::Prec::{self, Precedence};
+use super::{ends_statement, is_postfix_increment, splits_decrement};
+use crate::{
+    message::{Diagnostic, Message},
+    Dialect, VmOptions,
+};
+
+pub use index::{Symbol, SymbolIndex};
+#[cfg(feature = "js")]
+pub use js::to_js;
+
+mod format;
+mod index;
+#[cfg(feature = "js")]
+mod js;
+mod json;
+mod minify;
+
+#[cfg(test)]
+mod test;
+
+/// Where a node is in the source: the byte range it covers, and the line
+/// it starts on.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Expr {
+    #[serde(flatten)]
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum ExprKind {
+    #[serde(rename = "Literal", serialize_with = "json::null_value")]
+    Nil,
+    #[serde(rename = "Literal", serialize_with = "json::value")]
+    Bool(bool),
+    #[serde(rename = "Literal", serialize_with = "json::value")]
+    Number(f64),
+    #[serde(rename = "Literal", serialize_with = "json::value")]
+    String(String),
+    #[serde(serialize_with = "json::name")]
+    Variable(String),
+    Assign {
+        name: String,
+        value: Box<Expr>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    /// `++x`, `x--` and so on.
+    Update {
+        name: String,
+        op: UpdateOp,
+        prefix: bool,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Logical {
+        op: LogicalOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// `[a, b, c]`.
+    #[serde(serialize_with = "json::items")]
+    List(Vec<Expr>),
+    /// `{key: value, ...}`, as pairs of key and value.
+    #[serde(serialize_with = "json::entries")]
+    Map(Vec<(Expr, Expr)>),
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `object[index] = value`.
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    #[serde(serialize_with = "json::expr")]
+    Grouping(Box<Expr>),
+    /// A string with `${...}` in it, in order of its parts.
+    #[serde(serialize_with = "json::parts")]
+    Interpolation(Vec<StringPart>),
+    /// `start..end`, or `start..=end` if inclusive, which can only be
+    /// looped over by a for-in.
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
+}
+
+/// Part of an interpolated string: text, or an expression whose value is
+/// shown as `print` would.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum StringPart {
+    Text(String),
+    Expr(Expr),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpdateOp {
+    Increment,
+    Decrement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stmt {
+    #[serde(flatten)]
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum StmtKind {
+    #[serde(serialize_with = "json::expr")]
+    Expression(Expr),
+    #[serde(serialize_with = "json::expr")]
+    Print(Expr),
+    Var {
+        name: String,
+        init: Option<Expr>,
+    },
+    Fun(Function),
+    #[serde(serialize_with = "json::body")]
+    Block(Vec<Stmt>),
+    // `a, b = x, y;`, which assigns every value after evaluating them all.
+    MultipleAssign {
+        names: Vec<String>,
+        values: Vec<Expr>,
+    },
+    If {
+        cond: Expr,
+        then: Box<Stmt>,
+        #[serde(rename = "else")]
+        else_: Option<Box<Stmt>>,
+    },
+    While {
+        cond: Expr,
+        body: Box<Stmt>,
+        #[serde(rename = "else")]
+        else_: Option<Box<Stmt>>,
+    },
+    For {
+        init: Option<Box<Stmt>>,
+        cond: Option<Expr>,
+        incr: Option<Expr>,
+        body: Box<Stmt>,
+        #[serde(rename = "else")]
+        else_: Option<Box<Stmt>>,
+    },
+    // `for (var name in sequence)`, over a list's items or a map's keys.
+    ForIn {
+        name: String,
+        sequence: Expr,
+        body: Box<Stmt>,
+        #[serde(rename = "else")]
+        else_: Option<Box<Stmt>>,
+    },
+    #[serde(serialize_with = "json::value")]
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    // Runs when the block it's in is left, by any path but an error.
+    #[serde(serialize_with = "json::stmt")]
+    Defer(Box<Stmt>),
+    // A `var` or `fun` that scripts in other namespaces may import.
+    #[serde(serialize_with = "json::stmt")]
+    Export(Box<Stmt>),
+    // `import namespace::name;`
+    Import {
+        namespace: String,
+        name: String,
+    },
+    Switch {
+        subject: Expr,
+        cases: Vec<Case>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    /// The text of the `///` comments before the declaration.
+    pub doc: Option<String>,
+}
+
+/// One arm of a switch statement; `test` is None for `default`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub struct Case {
+    pub test: Option<Expr>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+struct AstParser {
+    scanner: Scanner,
+    current: Token,
+    previous: Token,
+    // Tokens scanned past current, as when a `--` is split in two.
+    lookahead: VecDeque<Token>,
+    options: VmOptions,
+    // Nesting so far, checked against the vm's max_nesting.
+    depth: usize,
+    // As in the compiler's Parser.
+    semicolon_required: bool,
+}
+
+/// Parse `source` into its top-level statements. Only syntax is checked, so
+/// a script that parses may still fail to compile (for instance, with a
+/// `break` outside of a loop). The error is the first one the compiler
+/// would report.
+pub fn parse(source: String, options: &VmOptions) -> Result<Vec<Stmt>> {
+    if let Err(msg) = options.check_source(&source) {
+        return Err(anyhow!("[line 1] Error: {}", msg));
+    }
+    let mut parser = AstParser {
+        scanner: Scanner::new(source),
+        current: Token::default(),
+        previous: Token::default(),
+        lookahead: VecDeque::new(),
+        options: options.clone(),
+        depth: 0,
+        semicolon_required: false,
+    };
+    parser.scanner.set_dialect(options.dialect);
+    parser.advance()?;
+    let mut stmts = Vec::new();
+    while !parser.matches(TokenType::Eof)? {
+        stmts.push(parser.declaration()?);
+    }
+    Ok(stmts)
+}
+
+/// The statements as Lox source, laid out one statement per line with
+/// four-space indents. Parsing the result gives back the same tree, apart
+/// from spans; comments other than doc comments are lost. Backslashes and
+/// quotes in strings are escaped, as the extended dialect reads them.
+pub fn format(stmts: &[Stmt]) -> String {
+    format::stmts(stmts)
+}
+
+/// The statements as a JSON array, one object per node. Each object has a
+/// "type", the node's fields, and a "span".
+pub fn to_json(stmts: &[Stmt]) -> String {
+    // Nothing in a tree can fail to serialize.
+    serde_json::to_string_pretty(stmts).unwrap()
+}
+
+/// Where each global is declared and referenced, for tools like
+/// go-to-definition and rename; `source` is the text `stmts` were parsed
+/// from. Locals are left out, and so are globals that a function reads
+/// while a local of the same name is in scope.
+pub fn symbols(source: &str, stmts: &[Stmt]) -> SymbolIndex {
+    index::symbols(source, stmts)
+}
+
+/// `source` with as little whitespace as will scan the same, and without
+/// comments, apart from the doc comments that `doc()` reads. With
+/// `rename_locals`, local variables and parameters get the shortest names
+/// that don't clash with anything.
+pub fn minify(
+    source: &str,
+    options: &VmOptions,
+    rename_locals: bool,
+) -> Result<String> {
+    minify::minify(source, options, rename_locals)
+}
+
+/// `source` with the global `old` renamed to `new`, everywhere it's
+/// declared or referenced. Fails instead if `new` is taken, or if the
+/// renamed global would be hidden by a local somewhere it's used.
+pub fn rename(
+    source: &str,
+    old: &str,
+    new: &str,
+    options: &VmOptions,
+) -> Result<String> {
+    index::rename(source, old, new, options)
+}
+
+impl UnaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+}
+
+impl UpdateOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateOp::Increment => "++",
+            UpdateOp::Decrement => "--",
+        }
+    }
+
+    fn for_token(ty: TokenType) -> UpdateOp {
+        match ty {
+            TokenType::PlusPlus => UpdateOp::Increment,
+            _ => UpdateOp::Decrement,
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Power => "**",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+        }
+    }
+
+    fn for_token(ty: TokenType) -> Option<BinaryOp> {
+        Some(match ty {
+            TokenType::Plus => BinaryOp::Add,
+            TokenType::Minus => BinaryOp::Subtract,
+            TokenType::Star => BinaryOp::Multiply,
+            TokenType::Slash => BinaryOp::Divide,
+            TokenType::StarStar => BinaryOp::Power,
+            TokenType::EqualEqual => BinaryOp::Equal,
+            TokenType::BangEqual => BinaryOp::NotEqual,
+            TokenType::Less => BinaryOp::Less,
+            TokenType::LessEqual => BinaryOp::LessEqual,
+            TokenType::Greater => BinaryOp::Greater,
+            TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+            _ => return None,
+        })
+    }
+}
+
+impl LogicalOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogicalOp::And => "and",
+            LogicalOp::Or => "or",
+        }
+    }
+}
+
+impl Span {
+    fn to(self, end: Span) -> Span {
+        Span {
+            end: end.end,
+            ..self
+        }
+    }
+}
+
+impl AstParser {
+    fn advance(&mut self) -> Result<()> {
+        self.previous = self.current;
+        self.current = match self.lookahead.pop_front() {
+            Some(token) => token,
+            None => self.scan()?,
+        };
+        Ok(())
+    }
+
+    fn argument_list(&mut self) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                args.push(self.expression()?);
+                if args.len() > 255 {
+                    return Err(self.error(Message::TooManyArguments));
+                }
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, Message::ExpectArgumentsEnd)?;
+        Ok(args)
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof)
+        {
+            stmts.push(self.declaration()?);
+        }
+        self.consume(TokenType::RightBrace, Message::ExpectBlockEnd)?;
+        Ok(stmts)
+    }
+
+    fn check(&self, ty: TokenType) -> bool {
+        self.current.ty() == ty
+    }
+
+    fn consume(&mut self, ty: TokenType, msg: Message) -> Result<()> {
+        if self.check(ty) {
+            self.advance()
+        } else {
+            Err(self.error_at(self.current, msg))
+        }
+    }
+
+    fn consume_semicolon(&mut self, msg: Message) -> Result<()> {
+        if self.implicit_semicolon() {
+            Ok(())
+        } else {
+            self.consume(TokenType::Semicolon, msg)
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Stmt> {
+        let start = self.span(self.current);
+        let kind = if self.matches(TokenType::Fun)? {
+            self.fun_declaration()?
+        } else if self.matches(TokenType::Var)? {
+            self.var_declaration()?
+        } else if matches!(
+            self.current.ty(),
+            TokenType::Defer | TokenType::Export | TokenType::Import
+        ) {
+            self.advance()?;
+            self.extension_declaration()?
+        } else {
+            return self.statement();
+        };
+        Ok(self.stmt(kind, start))
+    }
+
+    fn error(&self, msg: Message) -> Error {
+        self.error_at(self.previous, msg)
+    }
+
+    fn error_with(&self, msg: Message, args: &[&dyn Display]) -> Error {
+        self.report_at(self.previous, msg, args)
+    }
+
+    fn error_at(&self, token: Token, msg: Message) -> Error {
+        self.report_at(token, msg, &[])
+    }
+
+    // Messages go through the options' catalog, as the compiler's do.
+    fn report_at(
+        &self,
+        token: Token,
+        msg: Message,
+        args: &[&dyn Display],
+    ) -> Error {
+        let at = match token.ty() {
+            TokenType::Eof => " at end".to_string(),
+            _ => format!(" at '{}'", self.scanner.token_text(token)),
+        };
+        let msg = self.options.messages.format(msg, args);
+        anyhow!("[line {}] Error{}: {}", token.line(), at, msg)
+    }
+
+    fn export_declaration(&mut self) -> Result<StmtKind> {
+        let start = self.span(self.current);
+        let kind = if self.matches(TokenType::Fun)? {
+            self.fun_declaration()?
+        } else if self.matches(TokenType::Var)? {
+            self.var_declaration()?
+        } else {
+            return Err(self.error_at(self.current, Message::ExpectExported));
+        };
+        Ok(StmtKind::Export(Box::new(self.stmt(kind, start))))
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        self.parse_precedence(Prec::Assignment)
+    }
+
+    fn expression_statement(&mut self) -> Result<StmtKind> {
+        let expr = self.expression()?;
+        if let ExprKind::Variable(name) = &expr.kind {
+            if self.check(TokenType::Comma) {
+                return self.multiple_assignment(name.clone());
+            }
+        }
+        self.consume_semicolon(Message::ExpectSemicolonAfterExpression)?;
+        Ok(StmtKind::Expression(expr))
+    }
+
+    fn expr(&self, kind: ExprKind, start: Span) -> Expr {
+        Expr {
+            kind,
+            span: start.to(self.span(self.previous)),
+        }
+    }
+
+    // The declarations book Lox doesn't have, after their keyword. Kept out
+    // of `declaration`, which every level of nested blocks goes through, so
+    // as not to make its stack frame bigger.
+    fn extension_declaration(&mut self) -> Result<StmtKind> {
+        match self.previous.ty() {
+            TokenType::Defer => {
+                Ok(StmtKind::Defer(Box::new(self.statement()?)))
+            }
+            TokenType::Export => self.export_declaration(),
+            _ => self.import_declaration(),
+        }
+    }
+
+    fn for_statement(&mut self) -> Result<StmtKind> {
+        self.consume(TokenType::LeftParen, Message::ExpectForParen)?;
+        let start = self.span(self.current);
+        let init = if self.matches(TokenType::Semicolon)? {
+            None
+        } else if self.matches(TokenType::Var)? {
+            let name = self.name(Message::ExpectVariableName)?;
+            if self.options.dialect == Dialect::Extended
+                && self.check(TokenType::Identifier)
+                && self.scanner.token_text(self.current) == "in"
+            {
+                return self.for_in(name);
+            }
+            self.semicolon_required = true;
+            let kind = self.var_initializer(name);
+            self.semicolon_required = false;
+            Some(Box::new(self.stmt(kind?, start)))
+        } else {
+            self.semicolon_required = true;
+            let kind = self.expression_statement();
+            self.semicolon_required = false;
+            Some(Box::new(self.stmt(kind?, start)))
+        };
+
+        let cond = if self.matches(TokenType::Semicolon)? {
+            None
+        } else {
+            let cond = self.expression()?;
+            self.consume(
+                TokenType::Semicolon,
+                Message::ExpectLoopConditionEnd,
+            )?;
+            Some(cond)
+        };
+
+        let incr = if self.matches(TokenType::RightParen)? {
+            None
+        } else {
+            let incr = self.expression()?;
+            self.consume(TokenType::RightParen, Message::ExpectLoopClausesEnd)?;
+            Some(incr)
+        };
+
+        let body = Box::new(self.statement()?);
+        let else_ = self.loop_else()?;
+        Ok(StmtKind::For {
+            init,
+            cond,
+            incr,
+            body,
+            else_,
+        })
+    }
+
+    fn for_in(&mut self, name: String) -> Result<StmtKind> {
+        self.advance()?;
+        let mut sequence = self.expression()?;
+        let inclusive = self.matches(TokenType::DotDotEqual)?;
+        if inclusive || self.matches(TokenType::DotDot)? {
+            let end = self.expression()?;
+            let span = Span {
+                end: end.span.end,
+                ..sequence.span
+            };
+            let kind = ExprKind::Range {
+                start: Box::new(sequence),
+                end: Box::new(end),
+                inclusive,
+            };
+            sequence = Expr { kind, span };
+        }
+        self.consume(TokenType::RightParen, Message::ExpectForInSequenceEnd)?;
+        let body = Box::new(self.statement()?);
+        let else_ = self.loop_else()?;
+        Ok(StmtKind::ForIn {
+            name,
+            sequence,
+            body,
+            else_,
+        })
+    }
+
+    fn fun_declaration(&mut self) -> Result<StmtKind> {
+        self.nested(Message::FunctionTooDeep, |p| {
+            let doc = p.scanner.doc(p.previous);
+            let name = p.name(Message::ExpectFunctionName)?;
+            p.consume(TokenType::LeftParen, Message::ExpectParameters)?;
+            let mut params = Vec::new();
+            if !p.check(TokenType::RightParen) {
+                loop {
+                    if params.len() == 255 {
+                        return Err(
+                            p.error_at(p.current, Message::TooManyParameters)
+                        );
+                    }
+                    params.push(p.name(Message::ExpectParameterName)?);
+                    if !p.matches(TokenType::Comma)? {
+                        break;
+                    }
+                }
+            }
+            p.consume(TokenType::RightParen, Message::ExpectParametersEnd)?;
+            p.consume(TokenType::LeftBrace, Message::ExpectFunctionBody)?;
+            let body = p.block()?;
+            Ok(StmtKind::Fun(Function {
+                name,
+                params,
+                body,
+                doc,
+            }))
+        })
+    }
+
+    fn if_statement(&mut self) -> Result<StmtKind> {
+        self.consume(TokenType::LeftParen, Message::ExpectIfParen)?;
+        let cond = self.expression()?;
+        self.consume(TokenType::RightParen, Message::ExpectConditionEnd)?;
+        let then = Box::new(self.statement()?);
+        let else_ = match self.matches(TokenType::Else)? {
+            true => Some(Box::new(self.statement()?)),
+            false => None,
+        };
+        Ok(StmtKind::If { cond, then, else_ })
+    }
+
+    fn implicit_semicolon(&self) -> bool {
+        ends_statement(self.current, &self.options, self.semicolon_required)
+    }
+
+    fn import_declaration(&mut self) -> Result<StmtKind> {
+        let namespace = self.name(Message::ExpectNamespace)?;
+        self.consume(TokenType::ColonColon, Message::ExpectNamespaceSeparator)?;
+        let name = self.name(Message::ExpectImportName)?;
+        self.consume_semicolon(Message::ExpectSemicolonAfterImport)?;
+        Ok(StmtKind::Import { namespace, name })
+    }
+
+    fn index(&mut self, object: Expr, can_assign: bool) -> Result<ExprKind> {
+        let object = Box::new(object);
+        let index = Box::new(self.expression()?);
+        self.consume(TokenType::RightBracket, Message::ExpectIndexEnd)?;
+        if can_assign && self.matches(TokenType::Equal)? {
+            let value = Box::new(self.expression()?);
+            return Ok(ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            });
+        }
+        Ok(ExprKind::Index { object, index })
+    }
+
+    // As in the compiler, splitting a `--` that isn't a decrement.
+    fn infix_operator(&mut self) -> Result<TokenType> {
+        if self.splits_decrement()? {
+            self.split_decrement();
+        }
+        Ok(self.current.ty())
+    }
+
+    fn interpolation(&mut self) -> Result<ExprKind> {
+        let mut parts = Vec::new();
+        loop {
+            let text = self.scanner.string_value(self.previous);
+            if !text.is_empty() {
+                parts.push(StringPart::Text(text));
+            }
+            if self.previous.ty() == TokenType::InterpolationEnd {
+                return Ok(ExprKind::Interpolation(parts));
+            }
+            parts.push(StringPart::Expr(self.expression()?));
+            if !self.matches(TokenType::InterpolationMiddle)? {
+                let msg = Message::ExpectInterpolationEnd;
+                self.consume(TokenType::InterpolationEnd, msg)?;
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<ExprKind> {
+        let mut items = Vec::new();
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                items.push(self.expression()?);
+                if items.len() > 255 {
+                    return Err(self.error(Message::TooManyListItems));
+                }
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+        let msg = Message::ExpectListEnd;
+        self.consume(TokenType::RightBracket, msg)?;
+        Ok(ExprKind::List(items))
+    }
+
+    fn loop_else(&mut self) -> Result<Option<Box<Stmt>>> {
+        match self.matches(TokenType::Else)? {
+            true => Ok(Some(Box::new(self.statement()?))),
+            false => Ok(None),
+        }
+    }
+
+    fn map(&mut self) -> Result<ExprKind> {
+        let mut entries = Vec::new();
+        if !self.check(TokenType::RightBrace) {
+            loop {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, Message::ExpectMapColon)?;
+                entries.push((key, self.expression()?));
+                if entries.len() > 255 {
+                    return Err(self.error(Message::TooManyMapEntries));
+                }
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+        let msg = Message::ExpectMapEnd;
+        self.consume(TokenType::RightBrace, msg)?;
+        Ok(ExprKind::Map(entries))
+    }
+
+    fn matches(&mut self, ty: TokenType) -> Result<bool> {
+        if !self.check(ty) {
+            return Ok(false);
+        }
+        self.advance()?;
+        Ok(true)
+    }
+
+    fn multiple_assignment(&mut self, first: String) -> Result<StmtKind> {
+        let mut names = vec![first];
+        while self.matches(TokenType::Comma)? {
+            names.push(self.name(Message::ExpectVariableName)?);
+        }
+        self.consume(TokenType::Equal, Message::ExpectAssignmentEquals)?;
+        let mut values = vec![self.expression()?];
+        while self.matches(TokenType::Comma)? {
+            values.push(self.expression()?);
+        }
+        if values.len() != names.len() {
+            return Err(self.error_with(
+                Message::AssignmentCount,
+                &[&names.len(), &values.len()],
+            ));
+        }
+        self.consume_semicolon(Message::ExpectSemicolonAfterAssignment)?;
+        Ok(StmtKind::MultipleAssign { names, values })
+    }
+
+    // Fails with `too_deep` rather than call `parse` past the nesting
+    // limit.
+    fn nested<T, F>(&mut self, too_deep: Message, parse: F) -> Result<T>
+    where
+        F: FnOnce(&mut AstParser) -> Result<T>,
+    {
+        if self.depth >= self.options.max_nesting {
+            return Err(self.error_at(self.current, too_deep));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Consumes an identifier, returning its text.
+    fn name(&mut self, msg: Message) -> Result<String> {
+        self.consume(TokenType::Identifier, msg)?;
+        Ok(self.text().to_string())
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<Expr> {
+        self.nested(Message::ExpressionTooDeep, |p| {
+            // `--` not before a variable is two minus signs, as in `--(1)`.
+            if p.check(TokenType::MinusMinus)
+                && p.peek_next()?.ty() != TokenType::Identifier
+            {
+                p.split_decrement();
+            }
+            p.advance()?;
+            let start = p.span(p.previous);
+            let can_assign = precedence <= Prec::Assignment;
+            let kind = match p.previous.ty() {
+                TokenType::LeftParen => {
+                    let expr = p.expression()?;
+                    p.consume(TokenType::RightParen, Message::ExpectGroupEnd)?;
+                    ExprKind::Grouping(Box::new(expr))
+                }
+                TokenType::Minus | TokenType::Bang => {
+                    let op = match p.previous.ty() {
+                        TokenType::Minus => UnaryOp::Negate,
+                        _ => UnaryOp::Not,
+                    };
+                    let operand = Box::new(p.parse_precedence(Prec::Unary)?);
+                    ExprKind::Unary { op, operand }
+                }
+                TokenType::PlusPlus | TokenType::MinusMinus => {
+                    let op = UpdateOp::for_token(p.previous.ty());
+                    if !p.check(TokenType::Identifier) {
+                        return Err(p.error_at(
+                            p.current,
+                            Message::InvalidIncrementTarget,
+                        ));
+                    }
+                    p.advance()?;
+                    if p.check(TokenType::LeftParen) {
+                        return Err(p.error_at(
+                            p.current,
+                            Message::InvalidIncrementTarget,
+                        ));
+                    }
+                    ExprKind::Update {
+                        name: p.text().to_string(),
+                        op,
+                        prefix: true,
+                    }
+                }
+                TokenType::Number => {
+                    let n = p.text().parse::<f64>().map_err(|_| {
+                        let msg = Message::InternalError;
+                        p.error_with(msg, &[&"bad number literal"])
+                    })?;
+                    ExprKind::Number(n)
+                }
+                TokenType::String => {
+                    ExprKind::String(p.scanner.string_value(p.previous))
+                }
+                TokenType::InterpolationStart => p.interpolation()?,
+                TokenType::LeftBracket => p.list()?,
+                TokenType::LeftBrace
+                    if p.options.dialect == Dialect::Extended =>
+                {
+                    p.map()?
+                }
+                TokenType::Nil => ExprKind::Nil,
+                TokenType::True => ExprKind::Bool(true),
+                TokenType::False => ExprKind::Bool(false),
+                TokenType::Identifier => {
+                    let name = p.text().to_string();
+                    if can_assign && p.matches(TokenType::Equal)? {
+                        let value = Box::new(p.expression()?);
+                        ExprKind::Assign { name, value }
+                    } else if is_postfix_increment(p.current, &p.options)
+                        && !p.splits_decrement()?
+                    {
+                        p.advance()?;
+                        let op = UpdateOp::for_token(p.previous.ty());
+                        ExprKind::Update {
+                            name,
+                            op,
+                            prefix: false,
+                        }
+                    } else {
+                        ExprKind::Variable(name)
+                    }
+                }
+                _ => return Err(p.error(Message::ExpectExpression)),
+            };
+            let mut expr = p.expr(kind, start);
+
+            while precedence <= Prec::for_op_type(p.infix_operator()?) {
+                p.advance()?;
+                let ty = p.previous.ty();
+                let kind = if let Some(op) = BinaryOp::for_token(ty) {
+                    // `**` is right-associative.
+                    let right = match op {
+                        BinaryOp::Power => p.parse_precedence(Prec::Power)?,
+                        _ => p.parse_precedence(Prec::for_op_type(ty) + 1)?,
+                    };
+                    ExprKind::Binary {
+                        op,
+                        left: Box::new(expr),
+                        right: Box::new(right),
+                    }
+                } else if ty == TokenType::LeftBracket {
+                    p.index(expr, can_assign)?
+                } else if ty == TokenType::LeftParen {
+                    let args = p.argument_list()?;
+                    ExprKind::Call {
+                        callee: Box::new(expr),
+                        args,
+                    }
+                } else {
+                    let op = match ty {
+                        TokenType::And => LogicalOp::And,
+                        _ => LogicalOp::Or,
+                    };
+                    let right = p.parse_precedence(Prec::for_op_type(ty))?;
+                    ExprKind::Logical {
+                        op,
+                        left: Box::new(expr),
+                        right: Box::new(right),
+                    }
+                };
+                expr = p.expr(kind, start);
+            }
+
+            if is_postfix_increment(p.current, &p.options) {
+                return Err(
+                    p.error_at(p.current, Message::InvalidIncrementTarget)
+                );
+            }
+            if can_assign && p.check(TokenType::Equal) {
+                p.advance()?;
+                return Err(p.error(Message::InvalidAssignmentTarget));
+            }
+            Ok(expr)
+        })
+    }
+
+    fn peek_next(&mut self) -> Result<Token> {
+        if let Some(&token) = self.lookahead.front() {
+            return Ok(token);
+        }
+        let token = self.scan()?;
+        self.lookahead.push_back(token);
+        Ok(token)
+    }
+
+    fn print_statement(&mut self) -> Result<StmtKind> {
+        let expr = if self.options.strict {
+            self.consume(TokenType::LeftParen, Message::ExpectPrintParen)?;
+            let expr = self.expression()?;
+            self.consume(TokenType::RightParen, Message::ExpectPrintEnd)?;
+            expr
+        } else {
+            self.expression()?
+        };
+        self.consume_semicolon(Message::ExpectSemicolonAfterValue)?;
+        Ok(StmtKind::Print(expr))
+    }
+
+    fn return_statement(&mut self) -> Result<StmtKind> {
+        if self.matches(TokenType::Semicolon)? || self.implicit_semicolon() {
+            return Ok(StmtKind::Return(None));
+        }
+        let expr = self.expression()?;
+        self.consume_semicolon(Message::ExpectSemicolonAfterReturn)?;
+        Ok(StmtKind::Return(Some(expr)))
+    }
+
+    fn scan(&mut self) -> Result<Token> {
+        self.scanner.scan_token().map_err(|e| {
+            // Through the catalog, if it's one of the crate's own.
+            let msg = match e.downcast_ref::<Diagnostic>() {
+                Some(diagnostic) => diagnostic.format(&self.options.messages),
+                None => e.to_string(),
+            };
+            anyhow!("[line {}] Error: {}", self.scanner.line(), msg)
+        })
+    }
+
+    fn span(&self, token: Token) -> Span {
+        Span {
+            start: token.start(),
+            end: token.end(),
+            line: token.line(),
+        }
+    }
+
+    fn split_decrement(&mut self) {
+        let (minus, negate) = self.current.split_minus();
+        self.current = minus;
+        self.lookahead.push_front(negate);
+    }
+
+    fn splits_decrement(&mut self) -> Result<bool> {
+        Ok(self.check(TokenType::MinusMinus)
+            && splits_decrement(self.current, self.peek_next()?, &self.options))
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        self.nested(Message::StatementTooDeep, |p| {
+            let start = p.span(p.current);
+            let kind = if p.matches(TokenType::Print)? {
+                p.print_statement()?
+            } else if p.matches(TokenType::For)? {
+                p.for_statement()?
+            } else if p.matches(TokenType::If)? {
+                p.if_statement()?
+            } else if p.matches(TokenType::Return)? {
+                p.return_statement()?
+            } else if p.matches(TokenType::While)? {
+                p.while_statement()?
+            } else if p.matches(TokenType::Break)? {
+                p.consume_semicolon(Message::ExpectSemicolonAfterBreak)?;
+                StmtKind::Break
+            } else if p.matches(TokenType::Continue)? {
+                p.consume_semicolon(Message::ExpectSemicolonAfterContinue)?;
+                StmtKind::Continue
+            } else if p.matches(TokenType::Switch)? {
+                p.switch_statement()?
+            } else if p.matches(TokenType::Defer)? {
+                return Err(p.error(Message::DeferAsBody));
+            } else if p.matches(TokenType::LeftBrace)? {
+                StmtKind::Block(p.block()?)
+            } else {
+                p.expression_statement()?
+            };
+            Ok(p.stmt(kind, start))
+        })
+    }
+
+    fn stmt(&self, kind: StmtKind, start: Span) -> Stmt {
+        Stmt {
+            kind,
+            span: start.to(self.span(self.previous)),
+        }
+    }
+
+    // Statements up to the one that ends with a ';', as the compiler reads
+    // a case body.
+    fn switch_case(&mut self) -> Result<Vec<Stmt>> {
+        let mut body = Vec::new();
+        while !self.check(TokenType::Semicolon) && !self.check(TokenType::Eof) {
+            body.push(self.statement()?);
+            if self.previous.ty() == TokenType::Semicolon
+                || (self.options.optional_semicolons && self.current.newline())
+            {
+                return Ok(body);
+            }
+        }
+        self.consume(TokenType::Semicolon, Message::ExpectCaseEnd)?;
+        Ok(body)
+    }
+
+    fn switch_statement(&mut self) -> Result<StmtKind> {
+        self.consume(TokenType::LeftParen, Message::ExpectSwitchParen)?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, Message::ExpectSwitchSubjectEnd)?;
+        self.consume(TokenType::LeftBrace, Message::ExpectSwitchBody)?;
+        let mut cases = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof)
+        {
+            let start = self.span(self.current);
+            let test = if self.matches(TokenType::Case)? {
+                Some(self.expression()?)
+            } else if self.matches(TokenType::Default)? {
+                None
+            } else {
+                return Err(self.error_at(self.current, Message::ExpectCase));
+            };
+            self.consume(TokenType::Colon, Message::ExpectCaseColon)?;
+            let body = self.switch_case()?;
+            let default = test.is_none();
+            cases.push(Case {
+                test,
+                body,
+                span: start.to(self.span(self.previous)),
+            });
+            if default && !self.check(TokenType::RightBrace) {
+                return Err(self.error(Message::DefaultNotLast));
+            }
+        }
+        self.consume(TokenType::RightBrace, Message::ExpectSwitchEnd)?;
+        Ok(StmtKind::Switch { subject, cases })
+    }
+
+    fn text(&self) -> &str {
+        self.scanner.token_text(self.previous)
+    }
+
+    fn var_declaration(&mut self) -> Result<StmtKind> {
+        let name = self.name(Message::ExpectVariableName)?;
+        self.var_initializer(name)
+    }
+
+    fn var_initializer(&mut self, name: String) -> Result<StmtKind> {
+        let init = match self.matches(TokenType::Equal)? {
+            true => Some(self.expression()?),
+            false => None,
+        };
+        self.consume_semicolon(Message::ExpectSemicolonAfterVariable)?;
+        Ok(StmtKind::Var { name, init })
+    }
+
+    fn while_statement(&mut self) -> Result<StmtKind> {
+        self.consume(TokenType::LeftParen, Message::ExpectWhileParen)?;
+        let cond = self.expression()?;
+        self.consume(TokenType::RightParen, Message::ExpectConditionEnd)?;
+        let body = Box::new(self.statement()?);
+        let else_ = self.loop_else()?;
+        Ok(StmtKind::While { cond, body, else_ })
+    }
+}
@@ -0,0 +1,379 @@
+use super::{Case, Expr, ExprKind, Function, Stmt, StmtKind, StringPart};
+
+const INDENT: &str = "    ";
+
+pub(super) fn stmts(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in stmts {
+        self::stmt(&mut out, stmt, 0);
+    }
+    out
+}
+
+fn indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str(INDENT);
+    }
+}
+
+fn stmt(out: &mut String, stmt: &Stmt, depth: usize) {
+    indent(out, depth);
+    inline_stmt(out, stmt, depth);
+    out.push('\n');
+}
+
+// Writes `stmt` from the current position, leaving off the final newline.
+fn inline_stmt(out: &mut String, stmt: &Stmt, depth: usize) {
+    match &stmt.kind {
+        StmtKind::Expression(e) => {
+            expr(out, e);
+            out.push(';');
+        }
+        StmtKind::Print(e) => {
+            out.push_str("print ");
+            expr(out, e);
+            out.push(';');
+        }
+        StmtKind::Var { name, init } => {
+            out.push_str("var ");
+            out.push_str(name);
+            if let Some(init) = init {
+                out.push_str(" = ");
+                expr(out, init);
+            }
+            out.push(';');
+        }
+        StmtKind::Fun(f) => function(out, f, depth),
+        StmtKind::Block(body) => block(out, body, depth),
+        StmtKind::MultipleAssign { names, values } => {
+            out.push_str(&names.join(", "));
+            out.push_str(" = ");
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                expr(out, value);
+            }
+            out.push(';');
+        }
+        StmtKind::If { cond, then, else_ } => {
+            out.push_str("if (");
+            expr(out, cond);
+            out.push(')');
+            let braced = body(out, then, depth);
+            else_clause(out, else_.as_deref(), braced, depth);
+        }
+        StmtKind::While {
+            cond,
+            body: loop_body,
+            else_,
+        } => {
+            out.push_str("while (");
+            expr(out, cond);
+            out.push(')');
+            let braced = body(out, loop_body, depth);
+            else_clause(out, else_.as_deref(), braced, depth);
+        }
+        StmtKind::For {
+            init,
+            cond,
+            incr,
+            body: loop_body,
+            else_,
+        } => {
+            out.push_str("for (");
+            match init {
+                Some(init) => inline_stmt(out, init, depth),
+                None => out.push(';'),
+            }
+            if let Some(cond) = cond {
+                out.push(' ');
+                expr(out, cond);
+            }
+            out.push(';');
+            if let Some(incr) = incr {
+                out.push(' ');
+                expr(out, incr);
+            }
+            out.push(')');
+            let braced = body(out, loop_body, depth);
+            else_clause(out, else_.as_deref(), braced, depth);
+        }
+        StmtKind::ForIn {
+            name,
+            sequence,
+            body: loop_body,
+            else_,
+        } => {
+            out.push_str(&format!("for (var {} in ", name));
+            expr(out, sequence);
+            out.push(')');
+            let braced = body(out, loop_body, depth);
+            else_clause(out, else_.as_deref(), braced, depth);
+        }
+        StmtKind::Return(value) => {
+            out.push_str("return");
+            if let Some(value) = value {
+                out.push(' ');
+                expr(out, value);
+            }
+            out.push(';');
+        }
+        StmtKind::Break => out.push_str("break;"),
+        StmtKind::Continue => out.push_str("continue;"),
+        StmtKind::Defer(deferred) => {
+            out.push_str("defer ");
+            inline_stmt(out, deferred, depth);
+        }
+        StmtKind::Export(exported) => {
+            out.push_str("export ");
+            inline_stmt(out, exported, depth);
+        }
+        StmtKind::Import { namespace, name } => {
+            out.push_str(&format!("import {}::{};", namespace, name));
+        }
+        StmtKind::Switch { subject, cases } => {
+            out.push_str("switch (");
+            expr(out, subject);
+            out.push_str(") {\n");
+            for c in cases {
+                case(out, c, depth);
+            }
+            indent(out, depth);
+            out.push('}');
+        }
+    }
+}
+
+// Writes the body of an if, else or loop: on the same line if it's a block,
+// and indented on the next line if not. Returns whether it was a block.
+fn body(out: &mut String, stmt: &Stmt, depth: usize) -> bool {
+    match &stmt.kind {
+        StmtKind::Block(stmts) => {
+            out.push(' ');
+            block(out, stmts, depth);
+            true
+        }
+        _ => {
+            out.push('\n');
+            indent(out, depth + 1);
+            inline_stmt(out, stmt, depth + 1);
+            false
+        }
+    }
+}
+
+// Writes the else clause of an if or loop, if it has one, after a body
+// written by `body`.
+fn else_clause(
+    out: &mut String,
+    else_: Option<&Stmt>,
+    braced: bool,
+    depth: usize,
+) {
+    let Some(else_) = else_ else {
+        return;
+    };
+    if braced {
+        out.push(' ');
+    } else {
+        out.push('\n');
+        indent(out, depth);
+    }
+    out.push_str("else");
+    match &else_.kind {
+        StmtKind::If { .. } => {
+            out.push(' ');
+            inline_stmt(out, else_, depth);
+        }
+        _ => {
+            body(out, else_, depth);
+        }
+    }
+}
+
+fn block(out: &mut String, stmts: &[Stmt], depth: usize) {
+    out.push_str("{\n");
+    for s in stmts {
+        stmt(out, s, depth + 1);
+    }
+    indent(out, depth);
+    out.push('}');
+}
+
+fn function(out: &mut String, f: &Function, depth: usize) {
+    if let Some(doc) = &f.doc {
+        for line in doc.split('\n') {
+            out.push_str("///");
+            if !line.is_empty() {
+                out.push(' ');
+                out.push_str(line);
+            }
+            out.push('\n');
+            indent(out, depth);
+        }
+    }
+    out.push_str("fun ");
+    out.push_str(&f.name);
+    out.push('(');
+    out.push_str(&f.params.join(", "));
+    out.push_str(") ");
+    block(out, &f.body, depth);
+}
+
+// A case body runs up to the first statement that ends with a ';', so one
+// that ends otherwise needs an empty statement after it.
+fn case(out: &mut String, case: &Case, depth: usize) {
+    indent(out, depth);
+    match &case.test {
+        Some(test) => {
+            out.push_str("case ");
+            expr(out, test);
+            out.push_str(":\n");
+        }
+        None => out.push_str("default:\n"),
+    }
+    for s in &case.body {
+        stmt(out, s, depth + 1);
+    }
+    if !out.trim_end().ends_with(';') {
+        indent(out, depth + 1);
+        out.push_str(";\n");
+    }
+}
+
+fn expr(out: &mut String, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Nil => out.push_str("nil"),
+        ExprKind::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        ExprKind::Number(n) => out.push_str(&n.to_string()),
+        ExprKind::String(s) => {
+            out.push('"');
+            out.push_str(&escaped(s));
+            out.push('"');
+        }
+        ExprKind::Variable(name) => out.push_str(name),
+        ExprKind::Assign { name, value } => {
+            out.push_str(name);
+            out.push_str(" = ");
+            self::expr(out, value);
+        }
+        ExprKind::Unary { op, operand } => {
+            out.push_str(op.as_str());
+            let start = out.len();
+            self::expr(out, operand);
+            // Keep "- -x" from running together.
+            if out[start..].starts_with(op.as_str()) {
+                out.insert(start, ' ');
+            }
+        }
+        ExprKind::Update { name, op, prefix } => {
+            if *prefix {
+                out.push_str(op.as_str());
+            }
+            out.push_str(name);
+            if !*prefix {
+                out.push_str(op.as_str());
+            }
+        }
+        ExprKind::Binary { op, left, right } => {
+            self::expr(out, left);
+            out.push(' ');
+            out.push_str(op.as_str());
+            out.push(' ');
+            self::expr(out, right);
+        }
+        ExprKind::Logical { op, left, right } => {
+            self::expr(out, left);
+            out.push(' ');
+            out.push_str(op.as_str());
+            out.push(' ');
+            self::expr(out, right);
+        }
+        ExprKind::Call { callee, args } => {
+            self::expr(out, callee);
+            out.push('(');
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                self::expr(out, arg);
+            }
+            out.push(')');
+        }
+        ExprKind::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                self::expr(out, item);
+            }
+            out.push(']');
+        }
+        ExprKind::Map(entries) => {
+            out.push('{');
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                self::expr(out, key);
+                out.push_str(": ");
+                self::expr(out, value);
+            }
+            out.push('}');
+        }
+        ExprKind::Index { object, index } => {
+            self::expr(out, object);
+            out.push('[');
+            self::expr(out, index);
+            out.push(']');
+        }
+        ExprKind::SetIndex {
+            object,
+            index,
+            value,
+        } => {
+            self::expr(out, object);
+            out.push('[');
+            self::expr(out, index);
+            out.push_str("] = ");
+            self::expr(out, value);
+        }
+        ExprKind::Interpolation(parts) => {
+            out.push('"');
+            for part in parts {
+                match part {
+                    StringPart::Text(text) => out.push_str(&escaped(text)),
+                    StringPart::Expr(e) => {
+                        out.push_str("${");
+                        self::expr(out, e);
+                        out.push('}');
+                    }
+                }
+            }
+            out.push('"');
+        }
+        ExprKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            self::expr(out, start);
+            out.push_str(if *inclusive { "..=" } else { ".." });
+            self::expr(out, end);
+        }
+        ExprKind::Grouping(inner) => {
+            out.push('(');
+            self::expr(out, inner);
+            out.push(')');
+        }
+    }
+}
+
+// `text` as it's written between quotes.
+fn escaped(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+}
@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Result};
+
+use super::{Case, Expr, ExprKind, Function, Span, Stmt, StmtKind, StringPart};
+use crate::{parser::scanner, VmOptions};
+
+/// Where each global variable and function is declared and used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolIndex {
+    pub symbols: BTreeMap<String, Symbol>,
+}
+
+/// The spans of one global's name, in source order. Globals can be
+/// declared more than once, and natives aren't declared at all.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbol {
+    pub definitions: Vec<Span>,
+    /// Reads and assignments.
+    pub references: Vec<Span>,
+}
+
+// A local variable, parameter or function.
+pub(super) struct Local {
+    pub(super) name: String,
+    // Where it is among the locals of the function it's in. Locals that are
+    // in scope at the same time have different slots.
+    pub(super) slot: usize,
+    pub(super) is_function: bool,
+    // Where it's declared, then where it's used.
+    pub(super) spans: Vec<Span>,
+}
+
+pub(super) fn symbols(source: &str, stmts: &[Stmt]) -> SymbolIndex {
+    index(source, stmts).0
+}
+
+// The globals, as for `symbols`, and the locals.
+pub(super) fn index(source: &str, stmts: &[Stmt]) -> (SymbolIndex, Vec<Local>) {
+    let mut indexer = Indexer {
+        text: source.as_bytes(),
+        scopes: Vec::new(),
+        index: SymbolIndex::default(),
+        locals: Vec::new(),
+    };
+    indexer.stmts(stmts);
+    for symbol in indexer.index.symbols.values_mut() {
+        symbol.references.sort_by_key(|span| span.start);
+    }
+    for local in &mut indexer.locals {
+        local.spans.sort_by_key(|span| span.start);
+    }
+    (indexer.index, indexer.locals)
+}
+
+pub(super) fn rename(
+    source: &str,
+    old: &str,
+    new: &str,
+    options: &VmOptions,
+) -> Result<String> {
+    let index = symbols(source, &super::parse(source.to_string(), options)?);
+    let Some(symbol) = index.symbols.get(old) else {
+        bail!("no global named '{}'", old);
+    };
+    if symbol.definitions.is_empty() {
+        bail!("'{}' isn't declared in this script", old);
+    }
+    let mut chars = new.chars();
+    let is_ident = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_ident || scanner::keywords(options.dialect).any(|k| k == new) {
+        bail!("'{}' isn't a valid name", new);
+    }
+    if index.symbols.contains_key(new) {
+        bail!("there's already a global named '{}'", new);
+    }
+
+    let mut spans: Vec<_> = symbol
+        .definitions
+        .iter()
+        .chain(&symbol.references)
+        .collect();
+    spans.sort_by_key(|span| span.start);
+    let mut renamed = String::new();
+    let mut last = 0;
+    for span in &spans {
+        renamed.push_str(&source[last..span.start]);
+        renamed.push_str(new);
+        last = span.end;
+    }
+    renamed.push_str(&source[last..]);
+
+    // A local named `new` would hide the global from some of the renamed
+    // references, so check that they all still refer to it.
+    let index = symbols(&renamed, &super::parse(renamed.clone(), options)?);
+    let count =
+        |symbol: &Symbol| symbol.definitions.len() + symbol.references.len();
+    if index.symbols.get(new).map_or(0, count) != spans.len() {
+        bail!("a local named '{}' would hide the renamed global", new);
+    }
+    Ok(renamed)
+}
+
+struct Indexer<'a> {
+    text: &'a [u8],
+    // The locals in each block around the node being indexed, in the
+    // function it is in, as indexes into `locals`. As in the compiler, a
+    // function can't see the locals of the one it's declared in.
+    scopes: Vec<HashMap<String, usize>>,
+    index: SymbolIndex,
+    locals: Vec<Local>,
+}
+
+impl Indexer<'_> {
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        self.stmts(stmts);
+        self.scopes.pop();
+    }
+
+    // Adds `name` to the innermost scope, or the index at the top level.
+    fn declare(&mut self, name: &str, span: Span, is_function: bool) {
+        if self.scopes.is_empty() {
+            self.symbol(name).definitions.push(span);
+            return;
+        }
+        let slot = self.scopes.iter().map(HashMap::len).sum();
+        let id = self.locals.len();
+        self.locals.push(Local {
+            name: name.to_string(),
+            slot,
+            is_function,
+            spans: vec![span],
+        });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), id);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Nil
+            | ExprKind::Bool(_)
+            | ExprKind::Number(_)
+            | ExprKind::String(_) => (),
+            ExprKind::Variable(name) => self.reference(name, expr.span),
+            ExprKind::Assign { name, value } => {
+                self.expr(value);
+                let span = self.name_at(expr.span.start, expr.span.line);
+                self.reference(name, span);
+            }
+            ExprKind::Update { name, prefix, .. } => {
+                let at = expr.span.start + if *prefix { 2 } else { 0 };
+                let span = self.name_at(at, expr.span.line);
+                self.reference(name, span);
+            }
+            ExprKind::Unary { operand, .. } => self.expr(operand),
+            ExprKind::Binary { left, right, .. }
+            | ExprKind::Logical { left, right, .. }
+            | ExprKind::Range {
+                start: left,
+                end: right,
+                ..
+            }
+            | ExprKind::Index {
+                object: left,
+                index: right,
+            } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            ExprKind::Grouping(inner) => self.expr(inner),
+            ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    if let StringPart::Expr(e) = part {
+                        self.expr(e);
+                    }
+                }
+            }
+        }
+    }
+
+    // `name` is where the function's name is.
+    fn function(&mut self, f: &Function, name: Span) {
+        let scopes = std::mem::take(&mut self.scopes);
+        self.scopes.push(HashMap::new());
+        // The parameters are between the parentheses after the name,
+        // separated by commas.
+        let paren = self.name_at(name.end, name.line);
+        let (mut at, mut line) = (paren.start + 1, paren.line);
+        for param in &f.params {
+            let span = self.name_at(at, line);
+            self.declare(param, span, false);
+            let comma = self.name_at(span.end, span.line);
+            (at, line) = (comma.start + 1, comma.line);
+        }
+        self.stmts(&f.body);
+        self.scopes = scopes;
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    // The span of the name that starts at `start`, or after it once any
+    // whitespace and comments are skipped.
+    fn name_at(&self, mut start: usize, mut line: u32) -> Span {
+        while start < self.text.len() {
+            match self.text[start] {
+                b'\n' => line += 1,
+                b' ' | b'\r' | b'\t' => (),
+                b'/' if self.text.get(start + 1) == Some(&b'/') => {
+                    while self.text.get(start + 1).is_some_and(|&c| c != b'\n')
+                    {
+                        start += 1;
+                    }
+                }
+                _ => break,
+            }
+            start += 1;
+        }
+        let len = self.text[start..]
+            .iter()
+            .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+            .count();
+        Span {
+            start,
+            end: start + len,
+            line,
+        }
+    }
+
+    fn reference(&mut self, name: &str, span: Span) {
+        match self.local(name) {
+            Some(id) => self.locals[id].spans.push(span),
+            None => self.symbol(name).references.push(span),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let span = stmt.span;
+        match &stmt.kind {
+            StmtKind::Expression(e) | StmtKind::Print(e) => self.expr(e),
+            StmtKind::Var { name, init } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                let span = self.name_at(span.start + "var".len(), span.line);
+                self.declare(name, span, false);
+            }
+            StmtKind::Fun(f) => {
+                let span = self.name_at(span.start + "fun".len(), span.line);
+                self.declare(&f.name, span, true);
+                self.function(f, span);
+            }
+            StmtKind::Block(stmts) => self.block(stmts),
+            StmtKind::MultipleAssign { names, values } => {
+                for value in values {
+                    self.expr(value);
+                }
+                // The names come first, separated by commas.
+                let (mut at, mut line) = (span.start, span.line);
+                for name in names {
+                    let span = self.name_at(at, line);
+                    self.reference(name, span);
+                    let comma = self.name_at(span.end, span.line);
+                    (at, line) = (comma.start + 1, comma.line);
+                }
+            }
+            StmtKind::If { cond, then, else_ }
+            | StmtKind::While {
+                cond,
+                body: then,
+                else_,
+            } => {
+                self.expr(cond);
+                self.stmt(then);
+                if let Some(else_) = else_ {
+                    self.stmt(else_);
+                }
+            }
+            StmtKind::For {
+                init,
+                cond,
+                incr,
+                body,
+                else_,
+            } => {
+                self.scopes.push(HashMap::new());
+                if let Some(init) = init {
+                    self.stmt(init);
+                }
+                for e in cond.iter().chain(incr) {
+                    self.expr(e);
+                }
+                self.stmt(body);
+                if let Some(else_) = else_ {
+                    self.stmt(else_);
+                }
+                self.scopes.pop();
+            }
+            StmtKind::ForIn {
+                name,
+                sequence,
+                body,
+                else_,
+            } => {
+                self.expr(sequence);
+                // `for`, then `(`, then `var`, then the name.
+                let paren = self.name_at(span.start + "for".len(), span.line);
+                let var = self.name_at(paren.start + 1, paren.line);
+                let span = self.name_at(var.end, var.line);
+                self.scopes.push(HashMap::new());
+                self.declare(name, span, false);
+                self.stmt(body);
+                self.scopes.pop();
+                if let Some(else_) = else_ {
+                    self.stmt(else_);
+                }
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            StmtKind::Break | StmtKind::Continue => (),
+            StmtKind::Defer(inner) | StmtKind::Export(inner) => {
+                self.stmt(inner)
+            }
+            // An imported name is declared by another script, not this one.
+            StmtKind::Import { .. } => (),
+            StmtKind::Switch { subject, cases } => {
+                self.expr(subject);
+                for Case { test, body, .. } in cases {
+                    if let Some(test) = test {
+                        self.expr(test);
+                    }
+                    self.stmts(body);
+                }
+            }
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn symbol(&mut self, name: &str) -> &mut Symbol {
+        self.index.symbols.entry(name.to_string()).or_default()
+    }
+}
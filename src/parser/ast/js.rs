@@ -0,0 +1,625 @@
+use std::{fmt::Write, mem};
+
+use super::{
+    BinaryOp, Case, Expr, ExprKind, LogicalOp, Stmt, StmtKind, StringPart,
+    UnaryOp, UpdateOp,
+};
+
+// Helpers the emitted code calls for the places where Lox and JavaScript
+// disagree: truthiness, operand type checks, arity checks, list bounds and
+// printing.
+const PRELUDE: &str = r#""use strict";
+const $truthy = (v) => v !== null && v !== false;
+const $error = (msg) => { throw new Error(msg); };
+const $num = (v) =>
+  typeof v === "number" ? v : $error("operands must be numbers");
+const $neg = (v) =>
+  typeof v === "number" ? -v : $error("operand must be a number");
+const $operand = (v) =>
+  typeof v === "number" ? v : $error("operand must be a number");
+const $add = (a, b) =>
+  (typeof a === "number" && typeof b === "number") ||
+  (typeof a === "string" && typeof b === "string")
+    ? a + b
+    : $error("operands must be numbers or strings");
+const $call = (f, ...args) => {
+  if (typeof f !== "function") $error("can only call functions or classes");
+  if (f.length !== args.length) {
+    $error(`expected ${f.length} arguments but got ${args.length}`);
+  }
+  return f(...args);
+};
+const $index = (list, i) => {
+  if (!Array.isArray(list)) $error("can only index lists and maps");
+  if (typeof i !== "number") $error("index must be a number");
+  if (!Number.isInteger(i)) $error("index must be a whole number");
+  if (i < 0 || i >= list.length) {
+    $error(`index ${i} is out of bounds for a list of length ${list.length}`);
+  }
+  return i;
+};
+const $key = (k) => {
+  if (typeof k === "string") return k;
+  if (typeof k !== "number") $error("map keys must be strings or numbers");
+  return Number.isNaN(k) ? $error("map key can't be NaN") : k;
+};
+const $map = (...pairs) => {
+  const map = new Map();
+  for (let i = 0; i < pairs.length; i += 2) map.set($key(pairs[i]), pairs[i + 1]);
+  return map;
+};
+const $get = (o, i) =>
+  o instanceof Map ? o.get($key(i)) ?? null : o[$index(o, i)];
+const $set = (o, i, v) =>
+  o instanceof Map ? (o.set($key(i), v), v) : (o[$index(o, i)] = v);
+const $iter = (s) =>
+  Array.isArray(s) ? s
+  : s instanceof Map ? s.keys()
+  : $error("can only loop over lists and maps");
+const $range = function* (start, end, inclusive) {
+  if (typeof start !== "number" || typeof end !== "number") {
+    $error("range endpoints must be numbers");
+  }
+  for (let i = start; inclusive ? i <= end : i < end; i++) yield i;
+};
+const $type = (v) =>
+  v === null ? "nil"
+  : Array.isArray(v) ? "list"
+  : v instanceof Map ? "map"
+  : typeof v;
+const $arg = (name, i, v, type) =>
+  $type(v) === type ? v
+  : $error(`argument ${i} to '${name}' must be a ${type}, got ${$type(v)}`);
+const $showing = new Set();
+const $str = (v) => {
+  if (v === null) return "nil";
+  if (Array.isArray(v)) {
+    if ($showing.has(v)) return "[...]";
+    $showing.add(v);
+    try {
+      return `[${v.map($str).join(", ")}]`;
+    } finally {
+      $showing.delete(v);
+    }
+  }
+  if (v instanceof Map) {
+    if ($showing.has(v)) return "{...}";
+    $showing.add(v);
+    try {
+      const entries = [...v].map(([k, x]) => `${$str(k)}: ${$str(x)}`);
+      return `{${entries.join(", ")}}`;
+    } finally {
+      $showing.delete(v);
+    }
+  }
+  if (typeof v === "function") {
+    return v.$native ? "<native fn>" : `<fn ${v.name}>`;
+  }
+  if (typeof v === "number") {
+    if (Object.is(v, -0)) return "-0";
+    if (v === Infinity) return "inf";
+    if (v === -Infinity) return "-inf";
+  }
+  return String(v);
+};
+// With var, so that scripts can redefine them, as in Lox.
+var keys = (m) =>
+  m instanceof Map ? [...m.keys()] : $error("argument must be a map");
+var remove = (m, k) => {
+  if (!(m instanceof Map)) $error("argument must be a map");
+  const v = m.get($key(k)) ?? null;
+  m.delete(k);
+  return v;
+};
+var len = (v) =>
+  typeof v === "string" ? [...v].length
+  : Array.isArray(v) ? v.length
+  : v instanceof Map ? v.size
+  : $error("argument must be a string, list or map");
+var substr = (s, start, end) => {
+  const chars = [...$arg("substr", 1, s, "string")];
+  $arg("substr", 2, start, "number");
+  $arg("substr", 3, end, "number");
+  for (const n of [start, end]) {
+    if (!Number.isInteger(n)) $error("position must be a whole number");
+    if (n < 0 || n > chars.length) {
+      $error(`position ${n} is out of bounds for a string of length ${chars.length}`);
+    }
+  }
+  if (start > end) $error(`substring start ${start} is after its end ${end}`);
+  return chars.slice(start, end).join("");
+};
+var indexOf = (s, part) => {
+  const text = $arg("indexOf", 1, s, "string");
+  const at = text.indexOf($arg("indexOf", 2, part, "string"));
+  return at < 0 ? null : [...text.slice(0, at)].length;
+};
+var split = (s, sep) => {
+  const text = $arg("split", 1, s, "string");
+  const by = $arg("split", 2, sep, "string");
+  return by === "" ? [...text] : text.split(by);
+};
+keys.$native = remove.$native = true;
+len.$native = substr.$native = indexOf.$native = split.$native = true;
+const clock = () => performance.now() / 1000;
+clock.$native = true;
+"#;
+
+// Identifiers that Lox allows but JavaScript reserves or predefines.
+const RESERVED: &[&str] = &[
+    "arguments",
+    "await",
+    "catch",
+    "const",
+    "debugger",
+    "delete",
+    "do",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "finally",
+    "function",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "static",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "void",
+    "with",
+    "yield",
+    "Infinity",
+    "NaN",
+    "Array",
+    "Map",
+    "Number",
+    "Object",
+    "Set",
+    "String",
+    "Error",
+    "performance",
+];
+
+struct Emitter {
+    out: String,
+    indent: usize,
+    // The nesting depth of the switch statement being emitted, for naming
+    // the variable that holds its subject.
+    switches: usize,
+    // Likewise for defer statements, naming the flag that says whether the
+    // statements after one finished without an error.
+    defers: usize,
+    // For each loop around the statement being emitted, in the function it
+    // is in: the label of the block around the loop, if it has an else
+    // clause. Breaks leave that block, so as to skip the else.
+    loops: Vec<Option<String>>,
+}
+
+/// The statements as a standalone JavaScript program, which prints what the
+/// Lox script would. Runtime errors are thrown as `Error`s without a line
+/// number. Numbers print as JavaScript formats them, so very large and very
+/// small ones come out in exponent form where Lox would write out every
+/// digit.
+pub fn to_js(stmts: &[Stmt]) -> String {
+    let mut emitter = Emitter {
+        out: PRELUDE.to_string(),
+        indent: 0,
+        switches: 0,
+        defers: 0,
+        loops: Vec::new(),
+    };
+    emitter.stmts(stmts, true);
+    emitter.out
+}
+
+fn name(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}$", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\u{2028}' | '\u{2029}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Emitter {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // Emits `body` inside braces, after `head`.
+    fn braced(&mut self, head: &str, body: &[Stmt], tail: Option<&str>) {
+        match head {
+            "" => self.line("{"),
+            head => self.line(&format!("{} {{", head)),
+        }
+        self.indent += 1;
+        self.stmts(body, false);
+        if let Some(tail) = tail {
+            self.line(tail);
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    // Emits `stmt` as the body of a statement headed by `head`.
+    fn nested(&mut self, head: &str, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Block(body) => self.braced(head, body, None),
+            _ => self.braced(head, std::slice::from_ref(stmt), None),
+        }
+    }
+
+    // Emits a list of statements. Those after a defer go in a try block, so
+    // that it runs however they're left.
+    fn stmts(&mut self, stmts: &[Stmt], top_level: bool) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            if let StmtKind::Defer(deferred) = &stmt.kind {
+                self.defer(deferred, &stmts[i + 1..], top_level);
+                return;
+            }
+            self.stmt(stmt, top_level);
+        }
+    }
+
+    // A runtime error ends a Lox script without running deferred
+    // statements, so the finally block skips them after a throw.
+    fn defer(&mut self, deferred: &Stmt, rest: &[Stmt], top_level: bool) {
+        let var = format!("$defer{}", self.defers);
+        self.defers += 1;
+        self.line(&format!("let {} = true;", var));
+        self.line("try {");
+        self.indent += 1;
+        self.stmts(rest, top_level);
+        self.indent -= 1;
+        self.line("} catch ($e) {");
+        self.indent += 1;
+        self.line(&format!("{} = false;", var));
+        self.line("throw $e;");
+        self.indent -= 1;
+        self.line("} finally {");
+        self.indent += 1;
+        self.nested(&format!("if ({})", var), deferred);
+        self.indent -= 1;
+        self.line("}");
+        self.defers -= 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, top_level: bool) {
+        match &stmt.kind {
+            StmtKind::Expression(e) => {
+                let e = self.expr(e);
+                self.line(&format!("{};", e));
+            }
+            StmtKind::Print(e) => {
+                let e = self.expr(e);
+                self.line(&format!("console.log($str({}));", e));
+            }
+            StmtKind::Var { name: var, init } => {
+                let init = match init {
+                    Some(e) => self.expr(e),
+                    None => "null".to_string(),
+                };
+                // Lox lets globals be redeclared, which `let` doesn't.
+                let keyword = if top_level { "var" } else { "let" };
+                self.line(&format!("{} {} = {};", keyword, name(var), init));
+            }
+            StmtKind::Fun(f) => {
+                let params: Vec<_> = f.params.iter().map(|p| name(p)).collect();
+                let head = format!(
+                    "function {}({})",
+                    name(&f.name),
+                    params.join(", ")
+                );
+                let loops = mem::take(&mut self.loops);
+                self.braced(&head, &f.body, Some("return null;"));
+                self.loops = loops;
+            }
+            StmtKind::Block(body) => self.braced("", body, None),
+            StmtKind::MultipleAssign { names, values } => {
+                let names: Vec<_> = names.iter().map(|n| name(n)).collect();
+                let values: Vec<_> =
+                    values.iter().map(|e| self.expr(e)).collect();
+                self.line(&format!(
+                    "[{}] = [{}];",
+                    names.join(", "),
+                    values.join(", ")
+                ));
+            }
+            StmtKind::If { cond, then, else_ } => {
+                let cond = self.expr(cond);
+                self.nested(&format!("if ($truthy({}))", cond), then);
+                if let Some(else_) = else_ {
+                    self.nested("else", else_);
+                }
+            }
+            StmtKind::While { cond, body, else_ } => {
+                let cond = self.expr(cond);
+                let label = self.loop_label(else_.as_deref());
+                if let Some(label) = &label {
+                    self.line(&format!("{}: {{", label));
+                    self.indent += 1;
+                }
+                let head = format!("while ($truthy({}))", cond);
+                self.loop_body(&head, body, label);
+                if let Some(else_) = else_ {
+                    self.stmt(else_, false);
+                    self.indent -= 1;
+                    self.line("}");
+                }
+            }
+            StmtKind::For {
+                init,
+                cond,
+                incr,
+                body,
+                else_,
+            } => {
+                // The initializer gets its own scope, as in Lox.
+                let label = self.loop_label(else_.as_deref());
+                match &label {
+                    Some(label) => self.line(&format!("{}: {{", label)),
+                    None => self.line("{"),
+                }
+                self.indent += 1;
+                if let Some(init) = init {
+                    self.stmt(init, false);
+                }
+                let cond = match cond {
+                    Some(e) => format!("$truthy({})", self.expr(e)),
+                    None => String::new(),
+                };
+                let incr =
+                    incr.as_ref().map_or(String::new(), |e| self.expr(e));
+                let head = format!("for (; {}; {})", cond, incr);
+                self.loop_body(&head, body, label);
+                if let Some(else_) = else_ {
+                    self.stmt(else_, false);
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+            StmtKind::ForIn {
+                name: var,
+                sequence,
+                body,
+                else_,
+            } => {
+                let label = self.loop_label(else_.as_deref());
+                if let Some(label) = &label {
+                    self.line(&format!("{}: {{", label));
+                    self.indent += 1;
+                }
+                // A range is already something to loop over.
+                let sequence = match sequence.kind {
+                    ExprKind::Range { .. } => self.expr(sequence),
+                    _ => format!("$iter({})", self.expr(sequence)),
+                };
+                let head = format!("for (let {} of {})", name(var), sequence);
+                self.loop_body(&head, body, label);
+                if let Some(else_) = else_ {
+                    self.stmt(else_, false);
+                    self.indent -= 1;
+                    self.line("}");
+                }
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(e) => self.expr(e),
+                    None => "null".to_string(),
+                };
+                self.line(&format!("return {};", value));
+            }
+            StmtKind::Break => match self.loops.last() {
+                Some(Some(label)) => {
+                    let line = format!("break {};", label);
+                    self.line(&line);
+                }
+                _ => self.line("break;"),
+            },
+            StmtKind::Continue => self.line("continue;"),
+            StmtKind::Defer(deferred) => self.defer(deferred, &[], top_level),
+            StmtKind::Export(exported) => self.stmt(exported, top_level),
+            // There are no namespaces once compiled to JavaScript.
+            StmtKind::Import { namespace, name } => {
+                self.line(&format!("// import {}::{}", namespace, name))
+            }
+            StmtKind::Switch { subject, cases } => self.switch(subject, cases),
+        }
+    }
+
+    fn loop_label(&self, else_: Option<&Stmt>) -> Option<String> {
+        else_.map(|_| format!("$loop{}", self.loops.len()))
+    }
+
+    fn loop_body(&mut self, head: &str, body: &Stmt, label: Option<String>) {
+        self.loops.push(label);
+        self.nested(head, body);
+        self.loops.pop();
+    }
+
+    // A switch becomes an if/else chain, since a `break` in a Lox case
+    // leaves the enclosing loop rather than the switch.
+    fn switch(&mut self, subject: &Expr, cases: &[Case]) {
+        let subject = self.expr(subject);
+        let var = format!("$switch{}", self.switches);
+        self.switches += 1;
+        self.line("{");
+        self.indent += 1;
+        self.line(&format!("const {} = {};", var, subject));
+        for (i, case) in cases.iter().enumerate() {
+            let keyword = if i == 0 { "if" } else { "else if" };
+            let head = match &case.test {
+                Some(test) => {
+                    let test = self.expr(test);
+                    format!("{} ({} === {})", keyword, var, test)
+                }
+                None if i == 0 => "if (true)".to_string(),
+                None => "else".to_string(),
+            };
+            self.braced(&head, &case.body, None);
+        }
+        self.indent -= 1;
+        self.line("}");
+        self.switches -= 1;
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Nil => "null".to_string(),
+            ExprKind::Bool(b) => b.to_string(),
+            ExprKind::Number(n) => format!("{:?}", n),
+            ExprKind::String(s) => string(s),
+            ExprKind::Variable(var) => name(var),
+            ExprKind::Assign { name: var, value } => {
+                format!("({} = {})", name(var), self.expr(value))
+            }
+            ExprKind::Unary { op, operand } => {
+                let operand = self.expr(operand);
+                match op {
+                    UnaryOp::Negate => format!("$neg({})", operand),
+                    UnaryOp::Not => format!("!$truthy({})", operand),
+                }
+            }
+            ExprKind::Update {
+                name: var,
+                op,
+                prefix,
+            } => {
+                let var = name(var);
+                let update = |old: &str| match op {
+                    UpdateOp::Increment => {
+                        format!("{} = $operand({}) + 1", var, old)
+                    }
+                    UpdateOp::Decrement => {
+                        format!("{} = $operand({}) - 1", var, old)
+                    }
+                };
+                match prefix {
+                    true => format!("({})", update(&var)),
+                    // The old value, once the variable is updated.
+                    false => {
+                        format!("(($v) => ({}, $v))({})", update("$v"), var)
+                    }
+                }
+            }
+            ExprKind::Binary { op, left, right } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                match op {
+                    BinaryOp::Add => format!("$add({}, {})", left, right),
+                    BinaryOp::Equal => format!("({} === {})", left, right),
+                    BinaryOp::NotEqual => format!("({} !== {})", left, right),
+                    op => format!(
+                        "($num({}) {} $num({}))",
+                        left,
+                        op.as_str(),
+                        right
+                    ),
+                }
+            }
+            ExprKind::Logical { op, left, right } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                // Lox's and/or yield an operand, so the left one is
+                // evaluated once and kept.
+                let test = match op {
+                    LogicalOp::And => "$truthy($l)",
+                    LogicalOp::Or => "!$truthy($l)",
+                };
+                format!("(($l) => {} ? {} : $l)({})", test, right, left)
+            }
+            ExprKind::Call { callee, args } => {
+                let mut call = format!("$call({}", self.expr(callee));
+                for arg in args {
+                    call.push_str(", ");
+                    call.push_str(&self.expr(arg));
+                }
+                call.push(')');
+                call
+            }
+            ExprKind::List(items) => {
+                let items: Vec<_> =
+                    items.iter().map(|item| self.expr(item)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            ExprKind::Map(entries) => {
+                let mut args = Vec::new();
+                for (key, value) in entries {
+                    args.push(self.expr(key));
+                    args.push(self.expr(value));
+                }
+                format!("$map({})", args.join(", "))
+            }
+            ExprKind::Index { object, index } => {
+                format!("$get({}, {})", self.expr(object), self.expr(index))
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => format!(
+                "$set({}, {}, {})",
+                self.expr(object),
+                self.expr(index),
+                self.expr(value)
+            ),
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => format!(
+                "$range({}, {}, {})",
+                self.expr(start),
+                self.expr(end),
+                inclusive
+            ),
+            ExprKind::Grouping(inner) => self.expr(inner),
+            ExprKind::Interpolation(parts) => {
+                let parts: Vec<_> = parts
+                    .iter()
+                    .map(|part| match part {
+                        StringPart::Text(text) => string(text),
+                        StringPart::Expr(e) => {
+                            format!("$str({})", self.expr(e))
+                        }
+                    })
+                    .collect();
+                format!("({})", parts.join(" + "))
+            }
+        }
+    }
+}
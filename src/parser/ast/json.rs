@@ -0,0 +1,42 @@
+// How the tree is serialized where deriving `Serialize` isn't enough. Each
+// node is an object with its kind as "type", as serde writes internally
+// tagged enums; these give a field name to the value of each tuple variant,
+// as in `{"type": "Print", "expr": ...}`, and write operators as they
+// appear in the source.
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use super::{BinaryOp, LogicalOp, UnaryOp, UpdateOp};
+
+macro_rules! fields {
+    ($($name:ident),*) => {$(
+        pub(super) fn $name<T, S>(value: &T, s: S) -> Result<S::Ok, S::Error>
+        where
+            T: Serialize,
+            S: Serializer,
+        {
+            let mut map = s.serialize_map(Some(1))?;
+            map.serialize_entry(stringify!($name), value)?;
+            map.end()
+        }
+    )*};
+}
+
+fields!(body, entries, expr, items, name, parts, stmt, value);
+
+// `nil`, which has no value of its own to write.
+pub(super) fn null_value<S: Serializer>(s: S) -> Result<S::Ok, S::Error> {
+    value(&(), s)
+}
+
+macro_rules! operators {
+    ($($op:ty),*) => {$(
+        impl Serialize for $op {
+            fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.serialize_str(self.as_str())
+            }
+        }
+    )*};
+}
+
+operators!(UnaryOp, UpdateOp, BinaryOp, LogicalOp);
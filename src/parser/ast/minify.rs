@@ -0,0 +1,120 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+
+use super::index;
+use crate::{
+    parser::scanner::{self, Scanner, TokenType},
+    VmOptions,
+};
+
+pub(super) fn minify(
+    source: &str,
+    options: &VmOptions,
+    rename_locals: bool,
+) -> Result<String> {
+    let renamed = match rename_locals {
+        true => short_names(source, options)?,
+        false => HashMap::new(),
+    };
+
+    let mut scanner = Scanner::new(source.to_string());
+    scanner.set_dialect(options.dialect);
+    let mut out = String::new();
+    loop {
+        let token = scanner
+            .scan_token()
+            .map_err(|e| anyhow!("[line {}] Error: {}", scanner.line(), e))?;
+        if token.ty() == TokenType::Eof {
+            break;
+        }
+        // `doc()` reads doc comments, so those stay.
+        let doc = match token.ty() {
+            TokenType::Fun => scanner.doc(token),
+            _ => None,
+        };
+        if let Some(doc) = doc {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            for line in doc.split('\n') {
+                out.push_str("///");
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        let text = match renamed.get(&token.start()) {
+            Some(name) => name.as_str(),
+            None => scanner.token_text(token),
+        };
+        if options.optional_semicolons && token.newline() && !out.is_empty() {
+            out.push('\n');
+        } else if out.chars().last().is_some_and(|last| {
+            text.chars().next().is_some_and(|next| merges(last, next))
+        }) {
+            out.push(' ');
+        }
+        out.push_str(text);
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+// Whether two tokens would scan differently with nothing between them.
+fn merges(last: char, next: char) -> bool {
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    (word(last) && word(next))
+        || (last == '/' && next == '/')
+        || ("+-".contains(last) && next == last)
+        || ("=!<>".contains(last) && next == '=')
+}
+
+// The new name for each local, by where its name is in `source`. Locals
+// are named for their slots, skipping keywords and anything that would
+// clash with a global or a local function, whose names are kept since
+// they show up when printing the function.
+fn short_names(
+    source: &str,
+    options: &VmOptions,
+) -> Result<HashMap<usize, String>> {
+    let stmts = super::parse(source.to_string(), options)?;
+    let (globals, locals) = index::index(source, &stmts);
+    let mut taken: HashSet<&str> =
+        globals.symbols.keys().map(String::as_str).collect();
+    for keyword in scanner::keywords(options.dialect) {
+        taken.insert(keyword);
+    }
+    for local in locals.iter().filter(|local| local.is_function) {
+        taken.insert(&local.name);
+    }
+
+    let slots = locals.iter().map(|local| local.slot + 1).max().unwrap_or(0);
+    let names: Vec<String> = (0..)
+        .map(short_name)
+        .filter(|name| !taken.contains(name.as_str()))
+        .take(slots)
+        .collect();
+    let mut renamed = HashMap::new();
+    for local in locals.iter().filter(|local| !local.is_function) {
+        for span in &local.spans {
+            renamed.insert(span.start, names[local.slot].clone());
+        }
+    }
+    Ok(renamed)
+}
+
+// "a" to "z", then "aa", "ab" and so on.
+fn short_name(mut n: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'a' + (n % 26) as u8);
+        if n < 26 {
+            break;
+        }
+        n = n / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
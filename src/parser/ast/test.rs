@@ -0,0 +1,706 @@
+use std::{fs, path::PathBuf, sync::Arc};
+
+use super::{
+    parse, to_json, BinaryOp, Expr, ExprKind, LogicalOp, Span, Stmt, StmtKind,
+    StringPart, UpdateOp,
+};
+use crate::{
+    message::{Catalog, Message},
+    VmOptions,
+};
+
+fn stmts(source: &str) -> Vec<Stmt> {
+    parse(source.to_string(), &VmOptions::default()).unwrap()
+}
+
+fn error(source: &str) -> String {
+    parse(source.to_string(), &VmOptions::default())
+        .unwrap_err()
+        .to_string()
+}
+
+fn expr(source: &str) -> Expr {
+    match stmts(source).remove(0).kind {
+        StmtKind::Expression(expr) => expr,
+        _ => panic!("not an expression statement"),
+    }
+}
+
+#[test]
+fn precedence() {
+    let e = expr("1 + 2 * 3 == x or y and !z;");
+    let ExprKind::Logical { op, left, right } = e.kind else {
+        panic!("expected or");
+    };
+    assert_eq!(op, LogicalOp::Or);
+    assert!(matches!(right.kind, ExprKind::Logical { .. }));
+    let ExprKind::Binary { op, left, .. } = left.kind else {
+        panic!("expected ==");
+    };
+    assert_eq!(op, BinaryOp::Equal);
+    let ExprKind::Binary { op, right, .. } = left.kind else {
+        panic!("expected +");
+    };
+    assert_eq!(op, BinaryOp::Add);
+    assert!(matches!(
+        right.kind,
+        ExprKind::Binary {
+            op: BinaryOp::Multiply,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn left_associative() {
+    let e = expr("1 - 2 - 3;");
+    let ExprKind::Binary { left, right, .. } = e.kind else {
+        panic!("expected -");
+    };
+    assert!(matches!(left.kind, ExprKind::Binary { .. }));
+    assert_eq!(right.kind, ExprKind::Number(3.0));
+}
+
+#[test]
+fn spans() {
+    let source = "var a = 1;\nprint f(a,\n  2);";
+    let stmts = stmts(source);
+    assert_eq!(
+        stmts[0].span,
+        Span {
+            start: 0,
+            end: 10,
+            line: 1
+        }
+    );
+    assert_eq!(
+        &source[stmts[1].span.start..stmts[1].span.end],
+        "print f(a,\n  2);"
+    );
+    assert_eq!(stmts[1].span.line, 2);
+    let StmtKind::Print(call) = &stmts[1].kind else {
+        panic!("expected print");
+    };
+    assert_eq!(&source[call.span.start..call.span.end], "f(a,\n  2)");
+}
+
+#[test]
+fn statements() {
+    let source = r#"
+    /// Counts.
+    fun count(n) {
+        for (var i = 0; i < n; i = i + 1) {
+            if (i == 2) continue; else print i;
+        }
+        while (true) break;
+        switch (n) {
+            case 1: print "one";
+            default: return;
+        }
+    }
+    "#;
+    let stmts = stmts(source);
+    assert_eq!(stmts.len(), 1);
+    let StmtKind::Fun(f) = &stmts[0].kind else {
+        panic!("expected fun");
+    };
+    assert_eq!(f.name, "count");
+    assert_eq!(f.params, ["n"]);
+    assert_eq!(f.doc.as_deref(), Some("Counts."));
+    assert!(matches!(f.body[0].kind, StmtKind::For { .. }));
+    assert!(matches!(f.body[1].kind, StmtKind::While { .. }));
+    let StmtKind::Switch { cases, .. } = &f.body[2].kind else {
+        panic!("expected switch");
+    };
+    assert_eq!(cases.len(), 2);
+    assert!(cases[1].test.is_none());
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("print ;"), "[line 1] Error at ';': expect expression");
+    assert_eq!(
+        error("a + b = c;"),
+        "[line 1] Error at '=': invalid assignment target"
+    );
+    assert_eq!(
+        error("var x = 1"),
+        "[line 1] Error at end: expect ';' after variable declaration"
+    );
+    assert_eq!(error("\n$"), "[line 2] Error: unexpected character '$'");
+}
+
+#[test]
+fn errors_use_the_catalog() {
+    let mut catalog = Catalog::new();
+    catalog
+        .set(Message::ExpectExpression, "Ausdruck erwartet")
+        .unwrap();
+    catalog
+        .set(Message::AssignmentCount, "{1} statt {0}")
+        .unwrap();
+    catalog
+        .set(Message::UnexpectedCharacter, "Zeichen '{0}' unerwartet")
+        .unwrap();
+    let options = VmOptions {
+        messages: Arc::new(catalog),
+        ..Default::default()
+    };
+    let error = |source: &str| {
+        parse(source.to_string(), &options).unwrap_err().to_string()
+    };
+    assert_eq!(error("print ;"), "[line 1] Error at ';': Ausdruck erwartet");
+    assert_eq!(error("a, b = 1;"), "[line 1] Error at '1': 1 statt 2");
+    assert_eq!(error("$"), "[line 1] Error: Zeichen '$' unerwartet");
+}
+
+#[test]
+fn optional_semicolons() {
+    let options = VmOptions {
+        optional_semicolons: true,
+        ..Default::default()
+    };
+    let stmts = parse("var a = 1\nprint a".to_string(), &options).unwrap();
+    assert_eq!(stmts.len(), 2);
+    let err = parse("for (var i = 0\ni < 2;) {}".to_string(), &options);
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "[line 2] Error at 'i': expect ';' after variable declaration"
+    );
+}
+
+#[test]
+fn json() {
+    let json = to_json(&stmts("print -x;"));
+    assert_eq!(
+        json,
+        r#"[
+  {
+    "type": "Print",
+    "expr": {
+      "type": "Unary",
+      "op": "-",
+      "operand": {
+        "type": "Variable",
+        "name": "x",
+        "span": {
+          "start": 7,
+          "end": 8,
+          "line": 1
+        }
+      },
+      "span": {
+        "start": 6,
+        "end": 8,
+        "line": 1
+      }
+    },
+    "span": {
+      "start": 0,
+      "end": 9,
+      "line": 1
+    }
+  }
+]"#
+    );
+}
+
+#[test]
+fn json_literals() {
+    let json = to_json(&stmts("var s = \"a\\\\b\"; var n;"));
+    assert!(json.contains(r#""value": "a\\b""#));
+    assert!(json.contains(r#""init": null"#));
+    assert_eq!(to_json(&[]), "[]");
+
+    let json = to_json(&stmts("if (x) print [1]; else return {\"a\": nil};"));
+    assert!(json.contains(r#""items": ["#));
+    assert!(json.contains(r#""value": 1.0"#));
+    assert!(json.contains(r#""else": {"#));
+    assert!(json.contains(r#""entries": ["#));
+    assert!(json.contains(r#""value": null"#));
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js() {
+    let js = super::to_js(&stmts(
+        "var let = 1;\nfun f(a) { return a and !a; }\nprint f(let) + -2;",
+    ));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        r#"var let$ = 1.0;
+function f(a) {
+  return (($l) => $truthy($l) ? !$truthy(a) : $l)(a);
+  return null;
+}
+console.log($str($add($call(f, let$), $neg(2.0))));
+"#
+    );
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js_statements() {
+    let js = super::to_js(&stmts(
+        "for (var i = 0; i < 2; i = i + 1) switch (i) { case 1: break; }",
+    ));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        r#"{
+  let i = 0.0;
+  for (; $truthy(($num(i) < $num(2.0))); (i = $add(i, 1.0))) {
+    {
+      const $switch0 = i;
+      if ($switch0 === 1.0) {
+        break;
+      }
+    }
+  }
+}
+"#
+    );
+}
+
+#[test]
+fn format() {
+    let source = concat!(
+        "/// Doc.\nfun f(a,b){if(a)return - -b;else if (b) {print a;} ",
+        "switch(a){case 1: {}; default:;}}"
+    );
+    assert_eq!(
+        super::format(&stmts(source)),
+        r#"/// Doc.
+fun f(a, b) {
+    if (a)
+        return - -b;
+    else if (b) {
+        print a;
+    }
+    switch (a) {
+    case 1:
+        {
+        }
+        ;
+    default:
+        ;
+    }
+}
+"#
+    );
+}
+
+#[test]
+fn string_escapes() {
+    let stmts = stmts(r#"print "a\"b\\c\n\u{263A}";"#);
+    let StmtKind::Print(e) = &stmts[0].kind else {
+        panic!("expected print");
+    };
+    assert_eq!(e.kind, ExprKind::String("a\"b\\c\n\u{263A}".to_string()));
+    assert_eq!(super::format(&stmts), "print \"a\\\"b\\\\c\n☺\";\n");
+}
+
+#[test]
+fn interpolation() {
+    let source = r#"print "a${x + 1}\${b}${ "c${y}" }";"#;
+    let stmts = stmts(source);
+    let StmtKind::Print(e) = &stmts[0].kind else {
+        panic!("expected print");
+    };
+    let ExprKind::Interpolation(parts) = &e.kind else {
+        panic!("expected interpolation");
+    };
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts[0], StringPart::Text("a".to_string()));
+    assert_eq!(parts[2], StringPart::Text("${b}".to_string()));
+    assert_eq!(
+        super::format(&stmts),
+        "print \"a${x + 1}\\${b}${\"c${y}\"}\";\n"
+    );
+    assert_eq!(
+        error(r#"print "${1 2}";"#),
+        "[line 1] Error at '2': expect '}' after expression in string"
+    );
+}
+
+#[test]
+fn loop_else() {
+    let stmts = stmts("while (a) break; else print 1; for (;;) {} else {}");
+    let StmtKind::While { else_, .. } = &stmts[0].kind else {
+        panic!("expected while");
+    };
+    assert!(matches!(else_.as_deref().unwrap().kind, StmtKind::Print(_)));
+    assert_eq!(
+        super::format(&stmts),
+        r#"while (a)
+    break;
+else
+    print 1;
+for (;;) {
+} else {
+}
+"#
+    );
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js_loop_else() {
+    let js = super::to_js(&stmts(
+        "while (a) { while (b) break; break; } else print 1;",
+    ));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        r#"$loop0: {
+  while ($truthy(a)) {
+    while ($truthy(b)) {
+      break;
+    }
+    break $loop0;
+  }
+  console.log($str(1.0));
+}
+"#
+    );
+}
+
+#[test]
+fn multiple_assignment() {
+    let stmts = stmts("a, b = b, a + 1;");
+    let StmtKind::MultipleAssign { names, values } = &stmts[0].kind else {
+        panic!("expected multiple assignment");
+    };
+    assert_eq!(names, &["a", "b"]);
+    assert_eq!(values.len(), 2);
+    assert_eq!(super::format(&stmts), "a, b = b, a + 1;\n");
+    assert_eq!(
+        error("a, b = 1;"),
+        "[line 1] Error at '1': expect 2 values to assign, not 1"
+    );
+}
+
+#[test]
+fn defer() {
+    let stmts = stmts("{ defer print 1; defer { print 2; } }");
+    let StmtKind::Block(body) = &stmts[0].kind else {
+        panic!("expected block");
+    };
+    assert!(matches!(body[0].kind, StmtKind::Defer(_)));
+    assert_eq!(
+        super::format(&stmts),
+        r#"{
+    defer print 1;
+    defer {
+        print 2;
+    }
+}
+"#
+    );
+    assert_eq!(
+        error("while (a) defer print 1;"),
+        "[line 1] Error at 'defer': 'defer' can't be the body of another \
+         statement"
+    );
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js_defer() {
+    let js = super::to_js(&stmts("defer print 1; print 2;"));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        r#"let $defer0 = true;
+try {
+  console.log($str(2.0));
+} catch ($e) {
+  $defer0 = false;
+  throw $e;
+} finally {
+  if ($defer0) {
+    console.log($str(1.0));
+  }
+}
+"#
+    );
+}
+
+#[test]
+fn increment() {
+    let stmts = stmts("a++; print - --b;");
+    assert!(matches!(
+        &stmts[0].kind,
+        StmtKind::Expression(Expr {
+            kind: ExprKind::Update {
+                op: UpdateOp::Increment,
+                prefix: false,
+                ..
+            },
+            ..
+        })
+    ));
+    assert_eq!(super::format(&stmts), "a++;\nprint - --b;\n");
+    assert_eq!(
+        error("(a)++;"),
+        "[line 1] Error at '++': invalid increment target"
+    );
+    assert_eq!(
+        error("++1;"),
+        "[line 1] Error at '1': invalid increment target"
+    );
+    let negated = super::format(&self::stmts("print 1--1; print --(1);"));
+    assert_eq!(negated, "print 1 - -1;\nprint - -(1);\n");
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js_increment() {
+    let js = super::to_js(&stmts("a++; --b;"));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        "(($v) => (a = $operand($v) + 1, $v))(a);\n(b = $operand(b) - 1);\n"
+    );
+}
+
+#[test]
+fn import_export() {
+    let stmts = stmts("import lib::greet;\nexport fun f() {}\nexport var x;");
+    assert_eq!(
+        stmts[0].kind,
+        StmtKind::Import {
+            namespace: "lib".to_string(),
+            name: "greet".to_string()
+        }
+    );
+    assert!(matches!(stmts[1].kind, StmtKind::Export(_)));
+    assert_eq!(
+        super::format(&stmts),
+        "import lib::greet;\nexport fun f() {\n}\nexport var x;\n"
+    );
+    assert_eq!(
+        error("export 1;"),
+        "[line 1] Error at '1': expect 'var' or 'fun' after 'export'"
+    );
+}
+
+#[test]
+fn power() {
+    let stmts = stmts("-2 ** 3 ** 2;");
+    let StmtKind::Expression(e) = &stmts[0].kind else {
+        panic!("expected expression");
+    };
+    let ExprKind::Unary { operand, .. } = &e.kind else {
+        panic!("expected unary");
+    };
+    let ExprKind::Binary { op, right, .. } = &operand.kind else {
+        panic!("expected binary");
+    };
+    assert_eq!(*op, BinaryOp::Power);
+    assert!(matches!(
+        right.kind,
+        ExprKind::Binary {
+            op: BinaryOp::Power,
+            ..
+        }
+    ));
+    assert_eq!(super::format(&stmts), "-2 ** 3 ** 2;\n");
+}
+
+#[test]
+fn symbols() {
+    let source = "var a = 1;\nfun f(b) {\n    a = b + a;\n    var a;\n    \
+                  print a;\n}\n{ var f = 2; print f; }\na, clock = f, 2;";
+    let index = super::symbols(source, &stmts(source));
+    let spans = |name: &str, defs: bool| -> Vec<(usize, usize, u32)> {
+        let symbol = &index.symbols[name];
+        let spans = match defs {
+            true => &symbol.definitions,
+            false => &symbol.references,
+        };
+        spans.iter().map(|s| (s.start, s.end, s.line)).collect()
+    };
+
+    assert_eq!(spans("a", true), [(4, 5, 1)]);
+    assert_eq!(spans("a", false), [(26, 27, 3), (34, 35, 3), (87, 88, 8)]);
+    assert_eq!(spans("f", true), [(15, 16, 2)]);
+    assert_eq!(spans("f", false), [(98, 99, 8)]);
+    assert_eq!(spans("clock", true), []);
+    assert_eq!(spans("clock", false), [(90, 95, 8)]);
+    assert!(!index.symbols.contains_key("b"));
+}
+
+#[test]
+fn rename() {
+    let options = VmOptions::default();
+    let source = "var a = 1;\nfun f(b) { a = b + a; }\nprint a;\n";
+    assert_eq!(
+        super::rename(source, "a", "total", &options).unwrap(),
+        "var total = 1;\nfun f(b) { total = b + total; }\nprint total;\n"
+    );
+    let errors = [
+        ("x", "c", "no global named 'x'"),
+        ("a", "f", "there's already a global named 'f'"),
+        ("a", "while", "'while' isn't a valid name"),
+        ("a", "2a", "'2a' isn't a valid name"),
+        ("a", "b", "a local named 'b' would hide the renamed global"),
+    ];
+    for (old, new, msg) in errors {
+        let err = super::rename(source, old, new, &options).unwrap_err();
+        assert_eq!(err.to_string(), msg);
+    }
+}
+
+#[test]
+fn minify() {
+    let options = VmOptions::default();
+    let source = "// Not kept.\nvar b = 1;\n/// Kept.\nfun f(x, y) {\n    \
+                  var z = x - -y; // nor this\n    fun g() {}\n    \
+                  return z / b;\n}\nprint f(1, 2) == 1;\n";
+    assert_eq!(
+        super::minify(source, &options, false).unwrap(),
+        "var b=1;\n///Kept.\nfun f(x,y){var z=x- -y;fun g(){}return z/b;}\
+         print f(1,2)==1;\n"
+    );
+    // 'b' is a global, and 'f' and 'g' are taken too.
+    assert_eq!(
+        super::minify(source, &options, true).unwrap(),
+        "var b=1;\n///Kept.\nfun f(a,c){var d=a- -c;fun g(){}return d/b;}\
+         print f(1,2)==1;\n"
+    );
+}
+
+#[test]
+fn lists() {
+    let stmts = stmts("print [a, [1]][0];\nx[1] = y[2];");
+    let StmtKind::Print(e) = &stmts[0].kind else {
+        panic!("expected print");
+    };
+    let ExprKind::Index { object, index } = &e.kind else {
+        panic!("expected index");
+    };
+    assert!(matches!(&object.kind, ExprKind::List(items) if items.len() == 2));
+    assert_eq!(index.kind, ExprKind::Number(0.0));
+    let StmtKind::Expression(e) = &stmts[1].kind else {
+        panic!("expected expression");
+    };
+    let ExprKind::SetIndex { value, .. } = &e.kind else {
+        panic!("expected index assignment");
+    };
+    assert!(matches!(value.kind, ExprKind::Index { .. }));
+    assert_eq!(super::format(&stmts), "print [a, [1]][0];\nx[1] = y[2];\n");
+    assert_eq!(
+        error("x[1;"),
+        "[line 1] Error at ';': expect ']' after index"
+    );
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js_lists() {
+    let js = super::to_js(&stmts("var a = [1];\na[0] = {\"k\": a[0]};"));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        "var a = [1.0];\n$set(a, 0.0, $map(\"k\", $get(a, 0.0)));\n"
+    );
+}
+
+#[test]
+fn maps() {
+    let stmts = stmts("print {\"a\": 1, 2: {}}[x];");
+    let StmtKind::Print(e) = &stmts[0].kind else {
+        panic!("expected print");
+    };
+    let ExprKind::Index { object, .. } = &e.kind else {
+        panic!("expected index");
+    };
+    let ExprKind::Map(entries) = &object.kind else {
+        panic!("expected map");
+    };
+    assert_eq!(entries[0].0.kind, ExprKind::String("a".to_string()));
+    assert_eq!(entries[1].1.kind, ExprKind::Map(Vec::new()));
+    assert_eq!(super::format(&stmts), "print {\"a\": 1, 2: {}}[x];\n");
+    assert_eq!(
+        error("print {1};"),
+        "[line 1] Error at '}': expect ':' after map key"
+    );
+}
+
+#[test]
+fn for_in() {
+    let stmts = stmts("for (var x in [1]) print x; else print 0;");
+    let StmtKind::ForIn {
+        name,
+        sequence,
+        else_,
+        ..
+    } = &stmts[0].kind
+    else {
+        panic!("expected for-in");
+    };
+    assert_eq!(name, "x");
+    assert!(matches!(sequence.kind, ExprKind::List(_)));
+    assert!(else_.is_some());
+    assert_eq!(
+        super::format(&stmts),
+        "for (var x in [1])\n    print x;\nelse\n    print 0;\n"
+    );
+    assert!(to_json(&stmts).contains("\"type\": \"ForIn\""));
+    assert_eq!(
+        error("for (var x in y print x;"),
+        "[line 1] Error at 'print': expect ')' after for-in sequence"
+    );
+
+    let source = "for (var i in 0..=n + 1) {}";
+    let stmts = parse(source.to_string(), &VmOptions::default()).unwrap();
+    let StmtKind::ForIn { sequence, .. } = &stmts[0].kind else {
+        panic!("expected for-in");
+    };
+    let ExprKind::Range { inclusive, end, .. } = &sequence.kind else {
+        panic!("expected range");
+    };
+    assert!(inclusive);
+    assert!(matches!(end.kind, ExprKind::Binary { .. }));
+    assert_eq!(sequence.span.start, 14);
+    assert_eq!(sequence.span.end, 23);
+    assert_eq!(super::format(&stmts), "for (var i in 0..=n + 1) {\n}\n");
+
+    // The loop variable is a local.
+    let options = VmOptions::default();
+    let source = "for (var item in items) print item;";
+    assert_eq!(
+        super::minify(source, &options, true).unwrap(),
+        "for(var a in items)print a;\n"
+    );
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn js_for_in() {
+    let js = super::to_js(&stmts("for (var x in a) print x;"));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(
+        program,
+        "for (let x of $iter(a)) {\n  console.log($str(x));\n}\n"
+    );
+    let js = super::to_js(&stmts("for (var i in 0..n) {}"));
+    let program = js.split_once("clock.$native = true;\n").unwrap().1;
+    assert_eq!(program, "for (let i of $range(0.0, n, false)) {\n}\n");
+}
+
+// The programs in tests/lox, which the vm's tests compile and run, parse
+// here too.
+#[test]
+fn test_programs() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        if let Err(e) = parse(source, &VmOptions::default()) {
+            panic!("{}: {}", path.display(), e);
+        }
+    }
+}
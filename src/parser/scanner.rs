@@ -1,8 +1,18 @@
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::hint::black_box;
 use std::str::from_utf8_unchecked;
+use std::time::Instant;
 
 use anyhow::{bail, Result};
 
+use crate::{
+    fnv1a,
+    message::{Diagnostic, Message},
+    Benchmark, Dialect,
+};
+
 #[cfg(test)]
 mod test;
 
@@ -12,37 +22,113 @@ pub(super) struct Token {
     start: usize,
     end: usize,
     line: u32,
+    ident: u32,
+    newline: bool,
+}
+
+#[derive(Clone)]
+pub(super) struct Checkpoint {
+    current: usize,
+    line: u32,
+    interpolations: Vec<u32>,
 }
 
 pub(super) struct Scanner {
     source: Source,
     current: usize,
     line: u32,
+    dialect: Dialect,
+    idents: Idents,
+    newline: bool,
+    // The span of each block of `///` comments, by the start of the token
+    // that follows it.
+    docs: Vec<(usize, usize, usize)>,
+    // For each `${` in a string whose `}` hasn't been reached, how many
+    // braces are open inside it.
+    interpolations: Vec<u32>,
+}
+
+// Dense ids for the distinct identifiers in a source, bucketed by FNV hash
+// with the span of each first occurrence.
+struct Idents {
+    table: HashMap<u64, Vec<(usize, usize, u32)>, BuildHasherDefault<IdHasher>>,
+    count: u32,
 }
 
+#[derive(Default)]
+struct IdHasher(u64);
+
 struct Source {
     text: Vec<u8>,
     current: usize,
 }
 
-pub fn bench_scanner(text: String) -> Result<()> {
-    let mut b1 = 0usize;
-    let mut b2 = 0usize;
-    let mut b3 = 0usize;
-    let mut b4 = 0usize;
+pub fn bench_scanner(text: String) -> Result<Benchmark> {
+    let bytes = text.len();
+    let mut tokens = 0usize;
     let mut scanner = Scanner::new(text);
+    let start = Instant::now();
     loop {
-        let token = scanner.scan_token()?;
-        b1 += token.ty as u8 as usize;
-        b2 += token.start;
-        b3 += token.end;
-        b4 += token.line as usize;
+        let token = black_box(scanner.scan_token()?);
+        tokens += 1;
         if token.ty == TokenType::Eof {
             break;
         }
     }
-    println!("{} {} {} {}", b1, b2, b3, b4);
-    Ok(())
+    Ok(Benchmark::new("scan", tokens, bytes, start.elapsed()))
+}
+
+// Replaces `\n`, `\t`, `\\`, `\"`, `\$`, `\0` and `\u{...}`, with from one
+// to six hex digits, by the characters they stand for.
+fn unescape(text: &str) -> Result<String> {
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        value.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('$') => '$',
+            Some('0') => '\0',
+            Some('u') => {
+                let rest = chars.as_str();
+                let hex = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(hex, _)| hex)
+                    .filter(|hex| {
+                        (1..=6).contains(&hex.len())
+                            && hex.bytes().all(|b| b.is_ascii_hexdigit())
+                    });
+                let c = hex
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32);
+                let (Some(hex), Some(c)) = (hex, c) else {
+                    bail!(Diagnostic::new(Message::InvalidUnicodeEscape, &[]));
+                };
+                chars = rest[hex.len() + 2..].chars();
+                c
+            }
+            Some(c) => bail!(Diagnostic::new(Message::InvalidEscape, &[&c])),
+            None => bail!(Diagnostic::new(Message::InvalidEscape, &[&""])),
+        });
+    }
+    Ok(value)
+}
+
+// The words that scan as keywords in `dialect`.
+pub(crate) fn keywords(dialect: Dialect) -> impl Iterator<Item = &'static str> {
+    Scanner::KEYWORDS
+        .iter()
+        .filter(move |(_, ty)| {
+            dialect == Dialect::Extended || !Scanner::EXTENSIONS.contains(ty)
+        })
+        .map(|&(keyword, _)| keyword)
 }
 
 impl Display for TokenType {
@@ -52,15 +138,27 @@ impl Display for TokenType {
 }
 
 impl Token {
+    const NO_IDENT: u32 = u32::MAX;
+
     fn new() -> Self {
         Token {
             ty: TokenType::default(),
             start: 0,
             end: 0,
             line: 1,
+            ident: Token::NO_IDENT,
+            newline: false,
         }
     }
 
+    pub(super) fn ident(&self) -> Option<u32> {
+        (self.ident != Token::NO_IDENT).then_some(self.ident)
+    }
+
+    pub(super) fn newline(&self) -> bool {
+        self.newline
+    }
+
     pub(super) fn ty(&self) -> TokenType {
         self.ty
     }
@@ -68,6 +166,30 @@ impl Token {
     pub(super) fn line(&self) -> u32 {
         self.line
     }
+
+    pub(super) fn start(&self) -> usize {
+        self.start
+    }
+
+    pub(super) fn end(&self) -> usize {
+        self.end
+    }
+
+    // The two `-` of a `--` that's a minus and a negation, not a decrement.
+    pub(super) fn split_minus(&self) -> (Token, Token) {
+        let minus = Token {
+            ty: TokenType::Minus,
+            end: self.start + 1,
+            ..*self
+        };
+        let negate = Token {
+            ty: TokenType::Minus,
+            start: self.start + 1,
+            newline: false,
+            ..*self
+        };
+        (minus, negate)
+    }
 }
 
 impl Default for Token {
@@ -77,92 +199,101 @@ impl Default for Token {
 }
 
 impl Scanner {
+    pub(super) const KEYWORDS: &'static [(&'static str, TokenType)] = &[
+        ("and", TokenType::And),
+        ("break", TokenType::Break),
+        ("case", TokenType::Case),
+        ("class", TokenType::Class),
+        ("continue", TokenType::Continue),
+        ("default", TokenType::Default),
+        ("defer", TokenType::Defer),
+        ("else", TokenType::Else),
+        ("export", TokenType::Export),
+        ("false", TokenType::False),
+        ("for", TokenType::For),
+        ("fun", TokenType::Fun),
+        ("if", TokenType::If),
+        ("import", TokenType::Import),
+        ("nil", TokenType::Nil),
+        ("or", TokenType::Or),
+        ("print", TokenType::Print),
+        ("return", TokenType::Return),
+        ("super", TokenType::Super),
+        ("switch", TokenType::Switch),
+        ("this", TokenType::This),
+        ("true", TokenType::True),
+        ("var", TokenType::Var),
+        ("while", TokenType::While),
+    ];
+    const KEYWORD_SLOTS: [u8; 128] = Scanner::keyword_slots();
+    // Keywords that book Lox doesn't have, so scans as identifiers.
+    pub(super) const EXTENSIONS: &'static [TokenType] = &[
+        TokenType::Break,
+        TokenType::Case,
+        TokenType::Continue,
+        TokenType::Default,
+        TokenType::Defer,
+        TokenType::Export,
+        TokenType::Import,
+        TokenType::Switch,
+    ];
+
     pub(super) fn new(text: String) -> Self {
         Scanner {
             source: Source::new(text),
             current: 0,
             line: 1,
+            dialect: Dialect::default(),
+            idents: Idents::new(),
+            newline: false,
+            docs: Vec::new(),
+            interpolations: Vec::new(),
         }
     }
 
-    fn alpha(&mut self, c: u8) -> Token {
-        match c {
-            b'a' => self.check_keyword(false, b"nd", TokenType::And),
-            b'b' => self.check_keyword(false, b"reak", TokenType::Break),
-            b'c' => match self.source.peek() {
-                Some(b'a') => self.check_keyword(true, b"se", TokenType::Case),
-                Some(b'l') => {
-                    self.check_keyword(true, b"ass", TokenType::Class)
-                }
-                Some(b'o') => {
-                    self.check_keyword(true, b"ntinue", TokenType::Continue)
-                }
-                Some(_) => self.get_ident(),
-                None => self.make_token(TokenType::Identifier),
-            },
-            b'd' => self.check_keyword(false, b"efault", TokenType::Default),
-            b'e' => self.check_keyword(false, b"lse", TokenType::Else),
-            b'i' => self.check_keyword(false, b"f", TokenType::If),
-            b'n' => self.check_keyword(false, b"il", TokenType::Nil),
-            b'o' => self.check_keyword(false, b"r", TokenType::Or),
-            b'p' => self.check_keyword(false, b"rint", TokenType::Print),
-            b'r' => self.check_keyword(false, b"eturn", TokenType::Return),
-            b's' => match self.source.peek() {
-                Some(b'u') => {
-                    self.check_keyword(true, b"per", TokenType::Super)
-                }
-                Some(b'w') => {
-                    self.check_keyword(true, b"itch", TokenType::Switch)
-                }
-                Some(_) => self.get_ident(),
-                None => self.make_token(TokenType::Identifier),
-            },
-            b'v' => self.check_keyword(false, b"ar", TokenType::Var),
-            b'w' => self.check_keyword(false, b"hile", TokenType::While),
-            b'f' => match self.source.peek() {
-                Some(b'a') => {
-                    self.check_keyword(true, b"lse", TokenType::False)
-                }
-                Some(b'o') => self.check_keyword(true, b"r", TokenType::For),
-                Some(b'u') => self.check_keyword(true, b"n", TokenType::Fun),
-                Some(_) => self.get_ident(),
-                None => self.make_token(TokenType::Identifier),
-            },
-            b't' => match self.source.peek() {
-                Some(b'h') => self.check_keyword(true, b"is", TokenType::This),
-                Some(b'r') => self.check_keyword(true, b"ue", TokenType::True),
-                Some(_) => self.get_ident(),
-                None => self.make_token(TokenType::Identifier),
-            },
-            _ => self.get_ident(),
+    pub(super) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            current: self.source.current,
+            line: self.line,
+            interpolations: self.interpolations.clone(),
         }
     }
 
-    fn check_keyword(
-        &mut self,
-        skip: bool,
-        suffix: &[u8],
-        ty: TokenType,
-    ) -> Token {
-        if skip {
-            self.source.next();
-        }
-        let idx = self.source.current;
-        let mut iter = suffix.iter();
-        self.source.skip_while(|c| iter.next() == Some(&c));
-        if self.source.current - idx == suffix.len() {
-            let c = self.source.peek();
-            if c.map(|ch| !Scanner::is_ident(ch)).unwrap_or(true) {
-                return self.make_token(ty);
-            }
-        }
-
-        self.get_ident()
+    // The text of the `///` comments just before `token`, without the
+    // slashes, if there are any.
+    pub(super) fn doc(&self, token: Token) -> Option<String> {
+        let idx = self
+            .docs
+            .binary_search_by_key(&token.start, |&(at, _, _)| at)
+            .ok()?;
+        let (_, start, end) = self.docs[idx];
+        let text =
+            unsafe { from_utf8_unchecked(&self.source.text[start..end]) };
+        let lines: Vec<_> = text
+            .lines()
+            .map(|line| {
+                let line = line.trim_start().trim_start_matches("///");
+                line.strip_prefix(' ').unwrap_or(line).trim_end()
+            })
+            .collect();
+        Some(lines.join("\n"))
     }
 
-    fn get_ident(&mut self) -> Token {
+    fn identifier(&mut self) -> Token {
         self.source.skip_while(Scanner::is_ident);
-        self.make_token(TokenType::Identifier)
+        let word = &self.source.text[self.current..self.source.current];
+        let keyword = Scanner::keyword(word).filter(|ty| {
+            self.dialect == Dialect::Extended
+                || !Scanner::EXTENSIONS.contains(ty)
+        });
+        if let Some(ty) = keyword {
+            return self.make_token(ty);
+        }
+        let mut token = self.make_token(TokenType::Identifier);
+        token.ident =
+            self.idents
+                .intern(&self.source.text, token.start, token.end);
+        token
     }
 
     fn is_alpha(c: u8) -> bool {
@@ -177,16 +308,50 @@ impl Scanner {
         Scanner::is_alpha(c) || Scanner::is_digit(c)
     }
 
+    fn keyword(word: &[u8]) -> Option<TokenType> {
+        let idx = Scanner::KEYWORD_SLOTS[Scanner::keyword_slot(word)];
+        Scanner::KEYWORDS
+            .get(idx as usize)
+            .filter(|(keyword, _)| keyword.as_bytes() == word)
+            .map(|&(_, ty)| ty)
+    }
+
+    const fn keyword_slot(word: &[u8]) -> usize {
+        let first = word[0] as usize;
+        let last = word[word.len() - 1] as usize;
+        (first * 2 + last * 5 + word.len() * 5) & 127
+    }
+
+    const fn keyword_slots() -> [u8; 128] {
+        let mut slots = [u8::MAX; 128];
+        let mut i = 0;
+        while i < Scanner::KEYWORDS.len() {
+            let slot = Scanner::keyword_slot(Scanner::KEYWORDS[i].0.as_bytes());
+            // Pick new multipliers in keyword_slot if this ever fails
+            assert!(slots[slot] == u8::MAX, "keyword hash collision");
+            slots[slot] = i as u8;
+            i += 1;
+        }
+        slots
+    }
+
     pub(super) fn line(&self) -> u32 {
         self.line
     }
 
+    // Only affects words scanned from now on.
+    pub(super) fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
     fn make_token(&mut self, ty: TokenType) -> Token {
         Token {
             ty,
             start: self.current,
             end: self.source.current,
             line: self.line,
+            ident: Token::NO_IDENT,
+            newline: self.newline,
         }
     }
 
@@ -197,7 +362,7 @@ impl Scanner {
     fn number(&mut self) -> Token {
         self.source.skip_while(Scanner::is_digit);
         if self.source.peek() == Some(b'.')
-            && self.source.peek_peek().map_or(false, Scanner::is_digit)
+            && self.source.peek_peek().is_some_and(Scanner::is_digit)
         {
             self.source.next();
             self.source.skip_while(Scanner::is_digit);
@@ -205,6 +370,13 @@ impl Scanner {
         self.make_token(TokenType::Number)
     }
 
+    pub(super) fn restore(&mut self, checkpoint: Checkpoint) {
+        self.source.current = checkpoint.current;
+        self.current = checkpoint.current;
+        self.line = checkpoint.line;
+        self.interpolations = checkpoint.interpolations;
+    }
+
     #[inline]
     pub(super) fn scan_token(&mut self) -> Result<Token> {
         self.skip_whitespace();
@@ -215,18 +387,57 @@ impl Scanner {
 
         let token = match c {
             _ if Scanner::is_digit(c) => self.number(),
-            _ if Scanner::is_alpha(c) => self.alpha(c),
+            _ if Scanner::is_alpha(c) => self.identifier(),
             b'(' => self.make_token(TokenType::LeftParen),
             b')' => self.make_token(TokenType::RightParen),
-            b'{' => self.make_token(TokenType::LeftBrace),
-            b'}' => self.make_token(TokenType::RightBrace),
+            b'{' => {
+                if let Some(open) = self.interpolations.last_mut() {
+                    *open += 1;
+                }
+                self.make_token(TokenType::LeftBrace)
+            }
+            b'}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string(true)?
+                }
+                Some(open) => {
+                    *open -= 1;
+                    self.make_token(TokenType::RightBrace)
+                }
+                None => self.make_token(TokenType::RightBrace),
+            },
+            b'[' if self.dialect == Dialect::Extended => {
+                self.make_token(TokenType::LeftBracket)
+            }
+            b']' if self.dialect == Dialect::Extended => {
+                self.make_token(TokenType::RightBracket)
+            }
             b';' => self.make_token(TokenType::Semicolon),
             b',' => self.make_token(TokenType::Comma),
+            b'.' if self.dialect == Dialect::Extended && self.matches(b'.') => {
+                match self.matches(b'=') {
+                    true => self.make_token(TokenType::DotDotEqual),
+                    false => self.make_token(TokenType::DotDot),
+                }
+            }
             b'.' => self.make_token(TokenType::Dot),
+            b'-' if self.dialect == Dialect::Extended && self.matches(b'-') => {
+                self.make_token(TokenType::MinusMinus)
+            }
             b'-' => self.make_token(TokenType::Minus),
+            b'+' if self.dialect == Dialect::Extended && self.matches(b'+') => {
+                self.make_token(TokenType::PlusPlus)
+            }
             b'+' => self.make_token(TokenType::Plus),
             b'/' => self.make_token(TokenType::Slash),
+            b'*' if self.dialect == Dialect::Extended && self.matches(b'*') => {
+                self.make_token(TokenType::StarStar)
+            }
             b'*' => self.make_token(TokenType::Star),
+            b':' if self.matches(b':') => {
+                self.make_token(TokenType::ColonColon)
+            }
             b':' => self.make_token(TokenType::Colon),
             b'!' => {
                 if self.matches(b'=') {
@@ -256,10 +467,10 @@ impl Scanner {
                     self.make_token(TokenType::Greater)
                 }
             }
-            b'"' => self.string()?,
+            b'"' => self.string(false)?,
             _ => {
                 let ch = self.skip_unexpected();
-                bail!("unexpected character '{}'", ch);
+                bail!(Diagnostic::new(Message::UnexpectedCharacter, &[&ch]));
             }
         };
         Ok(token)
@@ -276,6 +487,8 @@ impl Scanner {
     }
 
     fn skip_whitespace(&mut self) {
+        let line = self.line;
+        let mut doc: Option<(usize, usize)> = None;
         loop {
             self.source.skip_while(|c| {
                 matches!(c, b' ' | b'\r' | b'\t')
@@ -288,29 +501,120 @@ impl Scanner {
             if self.source.peek() == Some(b'/')
                 && self.source.peek_peek() == Some(b'/')
             {
+                let start = self.source.current;
                 self.source.skip_while(|c| c != b'\n');
+                // Any other comment ends a doc comment.
+                let end = self.source.current;
+                doc = match self.source.text.get(start + 2) {
+                    Some(b'/') => Some((doc.map_or(start, |d| d.0), end)),
+                    _ => None,
+                };
                 continue;
             }
             break;
         }
 
+        self.newline = self.line != line;
         self.current = self.source.current;
+        // Rescanning after a restore finds the same comments again.
+        let rescanned = self.docs.last().is_some_and(|d| d.0 >= self.current);
+        if let (Some((start, end)), false) = (doc, rescanned) {
+            self.docs.push((self.current, start, end));
+        }
     }
 
-    fn string(&mut self) -> Result<Token> {
+    // Scans the rest of a string literal, or of the part of one after an
+    // interpolation if `resumed`. In the extended dialect, a backslash
+    // starts an escape sequence, so `\"` doesn't end the string, and `${`
+    // starts an interpolation, which ends at the matching `}`.
+    fn string(&mut self, resumed: bool) -> Result<Token> {
         let line = self.line;
-        self.source.skip_while(|c| {
-            (c == b'\n') && {
-                self.line += 1;
-                true
-            } || c != b'"'
-        });
-        if self.source.peek().is_none() {
-            self.line = line;
-            bail!("unterminated string");
+        let extended = self.dialect == Dialect::Extended;
+        let ty = loop {
+            self.source.skip_while(|c| {
+                (c == b'\n') && {
+                    self.line += 1;
+                    true
+                } || c != b'"' && !(extended && (c == b'\\' || c == b'$'))
+            });
+            match self.source.next() {
+                None => {
+                    self.line = line;
+                    bail!(Diagnostic::new(Message::UnterminatedString, &[]));
+                }
+                Some(b'"') if resumed => break TokenType::InterpolationEnd,
+                Some(b'"') => break TokenType::String,
+                Some(b'$') if self.matches(b'{') => {
+                    self.interpolations.push(0);
+                    break match resumed {
+                        true => TokenType::InterpolationMiddle,
+                        false => TokenType::InterpolationStart,
+                    };
+                }
+                Some(b'$') => (),
+                _ => {
+                    if matches!(self.source.peek(), Some(b'"' | b'\\' | b'$')) {
+                        self.source.next();
+                    }
+                }
+            }
+        };
+        let token = self.make_token(ty);
+        if extended {
+            unescape(self.string_contents(token))?;
         }
-        self.source.next();
-        Ok(self.make_token(TokenType::String))
+        Ok(token)
+    }
+
+    // The text of the string literal, or part of one, `token`, without the
+    // quotes and the braces around interpolations.
+    fn string_contents(&self, token: Token) -> &str {
+        let text = self.token_text(token);
+        match token.ty {
+            TokenType::InterpolationStart | TokenType::InterpolationMiddle => {
+                &text[1..text.len() - 2]
+            }
+            _ => &text[1..text.len() - 1],
+        }
+    }
+
+    // The value of the string literal, or part of one, `token`, with any
+    // escape sequences replaced by the characters they stand for.
+    pub(super) fn string_value(&self, token: Token) -> String {
+        let text = self.string_contents(token);
+        match self.dialect {
+            // Checked when the string was scanned.
+            Dialect::Extended => unescape(text).unwrap_or_default(),
+            Dialect::Book => text.to_string(),
+        }
+    }
+
+    // The line `token` starts on, and the column and width (in chars) of
+    // the token within it. The end of the source counts as just after the
+    // last thing in it.
+    pub(super) fn token_line(&self, token: Token) -> (&str, usize, usize) {
+        let text = self.text();
+        let start = match token.ty {
+            TokenType::Eof => text.trim_end().len(),
+            _ => token.start,
+        };
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end =
+            text[start..].find('\n').map_or(text.len(), |i| i + start);
+        let end = token.end.clamp(start, line_end);
+        let line = text[line_start..line_end].trim_end_matches('\r');
+        let column = text[line_start..start].chars().count();
+        (line, column, text[start..end].chars().count().max(1))
+    }
+
+    // The text of line number `line`, if there is one.
+    pub(super) fn line_text(&self, line: u32) -> Option<&str> {
+        crate::source_line(self.text(), line)
+    }
+
+    // All of the source being scanned.
+    pub(super) fn text(&self) -> &str {
+        unsafe { from_utf8_unchecked(&self.source.text) }
     }
 
     pub(super) fn token_text(&self, token: Token) -> &str {
@@ -320,6 +624,43 @@ impl Scanner {
     }
 }
 
+impl Idents {
+    fn new() -> Self {
+        Idents {
+            table: HashMap::default(),
+            count: 0,
+        }
+    }
+
+    fn intern(&mut self, text: &[u8], start: usize, end: usize) -> u32 {
+        let ident = &text[start..end];
+        let bucket = self.table.entry(fnv1a(ident)).or_default();
+        for &(s, e, id) in bucket.iter() {
+            if &text[s..e] == ident {
+                return id;
+            }
+        }
+        let id = self.count;
+        bucket.push((start, end, id));
+        self.count += 1;
+        id
+    }
+}
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("IdHasher only hashes u64 keys");
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+}
+
 impl Source {
     fn new(text: String) -> Self {
         Source {
@@ -329,9 +670,8 @@ impl Source {
     }
 
     fn next(&mut self) -> Option<u8> {
-        self.peek().map(|c| {
+        self.peek().inspect(|_| {
             self.current += 1;
-            c
         })
     }
 
@@ -347,7 +687,7 @@ impl Source {
     where
         P: FnMut(u8) -> bool,
     {
-        self.peek().map_or(false, |c| {
+        self.peek().is_some_and(|c| {
             predicate(c) && {
                 self.current += 1;
                 true
@@ -373,16 +713,21 @@ pub(crate) enum TokenType {
     Eof,
     // Punctuation
     Colon,
+    ColonColon,
     Comma,
     LeftBrace,
+    LeftBracket,
     LeftParen,
     RightBrace,
+    RightBracket,
     RightParen,
     Semicolon,
     // Operators
     Bang,
     BangEqual,
     Dot,
+    DotDot,
+    DotDotEqual,
     Equal,
     EqualEqual,
     Greater,
@@ -390,13 +735,21 @@ pub(crate) enum TokenType {
     Less,
     LessEqual,
     Minus,
+    MinusMinus,
     Plus,
+    PlusPlus,
     Slash,
     Star,
+    StarStar,
     // Values
     Identifier,
     Number,
     String,
+    // Parts of a string with `${...}` in it: up to the first `${`, between
+    // a `}` and the next `${`, and after the last `}`.
+    InterpolationStart,
+    InterpolationMiddle,
+    InterpolationEnd,
     // Keywords
     And,
     Break,
@@ -404,11 +757,14 @@ pub(crate) enum TokenType {
     Class,
     Continue,
     Default,
+    Defer,
     Else,
+    Export,
     False,
     For,
     Fun,
     If,
+    Import,
     Nil,
     Or,
     Print,
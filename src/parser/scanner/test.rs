@@ -1,6 +1,47 @@
 use anyhow::Result;
 
 use super::{Scanner, TokenType};
+use crate::Dialect;
+
+#[test]
+fn checkpoint() -> Result<()> {
+    let source = "a\n  b \"c\nd\" e";
+    let mut scanner = Scanner::new(source.into());
+
+    assert_eq!((TokenType::Identifier, "a"), tok(&mut scanner)?);
+    let checkpoint = scanner.checkpoint();
+    let b = scanner.scan_token()?;
+    assert_eq!(
+        (
+            TokenType::String,
+            r#""c
+d""#
+        ),
+        tok(&mut scanner)?
+    );
+    assert_eq!(3, scanner.line());
+
+    scanner.restore(checkpoint);
+    assert_eq!(1, scanner.line());
+    let again = scanner.scan_token()?;
+    assert_eq!(
+        (b.ty(), b.line(), b.ident()),
+        (again.ty(), again.line(), again.ident())
+    );
+    assert_eq!("b", scanner.token_text(again));
+    assert_eq!(
+        (
+            TokenType::String,
+            r#""c
+d""#
+        ),
+        tok(&mut scanner)?
+    );
+    assert_eq!((TokenType::Identifier, "e"), tok(&mut scanner)?);
+    assert_eq!(3, scanner.line());
+
+    Ok(())
+}
 
 #[test]
 fn identifiers() -> Result<()> {
@@ -29,6 +70,31 @@ fn identifiers() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn identifier_ids() -> Result<()> {
+    let source = "i j i count j count2 count";
+    let mut scanner = Scanner::new(source.into());
+
+    let ids = (0..7)
+        .map(|_| scanner.scan_token().map(|token| token.ident()))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        ids,
+        [
+            Some(0),
+            Some(1),
+            Some(0),
+            Some(2),
+            Some(1),
+            Some(3),
+            Some(2)
+        ]
+    );
+    assert_eq!(None, scanner.scan_token()?.ident());
+
+    Ok(())
+}
+
 #[test]
 fn keywords() -> Result<()> {
     let source = r#"
@@ -56,6 +122,18 @@ fn keywords() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn keyword_table() -> Result<()> {
+    for &(keyword, ty) in Scanner::KEYWORDS {
+        let mut scanner = Scanner::new(format!("{0} {0}_ _{0}", keyword));
+        assert_eq!((ty, keyword), tok(&mut scanner)?);
+        assert_eq!(TokenType::Identifier, tok(&mut scanner)?.0);
+        assert_eq!(TokenType::Identifier, tok(&mut scanner)?.0);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn numbers() -> Result<()> {
     let source = r#"
@@ -120,6 +198,7 @@ fn strings() -> Result<()> {
     Ok(())
 }
 
+#[test]
 fn whitespace() -> Result<()> {
     let source = r#"
     space    tabs				newlines
@@ -144,3 +223,18 @@ fn tok(scanner: &mut Scanner) -> Result<(TokenType, &str)> {
     let token = scanner.scan_token()?;
     Ok((token.ty(), scanner.token_text(token)))
 }
+
+#[test]
+fn book_dialect() -> Result<()> {
+    for &(keyword, ty) in Scanner::KEYWORDS {
+        let mut scanner = Scanner::new(keyword.to_string());
+        scanner.set_dialect(Dialect::Book);
+        let expected = match Scanner::EXTENSIONS.contains(&ty) {
+            true => TokenType::Identifier,
+            false => ty,
+        };
+        assert_eq!((expected, keyword), tok(&mut scanner)?);
+    }
+
+    Ok(())
+}
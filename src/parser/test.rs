@@ -0,0 +1,98 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{scanner::TokenType, Compiler, Locals, Message, Op, Parser};
+
+fn parser(source: &str) -> (Parser, Rc<RefCell<Vec<u8>>>) {
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut parser = Parser::new(source.to_string(), stderr.clone());
+    parser.compilers.push(Compiler::new("<test>"));
+    (parser, stderr)
+}
+
+#[test]
+fn peek_next() {
+    let (mut parser, stderr) = parser("a\n$ b\nc");
+
+    parser.advance();
+    parser.emit_op(Op::Nil);
+    assert_eq!(TokenType::Identifier, parser.current.ty());
+    let next = parser.peek_next();
+    assert_eq!("b", parser.scanner.token_text(next));
+    assert_eq!(2, next.line());
+    let again = parser.peek_next();
+    assert_eq!("b", parser.scanner.token_text(again));
+    assert!(stderr.borrow().is_empty());
+
+    parser.advance();
+    parser.emit_op(Op::Nil);
+    assert_eq!("b", parser.scanner.token_text(parser.current));
+    assert_eq!(
+        "[line 2] Error: unexpected character '$'\n",
+        String::from_utf8(stderr.borrow().to_vec()).unwrap()
+    );
+
+    parser.advance();
+    parser.emit_op(Op::Nil);
+    assert_eq!("c", parser.scanner.token_text(parser.current));
+    assert_eq!(TokenType::Eof, parser.peek_next().ty());
+    let lines: Vec<u32> = (0..3).map(|i| parser.chunk().get_line(i)).collect();
+    assert_eq!(vec![1, 2, 3], lines);
+}
+
+#[test]
+fn warning() {
+    let (mut parser, stderr) = parser("a b");
+    parser.advance();
+    parser.advance();
+    parser.warning(Message::ShadowsGlobal, &[]);
+    assert!(!parser.had_error);
+
+    parser.options.strict = true;
+    parser.advance();
+    parser.warning(Message::ShadowsGlobal, &[]);
+    assert!(parser.had_error);
+
+    assert_eq!(
+        "[line 1] Warning at 'a': shadows a global\n\
+         [line 1] Error at 'b': shadows a global\n",
+        String::from_utf8(stderr.borrow().to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn mark_initialized_without_local() {
+    let mut locals = Locals::new();
+    assert!(!locals.mark_initialized());
+    assert!(locals.add(0, 1));
+    assert!(locals.mark_initialized());
+    assert!(!locals.mark_initialized());
+
+    let (mut parser, stderr) = parser("a");
+    parser.advance();
+    parser.advance();
+    parser.mark_initialized();
+    assert!(parser.had_error);
+    assert_eq!(
+        "[line 1] Error at 'a': internal error: no local to initialize\n",
+        String::from_utf8(stderr.borrow().to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn internal_errors() {
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut parser = Parser::new("1 + 2".to_string(), stderr.clone());
+    parser.advance();
+    parser.advance();
+    parser.emit_op(Op::Nil);
+    assert!(parser.had_error);
+
+    // Only the first error is reported until the parser resynchronizes.
+    parser.panic_mode = false;
+    parser.literal();
+    assert_eq!(
+        "[line 1] Error at '1': internal error: no function being compiled\n\
+         [line 1] Error at '1': internal error: unexpected literal\n",
+        String::from_utf8(stderr.borrow().to_vec()).unwrap()
+    );
+}
@@ -0,0 +1,180 @@
+use std::rc::Rc;
+
+use anyhow::{bail, Result};
+
+use crate::vm::{Heap, LoxFunction, Vm};
+
+// A compiled script, along with the vm symbol names its global opcodes refer
+// to, so that it can be saved and later run on another vm, whatever ids that
+// vm has given those names.
+pub(crate) struct Program {
+    pub(crate) script: LoxFunction,
+    symbols: Vec<Rc<str>>,
+}
+
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    pub(crate) symbols: usize,
+}
+
+impl Program {
+    const MAGIC: &'static [u8] = b"RLOX";
+    // Programs run on any redlox that reads the same format, so this must
+    // change whenever the encoding or the meaning of an opcode does.
+    const FORMAT: u32 = 11;
+
+    pub(crate) fn new(script: LoxFunction, symbols: &[Rc<str>]) -> Self {
+        Program {
+            script,
+            symbols: symbols.to_vec(),
+        }
+    }
+
+    pub(crate) fn symbols(&self) -> &[Rc<str>] {
+        &self.symbols
+    }
+
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.bytes(Program::MAGIC);
+        w.u32(Program::FORMAT);
+        w.str(env!("CARGO_PKG_VERSION"));
+        w.u32(self.symbols.len() as u32);
+        for name in &self.symbols {
+            w.str(name);
+        }
+        self.script.serialize(&mut w);
+        w.finish()
+    }
+
+    pub(crate) fn deserialize(
+        bytes: &[u8],
+        heap: &mut Heap,
+    ) -> Result<Program> {
+        let mut r = Reader::new(bytes);
+        if r.bytes(Program::MAGIC.len())? != Program::MAGIC {
+            bail!("not a compiled lox program");
+        }
+        let format = r.u32()?;
+        let version = r.str()?;
+        if format != Program::FORMAT {
+            bail!(
+                "program was compiled by redlox {} (format {}), but this is \
+                 redlox {} (format {})",
+                version,
+                format,
+                env!("CARGO_PKG_VERSION"),
+                Program::FORMAT
+            );
+        }
+        let count = r.u32()?;
+        let mut symbols = Vec::new();
+        for _ in 0..count {
+            symbols.push(r.str()?.into());
+        }
+        r.symbols = symbols.len();
+        let script = LoxFunction::deserialize(&mut r, heap)?;
+        if !r.at_end() {
+            bail!("trailing data after program");
+        }
+        Ok(Program { script, symbols })
+    }
+
+    // Global opcodes use the compiling vm's symbol ids, so rewrite them to
+    // the ids `vm` has for the same names.
+    pub(crate) fn link(self, vm: &mut Vm) -> Result<LoxFunction> {
+        let ids: Vec<u32> = self
+            .symbols
+            .iter()
+            .map(|name| vm.get_symbol(name))
+            .collect();
+        if ids.iter().enumerate().any(|(sym, &id)| sym as u32 != id) {
+            self.script.chunk.relink(&ids)?;
+        }
+        Ok(self.script)
+    }
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer { bytes: Vec::new() }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    pub(crate) fn u16(&mut self, v: u16) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.bytes(&v.to_le_bytes());
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes,
+            pos: 0,
+            symbols: 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            bail!("unexpected end of program");
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn str(&mut self) -> Result<&'a str> {
+        let len = self.u32()? as usize;
+        Ok(std::str::from_utf8(self.bytes(len)?)?)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+}
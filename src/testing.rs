@@ -0,0 +1,191 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    io::{self, BufRead, Read, Write},
+    rc::Rc,
+};
+
+use crate::{code::Op, parser::Parser, Vm, VmOptions};
+
+/// Run `source` on a fresh Vm, returning what it wrote to stdout and stderr
+/// (a runtime error is written to stderr).
+pub fn interpret(source: &str) -> (String, String) {
+    interpret_with(source, VmOptions::default())
+}
+
+pub fn interpret_with(source: &str, options: VmOptions) -> (String, String) {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::with_options(stdout.clone(), stderr.clone(), options);
+    if let Err(e) = vm.interpret(source.to_string()) {
+        let _ = writeln!(stderr.borrow_mut(), "{}", e);
+    }
+    let ret = (
+        String::from_utf8(stdout.borrow().to_vec()).unwrap(),
+        String::from_utf8(stderr.borrow().to_vec()).unwrap(),
+    );
+    ret
+}
+
+/// The opcode names of the top-level script compiled from `source`, or the
+/// compile errors if it fails to compile.
+pub fn opcodes(source: &str) -> Result<Vec<&'static str>, String> {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(stdout, stderr.clone());
+    let mut parser = Parser::new(source.to_string(), stderr.clone());
+    match parser.parse(&mut vm, "<script>") {
+        Some(script) => Ok(script
+            .chunk
+            .instructions(0)
+            .map(|inst| Op::name(inst.opcode()))
+            .collect()),
+        None => Err(String::from_utf8(stderr.borrow().to_vec()).unwrap()),
+    }
+}
+
+/// Assert that `source` compiles to exactly the `expected` opcode names.
+pub fn assert_opcodes(source: &str, expected: &[&str]) {
+    match opcodes(source) {
+        Ok(ops) => assert_eq!(ops, expected),
+        Err(e) => panic!("compile error:\n{}", e),
+    }
+}
+
+/// Stand-ins for what a vm, and the natives registered on it, would
+/// otherwise get from the outside world: a clock that only moves when told
+/// to, a seeded random number generator, lines of input given up front, and
+/// buffers that collect whatever is printed. Clones share the same fakes,
+/// so a native can be given one while the test keeps another.
+#[derive(Clone)]
+pub struct FakeEnv {
+    stdout: Rc<RefCell<Vec<u8>>>,
+    stderr: Rc<RefCell<Vec<u8>>>,
+    time: Rc<Cell<f64>>,
+    rng: Rc<Cell<u64>>,
+    stdin: Rc<RefCell<VecDeque<String>>>,
+}
+
+impl FakeEnv {
+    /// Fakes whose clock reads 0 and whose random numbers come from `seed`.
+    pub fn new(seed: u64) -> Self {
+        FakeEnv {
+            stdout: Rc::new(RefCell::new(Vec::new())),
+            stderr: Rc::new(RefCell::new(Vec::new())),
+            time: Rc::new(Cell::new(0.0)),
+            rng: Rc::new(Cell::new(seed)),
+            stdin: Rc::new(RefCell::new(VecDeque::new())),
+        }
+    }
+
+    /// Have `vm` print to the capture buffers, read `clock()` from the fake
+    /// clock, and read `readLine()` from the fake input.
+    pub fn install(&self, vm: &mut Vm) {
+        // Writing to a Vec can't fail.
+        let _ = vm.set_output(self.stdout.clone(), self.stderr.clone());
+        vm.set_input(Rc::new(RefCell::new(FakeStdin {
+            lines: self.stdin.clone(),
+            line: Vec::new(),
+            pos: 0,
+        })));
+        let time = self.time.clone();
+        vm.set_clock(move || time.get());
+    }
+
+    /// A new vm with the fakes installed.
+    pub fn vm(&self, options: VmOptions) -> Vm {
+        let mut vm =
+            Vm::with_options(self.stdout.clone(), self.stderr.clone(), options);
+        self.install(&mut vm);
+        vm
+    }
+
+    /// Move the clock forward by `secs` seconds.
+    pub fn advance(&self, secs: f64) {
+        self.time.set(self.time.get() + secs);
+    }
+
+    /// The time on the clock, in seconds.
+    pub fn now(&self) -> f64 {
+        self.time.get()
+    }
+
+    /// The next random number, from 0 up to but not including 1. The same
+    /// seed always gives the same numbers.
+    pub fn random(&self) -> f64 {
+        // SplitMix64.
+        let state = self.rng.get().wrapping_add(0x9e3779b97f4a7c15);
+        self.rng.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Add lines to the end of the input.
+    pub fn push_input<I, S>(&self, lines: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.stdin
+            .borrow_mut()
+            .extend(lines.into_iter().map(Into::into));
+    }
+
+    /// The next line of input, or None once it has all been read.
+    pub fn read_line(&self) -> Option<String> {
+        self.stdin.borrow_mut().pop_front()
+    }
+
+    /// Everything printed so far, which is then cleared. The vm's stdout is
+    /// buffered, so flush it (see [`Vm::flush`]) if it may still be running.
+    pub fn take_stdout(&self) -> String {
+        take(&self.stdout)
+    }
+
+    /// Likewise for stderr.
+    pub fn take_stderr(&self) -> String {
+        take(&self.stderr)
+    }
+}
+
+// The fake input, as the vm reads it: a line at a time, each with a line
+// ending.
+struct FakeStdin {
+    lines: Rc<RefCell<VecDeque<String>>>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for FakeStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for FakeStdin {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            if let Some(line) = self.lines.borrow_mut().pop_front() {
+                self.line = format!("{}\n", line).into_bytes();
+                self.pos = 0;
+            }
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+fn take(buf: &RefCell<Vec<u8>>) -> String {
+    String::from_utf8_lossy(&std::mem::take(&mut *buf.borrow_mut()))
+        .into_owned()
+}
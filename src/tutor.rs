@@ -0,0 +1,194 @@
+//! `rlox tutor`: lessons on the language, each with an exercise whose
+//! answers are run in a vm and checked through the embedding API.
+//!
+//! A lesson is a Lox script under `tutor/`. Its `//!` lines are the text
+//! shown to the user, the first being the title. An exercise is checked
+//! in one of two ways: by comparing what an answer prints with the
+//! script's `// expect: ` lines, as in `tests/lox`, or by calling the
+//! script's `check()` function afterwards, which returns true when the
+//! exercise is solved, or else a hint.
+
+use std::cell::RefCell;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::rc::Rc;
+
+use anyhow::Result;
+
+use redlox::{HostValue, Vm, VmOptions};
+
+#[cfg(test)]
+mod test;
+
+const LESSONS: &[&str] = &[
+    include_str!("tutor/01_print.lox"),
+    include_str!("tutor/02_variables.lox"),
+    include_str!("tutor/03_strings.lox"),
+    include_str!("tutor/04_loops.lox"),
+    include_str!("tutor/05_functions.lox"),
+    include_str!("tutor/06_lists.lox"),
+];
+
+struct Lesson {
+    title: String,
+    text: String,
+    // What a correct answer prints, if the exercise is checked that way.
+    expect: Option<String>,
+    script: &'static str,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Passed,
+    // With a hint, if the lesson had one.
+    Failed(Option<String>),
+}
+
+// A lesson's exercise, with a vm of its own for the answers.
+struct Exercise {
+    vm: Vm,
+    expect: Option<String>,
+    stdout: Rc<RefCell<Vec<u8>>>,
+    stderr: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Lesson {
+    fn parse(script: &'static str) -> Self {
+        let mut doc = script
+            .lines()
+            .filter_map(|line| line.strip_prefix("//!"))
+            .map(|line| line.strip_prefix(' ').unwrap_or(line));
+        let title = doc.next().unwrap_or_default().to_string();
+        let text = doc.collect::<Vec<_>>().join("\n").trim().to_string();
+        let expect: String = script
+            .lines()
+            .filter_map(|line| line.strip_prefix("// expect: "))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        Lesson {
+            title,
+            text,
+            expect: Some(expect).filter(|e| !e.is_empty()),
+            script,
+        }
+    }
+}
+
+impl Exercise {
+    fn new(lesson: &Lesson) -> Self {
+        let stdout = Rc::new(RefCell::new(Vec::new()));
+        let stderr = Rc::new(RefCell::new(Vec::new()));
+        let options = VmOptions {
+            optional_semicolons: true,
+            show_source: true,
+            ..Default::default()
+        };
+        let mut vm = Vm::with_options(stdout.clone(), stderr.clone(), options);
+        // The lessons are part of the binary, and their tests make sure
+        // they run.
+        vm.interpret(lesson.script.to_string())
+            .expect("lesson script should run");
+        stdout.borrow_mut().clear();
+        Exercise {
+            vm,
+            expect: lesson.expect.clone(),
+            stdout,
+            stderr,
+        }
+    }
+
+    // Runs `entry` as the REPL would, returning what it printed, errors
+    // included, and whether it solved the exercise.
+    fn answer(&mut self, entry: &str) -> (String, Outcome) {
+        let result = self.vm.interpret_repl(entry.to_string());
+        // Writing to a Vec can't fail.
+        let _ = self.vm.flush();
+        let printed = String::from_utf8_lossy(&self.stdout.take()).to_string();
+        let mut errors =
+            String::from_utf8_lossy(&self.stderr.take()).to_string();
+        if let Err(e) = &result {
+            errors.push_str(&format!("{}\n", e));
+        }
+        let outcome = if !errors.is_empty() {
+            Outcome::Failed(None)
+        } else if let Some(expect) = &self.expect {
+            match printed == *expect {
+                true => Outcome::Passed,
+                false => Outcome::Failed(None),
+            }
+        } else {
+            self.check()
+        };
+        (printed + &errors, outcome)
+    }
+
+    fn check(&mut self) -> Outcome {
+        match self.vm.call("check", &[]) {
+            Ok(HostValue::Bool(true)) => Outcome::Passed,
+            Ok(HostValue::String(hint)) => Outcome::Failed(Some(hint)),
+            Ok(_) => Outcome::Failed(None),
+            // Most likely the answer didn't define what check() looks for,
+            // which the error says well enough.
+            Err(e) => {
+                let msg = e.to_string();
+                let msg = match msg.split_once("] ") {
+                    Some((line, rest)) if line.starts_with("[line ") => rest,
+                    _ => &msg,
+                };
+                Outcome::Failed(Some(msg.lines().next().unwrap().to_string()))
+            }
+        }
+    }
+}
+
+/// Goes through the lessons in order, moving on once an exercise is
+/// solved, or skipped with `:skip`. `:quit` stops early.
+pub fn run() -> Result<()> {
+    let mut lines = stdin().lock().lines();
+    let count = LESSONS.len();
+    'lessons: for (n, script) in LESSONS.iter().enumerate() {
+        let lesson = Lesson::parse(script);
+        println!("Lesson {} of {}: {}\n", n + 1, count, lesson.title);
+        println!("{}\n", lesson.text);
+        let mut exercise = Exercise::new(&lesson);
+        let mut source: Vec<String> = Vec::new();
+        loop {
+            match source.is_empty() {
+                true => print!("tutor> "),
+                false => print!("  ...> "),
+            }
+            stdout().flush()?;
+            let mut line = match lines.next() {
+                None => return Ok(()),
+                Some(line) => line?,
+            };
+            if line.ends_with('\\') {
+                line.pop();
+                source.push(line);
+                continue;
+            }
+            source.push(line);
+            let entry = source.join("\n");
+            source.clear();
+            match entry.trim() {
+                "" => continue,
+                ":skip" => break,
+                ":quit" => break 'lessons,
+                _ => {}
+            }
+            let (printed, outcome) = exercise.answer(&entry);
+            print!("{}", printed);
+            match outcome {
+                Outcome::Passed => {
+                    println!("Correct!\n");
+                    break;
+                }
+                Outcome::Failed(Some(hint)) => println!("Not yet: {}", hint),
+                Outcome::Failed(None) => println!("Not yet, try again."),
+            }
+        }
+        if n + 1 == count {
+            println!("That's all the lessons!");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use super::{Exercise, Lesson, Outcome, LESSONS};
+
+// An answer to each lesson, in order.
+const SOLUTIONS: &[&str] = &[
+    "print 6 * 7;",
+    "var answer = 42;",
+    "var greeting = \"Hello, ${language}!\";",
+    "for (var i in 1..=5) print i;",
+    "fun square(n) {\n  return n * n;\n}",
+    "fun total(list) {\n  var sum = 0;\n  for (var n in list) sum = sum + n;\n  \
+     return sum;\n}",
+];
+
+fn exercise(n: usize) -> Exercise {
+    Exercise::new(&Lesson::parse(LESSONS[n]))
+}
+
+#[test]
+fn parse() {
+    let lesson = Lesson::parse(LESSONS[0]);
+    assert_eq!(lesson.title, "Printing");
+    assert!(lesson.text.starts_with("A Lox program"), "{}", lesson.text);
+    assert!(lesson.text.ends_with("6 and 7."), "{}", lesson.text);
+    assert_eq!(lesson.expect.as_deref(), Some("42\n"));
+    assert_eq!(Lesson::parse(LESSONS[1]).expect, None);
+}
+
+#[test]
+fn solutions() {
+    assert_eq!(SOLUTIONS.len(), LESSONS.len());
+    for (n, solution) in SOLUTIONS.iter().enumerate() {
+        let (printed, outcome) = exercise(n).answer(solution);
+        assert_eq!(outcome, Outcome::Passed, "lesson {}: {}", n + 1, printed);
+    }
+}
+
+#[test]
+fn wrong_answers() {
+    let (printed, outcome) = exercise(0).answer("print 6 + 7;");
+    assert_eq!(printed, "13\n");
+    assert_eq!(outcome, Outcome::Failed(None));
+
+    let mut ex = exercise(1);
+    let (_, outcome) = ex.answer("var question = 42;");
+    let hint = "undefined variable 'answer'".to_string();
+    assert_eq!(outcome, Outcome::Failed(Some(hint)));
+    let (_, outcome) = ex.answer("var answer = 41;");
+    let hint = "answer is 41, not 42".to_string();
+    assert_eq!(outcome, Outcome::Failed(Some(hint)));
+    assert_eq!(ex.answer("answer = answer + 1;").1, Outcome::Passed);
+
+    let (_, outcome) = exercise(4).answer("fun square(n) { return n + n; }");
+    let hint = "square(3) returned 6, not 9".to_string();
+    assert_eq!(outcome, Outcome::Failed(Some(hint)));
+}
+
+#[test]
+fn errors_fail() {
+    let (printed, outcome) = exercise(0).answer("print 6 *;");
+    assert!(printed.contains("expect expression"), "{}", printed);
+    assert_eq!(outcome, Outcome::Failed(None));
+    let (printed, outcome) = exercise(1).answer("var answer = 42 + nil;");
+    assert!(printed.starts_with("[line 1]"), "{}", printed);
+    assert_eq!(outcome, Outcome::Failed(None));
+}
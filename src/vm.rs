@@ -1,21 +1,46 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap, HashSet},
     fmt::Display,
-    ops::Deref,
+    io::{self, Write},
+    ops::{Deref, RangeInclusive},
     rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
+use anyhow::bail;
+
 use crate::{
-    code::{Chunk, Op},
-    parser::Parser,
-    Obj, Stderr, Stdout, Value,
+    code::{Chunk, Instruction, Op, Opcode},
+    message::{Catalog, Message},
+    parser::{
+        scanner::{bench_scanner, keywords},
+        Parser,
+    },
+    program::{Program, Reader, Writer},
+    Benchmark, BytecodeCache, HostValue, Stderr, Stdin, Stdout, Value,
 };
 
+mod dump;
+mod gc;
+#[cfg(feature = "jit")]
+mod jit;
 mod native;
+#[cfg(feature = "profiling")]
+mod profile;
+mod safepoint;
+#[cfg(feature = "tracing")]
+mod spans;
 
 #[cfg(test)]
 mod test;
 
+pub(crate) use gc::{Heap, Obj, Trace};
+
 struct Frame {
     func: Obj<LoxFunction>,
     offset: usize,
@@ -27,6 +52,20 @@ pub(crate) struct LoxFunction {
     name: String,
     pub(crate) arity: usize,
     pub(crate) chunk: Chunk,
+    // Where the function was declared; 0 for a script.
+    pub(crate) line: u32,
+    // The `///` comments before its declaration.
+    pub(crate) doc: Option<String>,
+    // The most stack slots a call can use, including the callee's.
+    max_slots: u32,
+    // The expected stack height before each instruction.
+    #[cfg(feature = "check_stack")]
+    depths: Vec<u32>,
+    #[cfg(feature = "jit")]
+    jit: jit::Jit,
+    // Which function the profile has its sites under; 0 until it has any.
+    #[cfg(feature = "profiling")]
+    profile_id: std::cell::Cell<u32>,
 }
 
 #[derive(PartialEq)]
@@ -34,16 +73,41 @@ pub(crate) struct LoxString {
     text: Box<str>,
 }
 
-#[derive(Debug, thiserror::Error)]
-#[error("{}", .msg)]
+pub(crate) struct LoxList {
+    pub(crate) items: Vec<Value>,
+}
+
+// Entries are kept in the order they were added, with an index by key.
+#[derive(Default)]
+pub(crate) struct LoxMap {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<MapKey, usize>,
+    // The length of every string key, for the heap to count.
+    texts: usize,
+}
+
+// What a map key is compared by: the text of a string, or the bits of a
+// number, with -0 taken as 0.
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub(crate) enum MapKey {
+    Number(u64),
+    String(Box<str>),
+}
+
+#[derive(Debug)]
 pub struct RuntimeError {
     msg: String,
+    line: Option<u32>,
+    source_line: Option<String>,
+    timeout: bool,
+    interrupted: bool,
 }
 
-#[derive(Clone)]
 pub(crate) struct RustFunction {
     name: String,
+    // The most arguments it takes, and the fewest.
     arity: usize,
+    min_arity: usize,
     func: NativeFn,
 }
 
@@ -52,17 +116,230 @@ struct SymTable {
     names: Vec<Rc<str>>,
 }
 
+#[derive(Clone)]
+pub struct VmOptions {
+    /// Let a line break end a statement wherever a ';' is expected.
+    pub optional_semicolons: bool,
+    /// Reject assignment to undeclared globals and redeclared globals,
+    /// treat warnings as errors, and require `print(...)`.
+    pub strict: bool,
+    /// Name the operand types and callee in runtime error messages.
+    pub verbose_errors: bool,
+    /// When to flush stdout after `print`.
+    pub flush: FlushPolicy,
+    /// Quote the source line under error messages, with carets under the
+    /// offending token for compile errors. Scripts run from compiled form
+    /// have no source to quote.
+    pub show_source: bool,
+    /// How deeply expressions, statements and functions may nest before
+    /// the compiler gives up with an error, rather than running out of
+    /// stack.
+    pub max_nesting: usize,
+    /// Warn when a local variable or parameter hides an outer local or a
+    /// global, saying where that was declared.
+    pub warn_shadowing: bool,
+    /// Which keywords the language has.
+    pub dialect: Dialect,
+    /// The most bytes the objects a script keeps alive may take up, as the
+    /// collector estimates them. A script that needs more stops with an
+    /// "out of memory" runtime error. Unlimited by default.
+    pub max_heap: Option<usize>,
+    /// Store the globals scripts define as `namespace::name`, so that
+    /// scripts compiled in different namespaces, such as plugins loaded
+    /// into one vm, can't see or clobber each other's. Globals a script
+    /// doesn't define, like natives and those from the host, are still
+    /// found by their own names, but assigning one gives the namespace a
+    /// copy of its own. Another namespace's globals are found only by
+    /// `import`ing ones declared with `export`.
+    pub namespace: Option<String>,
+    /// The longest source, in bytes, the compiler will take. A longer one
+    /// is rejected with a "source too large" error before any of it is
+    /// compiled. Unlimited by default.
+    pub max_source_bytes: Option<usize>,
+    /// The most lines a source may have. Line numbers are 32 bits, so
+    /// there can't be more than `u32::MAX` of them, which is the default.
+    pub max_source_lines: u32,
+    /// The longest string, in bytes, a script may make, by `+`,
+    /// interpolation or natives. Making a longer one is a "string too
+    /// long" runtime error. Unlimited by default.
+    pub max_string_bytes: Option<usize>,
+    /// The most items a list a script makes may have, as for
+    /// `max_string_bytes`. Unlimited by default.
+    pub max_list_items: Option<usize>,
+    /// Templates for compile errors, warnings and runtime errors, to
+    /// translate or reword them. Any not in the catalog are in English.
+    pub messages: Arc<Catalog>,
+}
+
+/// A script compiled by [`Vm::compile_script`], which can be run any
+/// number of times by the same vm, until [`Vm::release_script`] frees it.
+#[derive(Clone)]
+pub struct CompiledScript(u64);
+
+/// The language a vm accepts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dialect {
+    /// Lox with this crate's additions, such as `switch`, `break`, `++`,
+    /// lists like `[1, 2]`, maps like `{"a": 1}`, `for (var x in list)`
+    /// and `for (var i in 0..n)` loops, and escape sequences like `\n` and
+    /// `${...}` in strings.
+    #[default]
+    Extended,
+    /// Lox as in the book, where `break`, `case`, `continue`, `default`,
+    /// `defer`, `export`, `import` and `switch` are ordinary identifiers,
+    /// `--x` is `-(-x)`, there are no lists, maps or for-in loops, and
+    /// strings are taken as they're written.
+    Book,
+}
+
+/// When the vm flushes its stdout sink.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FlushPolicy {
+    /// Leave flushing to the sink.
+    #[default]
+    Buffered,
+    /// Flush after every `print`.
+    Line,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        VmOptions {
+            optional_semicolons: false,
+            strict: false,
+            verbose_errors: false,
+            flush: FlushPolicy::default(),
+            show_source: false,
+            max_nesting: VmOptions::MAX_NESTING,
+            warn_shadowing: false,
+            dialect: Dialect::default(),
+            max_heap: None,
+            namespace: None,
+            max_source_bytes: None,
+            max_source_lines: u32::MAX,
+            max_string_bytes: None,
+            max_list_items: None,
+            messages: Arc::new(Catalog::new()),
+        }
+    }
+}
+
+impl VmOptions {
+    // Deep enough for any reasonable script, and shallow enough to parse
+    // on a 2 MiB thread stack (the default for spawned threads).
+    const MAX_NESTING: usize = 256;
+
+    // The options that change what the parser emits, or (with strict
+    // making warnings errors) whether it accepts the script.
+    pub(crate) fn compile_key(&self) -> Vec<u8> {
+        let flags = self.optional_semicolons as u8
+            | (self.strict as u8) << 1
+            | (self.warn_shadowing as u8) << 2
+            | ((self.dialect == Dialect::Book) as u8) << 3;
+        let mut key = vec![flags];
+        if let Some(namespace) = &self.namespace {
+            key.extend_from_slice(namespace.as_bytes());
+        }
+        key
+    }
+
+    // Whether `source` is within the size limits, checked before compiling
+    // so that huge sources fail cleanly rather than running line numbers
+    // past what they can hold.
+    pub(crate) fn check_source(
+        &self,
+        source: &str,
+    ) -> std::result::Result<(), String> {
+        if let Some(max) = self.max_source_bytes.filter(|&m| source.len() > m) {
+            let msg = Message::SourceTooLarge;
+            return Err(self.messages.format(msg, &[&source.len(), &max]));
+        }
+        // A source can't have more lines than it has bytes, plus one, so
+        // most of them needn't be counted.
+        let max = self.max_source_lines as usize;
+        if source.len() >= max {
+            let lines = source.bytes().filter(|&b| b == b'\n').count() + 1;
+            if lines > max {
+                let msg = Message::SourceTooManyLines;
+                return Err(self.messages.format(msg, &[&lines, &max]));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Forwards to a shared sink, so that it can sit inside a BufWriter.
+struct Sink(Stdout);
+
 pub struct Vm {
-    stdout: Stdout,
+    options: VmOptions,
+    stdout: io::BufWriter<Sink>,
     stderr: Stderr,
+    // Where the print_code and trace_execution features write, kept apart
+    // from the output and errors of the scripts being listed or traced.
+    #[cfg_attr(
+        not(any(feature = "print_code", feature = "trace_execution")),
+        allow(dead_code)
+    )]
+    trace: Stderr,
+    // Where readLine() reads from; None for the process's stdin.
+    stdin: Option<Stdin>,
+    heap: Heap,
+    // Reused by print, so that each value is formatted without allocating
+    // and written to stdout in one call.
+    scratch: String,
+    // Shared by every "" the vm pushes.
+    empty_string: Value,
     frames: Vec<Frame>,
     stack: Vec<Value>,
     globals: HashMap<u32, Value>,
+    // While an isolated script runs, the globals it defines or assigns,
+    // layered over `globals`.
+    isolated: Option<HashMap<u32, Value>>,
     symbols: SymTable,
+    // The globals declared with `export`, which scripts in other namespaces
+    // may import.
+    exports: HashSet<u32>,
+    // For each restricted namespace, the globals outside it that its
+    // scripts can still see.
+    restricted: HashMap<Box<str>, HashSet<u32>>,
+    // For a restricted namespace's symbol of each list or map outside it
+    // that its scripts have read, the value read and the namespace's own
+    // copy of it.
+    namespace_copies: HashMap<u32, (Value, Value)>,
+    // The scripts from compile_script not yet released, by the id in their
+    // CompiledScript.
+    scripts: Vec<(u64, Obj<LoxFunction>)>,
+    safepoints: safepoint::Safepoints,
+    clock: Clock,
+    #[cfg(feature = "profiling")]
+    profile: profile::Profile,
+    #[cfg(feature = "tracing")]
+    spans: spans::CallSpans,
 }
 
+pub use native::{Clock, NativeContext};
+#[cfg(feature = "profiling")]
+pub use profile::{Site, Stats};
+pub use safepoint::{InterruptHandle, Safepoint, SafepointHook};
+
 type Result<T> = std::result::Result<T, RuntimeError>;
-type NativeFn = fn(usize, vm: &mut Vm) -> Result<Value>;
+type NativeFn = Box<dyn Fn(&mut NativeContext) -> Result<Value>>;
+
+pub fn bench_vm(text: String) -> anyhow::Result<Benchmark> {
+    let tokens = bench_scanner(text.clone())?.tokens;
+    let bytes = text.len();
+    let stderr = Rc::new(RefCell::new(io::stderr()));
+    let mut vm = Vm::new(Rc::new(RefCell::new(io::sink())), stderr.clone());
+    let mut parser = Parser::new(text, stderr);
+    let func = match parser.parse(&mut vm, "<script>") {
+        Some(func) => func,
+        None => bail!("compile error"),
+    };
+    let start = Instant::now();
+    vm.run(func)?;
+    Ok(Benchmark::new("vm", tokens, bytes, start.elapsed()))
+}
 
 impl LoxFunction {
     pub(crate) fn new(name: &str) -> Self {
@@ -70,13 +347,197 @@ impl LoxFunction {
             name: name.to_string(),
             arity: 0,
             chunk: Chunk::default(),
+            line: 0,
+            doc: None,
+            max_slots: 0,
+            #[cfg(feature = "check_stack")]
+            depths: Vec::new(),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
+            #[cfg(feature = "profiling")]
+            profile_id: Default::default(),
+        }
+    }
+
+    // Works out the stack space the finished chunk needs, failing if its
+    // stack use is inconsistent.
+    pub(crate) fn check_stack(&mut self) -> anyhow::Result<()> {
+        let depths = self.chunk.stack_depths(self.arity)?;
+        self.max_slots = depths
+            .iter()
+            .filter(|&&d| d != u32::MAX)
+            .max()
+            .copied()
+            .unwrap_or(0);
+        #[cfg(feature = "check_stack")]
+        {
+            self.depths = depths;
+        }
+        Ok(())
+    }
+
+    /// The function's name, qualified by the names of any functions it is
+    /// nested in ("outer.inner").
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn deserialize(
+        r: &mut Reader,
+        heap: &mut Heap,
+    ) -> anyhow::Result<Self> {
+        let mut func = LoxFunction::new(r.str()?);
+        func.arity = r.u8()? as usize;
+        func.line = r.u32()?;
+        if r.u8()? != 0 {
+            func.doc = Some(r.str()?.to_string());
+        }
+        func.chunk = Chunk::deserialize(r, heap)?;
+        func.check_stack()?;
+        Ok(func)
+    }
+
+    pub(crate) fn serialize(&self, w: &mut Writer) {
+        w.str(&self.name);
+        w.u8(self.arity as u8);
+        w.u32(self.line);
+        match &self.doc {
+            Some(doc) => {
+                w.u8(1);
+                w.str(doc);
+            }
+            None => w.u8(0),
         }
+        self.chunk.serialize(w);
     }
 }
 
 impl Display for LoxFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+        match self.name.as_str() {
+            "<script>" => write!(f, "<script>"),
+            name => write!(f, "<fn {}>", name),
+        }
+    }
+}
+
+thread_local! {
+    // The lists and maps being shown, so that one inside itself is shown as
+    // `[...]` or `{...}` there rather than forever.
+    static SHOWING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+// Shows the object at `addr` with `show`, or `cycle` if it's already being
+// shown.
+fn show_once<F>(
+    f: &mut std::fmt::Formatter<'_>,
+    addr: usize,
+    cycle: &str,
+    show: F,
+) -> std::fmt::Result
+where
+    F: FnOnce(&mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+{
+    if SHOWING.with_borrow(|showing| showing.contains(&addr)) {
+        return write!(f, "{}", cycle);
+    }
+    SHOWING.with_borrow_mut(|showing| showing.push(addr));
+    let result = show(f);
+    SHOWING.with_borrow_mut(|showing| showing.pop());
+    result
+}
+
+impl Display for Obj<LoxList> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        show_once(f, self.addr(), "[...]", |f| {
+            write!(f, "[")?;
+            for (i, item) in self.borrow().items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            write!(f, "]")
+        })
+    }
+}
+
+impl LoxMap {
+    pub(crate) fn get(&self, key: &MapKey) -> Option<&Value> {
+        self.index.get(key).map(|&idx| &self.entries[idx].1)
+    }
+
+    // Replaces the value of an existing key, leaving it where it was.
+    pub(crate) fn insert(
+        &mut self,
+        key: MapKey,
+        key_value: Value,
+        value: Value,
+    ) {
+        match self.index.entry(key) {
+            Entry::Occupied(entry) => self.entries[*entry.get()].1 = value,
+            Entry::Vacant(entry) => {
+                if let MapKey::String(s) = entry.key() {
+                    self.texts += s.len();
+                }
+                entry.insert(self.entries.len());
+                // So that -0 shows as 0, like the key it's found by.
+                let key_value = match key_value {
+                    Value::Number(n) => Value::Number(n + 0.0),
+                    key_value => key_value,
+                };
+                self.entries.push((key_value, value));
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &MapKey) -> Option<Value> {
+        let idx = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(idx);
+        if let MapKey::String(s) = key {
+            self.texts -= s.len();
+        }
+        for later in self.index.values_mut() {
+            if *later > idx {
+                *later -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub(crate) fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    pub(crate) fn texts(&self) -> usize {
+        self.texts
+    }
+}
+
+impl MapKey {
+    // The key `value` is looked up by, if it can be one.
+    pub(crate) fn new(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::Number(n) if n.is_nan() => None,
+            Value::Number(n) => Some(MapKey::Number((n + 0.0).to_bits())),
+            Value::String(s) => Some(MapKey::String(s.borrow().text.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Obj<LoxMap> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        show_once(f, self.addr(), "{...}", |f| {
+            write!(f, "{{")?;
+            for (i, (key, value)) in self.borrow().entries.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", key, value)?;
+            }
+            write!(f, "}}")
+        })
     }
 }
 
@@ -102,27 +563,111 @@ impl Display for LoxString {
     }
 }
 
+impl io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
 impl RuntimeError {
     fn new(msg: String) -> Self {
-        RuntimeError { msg }
+        RuntimeError {
+            msg,
+            line: None,
+            source_line: None,
+            timeout: false,
+            interrupted: false,
+        }
+    }
+
+    fn timeout(msg: String) -> Self {
+        RuntimeError {
+            timeout: true,
+            ..RuntimeError::new(msg)
+        }
+    }
+
+    fn interrupted(msg: String) -> Self {
+        RuntimeError {
+            interrupted: true,
+            ..RuntimeError::new(msg)
+        }
     }
 
     fn with_line(&self, line: u32) -> Self {
         RuntimeError {
             msg: format!("[line {}] {}", line, self.msg),
+            line: Some(line),
+            source_line: self.source_line.clone(),
+            timeout: self.timeout,
+            interrupted: self.interrupted,
         }
     }
+
+    fn with_source(mut self, text: Option<&str>) -> Self {
+        self.source_line = text.map(str::to_string);
+        self
+    }
+
+    /// The line of the script the error happened on.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The text of that line, if the vm was keeping source (see
+    /// [`VmOptions::show_source`]).
+    pub fn source_line(&self) -> Option<&str> {
+        self.source_line.as_deref()
+    }
+
+    /// Whether the script was stopped for running out of fuel (see
+    /// [`Vm::interpret_with_fuel`]).
+    pub fn is_timeout(&self) -> bool {
+        self.timeout
+    }
+
+    /// Whether the script was stopped through an [`InterruptHandle`].
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
 }
 
-impl Display for RustFunction {
+impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+        match &self.source_line {
+            Some(text) => write!(f, "{}\n    {}", self.msg, text),
+            None => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// So that natives registered by the host, which fail with a message, can
+// use `?` on the likes of NativeContext::number_arg.
+impl From<RuntimeError> for String {
+    fn from(e: RuntimeError) -> Self {
+        e.to_string()
+    }
+}
+
+impl RustFunction {
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn accepts(&self, arg_count: usize) -> bool {
+        (self.min_arity..=self.arity).contains(&arg_count)
     }
 }
 
-impl PartialEq for RustFunction {
-    fn eq(&self, other: &Self) -> bool {
-        self.func as usize == other.func as usize
+impl Display for RustFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
     }
 }
 
@@ -154,42 +699,889 @@ impl Vm {
     const MAX_STACK: usize = 65536;
 
     pub fn new(stdout: Stdout, stderr: Stderr) -> Self {
+        Vm::with_options(stdout, stderr, VmOptions::default())
+    }
+
+    pub fn with_options(
+        stdout: Stdout,
+        stderr: Stderr,
+        options: VmOptions,
+    ) -> Self {
+        let mut heap = Heap::new();
+        heap.set_limit(options.max_heap);
+        let empty_string = Value::String(heap.alloc(LoxString::new("")));
         let mut vm = Vm {
-            stdout,
+            options,
+            stdout: io::BufWriter::new(Sink(stdout)),
             stderr,
+            trace: Rc::new(RefCell::new(io::stderr())),
+            stdin: None,
+            heap,
+            scratch: String::new(),
+            empty_string,
             frames: Vec::new(),
             stack: Vec::new(),
             globals: HashMap::new(),
+            isolated: None,
             symbols: SymTable::new(),
+            exports: HashSet::new(),
+            restricted: HashMap::new(),
+            namespace_copies: HashMap::new(),
+            scripts: Vec::new(),
+            safepoints: safepoint::Safepoints::new(),
+            clock: Box::new(native::monotonic),
+            #[cfg(feature = "profiling")]
+            profile: Default::default(),
+            #[cfg(feature = "tracing")]
+            spans: spans::CallSpans::new(),
         };
         vm.add_native("clock", 0, native::clock);
+        vm.add_native("flush", 0, native::flush);
+        vm.add_native("doc", 1, native::doc);
+        vm.add_native("formatNumber", 3, native::format_number);
+        vm.add_native("parseNumber", 1, native::parse_number);
+        vm.add_native("str", 1, native::str);
+        vm.add_native("num", 1, native::num);
+        vm.add_native("keys", 1, native::keys);
+        vm.add_native("remove", 2, native::remove);
+        vm.add_native("len", 1, native::len);
+        vm.add_native("type", 1, native::type_);
+        vm.add_native("substr", 3, native::substr);
+        vm.add_native("indexOf", 2, native::index_of);
+        vm.add_native("split", 2, native::split);
+        vm.add_native("readLine", 0, native::read_line);
+        vm.add_native_optional("assert", 2, 1, native::assert);
         vm
     }
 
-    fn add_native(&mut self, name: &str, arity: usize, func: NativeFn) {
+    /// Run `hook` at every safepoint (see [`Vm::set_safepoint_interval`]).
+    pub fn add_safepoint_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&Safepoint) -> std::result::Result<(), String> + 'static,
+    {
+        self.safepoints.add_hook(Box::new(hook));
+    }
+
+    /// A handle for stopping the scripts this vm runs, polled at each
+    /// safepoint.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.safepoints.interrupt_handle()
+    }
+
+    /// Reach a safepoint every `interval` instructions (1024 by default).
+    pub fn set_safepoint_interval(&mut self, interval: u32) {
+        self.safepoints.set_interval(interval);
+    }
+
+    /// Have only one in every `every` calls traced as a `call` span, and
+    /// none more than `max_depth` calls deep, to bound what tracing costs
+    /// a script that makes many calls. An `every` of 0 turns call spans
+    /// off. By default every call is traced.
+    #[cfg(feature = "tracing")]
+    pub fn set_call_spans(&mut self, every: u32, max_depth: usize) {
+        self.spans.set_sampling(every, max_depth);
+    }
+
+    /// Call the global function `name` with `args`, returning its result.
+    /// Functions defined by a script stay around after it finishes, so
+    /// they can be called any number of times without compiling again.
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: &[HostValue],
+    ) -> Result<HostValue> {
+        let callee = match self.symbols.symbols.get(name) {
+            Some(sym) => self.global(*sym).cloned(),
+            None => None,
+        };
+        let (arity, declared) = match &callee {
+            Some(Value::Function(f)) => {
+                (f.borrow().arity, Some(f.borrow().line))
+            }
+            Some(Value::Builtin(f)) => {
+                let f = f.borrow();
+                if !f.accepts(args.len()) {
+                    return Err(self.native_arity_error(&f, args.len()));
+                }
+                (args.len(), None)
+            }
+            Some(_) => {
+                return Err(self.runtime_error(Message::NotAFunction, &[&name]));
+            }
+            None => {
+                let msg = Message::UndefinedVariable;
+                return Err(self.runtime_error(msg, &[&name]));
+            }
+        };
+        if arity != args.len() {
+            return Err(self.arity_error(name, declared, arity, args.len()));
+        }
+        if let Some(HostValue::Function(arg)) = args
+            .iter()
+            .find(|arg| matches!(arg, HostValue::Function(_)))
+        {
+            let msg = Message::HostFunctionArgument;
+            return Err(self.runtime_error(msg, &[arg]));
+        }
+
+        let callee = callee.unwrap();
+        self.push(callee.clone())?;
+        for arg in args {
+            let arg = self.host_value(arg.clone());
+            self.push(arg)?;
+        }
+        let result = match callee {
+            Value::Function(f) => self.run_from(f)?,
+            Value::Builtin(f) => match self.call_native(&f, args.len()) {
+                Ok(()) => self.pop(),
+                Err(e) => {
+                    self.stack.clear();
+                    return Err(e);
+                }
+            },
+            _ => unreachable!(),
+        };
+        Ok(HostValue::from_value(&result))
+    }
+
+    /// Define the global `name` as a native function taking `arity`
+    /// arguments. Calls with any other number of arguments are runtime
+    /// errors, and so is `func` returning an error or a function. `func`
+    /// is also given a [`NativeContext`] for the call.
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, func: F)
+    where
+        F: Fn(
+                &mut NativeContext,
+                &[HostValue],
+            ) -> std::result::Result<HostValue, String>
+            + 'static,
+    {
+        let sym = self.get_symbol(name);
+        self.register_host_native(sym, name, arity, func);
+    }
+
+    /// Like [`Vm::register_native`], but only for scripts compiled in
+    /// `namespace` (see [`VmOptions::namespace`]), where it hides any
+    /// native of the same name outside the namespace.
+    pub fn register_native_in<F>(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        arity: usize,
+        func: F,
+    ) where
+        F: Fn(
+                &mut NativeContext,
+                &[HostValue],
+            ) -> std::result::Result<HostValue, String>
+            + 'static,
+    {
+        let sym = self.get_symbol(&format!("{}::{}", namespace, name));
+        self.register_host_native(sym, name, arity, func);
+    }
+
+    /// Hide the globals outside `namespace` from the scripts compiled in
+    /// it, except for those named in `visible`, such as the natives they
+    /// are allowed to call, which the scripts can't assign. A list or map
+    /// among them is copied for the namespace the first time its scripts
+    /// read it, so they can't change the original. Globals of the
+    /// namespace's own, including its imports and natives from
+    /// [`Vm::register_native_in`], stay visible.
+    pub fn restrict_namespace(&mut self, namespace: &str, visible: &[&str]) {
+        let visible = visible.iter().map(|name| self.get_symbol(name));
+        let visible = visible.collect();
+        self.restricted.insert(namespace.into(), visible);
+    }
+
+    fn register_host_native<F>(
+        &mut self,
+        sym: u32,
+        name: &str,
+        arity: usize,
+        func: F,
+    ) where
+        F: Fn(
+                &mut NativeContext,
+                &[HostValue],
+            ) -> std::result::Result<HostValue, String>
+            + 'static,
+    {
+        let fn_name = name.to_string();
+        self.define_native(sym, name, arity..=arity, move |ctx| {
+            let args: Vec<_> =
+                ctx.args().iter().map(HostValue::from_value).collect();
+            match func(ctx, &args) {
+                Ok(HostValue::Function(returned)) => {
+                    let msg = Message::NativeReturnedFunction;
+                    Err(ctx.error(msg, &[&fn_name, &returned]))
+                }
+                Ok(value) => Ok(ctx.host_value(value)),
+                Err(msg) => Err(RuntimeError::new(msg)),
+            }
+        });
+    }
+
+    /// Have `clock()` read the time from `clock` instead of the system's
+    /// monotonic clock.
+    pub fn set_clock<F>(&mut self, clock: F)
+    where
+        F: Fn() -> f64 + 'static,
+    {
+        self.clock = Box::new(clock);
+    }
+
+    fn add_native<F>(&mut self, name: &str, arity: usize, func: F)
+    where
+        F: Fn(&mut NativeContext) -> Result<Value> + 'static,
+    {
+        let sym = self.get_symbol(name);
+        self.define_native(sym, name, arity..=arity, func);
+    }
+
+    // Like `add_native`, but the last `optional` arguments can be left out.
+    fn add_native_optional<F>(
+        &mut self,
+        name: &str,
+        arity: usize,
+        optional: usize,
+        func: F,
+    ) where
+        F: Fn(&mut NativeContext) -> Result<Value> + 'static,
+    {
+        let sym = self.get_symbol(name);
+        self.define_native(sym, name, arity - optional..=arity, func);
+    }
+
+    // Makes the global `sym` a native function, taking any number of
+    // arguments in `arity`, which reports errors as `name`.
+    fn define_native<F>(
+        &mut self,
+        sym: u32,
+        name: &str,
+        arity: RangeInclusive<usize>,
+        func: F,
+    ) where
+        F: Fn(&mut NativeContext) -> Result<Value> + 'static,
+    {
         let native_fn = RustFunction {
             name: name.to_string(),
-            arity,
-            func,
+            arity: *arity.end(),
+            min_arity: *arity.start(),
+            func: Box::new(func),
         };
-        let sym = self.get_symbol(name);
-        self.globals.insert(sym, Value::Builtin(native_fn.into()));
+        #[cfg(feature = "log")]
+        if arity.start() == arity.end() {
+            log::trace!("registered native {}, arity {}", name, arity.end());
+        } else {
+            let (min, max) = (arity.start(), arity.end());
+            log::trace!("registered native {}, arity {} to {}", name, min, max);
+        }
+        let native_fn = self.alloc(native_fn);
+        self.globals.insert(sym, Value::Builtin(native_fn));
+    }
+
+    // Returns the quickened opcode for the operand types.
+    fn add(&mut self) -> Result<Opcode> {
+        let b = self.pop();
+        let a = self.peek(0);
+        match (&a, &b) {
+            (&Value::Number(a), &Value::Number(b)) => {
+                self.poke(0, Value::Number(a + b))?;
+                Ok(Op::AddNumber)
+            }
+            (Value::String(a), Value::String(b)) => {
+                self.check_string(a.borrow().len() + b.borrow().len())?;
+                let text = [a.borrow().as_ref(), b.borrow().as_ref()].concat();
+                let value = Value::String(self.alloc(LoxString::new(&text)));
+                self.poke(0, value)?;
+                Ok(Op::AddString)
+            }
+            _ => {
+                self.pop();
+                let msg = Message::OperandsNumbersOrStrings;
+                Err(self.operand_error(msg, &[&a, &b]))
+            }
+        }
+    }
+
+    // Objects are only freed by a collection, which happens between
+    // instructions; see gc.rs.
+    pub(crate) fn alloc<T: Trace>(&mut self, value: T) -> Obj<T> {
+        self.heap.alloc(value)
     }
 
     fn arithmetic_args(&mut self) -> Result<(f64, f64)> {
         let b = self.pop();
         let a = self.peek(0);
-        match (a, b) {
-            (Value::Number(a), Value::Number(b)) => Ok((a, b)),
+        match (&a, &b) {
+            (&Value::Number(a), &Value::Number(b)) => Ok((a, b)),
             _ => {
                 self.pop();
-                Err(RuntimeError::new("operands must be numbers".to_string()))
+                Err(self.operand_error(Message::OperandsNumbers, &[&a, &b]))
+            }
+        }
+    }
+
+    // Errors if a list of `len` items would be over the limit.
+    pub(crate) fn check_list(&self, len: usize) -> Result<()> {
+        match self.options.max_list_items {
+            Some(max) if len > max => {
+                Err(self.runtime_error(Message::ListTooLong, &[&len, &max]))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Errors if a string of `len` bytes would be over the limit.
+    pub(crate) fn check_string(&self, len: usize) -> Result<()> {
+        match self.options.max_string_bytes {
+            Some(max) if len > max => {
+                Err(self.runtime_error(Message::StringTooLong, &[&len, &max]))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // `declared` is the line of the callee's declaration, for functions
+    // written in Lox.
+    fn arity_error(
+        &self,
+        callee: &str,
+        declared: Option<u32>,
+        arity: usize,
+        arg_count: usize,
+    ) -> RuntimeError {
+        let messages = &self.options.messages;
+        let msg = match self.options.verbose_errors {
+            true => messages.format(
+                Message::CalleeArgumentCount,
+                &[&callee, &arity, &arg_count],
+            ),
+            false => {
+                messages.format(Message::ArgumentCount, &[&arity, &arg_count])
+            }
+        };
+        let msg = match declared {
+            Some(line) if self.options.verbose_errors => {
+                messages.format(Message::DeclaredOnLine, &[&msg, &line])
+            }
+            Some(line) => messages
+                .format(Message::CalleeDeclaredOnLine, &[&msg, &callee, &line]),
+            None => msg,
+        };
+        RuntimeError::new(msg)
+    }
+
+    fn native_arity_error(
+        &self,
+        native: &RustFunction,
+        arg_count: usize,
+    ) -> RuntimeError {
+        if native.min_arity == native.arity {
+            return self.arity_error(
+                &native.name,
+                None,
+                native.arity,
+                arg_count,
+            );
+        }
+        let (min, max) = (native.min_arity, native.arity);
+        let messages = &self.options.messages;
+        RuntimeError::new(match self.options.verbose_errors {
+            true => messages.format(
+                Message::CalleeArgumentRange,
+                &[&native.name, &min, &max, &arg_count],
+            ),
+            false => messages
+                .format(Message::ArgumentRange, &[&min, &max, &arg_count]),
+        })
+    }
+
+    // Calls the callee below the top `arg_count` values, for the generic
+    // Call `inst` in `chunk`, which ends at `next`. A Lox function the jit
+    // doesn't run is returned as the frame to run next. Errors are located.
+    fn call_value(
+        &mut self,
+        chunk: &Chunk,
+        current: usize,
+        inst: Instruction,
+        next: usize,
+    ) -> Result<Option<Frame>> {
+        let arg_count = inst.operand() as usize;
+        let result = match self.peek(arg_count) {
+            Value::Function(f) => {
+                let arity = f.borrow().arity;
+                if arity != arg_count {
+                    let f = f.borrow();
+                    Err(self.arity_error(
+                        &f.name,
+                        Some(f.line),
+                        arity,
+                        arg_count,
+                    ))
+                } else if let Some(result) =
+                    self.call_compiled(&f, arg_count)
+                {
+                    // An error is already located in `f`.
+                    let v = result?;
+                    let new_len = self.stack.len() - arg_count - 1;
+                    self.stack.truncate(new_len);
+                    self.push(v)
+                } else if self.stack.len() - arg_count - 1
+                    + f.borrow().max_slots as usize
+                    > Vm::MAX_STACK
+                {
+                    self.error(Message::StackOverflow, &[])
+                } else {
+                    self.frames[current].offset = next;
+                    return Ok(Some(Frame {
+                        func: f,
+                        base: self.stack.len() - arg_count - 1,
+                        offset: 0,
+                    }));
+                }
+            }
+            Value::Builtin(f) => {
+                if !f.borrow().accepts(arg_count) {
+                    let f = f.borrow();
+                    Err(self.native_arity_error(&f, arg_count))
+                } else {
+                    chunk.quicken(next - 1, Op::CallNative);
+                    self.call_native(&f, arg_count)
+                }
+            }
+            callee => {
+                let msg = Message::NotCallable;
+                Err(self.operand_error(msg, &[&callee]))
+            }
+        };
+        result
+            .map(|_| None)
+            .map_err(|e| self.locate(e, chunk, next - inst.len()))
+    }
+
+    // Calls `func`, whose arity has been checked, on the top `arg_count`
+    // values.
+    fn call_native(
+        &mut self,
+        func: &Obj<RustFunction>,
+        arg_count: usize,
+    ) -> Result<()> {
+        let native = func.borrow();
+        let mut ctx = NativeContext::new(self, &native.name, arg_count);
+        let value = (native.func)(&mut ctx)?;
+        self.stack.truncate(self.stack.len() - arg_count - 1);
+        self.push(value)
+    }
+
+    // The result of calling `func` on the top `arg_count` values, if the jit
+    // can handle it. An error is located in `func`, where it stopped.
+    #[cfg(feature = "jit")]
+    fn call_compiled(
+        &mut self,
+        func: &Obj<LoxFunction>,
+        arg_count: usize,
+    ) -> Option<Result<Value>> {
+        // Compiled code has no instructions to trace.
+        if cfg!(feature = "trace_execution") {
+            return None;
+        }
+        let callee = func.borrow();
+        let args = &self.stack[self.stack.len() - arg_count..];
+        let code = callee.jit.code(&callee.chunk, args)?;
+        #[cfg(feature = "log")]
+        log::debug!(
+            "call {} compiled at depth {}",
+            callee.name,
+            self.frames.len()
+        );
+        #[cfg(feature = "tracing")]
+        self.spans.enter(&callee, self.frames.len());
+        let messages = &self.options.messages;
+        let result = code.call(args, &mut self.safepoints, messages);
+        #[cfg(feature = "tracing")]
+        self.spans.exit();
+        #[cfg(feature = "profiling")]
+        code.take_counts(|offset, op, types, n| {
+            self.profile
+                .count(func, &callee.chunk, op, offset, types, n)
+        });
+        Some(
+            result.map_err(|(e, offset)| self.locate(e, &callee.chunk, offset)),
+        )
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn call_compiled(
+        &mut self,
+        _: &Obj<LoxFunction>,
+        _: usize,
+    ) -> Option<Result<Value>> {
+        None
+    }
+
+    // Frees every object the running script can no longer reach.
+    fn collect_garbage(&mut self) {
+        let frames = self
+            .frames
+            .iter()
+            .map(|f| &f.func)
+            .chain(self.scripts.iter().map(|(_, script)| script))
+            .map(|func| Value::Function(func.clone()));
+        let globals = self.isolated.iter().flat_map(|child| child.values());
+        let copies = self
+            .namespace_copies
+            .values()
+            .flat_map(|(original, copy)| [original, copy]);
+        let roots = self
+            .stack
+            .iter()
+            .chain(self.globals.values())
+            .chain(globals)
+            .chain(copies)
+            .chain([&self.empty_string])
+            .cloned()
+            .chain(frames);
+        self.heap.collect(roots);
+    }
+
+    fn error(&self, msg: Message, args: &[&dyn Display]) -> Result<()> {
+        Err(self.runtime_error(msg, args))
+    }
+
+    /// Write every object reachable from the vm's roots (globals, the
+    /// stack, running functions and compiled scripts) to `w`, as a graphviz
+    /// digraph with each object's type and size and the references between
+    /// them.
+    pub fn dump_heap<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let name = |sym: &u32| &*self.symbols.names[*sym as usize];
+        let mut globals: Vec<_> = self.globals.iter().collect();
+        globals.sort_by_key(|(sym, _)| name(sym));
+        let mut roots: Vec<(String, Value)> = globals
+            .into_iter()
+            .map(|(sym, value)| {
+                (format!("global {}", name(sym)), value.clone())
+            })
+            .collect();
+        if let Some(isolated) = &self.isolated {
+            let mut isolated: Vec<_> = isolated.iter().collect();
+            isolated.sort_by_key(|(sym, _)| name(sym));
+            roots.extend(isolated.into_iter().map(|(sym, value)| {
+                (format!("isolated global {}", name(sym)), value.clone())
+            }));
+        }
+        let mut copies: Vec<_> = self.namespace_copies.iter().collect();
+        copies.sort_by_key(|(sym, _)| name(sym));
+        for (sym, (original, copy)) in copies {
+            roots
+                .push((format!("copied from {}", name(sym)), original.clone()));
+            roots.push((format!("copy {}", name(sym)), copy.clone()));
+        }
+        let stack = self.stack.iter().enumerate();
+        roots.extend(stack.map(|(i, v)| (format!("stack {}", i), v.clone())));
+        let frames = self.frames.iter().enumerate();
+        roots.extend(frames.map(|(i, frame)| {
+            (format!("frame {}", i), Value::Function(frame.func.clone()))
+        }));
+        roots.extend(self.scripts.iter().map(|(id, script)| {
+            (
+                format!("compiled script {}", id),
+                Value::Function(script.clone()),
+            )
+        }));
+        roots.push(("empty string".to_string(), self.empty_string.clone()));
+        dump::write_dot(w, roots)
+    }
+
+    /// The keywords and global names (including natives) that start with
+    /// `prefix`, sorted, for completing identifiers in a REPL.
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        let globals = self
+            .globals
+            .keys()
+            .map(|&sym| self.symbols.names[sym as usize].to_string());
+        let mut names: Vec<String> = keywords(self.options.dialect)
+            .map(str::to_string)
+            .chain(globals)
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The doc comment of the global function `name`, if it has one.
+    pub fn doc(&self, name: &str) -> Option<String> {
+        let sym = self.symbols.symbols.get(name)?;
+        match self.globals.get(sym)? {
+            Value::Function(func) => func.borrow().doc.clone(),
+            _ => None,
+        }
+    }
+
+    /// Compile scripts from now on in `namespace`, or in none; see
+    /// [`VmOptions::namespace`].
+    pub fn set_namespace(&mut self, namespace: Option<&str>) {
+        self.options.namespace = namespace.map(str::to_string);
+    }
+
+    /// Define (or redefine) the global `name`, for scripts run afterwards
+    /// to use. Functions can't be defined this way.
+    pub fn define_global<T>(&mut self, name: &str, value: T) -> Result<()>
+    where
+        T: Into<HostValue>,
+    {
+        let value = match value.into() {
+            HostValue::Function(name) => {
+                return self.error(Message::HostFunction, &[&name]);
+            }
+            value => self.host_value(value),
+        };
+        let sym = self.symbols.intern(name);
+        self.define(sym, value);
+        Ok(())
+    }
+
+    /// The value of the global `name`, if there is one, as a script run
+    /// before left it.
+    pub fn get_global(&self, name: &str) -> Option<HostValue> {
+        let sym = self.symbols.symbols.get(name)?;
+        self.global(*sym).map(HostValue::from_value)
+    }
+
+    /// Send what scripts print to `stdout`, and errors to `stderr`, from now
+    /// on. Anything printed to the old stdout is flushed first.
+    pub fn set_output(
+        &mut self,
+        stdout: Stdout,
+        stderr: Stderr,
+    ) -> io::Result<()> {
+        self.stdout.flush()?;
+        self.stdout = io::BufWriter::new(Sink(stdout));
+        self.stderr = stderr;
+        Ok(())
+    }
+
+    /// Write the code listings of the `print_code` feature and the traces
+    /// of the `trace_execution` feature to `trace`, instead of the
+    /// process's stderr.
+    pub fn set_trace_output(&mut self, trace: Stderr) {
+        self.trace = trace;
+    }
+
+    #[cfg(feature = "print_code")]
+    pub(crate) fn trace_output(&self) -> Stderr {
+        self.trace.clone()
+    }
+
+    /// Have `readLine()` read from `stdin` from now on, instead of the
+    /// process's stdin.
+    pub fn set_input(&mut self, stdin: Stdin) {
+        self.stdin = Some(stdin);
+    }
+
+    /// Write out everything printed so far. This happens anyway whenever a
+    /// script finishes.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+
+    // Functions can't be made from a `HostValue`; they come out as nil.
+    fn host_value(&mut self, value: HostValue) -> Value {
+        match value {
+            HostValue::Nil | HostValue::Function(_) => Value::Nil,
+            HostValue::Bool(b) => Value::Boolean(b),
+            HostValue::Number(n) => Value::Number(n),
+            HostValue::String(s) => {
+                Value::String(self.alloc(LoxString::new(&s)))
+            }
+            HostValue::List(items) => {
+                let items = items.into_iter().map(|v| self.host_value(v));
+                let items = items.collect();
+                Value::List(self.alloc(LoxList { items }))
+            }
+            // Entries whose keys can't be map keys are left out.
+            HostValue::Map(entries) => {
+                let mut map = LoxMap::default();
+                for (key, value) in entries {
+                    let key = self.host_value(key);
+                    if let Some(map_key) = MapKey::new(&key) {
+                        let value = self.host_value(value);
+                        map.insert(map_key, key, value);
+                    }
+                }
+                Value::Map(self.alloc(map))
             }
         }
     }
 
-    fn error(msg: &str) -> Result<()> {
-        Err(RuntimeError::new(msg.to_string()))
+    pub(crate) fn has_global(&self, sym: u32) -> bool {
+        self.global(sym).is_some()
+    }
+
+    fn global(&self, sym: u32) -> Option<&Value> {
+        match self.isolated.as_ref().and_then(|child| child.get(&sym)) {
+            Some(val) => Some(val),
+            None => match self.globals.get(&sym) {
+                Some(val) => Some(val),
+                None => self.global(self.outer_symbol(sym)?),
+            },
+        }
+    }
+
+    // Like `global`, for a running script to use. Lists and maps that an
+    // isolated script, or a script in a restricted namespace, reads from
+    // globals that aren't its own are copied the first time, so that
+    // changing them leaves the originals alone.
+    fn read_global(&mut self, sym: u32) -> Option<Value> {
+        if let Some(child) = &self.isolated {
+            if let Some(val) = child.get(&sym) {
+                return Some(val.clone());
+            }
+        }
+        if let Some(val) = self.globals.get(&sym).cloned() {
+            if self.isolated.is_none() || !val.is_container() {
+                return Some(val);
+            }
+            let copy = self.copy_value(&val, &mut HashMap::new());
+            self.define(sym, copy.clone());
+            return Some(copy);
+        }
+        let val = self.read_global(self.outer_symbol(sym)?)?;
+        if !self.is_restricted(sym) || !val.is_container() {
+            return Some(val);
+        }
+        match self.namespace_copies.get(&sym) {
+            Some((original, copy)) if *original == val => Some(copy.clone()),
+            _ => {
+                let copy = self.copy_value(&val, &mut HashMap::new());
+                self.namespace_copies.insert(sym, (val, copy.clone()));
+                Some(copy)
+            }
+        }
+    }
+
+    // A copy of `value` that shares no list or map with it. `copies` has
+    // the copy of each list or map copied so far, by address, so that one
+    // that appears twice, or inside itself, is only copied once.
+    fn copy_value(
+        &mut self,
+        value: &Value,
+        copies: &mut HashMap<usize, Value>,
+    ) -> Value {
+        match value {
+            Value::List(list) => {
+                if let Some(copy) = copies.get(&list.addr()) {
+                    return copy.clone();
+                }
+                let copy = self.alloc(LoxList { items: Vec::new() });
+                copies.insert(list.addr(), Value::List(copy.clone()));
+                let items = list.borrow().items.clone();
+                let items =
+                    items.iter().map(|item| self.copy_value(item, copies));
+                copy.borrow_mut().items = items.collect();
+                self.heap.resize(&copy);
+                Value::List(copy)
+            }
+            Value::Map(map) => {
+                if let Some(copy) = copies.get(&map.addr()) {
+                    return copy.clone();
+                }
+                let copy = self.alloc(LoxMap::default());
+                copies.insert(map.addr(), Value::Map(copy.clone()));
+                let entries = map.borrow().entries().to_vec();
+                for (key, value) in entries {
+                    let value = self.copy_value(&value, copies);
+                    let map_key = MapKey::new(&key).unwrap();
+                    copy.borrow_mut().insert(map_key, key, value);
+                }
+                self.heap.resize(&copy);
+                Value::Map(copy)
+            }
+            value => value.clone(),
+        }
+    }
+
+    // Whether `sym` belongs to a restricted namespace.
+    fn is_restricted(&self, sym: u32) -> bool {
+        let name = &self.symbols.names[sym as usize];
+        let namespace = name.rsplit_once("::").map(|(ns, _)| ns);
+        namespace.is_some_and(|ns| self.restricted.contains_key(ns))
+    }
+
+    // For `ns::name`, the vm's symbol for plain `name`, which is what a
+    // script in namespace `ns` gets when it hasn't defined its own, unless
+    // `ns` is restricted from seeing it.
+    fn outer_symbol(&self, sym: u32) -> Option<u32> {
+        let name = &self.symbols.names[sym as usize];
+        let (namespace, name) = name.rsplit_once("::")?;
+        let outer = *self.symbols.symbols.get(name)?;
+        match self.restricted.get(namespace) {
+            Some(visible) if !visible.contains(&outer) => None,
+            _ => Some(outer),
+        }
+    }
+
+    fn define(&mut self, sym: u32, val: Value) {
+        match &mut self.isolated {
+            Some(child) => child.insert(sym, val),
+            None => self.globals.insert(sym, val),
+        };
+    }
+
+    // Fails with the error to report if global `sym` can't be assigned.
+    fn set_global(
+        &mut self,
+        sym: u32,
+        val: Value,
+    ) -> std::result::Result<(), Message> {
+        if let Some(child) = &mut self.isolated {
+            // The first assignment to a base global copies it into the
+            // child, leaving the base value alone.
+            if child.contains_key(&sym) || self.globals.contains_key(&sym) {
+                child.insert(sym, val);
+                return Ok(());
+            }
+        } else if let Entry::Occupied(mut entry) = self.globals.entry(sym) {
+            entry.insert(val);
+            return Ok(());
+        }
+        // A script in a namespace never assigns the outer global it reads
+        // through `outer_symbol`, which other namespaces share; it gets a
+        // copy of its own instead, unless the namespace is restricted, for
+        // which the globals it was let see are read-only.
+        match self.outer_symbol(sym) {
+            Some(outer) if self.has_global(outer) => {
+                if self.is_restricted(sym) {
+                    return Err(Message::ReadOnlyGlobal);
+                }
+                self.define(sym, val);
+                Ok(())
+            }
+            _ => Err(Message::UndefinedVariable),
+        }
+    }
+
+    // The name a script used for global `sym`, without the namespace the
+    // compiler qualified it with.
+    fn global_name(&self, sym: u32) -> &str {
+        let name = &self.symbols.names[sym as usize];
+        match name.rsplit_once("::") {
+            Some((_, name)) => name,
+            None => name,
+        }
+    }
+
+    pub(crate) fn export(&mut self, sym: u32) {
+        self.exports.insert(sym);
+    }
+
+    pub(crate) fn is_exported(&self, sym: u32) -> bool {
+        self.exports.contains(&sym)
+    }
+
+    pub(crate) fn options(&self) -> &VmOptions {
+        &self.options
     }
 
     pub(crate) fn get_sym_name(&self, sym: u32) -> Rc<str> {
@@ -204,7 +1596,194 @@ impl Vm {
         self.symbols.intern(ident)
     }
 
+    /// Like interpret, but reuses the compiled form of `source` from `cache`
+    /// when there is one, and saves it there when there isn't, unless
+    /// compiling it gave warnings.
+    ///
+    /// Only the source and the options decide whether a saved script is
+    /// reused, not the globals of the vm that compiled it. Checks that
+    /// depend on those, such as strict mode's errors for assigning
+    /// undeclared globals, are skipped for a script cached by a vm that had
+    /// the globals but run on one that doesn't; so a host that defines
+    /// globals of its own before running scripts should define the same
+    /// ones on every vm that shares a cache.
+    pub fn interpret_cached(
+        &mut self,
+        source: String,
+        cache: &BytecodeCache,
+    ) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("interpret").entered();
+        let key = self.options.compile_key();
+        let cached = match self.options.check_source(&source) {
+            Ok(()) => cache.load(&source, &key, &mut self.heap),
+            // Left to the parser to report.
+            Err(_) => None,
+        };
+        if let Some(program) = cached {
+            if let Ok(mut script) = program.link(self) {
+                if self.options.show_source {
+                    script.chunk.set_source(source.into());
+                }
+                return self.run(script);
+            }
+        }
+        let mut parser = Parser::new(source.clone(), self.stderr.clone());
+        match parser.parse(self, "<script>") {
+            Some(script) => {
+                let program = Program::new(script, &self.symbols.names);
+                // A cached program would run without its warnings.
+                if !parser.had_warning() {
+                    cache.store(&source, &key, &program);
+                }
+                self.run(program.script)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // The value of `object[index]`, which for a map is nil if it has no
+    // such key.
+    fn get_index(&self, object: &Value, index: &Value) -> Result<Value> {
+        match object {
+            Value::List(list) => {
+                let idx = self.list_index(list, index)?;
+                Ok(list.borrow().items[idx].clone())
+            }
+            Value::Map(map) => {
+                let key = self.map_key(index)?;
+                Ok(map.borrow().get(&key).cloned().unwrap_or(Value::Nil))
+            }
+            _ => Err(self.index_error(object)),
+        }
+    }
+
+    fn set_index(
+        &mut self,
+        object: &Value,
+        index: Value,
+        item: Value,
+    ) -> Result<()> {
+        match object {
+            Value::List(list) => {
+                let idx = self.list_index(list, &index)?;
+                list.borrow_mut().items[idx] = item;
+            }
+            Value::Map(map) => {
+                let key = self.map_key(&index)?;
+                map.borrow_mut().insert(key, index, item);
+                self.heap.resize(map);
+            }
+            _ => return Err(self.index_error(object)),
+        }
+        Ok(())
+    }
+
+    fn index_error(&self, object: &Value) -> RuntimeError {
+        self.operand_error(Message::NotIndexable, &[object])
+    }
+
+    // Steps a for-in loop: the sequence is in `slot` and the index of the
+    // next item in the slot after it. Pushes the item (or nil) and whether
+    // there was one.
+    fn iter_next(&mut self, slot: usize) -> Result<()> {
+        let Value::Number(n) = self.stack[slot + 1] else {
+            return Err(self.runtime_error(Message::ForInIndex, &[]));
+        };
+        let idx = n as usize;
+        let item = match &self.stack[slot] {
+            Value::List(list) => list.borrow().items.get(idx).cloned(),
+            Value::Map(map) => {
+                map.borrow().entries().get(idx).map(|(key, _)| key.clone())
+            }
+            sequence => {
+                let msg = Message::NotIterable;
+                return Err(self.operand_error(msg, &[sequence]));
+            }
+        };
+        let more = item.is_some();
+        if more {
+            self.stack[slot + 1] = Value::Number(n + 1.0);
+        }
+        self.push(item.unwrap_or(Value::Nil))?;
+        self.push(Value::Boolean(more))
+    }
+
+    // The position in `list` that `list[index]` refers to.
+    fn list_index(&self, list: &Obj<LoxList>, index: &Value) -> Result<usize> {
+        let Value::Number(n) = *index else {
+            let msg = Message::IndexNotNumber;
+            return Err(self.operand_error(msg, &[index]));
+        };
+        if n.fract() != 0.0 {
+            return Err(self.runtime_error(Message::IndexNotWhole, &[]));
+        }
+        let len = list.borrow().items.len();
+        if n < 0.0 || n >= len as f64 {
+            let msg = Message::IndexOutOfBounds;
+            return Err(self.runtime_error(msg, &[&n, &len]));
+        }
+        Ok(n as usize)
+    }
+
+    fn map_key(&self, key: &Value) -> Result<MapKey> {
+        match (key, MapKey::new(key)) {
+            (_, Some(key)) => Ok(key),
+            (Value::Number(_), None) => {
+                Err(self.runtime_error(Message::MapKeyNaN, &[]))
+            }
+            _ => Err(self.operand_error(Message::MapKeyType, &[key])),
+        }
+    }
+
+    // A map of each key in `pairs` to the value after it; a later key
+    // replaces an earlier one.
+    fn new_map(&mut self, pairs: &[Value]) -> Result<Obj<LoxMap>> {
+        let mut map = LoxMap::default();
+        for pair in pairs.chunks(2) {
+            let key = self.map_key(&pair[0])?;
+            map.insert(key, pair[0].clone(), pair[1].clone());
+        }
+        Ok(self.alloc(map))
+    }
+
+    // Add the line of the instruction at `offset` to an error that is
+    // ending the script.
+    fn locate(
+        &mut self,
+        e: RuntimeError,
+        chunk: &Chunk,
+        offset: usize,
+    ) -> RuntimeError {
+        self.stack.clear();
+        let line = chunk.get_line(offset);
+        e.with_line(line).with_source(chunk.source_line(line))
+    }
+
+    fn operand_error(&self, msg: Message, operands: &[&Value]) -> RuntimeError {
+        if self.options.verbose_errors {
+            let types: Vec<_> =
+                operands.iter().map(|v| v.type_name()).collect();
+            let msg = self.options.messages.format(msg, &[]);
+            let types = types.join(" and ");
+            self.runtime_error(Message::OperandTypes, &[&msg, &types])
+        } else {
+            self.runtime_error(msg, &[])
+        }
+    }
+
+    // An error with the text of `msg`, from the catalog if it has it.
+    fn runtime_error(
+        &self,
+        msg: Message,
+        args: &[&dyn Display],
+    ) -> RuntimeError {
+        RuntimeError::new(self.options.messages.format(msg, args))
+    }
+
     pub fn interpret(&mut self, source: String) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("interpret").entered();
         let mut parser = Parser::new(source, self.stderr.clone());
         match parser.parse(self, "<script>") {
             Some(func) => self.run(func),
@@ -212,6 +1791,124 @@ impl Vm {
         }
     }
 
+    /// Like interpret, but stop the script with an "out of fuel" error
+    /// (see [`RuntimeError::is_timeout`]) if it tries to run more than
+    /// `fuel` instructions.
+    pub fn interpret_with_fuel(
+        &mut self,
+        source: String,
+        fuel: u64,
+    ) -> Result<()> {
+        self.safepoints.set_fuel(Some(fuel));
+        let result = self.interpret(source);
+        self.safepoints.set_fuel(None);
+        result
+    }
+
+    /// Like interpret, but for a REPL: if `source` is a single expression,
+    /// with or without a ';', its value is printed.
+    pub fn interpret_repl(&mut self, source: String) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("interpret").entered();
+        let mut parser = Parser::repl(source, self.stderr.clone());
+        match parser.parse(self, "<script>") {
+            Some(func) => self.run(func),
+            None => Ok(()),
+        }
+    }
+
+    /// Like interpret, but any globals the script defines or assigns are
+    /// kept apart from the vm's own, and dropped when it finishes. The
+    /// script can still read the vm's globals; assigning one gives the
+    /// script its own copy, and so does reading a list or map, so changing
+    /// its items leaves the vm's alone.
+    pub fn interpret_isolated(&mut self, source: String) -> Result<()> {
+        let outer = self.isolated.replace(HashMap::new());
+        let result = self.interpret(source);
+        self.isolated = outer;
+        result
+    }
+
+    /// Compile `source` without running it, returning the serialized script
+    /// for [`Vm::run_compiled`], or None if there were compile errors.
+    pub fn compile(&mut self, source: String) -> Option<Vec<u8>> {
+        let mut parser = Parser::new(source, self.stderr.clone());
+        let script = parser.parse(self, "<script>")?;
+        Some(Program::new(script, &self.symbols.names).serialize())
+    }
+
+    /// Compile `source` once for [`Vm::run_script`] to run as often as
+    /// needed, or return None if there were compile errors.
+    pub fn compile_script(&mut self, source: String) -> Option<CompiledScript> {
+        let mut parser = Parser::new(source, self.stderr.clone());
+        let script = parser.parse(self, "<script>")?;
+        // Ids are unique across vms, so that no handle is taken for
+        // another's script, or for a script compiled after its own was
+        // released.
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let script = self.alloc(script);
+        self.scripts.push((id, script));
+        Some(CompiledScript(id))
+    }
+
+    /// Run a script from [`Vm::compile_script`] on this vm.
+    pub fn run_script(&mut self, script: &CompiledScript) -> Result<()> {
+        let Some(func) = self.script_function(script) else {
+            return self.error(Message::ForeignScript, &[]);
+        };
+        self.push(Value::Nil).unwrap();
+        self.run_from(func).map(|_| ())
+    }
+
+    // The function compiled for `script`, unless it was released or
+    // compiled by another vm.
+    pub(crate) fn script_function(
+        &self,
+        script: &CompiledScript,
+    ) -> Option<Obj<LoxFunction>> {
+        let found = self.scripts.iter().find(|(id, _)| *id == script.0);
+        found.map(|(_, func)| func.clone())
+    }
+
+    /// Let the garbage collector free a script from [`Vm::compile_script`]
+    /// once nothing running needs it. It can't be run afterwards.
+    pub fn release_script(&mut self, script: &CompiledScript) {
+        self.scripts.retain(|(id, _)| *id != script.0);
+    }
+
+    /// Compile `source` without running it, and write its bytecode along
+    /// with that of every function it declares to stdout. Returns false if
+    /// there were compile errors.
+    pub fn disassemble(&mut self, source: String) -> bool {
+        let mut parser = Parser::new(source, self.stderr.clone());
+        match parser.parse(self, "<script>") {
+            Some(script) => {
+                let listing =
+                    script.chunk.disassemble("<script>", self.get_sym_names());
+                let _ = self.stdout.write_all(listing.as_bytes());
+                let _ = self.flush();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run a script serialized by [`Vm::compile`], possibly on another vm or
+    /// by another version of redlox that reads the same format.
+    pub fn run_compiled(&mut self, program: &[u8]) -> anyhow::Result<()> {
+        let script = Program::deserialize(program, &mut self.heap)?;
+        let script = script.link(self)?;
+        Ok(self.run(script)?)
+    }
+
+    /// The operand types seen so far at each arithmetic and comparison
+    /// instruction.
+    #[cfg(feature = "profiling")]
+    pub fn stats(&self) -> Stats {
+        self.profile.stats()
+    }
+
     fn peek(&self, count: usize) -> Value {
         let idx = self.stack.len() - (count + 1);
         self.stack[idx].clone()
@@ -227,44 +1924,86 @@ impl Vm {
         self.stack.pop().unwrap()
     }
 
+    fn print(&mut self, val: &Value) {
+        use std::fmt::Write as _;
+        self.scratch.clear();
+        let _ = writeln!(self.scratch, "{}", val);
+        let _ = self.stdout.write_all(self.scratch.as_bytes());
+        if self.options.flush == FlushPolicy::Line {
+            let _ = self.flush();
+        }
+    }
+
     fn push(&mut self, val: Value) -> Result<()> {
         if self.stack.len() < Vm::MAX_STACK {
             self.stack.push(val);
             Ok(())
         } else {
-            Vm::error("stack overflow")
+            self.error(Message::StackOverflow, &[])
         }
     }
 
     fn run(&mut self, script: LoxFunction) -> Result<()> {
+        let script = self.alloc(script);
+        self.push(Value::Nil).unwrap();
+        self.run_from(script).map(|_| ())
+    }
+
+    // Runs `func` in a frame at the bottom of the stack, where its slot 0
+    // and arguments have already been pushed, and returns its result.
+    fn run_from(&mut self, func: Obj<LoxFunction>) -> Result<Value> {
+        self.safepoints.clear_interrupt();
+        #[cfg(feature = "log")]
+        log::debug!("call {} at depth 0", func.borrow().name);
+        #[cfg(feature = "tracing")]
+        self.spans.enter(&func.borrow(), 0);
         self.frames.push(Frame {
-            func: script.into(),
+            func,
             base: 0,
             offset: 0,
         });
-        self.push(Value::Nil).unwrap();
         let mut current = 0;
         loop {
             match self.run_frame(current) {
                 Ok(None) => {
                     let frame = self.frames.pop().unwrap();
+                    #[cfg(feature = "log")]
+                    log::debug!("return from {}", frame.func.borrow().name);
+                    #[cfg(feature = "tracing")]
+                    self.spans.exit();
                     let result = self.pop();
                     self.stack.truncate(frame.base);
                     if current == 0 {
-                        break;
+                        let _ = self.flush();
+                        return Ok(result);
                     }
                     self.push(result).unwrap();
                     current -= 1;
                 }
                 Ok(Some(frame)) => {
-                    self.frames.push(frame);
                     current += 1;
+                    #[cfg(feature = "log")]
+                    log::debug!(
+                        "call {} at depth {}",
+                        frame.func.borrow().name,
+                        current
+                    );
+                    #[cfg(feature = "tracing")]
+                    self.spans.enter(&frame.func.borrow(), current);
+                    self.frames.push(frame);
                 }
                 // TODO: stack traces
-                Err(e) => return Err(e),
+                Err(e) => {
+                    #[cfg(feature = "log")]
+                    log::debug!("error unwound {} frames", self.frames.len());
+                    #[cfg(feature = "tracing")]
+                    self.spans.exit_all();
+                    self.frames.clear();
+                    let _ = self.flush();
+                    return Err(e);
+                }
             }
         }
-        Ok(())
     }
 
     fn run_frame(&mut self, current: usize) -> Result<Option<Frame>> {
@@ -275,27 +2014,68 @@ impl Vm {
         let base = self.frames[current].base;
 
         while let Some(inst) = ip.next() {
+            #[cfg(feature = "check_stack")]
+            {
+                let offset = ip.offset - inst.len();
+                let expected = func.borrow().depths[offset] as usize;
+                if self.stack.len() - base != expected {
+                    let msg = format!(
+                        "stack height {} before {}, expected {}",
+                        self.stack.len() - base,
+                        Op::name(inst.opcode()),
+                        expected
+                    );
+                    let e = self.runtime_error(Message::InternalError, &[&msg]);
+                    return Err(self.locate(e, chunk, offset));
+                }
+            }
+
+            if self.heap.wants_collection() {
+                self.collect_garbage();
+                if self.heap.over_limit() {
+                    let e = self.runtime_error(Message::OutOfMemory, &[]);
+                    return Err(self.locate(e, chunk, ip.offset - inst.len()));
+                }
+            }
+
+            self.safepoints
+                .tick(&self.options.messages)
+                .map_err(|e| self.locate(e, chunk, ip.offset - inst.len()))?;
+
             #[cfg(feature = "trace_execution")]
             {
                 self.trace_stack();
-                chunk.disassemble_instruction(
+                chunk.print_instruction(
+                    &self.trace,
                     inst,
                     ip.offset - inst.len(),
                     self.get_sym_names(),
                 );
             }
 
+            #[cfg(feature = "profiling")]
+            self.profile.record(
+                &func,
+                chunk,
+                inst,
+                ip.offset - inst.len(),
+                &self.stack,
+            );
+
             let result = match inst.opcode() {
                 Op::Nil => self.push(Value::Nil),
                 Op::True => self.push(Value::TRUE),
                 Op::False => self.push(Value::FALSE),
+                Op::Zero => self.push(Value::Number(0.0)),
+                Op::One => self.push(Value::Number(1.0)),
+                Op::EmptyString => self.push(self.empty_string.clone()),
                 Op::Pop => {
                     self.pop();
                     Ok(())
                 }
                 Op::Print => {
                     let val = self.pop();
-                    let _ = writeln!(self.stdout.borrow_mut(), "{}", val);
+                    self.print(&val);
                     Ok(())
                 }
                 Op::Return => {
@@ -311,7 +2091,8 @@ impl Vm {
                         Value::Number(v) => self.poke(0, Value::Number(-v)),
                         _ => {
                             self.pop();
-                            Vm::error("operand must be a number")
+                            let msg = Message::OperandNumber;
+                            Err(self.operand_error(msg, &[&arg]))
                         }
                     }
                 }
@@ -327,25 +2108,30 @@ impl Vm {
                     .arithmetic_args()
                     .and_then(|(a, b)| self.poke(0, Value::Boolean(a < b))),
                 Op::Add => {
-                    let b = self.pop();
-                    let a = self.peek(0);
-                    match (a, b) {
-                        (Value::Number(a), Value::Number(b)) => {
+                    self.add().map(|op| chunk.quicken(ip.offset - 1, op))
+                }
+                Op::AddNumber => {
+                    let top = self.stack.len() - 1;
+                    match (&self.stack[top - 1], &self.stack[top]) {
+                        (&Value::Number(a), &Value::Number(b)) => {
+                            self.pop();
                             self.poke(0, Value::Number(a + b))
                         }
-                        (Value::String(a), Value::String(b)) => {
-                            let value = Value::String(
-                                LoxString::new(
-                                    &[a.borrow().as_ref(), b.borrow().as_ref()]
-                                        .concat(),
-                                )
-                                .into(),
-                            );
-                            self.poke(0, value)
+                        _ => {
+                            chunk.quicken(ip.offset - 1, Op::Add);
+                            self.add().map(|_| ())
+                        }
+                    }
+                }
+                Op::AddString => {
+                    let top = self.stack.len() - 1;
+                    match (&self.stack[top - 1], &self.stack[top]) {
+                        (Value::String(_), Value::String(_)) => {
+                            self.add().map(|_| ())
                         }
                         _ => {
-                            self.pop();
-                            Vm::error("operands must be numbers or strings")
+                            chunk.quicken(ip.offset - 1, Op::Add);
+                            self.add().map(|_| ())
                         }
                     }
                 }
@@ -358,50 +2144,91 @@ impl Vm {
                 Op::Divide => self
                     .arithmetic_args()
                     .and_then(|(a, b)| self.poke(0, Value::Number(a / b))),
+                Op::Power => self
+                    .arithmetic_args()
+                    .and_then(|(a, b)| self.poke(0, Value::Number(a.powf(b)))),
+                Op::List => {
+                    let count = inst.operand() as usize;
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.check_list(count).and_then(|_| {
+                        let list = self.alloc(LoxList { items });
+                        self.push(Value::List(list))
+                    })
+                }
+                Op::Map => {
+                    let count = inst.operand() as usize;
+                    let pairs =
+                        self.stack.split_off(self.stack.len() - 2 * count);
+                    self.new_map(&pairs)
+                        .and_then(|map| self.push(Value::Map(map)))
+                }
+                Op::Range => match (self.peek(1), self.peek(0)) {
+                    (Value::Number(_), Value::Number(_)) => Ok(()),
+                    (start, end) => {
+                        let msg = Message::RangeEndpoints;
+                        Err(self.operand_error(msg, &[&start, &end]))
+                    }
+                },
+                Op::CheckNumber => match self.peek(0) {
+                    Value::Number(_) => Ok(()),
+                    arg => {
+                        let msg = Message::OperandNumber;
+                        Err(self.operand_error(msg, &[&arg]))
+                    }
+                },
+                Op::IterNext => self.iter_next(inst.operand() as usize + base),
+                Op::GetIndex => {
+                    let index = self.pop();
+                    let object = self.peek(0);
+                    self.get_index(&object, &index)
+                        .and_then(|item| self.poke(0, item))
+                }
+                Op::SetIndex => {
+                    let item = self.pop();
+                    let index = self.pop();
+                    let object = self.peek(0);
+                    self.set_index(&object, index, item.clone())
+                        .and_then(|_| self.poke(0, item))
+                }
+                Op::Stringify => match self.peek(0) {
+                    Value::String(_) => Ok(()),
+                    val => {
+                        let text = val.to_string();
+                        self.check_string(text.len()).and_then(|_| {
+                            let text = LoxString::new(&text);
+                            let text = Value::String(self.alloc(text));
+                            self.poke(0, text)
+                        })
+                    }
+                },
                 Op::Constant => {
                     let constant = chunk.get_constant(inst.operand());
                     self.push(constant)
                 }
+                Op::Int => self.push(Value::Number(inst.operand() as f64)),
                 Op::Call => {
+                    let next = ip.offset;
+                    match self.call_value(chunk, current, inst, next)? {
+                        Some(frame) => return Ok(Some(frame)),
+                        None => Ok(()),
+                    }
+                }
+                Op::CallNative => {
                     let arg_count = inst.operand() as usize;
-                    match self.peek(arg_count) {
-                        Value::Function(f) => {
-                            let arity = f.borrow().arity;
-                            if arity != arg_count {
-                                Vm::error(&format!(
-                                    "expected {} arguments but got {}",
-                                    arity, arg_count
-                                ))
-                            } else {
-                                self.frames[current].offset = ip.offset;
-                                return Ok(Some(Frame {
-                                    func: f,
-                                    base: self.stack.len() - arg_count - 1,
-                                    offset: 0,
-                                }));
-                            }
+                    let callee = self.stack.len() - arg_count - 1;
+                    match &self.stack[callee] {
+                        Value::Builtin(f) if f.borrow().accepts(arg_count) => {
+                            let f = f.clone();
+                            self.call_native(&f, arg_count)
                         }
-                        Value::Builtin(f) => {
-                            let arity = f.borrow().arity;
-                            if arity != arg_count {
-                                Vm::error(&format!(
-                                    "expected {} arguments but got {}",
-                                    arity, arg_count
-                                ))
-                            } else {
-                                let func = f.borrow().func;
-                                match func(arg_count, self) {
-                                    Ok(v) => {
-                                        self.stack.truncate(
-                                            self.stack.len() - arg_count + 1,
-                                        );
-                                        self.push(v)
-                                    }
-                                    Err(e) => Err(e),
-                                }
+                        _ => {
+                            chunk.quicken(ip.offset - 1, Op::Call);
+                            let next = ip.offset;
+                            match self.call_value(chunk, current, inst, next)? {
+                                Some(frame) => return Ok(Some(frame)),
+                                None => Ok(()),
                             }
                         }
-                        _ => Vm::error("can only call functions or classes"),
                     }
                 }
                 Op::PopN => {
@@ -411,27 +2238,22 @@ impl Vm {
                 }
                 Op::DefineGlobal => {
                     let global = self.pop();
-                    self.globals.insert(inst.operand(), global);
+                    self.define(inst.operand(), global);
                     Ok(())
                 }
-                Op::GetGlobal => match self.globals.get(&inst.operand()) {
-                    None => Vm::error(&format!(
-                        "undefined variable '{}'",
-                        self.symbols.names[inst.operand() as usize]
-                    )),
-                    Some(val) => self.push(val.clone()),
+                Op::GetGlobal => match self.read_global(inst.operand()) {
+                    None => self.error(
+                        Message::UndefinedVariable,
+                        &[&self.global_name(inst.operand())],
+                    ),
+                    Some(val) => self.push(val),
                 },
                 Op::SetGlobal => {
                     let val = self.peek(0);
-                    match self.globals.entry(inst.operand()) {
-                        Entry::Occupied(mut entry) => {
-                            entry.insert(val);
-                            Ok(())
-                        }
-                        Entry::Vacant(_) => Err(RuntimeError::new(format!(
-                            "undefined variable '{}'",
-                            self.symbols.names[inst.operand() as usize]
-                        ))),
+                    match self.set_global(inst.operand(), val) {
+                        Ok(()) => Ok(()),
+                        Err(msg) => self
+                            .error(msg, &[&self.global_name(inst.operand())]),
                     }
                 }
                 Op::GetLocal => {
@@ -460,14 +2282,10 @@ impl Vm {
                     Ok(())
                 }
                 Op::Nop => Ok(()),
-                _ => Vm::error(&format!("unknown opcode {}", inst.opcode())),
+                op => self.error(Message::UnknownOpcode, &[&op]),
             };
-            result.map_err(|e| {
-                let offset = ip.offset - inst.len();
-                let line = chunk.get_line(offset);
-                self.stack.clear();
-                e.with_line(line)
-            })?;
+            result
+                .map_err(|e| self.locate(e, chunk, ip.offset - inst.len()))?;
         }
 
         Ok(None)
@@ -475,10 +2293,10 @@ impl Vm {
 
     #[cfg(feature = "trace_execution")]
     fn trace_stack(&self) {
-        print!("          ");
+        let mut line = String::from("          ");
         for elem in &self.stack {
-            print!("[ {} ]", elem);
+            line.push_str(&format!("[ {} ]", elem));
         }
-        println!();
+        let _ = writeln!(self.trace.borrow_mut(), "{}", line);
     }
 }
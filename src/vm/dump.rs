@@ -0,0 +1,115 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use super::{Obj, Trace};
+use crate::Value;
+
+// Writes the objects reachable from `roots` as a graphviz digraph: one box
+// per object, labeled with its type and size, an arrow for each reference,
+// and a plain node for each named root.
+pub(super) fn write_dot(
+    w: &mut impl Write,
+    roots: Vec<(String, Value)>,
+) -> io::Result<()> {
+    writeln!(w, "digraph heap {{")?;
+    writeln!(w, "    node [shape=box, fontname=monospace];")?;
+    let mut gray = Vec::new();
+    for (idx, (name, value)) in roots.into_iter().enumerate() {
+        if let Some(id) = id(&value) {
+            writeln!(
+                w,
+                "    r{} [shape=plaintext, label={}];",
+                idx,
+                quote(&name)
+            )?;
+            writeln!(w, "    r{} -> {};", idx, id)?;
+            gray.push(value);
+        }
+    }
+    let mut seen = HashSet::new();
+    let (mut count, mut bytes) = (0, 0);
+    while let Some(value) = gray.pop() {
+        let Some(id) = id(&value) else { continue };
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let (label, size, refs) = match &value {
+            Value::String(s) => {
+                let text: String = s.borrow().to_string();
+                (format!("string {}", preview(&text)), s.size(), refs(s))
+            }
+            Value::Function(f) => (f.borrow().to_string(), f.size(), refs(f)),
+            Value::Builtin(f) => {
+                let label = format!("<native fn {}>", f.borrow().name());
+                (label, f.size(), refs(f))
+            }
+            Value::List(l) => {
+                let label = format!("list of {}", l.borrow().items.len());
+                (label, l.size(), refs(l))
+            }
+            Value::Map(m) => {
+                let label = format!("map of {}", m.borrow().entries().len());
+                (label, m.size(), refs(m))
+            }
+            _ => unreachable!("only objects have ids"),
+        };
+        count += 1;
+        bytes += size;
+        let label = format!("{}\n{} bytes", label, size);
+        writeln!(w, "    {} [label={}];", id, quote(&label))?;
+        for child in refs {
+            if let Some(child_id) = self::id(&child) {
+                writeln!(w, "    {} -> {};", id, child_id)?;
+                gray.push(child);
+            }
+        }
+    }
+    let summary = format!("{} objects, {} bytes", count, bytes);
+    writeln!(w, "    label={};", quote(&summary))?;
+    writeln!(w, "}}")
+}
+
+fn id(value: &Value) -> Option<String> {
+    let addr = match value {
+        Value::String(obj) => obj.addr(),
+        Value::Function(obj) => obj.addr(),
+        Value::Builtin(obj) => obj.addr(),
+        Value::List(obj) => obj.addr(),
+        Value::Map(obj) => obj.addr(),
+        Value::Nil | Value::Boolean(_) | Value::Number(_) => return None,
+    };
+    Some(format!("o{:x}", addr))
+}
+
+fn refs<T: Trace>(obj: &Obj<T>) -> Vec<Value> {
+    let mut refs = Vec::new();
+    obj.borrow().trace(&mut refs);
+    refs
+}
+
+// Long strings are cut short, so that the boxes stay readable.
+fn preview(text: &str) -> String {
+    const MAX: usize = 32;
+    match text.char_indices().nth(MAX) {
+        Some((end, _)) => format!("{:?}...", &text[..end]),
+        None => format!("{:?}", text),
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,318 @@
+// A mark-sweep heap. Every object the vm creates lives here, behind a
+// header the collector uses, and is freed once a collection finds that
+// nothing can reach it any more.
+//
+// An Obj is a plain pointer into the heap, so it is only valid while the
+// heap holds the object. The vm keeps that true by collecting only between
+// instructions, when every value it will use again is reachable from its
+// roots (the stack, the globals and the call frames).
+
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    ops::Deref,
+    ptr::NonNull,
+};
+
+use super::{LoxFunction, LoxList, LoxMap, LoxString, MapKey, RustFunction};
+use crate::Value;
+
+pub(crate) struct Heap {
+    objects: Vec<NonNull<dyn Object>>,
+    // Estimated bytes held by live objects, and the total at which the next
+    // collection happens.
+    allocated: usize,
+    next_gc: usize,
+    // The most bytes live objects may hold after a collection.
+    limit: Option<usize>,
+}
+
+pub(crate) struct Obj<T>(NonNull<GcBox<T>>);
+
+struct GcBox<T> {
+    header: Header,
+    value: RefCell<T>,
+}
+
+struct Header {
+    marked: Cell<bool>,
+    // Measured when the object is allocated, and again whenever it is
+    // marked or the vm grows it.
+    size: Cell<usize>,
+}
+
+// An object of any type, as the heap sees it.
+trait Object {
+    fn header(&self) -> &Header;
+}
+
+// Implemented by everything that can live on the heap.
+pub(crate) trait Trace: 'static {
+    // Queues the values the object refers to, for marking.
+    fn trace(&self, _gray: &mut Vec<Value>) {}
+
+    // Bytes the object owns outside of its box.
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl Heap {
+    const INITIAL_GC: usize = 1024 * 1024;
+    const GROWTH: usize = 2;
+
+    pub(crate) fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            allocated: 0,
+            next_gc: Heap::INITIAL_GC,
+            limit: None,
+        }
+    }
+
+    pub(crate) fn alloc<T: Trace>(&mut self, value: T) -> Obj<T> {
+        let size = mem::size_of::<GcBox<T>>() + value.heap_size();
+        self.allocated += size;
+        let boxed = Box::new(GcBox {
+            header: Header {
+                marked: Cell::new(false),
+                size: Cell::new(size),
+            },
+            value: RefCell::new(value),
+        });
+        // Safety: Box::into_raw never returns null.
+        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(boxed)) };
+        self.objects.push(ptr);
+        Obj(ptr)
+    }
+
+    // Counts the bytes `obj` has come to hold since it was last measured,
+    // such as a map's new entries.
+    pub(crate) fn resize<T: Trace>(&mut self, obj: &Obj<T>) {
+        let old = obj.header().size.replace(obj.measure());
+        self.allocated = (self.allocated + obj.size()).saturating_sub(old);
+    }
+
+    // Frees every object that can't be reached from `roots`.
+    pub(crate) fn collect<I>(&mut self, roots: I)
+    where
+        I: IntoIterator<Item = Value>,
+    {
+        #[cfg(feature = "log")]
+        let (started, before) = (std::time::Instant::now(), self.objects.len());
+        let mut gray: Vec<Value> = roots.into_iter().collect();
+        while let Some(value) = gray.pop() {
+            match &value {
+                Value::String(obj) => obj.mark(&mut gray),
+                Value::Function(obj) => obj.mark(&mut gray),
+                Value::Builtin(obj) => obj.mark(&mut gray),
+                Value::List(obj) => obj.mark(&mut gray),
+                Value::Map(obj) => obj.mark(&mut gray),
+                Value::Nil | Value::Boolean(_) | Value::Number(_) => (),
+            }
+        }
+
+        let mut allocated = 0;
+        self.objects.retain(|&ptr| {
+            // Safety: the heap owns every object in the list.
+            let header = unsafe { ptr.as_ref() }.header();
+            if header.marked.replace(false) {
+                allocated += header.size.get();
+                return true;
+            }
+            // Safety: nothing reachable refers to the object, and it is
+            // dropped from the list.
+            unsafe { drop(Box::from_raw(ptr.as_ptr())) };
+            false
+        });
+        self.allocated = allocated;
+        self.next_gc = if cfg!(feature = "stress_gc") {
+            allocated
+        } else {
+            (allocated * Heap::GROWTH).max(Heap::INITIAL_GC)
+        };
+        if let Some(limit) = self.limit {
+            self.next_gc = self.next_gc.min(limit);
+        }
+        #[cfg(feature = "log")]
+        log::debug!(
+            "collected {} of {} objects in {:?}; {} bytes live, next at {}",
+            before - self.objects.len(),
+            before,
+            started.elapsed(),
+            allocated,
+            self.next_gc
+        );
+    }
+
+    // How many objects there are, live or not yet collected.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub(crate) fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    // Whether the last collection left more live than the limit allows.
+    pub(crate) fn over_limit(&self) -> bool {
+        self.limit.is_some_and(|limit| self.allocated > limit)
+    }
+
+    // Collect whenever more than `limit` bytes are allocated, so that
+    // over_limit can be checked afterwards.
+    pub(crate) fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        if let Some(limit) = limit {
+            self.next_gc = self.next_gc.min(limit);
+        }
+    }
+
+    // Whether enough has been allocated since the last collection to make
+    // another worthwhile; with the stress_gc feature, anything at all is.
+    pub(crate) fn wants_collection(&self) -> bool {
+        self.allocated > self.next_gc
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for &ptr in &self.objects {
+            // Safety: the heap owns every object in the list, and is going
+            // away along with every Obj the vm held.
+            unsafe { drop(Box::from_raw(ptr.as_ptr())) };
+        }
+    }
+}
+
+impl<T: Trace> Object for GcBox<T> {
+    fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<T: Trace> Obj<T> {
+    fn mark(&self, gray: &mut Vec<Value>) {
+        let header = self.header();
+        if !header.marked.replace(true) {
+            self.borrow().trace(gray);
+            header.size.set(self.measure());
+        }
+    }
+
+    fn measure(&self) -> usize {
+        mem::size_of::<GcBox<T>>() + self.borrow().heap_size()
+    }
+}
+
+impl<T> Obj<T> {
+    // Where the object lives, which no other live object shares.
+    pub(crate) fn addr(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+
+    fn ptr_eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+
+    // The bytes the heap counts the object as holding.
+    pub(super) fn size(&self) -> usize {
+        self.header().size.get()
+    }
+
+    fn header(&self) -> &Header {
+        // Safety: see the note at the top of the file.
+        unsafe { &self.0.as_ref().header }
+    }
+}
+
+impl<T> Clone for Obj<T> {
+    fn clone(&self) -> Self {
+        Obj(self.0)
+    }
+}
+
+impl<T> Deref for Obj<T> {
+    type Target = RefCell<T>;
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: see the note at the top of the file.
+        unsafe { &self.0.as_ref().value }
+    }
+}
+
+impl PartialEq for Obj<LoxFunction> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl PartialEq for Obj<LoxString> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || *self.borrow() == *other.borrow()
+    }
+}
+
+impl PartialEq for Obj<LoxList> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl PartialEq for Obj<LoxMap> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl PartialEq for Obj<RustFunction> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, gray: &mut Vec<Value>) {
+        gray.extend(self.chunk.constants().iter().cloned());
+    }
+}
+
+impl Trace for LoxString {
+    fn heap_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Trace for LoxList {
+    fn trace(&self, gray: &mut Vec<Value>) {
+        gray.extend(self.items.iter().cloned());
+    }
+
+    fn heap_size(&self) -> usize {
+        self.items.capacity() * mem::size_of::<Value>()
+    }
+}
+
+impl Trace for LoxMap {
+    fn trace(&self, gray: &mut Vec<Value>) {
+        for (key, value) in self.entries() {
+            gray.push(key.clone());
+            gray.push(value.clone());
+        }
+    }
+
+    // String keys are counted twice, as the string and its copy in the
+    // index.
+    fn heap_size(&self) -> usize {
+        let entry = 2 * mem::size_of::<Value>();
+        let indexed = mem::size_of::<MapKey>() + mem::size_of::<usize>();
+        self.entries().len() * entry
+            + self.index.capacity() * indexed
+            + self.texts()
+    }
+}
+
+impl Trace for RustFunction {}
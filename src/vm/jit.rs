@@ -0,0 +1,687 @@
+// A second tier for hot functions that only do arithmetic, comparisons and
+// control flow on numbers and booleans. Such a function's bytecode is checked
+// to be statically typed, then compiled to machine code with cranelift, each
+// stack slot an f64 variable, with no Values, refcounts or per-op type
+// checks. Anything else, or a call whose arguments aren't all numbers, stays
+// in the interpreter.
+//
+// The code counts down the safepoints once for each instruction it was
+// compiled from, as the interpreter does, so fuel, interrupts and hooks work
+// the same in either tier, and an error there is reported at that
+// instruction. It never allocates, so it can't need a collection or go over
+// the heap limit. Under `profiling` it counts the runs of each arithmetic
+// site, with the types the check found, for the profile.
+
+use std::{
+    any::Any,
+    cell::{Cell, OnceCell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::OnceLock,
+};
+
+use cranelift_codegen::{
+    entity::EntityRef,
+    ir::{
+        condcodes::FloatCC, types, AbiParam, InstBuilder, MemFlags,
+        UserFuncName, Value as Reg,
+    },
+    isa::OwnedTargetIsa,
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use super::{safepoint::Safepoints, RuntimeError};
+use crate::{
+    code::{Chunk, Op},
+    message::Catalog,
+    Value,
+};
+
+// How many calls before a function is worth translating.
+const HOT_CALLS: u32 = 1000;
+
+#[derive(Default)]
+pub(crate) struct Jit {
+    calls: Cell<u32>,
+    // Boxed, as every function has a Jit, and a module is large.
+    code: OnceCell<Option<Box<Compiled>>>,
+}
+
+pub(crate) struct Compiled {
+    // Owns the machine code, which is freed with it.
+    module: Option<JITModule>,
+    entry: Entry,
+    result: Ty,
+    // The offset of the instruction each op was translated from.
+    offsets: Vec<usize>,
+    // Where the arguments are copied for the code to read.
+    args: RefCell<Vec<f64>>,
+    // The offset, opcode and operand types of each arithmetic site, and how
+    // many times it has run since the vm last took the counts.
+    #[cfg(feature = "profiling")]
+    sites: Vec<(usize, u8, (&'static str, &'static str))>,
+    #[cfg(feature = "profiling")]
+    counts: RefCell<Vec<u64>>,
+}
+
+// Takes the arguments, where to write the result, the Env, the safepoint
+// countdown and the site counts. Returns -1, or the index of the op whose
+// safepoint stopped the script.
+type Entry = unsafe extern "C" fn(
+    *const f64,
+    *mut f64,
+    *mut Env<'_>,
+    *mut u32,
+    *mut u64,
+) -> i64;
+
+// What the code's calls back into the vm need.
+struct Env<'a> {
+    safepoints: *mut Safepoints,
+    messages: &'a Catalog,
+    error: Option<RuntimeError>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Ty {
+    Callee,
+    Num,
+    Bool,
+}
+
+// Booleans are stored as 1.0 and 0.0; the type check guarantees they are
+// never mixed up with numbers. Jump targets are op indexes.
+#[derive(Copy, Clone)]
+enum NumOp {
+    Num(f64),
+    Bool(bool),
+    Get(usize),
+    Set(usize),
+    PopN(usize),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Negate,
+    CheckNumber,
+    Not,
+    Equal,
+    Greater,
+    Less,
+    JumpIfFalse(usize),
+    Jump(usize),
+    Return,
+    Unsupported,
+}
+
+impl Jit {
+    /// The compiled form of `chunk`, once the function is hot, if it could
+    /// be compiled and can be called with `args`.
+    pub(crate) fn code(
+        &self,
+        chunk: &Chunk,
+        args: &[Value],
+    ) -> Option<&Compiled> {
+        let code = match self.code.get() {
+            Some(code) => code.as_deref()?,
+            None => {
+                let calls = self.calls.get() + 1;
+                self.calls.set(calls);
+                if calls < HOT_CALLS {
+                    return None;
+                }
+                self.code
+                    .get_or_init(|| Compiled::new(chunk, args.len()))
+                    .as_deref()?
+            }
+        };
+        args.iter()
+            .all(|arg| matches!(arg, Value::Number(_)))
+            .then_some(code)
+    }
+
+    #[cfg_attr(any(not(test), feature = "trace_execution"), allow(dead_code))]
+    pub(crate) fn is_compiled(&self) -> bool {
+        matches!(self.code.get(), Some(Some(_)))
+    }
+}
+
+impl Ty {
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Bool => "boolean",
+            _ => "number",
+        }
+    }
+}
+
+impl Compiled {
+    fn new(chunk: &Chunk, arity: usize) -> Option<Box<Compiled>> {
+        // Map each instruction's offset to its op index, so that jumps can
+        // be translated.
+        let mut insts = Vec::new();
+        let mut index = vec![usize::MAX; chunk.len() + 1];
+        let mut offset = 0;
+        for inst in chunk.instructions(0) {
+            index[offset] = insts.len();
+            insts.push((inst, offset));
+            offset += inst.len();
+        }
+        index[offset] = insts.len();
+        let target = |offset: usize| match index.get(offset) {
+            Some(&idx) if idx != usize::MAX => idx,
+            _ => usize::MAX,
+        };
+
+        let ops: Vec<_> = insts
+            .iter()
+            .map(|&(inst, offset)| {
+                let next = offset + inst.len();
+                let operand = inst.operand() as usize;
+                match inst.opcode() {
+                    Op::Constant => match chunk.get_constant(inst.operand()) {
+                        Value::Number(v) => NumOp::Num(v),
+                        Value::Boolean(b) => NumOp::Bool(b),
+                        _ => NumOp::Unsupported,
+                    },
+                    Op::Zero => NumOp::Num(0.0),
+                    Op::One => NumOp::Num(1.0),
+                    Op::Int => NumOp::Num(operand as f64),
+                    Op::True => NumOp::Bool(true),
+                    Op::False => NumOp::Bool(false),
+                    Op::GetLocal => NumOp::Get(operand),
+                    Op::SetLocal => NumOp::Set(operand),
+                    Op::Pop => NumOp::PopN(1),
+                    Op::PopN => NumOp::PopN(operand),
+                    Op::Add | Op::AddNumber => NumOp::Add,
+                    Op::Subtract => NumOp::Subtract,
+                    Op::Multiply => NumOp::Multiply,
+                    Op::Divide => NumOp::Divide,
+                    Op::Power => NumOp::Power,
+                    Op::Negate => NumOp::Negate,
+                    Op::CheckNumber => NumOp::CheckNumber,
+                    Op::Not => NumOp::Not,
+                    Op::Equal => NumOp::Equal,
+                    Op::Greater => NumOp::Greater,
+                    Op::Less => NumOp::Less,
+                    Op::JumpIfFalse => {
+                        NumOp::JumpIfFalse(target(next + operand))
+                    }
+                    Op::Jump => NumOp::Jump(target(next + operand)),
+                    Op::Loop => match next.checked_sub(operand) {
+                        Some(offset) => NumOp::Jump(target(offset)),
+                        None => NumOp::Unsupported,
+                    },
+                    Op::Return => NumOp::Return,
+                    _ => NumOp::Unsupported,
+                }
+            })
+            .collect();
+
+        let (result, states) = Compiled::check(&ops, arity)?;
+        let offsets: Vec<_> = insts.iter().map(|&(_, offset)| offset).collect();
+        let sites: Vec<_> = if cfg!(feature = "profiling") {
+            ops.iter()
+                .zip(&states)
+                .enumerate()
+                .filter_map(|(pc, (op, stack))| {
+                    let operands = match op {
+                        NumOp::Negate => 1,
+                        NumOp::Add
+                        | NumOp::Subtract
+                        | NumOp::Multiply
+                        | NumOp::Divide
+                        | NumOp::Power
+                        | NumOp::Greater
+                        | NumOp::Less
+                        | NumOp::Equal => 2,
+                        _ => return None,
+                    };
+                    let stack = stack.as_ref()?;
+                    let top = &stack[stack.len() - operands..];
+                    let b = top.get(1).map_or("", |ty| ty.name());
+                    let op = Op::generic(insts[pc].0.opcode());
+                    Some((pc, (offsets[pc], op, (top[0].name(), b))))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let counted: Vec<_> = sites.iter().map(|&(pc, _)| pc).collect();
+        let (module, entry) =
+            Compiled::generate(&ops, &states, arity, &counted)?;
+        Some(Box::new(Compiled {
+            module: Some(module),
+            entry,
+            result,
+            offsets,
+            args: RefCell::new(Vec::with_capacity(arity)),
+            #[cfg(feature = "profiling")]
+            counts: RefCell::new(vec![0; sites.len()]),
+            #[cfg(feature = "profiling")]
+            sites: sites.into_iter().map(|(_, site)| site).collect(),
+        }))
+    }
+
+    // Follows every reachable path with a stack of static types, which must
+    // agree wherever paths meet. Returns the result type, and the stack
+    // before each op, or None where it can't be reached.
+    fn check(
+        ops: &[NumOp],
+        arity: usize,
+    ) -> Option<(Ty, Vec<Option<Vec<Ty>>>)> {
+        let mut entry = vec![Ty::Callee];
+        entry.resize(arity + 1, Ty::Num);
+        let mut states: Vec<Option<Vec<Ty>>> = vec![None; ops.len()];
+        states[0] = Some(entry);
+        let mut work = vec![0];
+        let mut result = None;
+
+        while let Some(pc) = work.pop() {
+            let mut stack = states[pc].clone().unwrap();
+            let mut next = vec![pc + 1];
+            match ops[pc] {
+                NumOp::Num(_) => stack.push(Ty::Num),
+                NumOp::Bool(_) => stack.push(Ty::Bool),
+                NumOp::Get(slot) => match stack.get(slot) {
+                    Some(&ty) if ty != Ty::Callee => stack.push(ty),
+                    _ => return None,
+                },
+                NumOp::Set(slot) => {
+                    if stack.get(slot) != Some(stack.last()?) {
+                        return None;
+                    }
+                }
+                NumOp::PopN(count) => {
+                    if count >= stack.len() {
+                        return None;
+                    }
+                    stack.truncate(stack.len() - count);
+                }
+                NumOp::Add
+                | NumOp::Subtract
+                | NumOp::Multiply
+                | NumOp::Divide
+                | NumOp::Power
+                | NumOp::Greater
+                | NumOp::Less => {
+                    if stack.pop()? != Ty::Num || stack.pop()? != Ty::Num {
+                        return None;
+                    }
+                    stack.push(match ops[pc] {
+                        NumOp::Greater | NumOp::Less => Ty::Bool,
+                        _ => Ty::Num,
+                    });
+                }
+                NumOp::Equal => {
+                    let (b, a) = (stack.pop()?, stack.pop()?);
+                    if a != b || a == Ty::Callee {
+                        return None;
+                    }
+                    stack.push(Ty::Bool);
+                }
+                NumOp::Negate | NumOp::CheckNumber => {
+                    if stack.last()? != &Ty::Num {
+                        return None;
+                    }
+                }
+                NumOp::Not => {
+                    if stack.last()? != &Ty::Bool {
+                        return None;
+                    }
+                }
+                NumOp::JumpIfFalse(target) => {
+                    if stack.last()? != &Ty::Bool {
+                        return None;
+                    }
+                    next.push(target);
+                }
+                NumOp::Jump(target) => next = vec![target],
+                NumOp::Return => {
+                    let ty = *stack.last()?;
+                    if ty == Ty::Callee || result.is_some_and(|r| r != ty) {
+                        return None;
+                    }
+                    result = Some(ty);
+                    next.clear();
+                }
+                NumOp::Unsupported => return None,
+            }
+            for pc in next {
+                match states.get_mut(pc)? {
+                    Some(seen) if *seen != stack => return None,
+                    Some(_) => (),
+                    state => {
+                        *state = Some(stack.clone());
+                        work.push(pc);
+                    }
+                }
+            }
+        }
+        Some((result?, states))
+    }
+
+    // Generates the code for `ops`, given the stack before each one, with
+    // a count kept for each op in `counted`.
+    fn generate(
+        ops: &[NumOp],
+        states: &[Option<Vec<Ty>>],
+        arity: usize,
+        counted: &[usize],
+    ) -> Option<(JITModule, Entry)> {
+        let mut builder = JITBuilder::with_isa(isa()?, default_libcall_names());
+        builder.symbol("redlox_pow", pow as *const u8);
+        builder.symbol("redlox_safepoint", safepoint as *const u8);
+        let mut module = JITModule::new(builder);
+        let ptr = module.target_config().pointer_type();
+
+        let mut sig = module.make_signature();
+        sig.params.extend([AbiParam::new(ptr); 5]);
+        sig.returns.push(AbiParam::new(types::I64));
+        let mut pow_sig = module.make_signature();
+        pow_sig.params.extend([AbiParam::new(types::F64); 2]);
+        pow_sig.returns.push(AbiParam::new(types::F64));
+        let mut safepoint_sig = module.make_signature();
+        safepoint_sig.params.push(AbiParam::new(ptr));
+        safepoint_sig.returns.push(AbiParam::new(types::I8));
+        let id = module.declare_function("code", Linkage::Local, &sig).ok()?;
+        let pow_id = module
+            .declare_function("redlox_pow", Linkage::Import, &pow_sig)
+            .ok()?;
+        let safepoint_id = module
+            .declare_function(
+                "redlox_safepoint",
+                Linkage::Import,
+                &safepoint_sig,
+            )
+            .ok()?;
+
+        let mut ctx = module.make_context();
+        ctx.func.signature = sig;
+        ctx.func.name = UserFuncName::user(0, id.as_u32());
+        let mut fctx = FunctionBuilderContext::new();
+        let mut b = FunctionBuilder::new(&mut ctx.func, &mut fctx);
+        let pow = module.declare_func_in_func(pow_id, b.func);
+        let safepoint = module.declare_func_in_func(safepoint_id, b.func);
+        let flags = MemFlags::trusted();
+        let var = Variable::new;
+
+        let slots = states.iter().flatten().map(Vec::len).max()? + 1;
+        for slot in 0..slots {
+            b.declare_var(var(slot), types::F64);
+        }
+        let start = b.create_block();
+        b.append_block_params_for_function_params(start);
+        b.switch_to_block(start);
+        let params = b.block_params(start).to_vec();
+        let (args, result, env) = (params[0], params[1], params[2]);
+        let (countdown, counts) = (params[3], params[4]);
+        for slot in 1..=arity {
+            let offset = 8 * (slot as i32 - 1);
+            let arg = b.ins().load(types::F64, flags, args, offset);
+            b.def_var(var(slot), arg);
+        }
+        let blocks: Vec<_> = states
+            .iter()
+            .map(|stack| stack.as_ref().map(|_| b.create_block()))
+            .collect();
+        b.ins().jump(blocks[0]?, &[]);
+        let fail = b.create_block();
+        b.append_block_param(fail, types::I64);
+
+        for (pc, op) in ops.iter().enumerate() {
+            let (Some(block), Some(stack)) = (blocks[pc], &states[pc]) else {
+                continue;
+            };
+            let depth = stack.len();
+            b.switch_to_block(block);
+
+            // Safepoints::tick, with the rest of it in `safepoint`.
+            let left = b.ins().load(types::I32, flags, countdown, 0);
+            let left = b.ins().iadd_imm(left, -1);
+            b.ins().store(flags, left, countdown, 0);
+            let (body, tick) = (b.create_block(), b.create_block());
+            b.ins().brif(left, body, &[], tick, &[]);
+            b.switch_to_block(tick);
+            let call = b.ins().call(safepoint, &[env]);
+            let stopped = b.inst_results(call)[0];
+            let index = b.ins().iconst(types::I64, pc as i64);
+            b.ins().brif(stopped, fail, &[index], body, &[]);
+            b.switch_to_block(body);
+
+            if let Ok(site) = counted.binary_search(&pc) {
+                let offset = 8 * site as i32;
+                let count = b.ins().load(types::I64, flags, counts, offset);
+                let count = b.ins().iadd_imm(count, 1);
+                b.ins().store(flags, count, counts, offset);
+            }
+
+            match *op {
+                NumOp::Num(v) => {
+                    let v = b.ins().f64const(v);
+                    b.def_var(var(depth), v);
+                }
+                NumOp::Bool(v) => {
+                    let v = b.ins().f64const(if v { 1.0 } else { 0.0 });
+                    b.def_var(var(depth), v);
+                }
+                NumOp::Get(slot) => {
+                    let v = b.use_var(var(slot));
+                    b.def_var(var(depth), v);
+                }
+                NumOp::Set(slot) => {
+                    let v = b.use_var(var(depth - 1));
+                    b.def_var(var(slot), v);
+                }
+                NumOp::PopN(_) | NumOp::CheckNumber => (),
+                NumOp::Negate => {
+                    let v = b.use_var(var(depth - 1));
+                    let v = b.ins().fneg(v);
+                    b.def_var(var(depth - 1), v);
+                }
+                NumOp::Not => {
+                    let v = b.use_var(var(depth - 1));
+                    let v = compare(&mut b, FloatCC::Equal, v, None);
+                    b.def_var(var(depth - 1), v);
+                }
+                NumOp::JumpIfFalse(target) => {
+                    let v = b.use_var(var(depth - 1));
+                    let zero = b.ins().f64const(0.0);
+                    let v = b.ins().fcmp(FloatCC::NotEqual, v, zero);
+                    let next = blocks[pc + 1]?;
+                    b.ins().brif(v, next, &[], blocks[target]?, &[]);
+                    continue;
+                }
+                NumOp::Jump(target) => {
+                    b.ins().jump(blocks[target]?, &[]);
+                    continue;
+                }
+                NumOp::Return => {
+                    let v = b.use_var(var(depth - 1));
+                    b.ins().store(flags, v, result, 0);
+                    let done = b.ins().iconst(types::I64, -1);
+                    b.ins().return_(&[done]);
+                    continue;
+                }
+                NumOp::Unsupported => unreachable!(),
+                op => {
+                    let x = b.use_var(var(depth - 2));
+                    let y = b.use_var(var(depth - 1));
+                    let v = match op {
+                        NumOp::Add => b.ins().fadd(x, y),
+                        NumOp::Subtract => b.ins().fsub(x, y),
+                        NumOp::Multiply => b.ins().fmul(x, y),
+                        NumOp::Divide => b.ins().fdiv(x, y),
+                        NumOp::Power => {
+                            let call = b.ins().call(pow, &[x, y]);
+                            b.inst_results(call)[0]
+                        }
+                        NumOp::Equal => {
+                            compare(&mut b, FloatCC::Equal, x, Some(y))
+                        }
+                        NumOp::Greater => {
+                            compare(&mut b, FloatCC::GreaterThan, x, Some(y))
+                        }
+                        _ => compare(&mut b, FloatCC::LessThan, x, Some(y)),
+                    };
+                    b.def_var(var(depth - 2), v);
+                }
+            }
+            b.ins().jump(blocks[pc + 1]?, &[]);
+        }
+
+        b.switch_to_block(fail);
+        let index = b.block_params(fail)[0];
+        b.ins().return_(&[index]);
+        b.seal_all_blocks();
+        b.finalize();
+
+        let defined = module.define_function(id, &mut ctx).is_ok()
+            && module.finalize_definitions().is_ok();
+        if !defined {
+            // Safety: nothing in the module has run.
+            unsafe { module.free_memory() };
+            return None;
+        }
+        let code = module.get_finalized_function(id);
+        // Safety: the function was declared with Entry's signature.
+        let entry = unsafe { std::mem::transmute::<*const u8, Entry>(code) };
+        Some((module, entry))
+    }
+
+    /// Runs the code on `args`, which [`Jit::code`] has checked are all
+    /// numbers. An error comes with the offset of the instruction it
+    /// stopped at.
+    pub(crate) fn call(
+        &self,
+        args: &[Value],
+        safepoints: &mut Safepoints,
+        messages: &Catalog,
+    ) -> Result<Value, (RuntimeError, usize)> {
+        let mut numbers = self.args.borrow_mut();
+        numbers.clear();
+        numbers.extend(args.iter().map(|arg| match arg {
+            Value::Number(v) => *v,
+            _ => unreachable!(),
+        }));
+        let safepoints = ptr::from_mut(safepoints);
+        let mut env = Env {
+            safepoints,
+            messages,
+            error: None,
+            panic: None,
+        };
+        #[cfg(feature = "profiling")]
+        let counts = self.counts.borrow_mut().as_mut_ptr();
+        #[cfg(not(feature = "profiling"))]
+        let counts = ptr::null_mut();
+        let mut result = 0.0;
+        // Safety: the code reads as many arguments as the function takes,
+        // which the vm has checked it was called with, and writes only the
+        // result, the countdown, and a count for each of its sites.
+        let status = unsafe {
+            let countdown = ptr::addr_of_mut!((*safepoints).countdown);
+            (self.entry)(
+                numbers.as_ptr(),
+                &mut result,
+                &mut env,
+                countdown,
+                counts,
+            )
+        };
+        if let Some(payload) = env.panic {
+            panic::resume_unwind(payload);
+        }
+        match status {
+            -1 => Ok(match self.result {
+                Ty::Bool => Value::Boolean(result != 0.0),
+                _ => Value::Number(result),
+            }),
+            pc => Err((env.error.unwrap(), self.offsets[pc as usize])),
+        }
+    }
+
+    /// Passes how many times each site has run, since the last time this
+    /// was called, to `count`, with the offset, opcode and operand types of
+    /// its instruction.
+    #[cfg(feature = "profiling")]
+    pub(crate) fn take_counts(
+        &self,
+        mut count: impl FnMut(usize, u8, (&'static str, &'static str), u64),
+    ) {
+        let mut counts = self.counts.borrow_mut();
+        for (&(offset, op, types), n) in self.sites.iter().zip(&mut *counts) {
+            if *n > 0 {
+                count(offset, op, types, std::mem::take(n));
+            }
+        }
+    }
+}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safety: the code only runs from `call`, which borrows this.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+// A boolean, 1.0 or 0.0, for how `x` compares to `y`, or to zero.
+fn compare(
+    b: &mut FunctionBuilder,
+    cc: FloatCC,
+    x: Reg,
+    y: Option<Reg>,
+) -> Reg {
+    let y = y.unwrap_or_else(|| b.ins().f64const(0.0));
+    let c = b.ins().fcmp(cc, x, y);
+    let one = b.ins().f64const(1.0);
+    let zero = b.ins().f64const(0.0);
+    b.ins().select(c, one, zero)
+}
+
+// The host's target, or None where cranelift can't generate code for it.
+fn isa() -> Option<OwnedTargetIsa> {
+    static ISA: OnceLock<Option<OwnedTargetIsa>> = OnceLock::new();
+    ISA.get_or_init(|| {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").ok()?;
+        flags.set("use_colocated_libcalls", "false").ok()?;
+        flags.set("is_pic", "false").ok()?;
+        let isa = cranelift_native::builder().ok()?;
+        isa.finish(settings::Flags::new(flags)).ok()
+    })
+    .clone()
+}
+
+extern "C" fn pow(x: f64, y: f64) -> f64 {
+    x.powf(y)
+}
+
+// The rest of a safepoint, once the code has counted down to it. Returns 1
+// if the script has to stop, with what stopped it in `env`.
+unsafe extern "C" fn safepoint(env: *mut Env<'_>) -> u8 {
+    // Safety: the code passes on the Env `call` gave it, and `call` isn't
+    // using the safepoints while the code runs.
+    let env = unsafe { &mut *env };
+    let safepoints = unsafe { &mut *env.safepoints };
+    let messages = env.messages;
+    match panic::catch_unwind(AssertUnwindSafe(|| safepoints.expired(messages)))
+    {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            env.error = Some(e);
+            1
+        }
+        Err(payload) => {
+            env.panic = Some(payload);
+            1
+        }
+    }
+}
@@ -1,27 +1,393 @@
-use std::time::Duration;
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    sync::OnceLock,
+    time::Instant,
+};
 
-use super::{Result, Vm};
-use crate::Value;
+use super::{
+    FlushPolicy, LoxList, LoxString, MapKey, Result, RuntimeError, Vm,
+};
+use crate::{message::Message, HostValue, Value};
 
-// https://stackoverflow.com/a/36719115
-mod ffi {
-    use libc::{c_int, timespec};
-    extern "C" {
-        pub fn clock_gettime(clk_id: c_int, tp: *mut timespec) -> c_int;
+/// Where `clock()` gets the time from: seconds since some fixed point,
+/// never going backwards.
+pub type Clock = Box<dyn Fn() -> f64>;
+
+/// What a native can see of, and do to, the vm calling it: its
+/// arguments, the vm's input and output, and the instruction count.
+pub struct NativeContext<'a> {
+    vm: &'a mut Vm,
+    // The native's, for error messages.
+    name: &'a str,
+    arg_count: usize,
+}
+
+impl<'a> NativeContext<'a> {
+    pub(super) fn new(vm: &'a mut Vm, name: &'a str, arg_count: usize) -> Self {
+        NativeContext {
+            vm,
+            name,
+            arg_count,
+        }
+    }
+
+    /// The argument at `idx`, counting from 0, if it's a number. If not,
+    /// the error says which argument of which native it is, and what it
+    /// is instead, as in "argument 2 to 'substring' must be a number, got
+    /// nil".
+    pub fn number_arg(&self, idx: usize) -> Result<f64> {
+        match self.args().get(idx) {
+            Some(&Value::Number(n)) => Ok(n),
+            arg => Err(self.arg_error(idx, Message::ArgumentNotNumber, arg)),
+        }
+    }
+
+    /// Like [`NativeContext::number_arg`], for a string.
+    pub fn string_arg(&self, idx: usize) -> Result<String> {
+        match self.args().get(idx) {
+            Some(Value::String(s)) => Ok(s.borrow().to_string()),
+            arg => Err(self.arg_error(idx, Message::ArgumentNotString, arg)),
+        }
+    }
+
+    /// The argument at `idx`, or None if it's nil, for arguments that can
+    /// be left out by passing nil.
+    pub fn opt_arg(&self, idx: usize) -> Option<HostValue> {
+        match self.args().get(idx) {
+            None | Some(Value::Nil) => None,
+            Some(arg) => Some(HostValue::from_value(arg)),
+        }
+    }
+
+    // `msg` is the kind for the type expected.
+    fn arg_error(
+        &self,
+        idx: usize,
+        msg: Message,
+        arg: Option<&Value>,
+    ) -> RuntimeError {
+        let got = match arg {
+            Some(arg) => arg.type_name(),
+            None => "nothing",
+        };
+        self.error(msg, &[&(idx + 1), &self.name, &got])
+    }
+
+    /// Count the call as `n` more instructions, for fuel (see
+    /// [`Vm::interpret_with_fuel`]) and safepoint hooks, so that natives
+    /// doing expensive work can't be used to get around the limits. If
+    /// that uses up the fuel, the script stops once the native returns.
+    pub fn charge(&mut self, n: u64) {
+        self.vm.safepoints.charge(n);
+    }
+
+    /// Flush the vm's stdout.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.vm.flush()
+    }
+
+    /// Write `text` to the vm's stdout, flushing it if `print` would.
+    pub fn print(&mut self, text: &str) -> io::Result<()> {
+        self.vm.stdout.write_all(text.as_bytes())?;
+        match self.vm.options.flush {
+            FlushPolicy::Line => self.vm.flush(),
+            FlushPolicy::Buffered => Ok(()),
+        }
+    }
+
+    /// Write `text` to the vm's stderr.
+    pub fn eprint(&mut self, text: &str) -> io::Result<()> {
+        self.vm.stderr.borrow_mut().write_all(text.as_bytes())
+    }
+
+    /// The next line of the vm's input (see [`Vm::set_input`]), without
+    /// its line ending, or None at the end of the input. Stdout is flushed
+    /// first, so that a prompt printed before it is seen.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        self.vm.flush()?;
+        let mut line = String::new();
+        let read = match &self.vm.stdin {
+            Some(stdin) => stdin.borrow_mut().read_line(&mut line)?,
+            None => io::stdin().read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    // The arguments, as they are on the stack.
+    pub(super) fn args(&self) -> &[Value] {
+        &self.vm.stack[self.vm.stack.len() - self.arg_count..]
+    }
+
+    pub(super) fn arg(&self, idx: usize) -> Value {
+        self.args()[idx].clone()
+    }
+
+    // Like the vm's own, new strings and lists are held to its size limits.
+    pub(super) fn string(&mut self, text: &str) -> Result<Value> {
+        self.vm.check_string(text.len())?;
+        Ok(Value::String(self.vm.alloc(LoxString::new(text))))
+    }
+
+    pub(super) fn list(&mut self, items: Vec<Value>) -> Result<Value> {
+        self.vm.check_list(items.len())?;
+        Ok(Value::List(self.vm.alloc(LoxList { items })))
+    }
+
+    pub(super) fn host_value(&mut self, value: HostValue) -> Value {
+        self.vm.host_value(value)
+    }
+
+    pub(super) fn error(
+        &self,
+        msg: Message,
+        args: &[&dyn Display],
+    ) -> RuntimeError {
+        self.vm.runtime_error(msg, args)
+    }
+
+    pub(super) fn operand_error(
+        &self,
+        msg: Message,
+        operands: &[&Value],
+    ) -> RuntimeError {
+        self.vm.operand_error(msg, operands)
+    }
+
+    pub(super) fn map_key(&self, key: &Value) -> Result<MapKey> {
+        self.vm.map_key(key)
+    }
+}
+
+// Seconds since the first call, in any vm.
+pub(super) fn monotonic() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+pub(super) fn clock(ctx: &mut NativeContext) -> Result<Value> {
+    Ok(Value::Number((ctx.vm.clock)()))
+}
+
+pub(super) fn flush(ctx: &mut NativeContext) -> Result<Value> {
+    let _ = ctx.flush();
+    Ok(Value::Nil)
+}
+
+pub(super) fn read_line(ctx: &mut NativeContext) -> Result<Value> {
+    match ctx.read_line() {
+        Ok(Some(line)) => ctx.string(&line),
+        Ok(None) => Ok(Value::Nil),
+        Err(e) => Err(ctx.error(Message::ReadInput, &[&e])),
+    }
+}
+
+// A runtime error unless the condition is truthy. The message can be left
+// out, or nil.
+pub(super) fn assert(ctx: &mut NativeContext) -> Result<Value> {
+    if bool::from(ctx.arg(0)) {
+        return Ok(Value::Nil);
+    }
+    Err(match ctx.args().get(1) {
+        None | Some(Value::Nil) => ctx.error(Message::AssertionFailed, &[]),
+        Some(msg) => ctx.error(Message::AssertionFailedWith, &[msg]),
+    })
+}
+
+pub(super) fn doc(ctx: &mut NativeContext) -> Result<Value> {
+    match ctx.arg(0) {
+        Value::Function(func) => Ok(match &func.borrow().doc {
+            Some(doc) => ctx.string(doc)?,
+            None => Value::Nil,
+        }),
+        Value::Builtin(_) => Ok(Value::Nil),
+        arg => Err(ctx.operand_error(Message::ArgumentNotFunction, &[&arg])),
+    }
+}
+
+// A new list of a map's keys, in the order they were added.
+pub(super) fn keys(ctx: &mut NativeContext) -> Result<Value> {
+    match ctx.arg(0) {
+        Value::Map(map) => {
+            let keys =
+                map.borrow().entries().iter().map(|e| e.0.clone()).collect();
+            ctx.list(keys)
+        }
+        arg => Err(ctx.operand_error(Message::ArgumentNotMap, &[&arg])),
+    }
+}
+
+// Takes the key out of the map, returning its value, or nil if it wasn't
+// there.
+pub(super) fn remove(ctx: &mut NativeContext) -> Result<Value> {
+    match ctx.arg(0) {
+        Value::Map(map) => {
+            let key = ctx.map_key(&ctx.arg(1))?;
+            Ok(map.borrow_mut().remove(&key).unwrap_or(Value::Nil))
+        }
+        arg => Err(ctx.operand_error(Message::ArgumentNotMap, &[&arg])),
+    }
+}
+
+// The name of the value's type, as in runtime errors.
+pub(super) fn type_(ctx: &mut NativeContext) -> Result<Value> {
+    let name = ctx.arg(0).type_name();
+    ctx.string(name)
+}
+
+// The number of characters in a string, or of items in a list or map.
+pub(super) fn len(ctx: &mut NativeContext) -> Result<Value> {
+    let len = match ctx.arg(0) {
+        Value::String(s) => s.borrow().chars().count(),
+        Value::List(list) => list.borrow().items.len(),
+        Value::Map(map) => map.borrow().entries().len(),
+        arg => {
+            let msg = Message::ArgumentNotSized;
+            return Err(ctx.operand_error(msg, &[&arg]));
+        }
+    };
+    Ok(Value::Number(len as f64))
+}
+
+// The characters from `start` up to, but not including, `end`. Positions
+// count characters from 0, and go up to the length of the string.
+pub(super) fn substr(ctx: &mut NativeContext) -> Result<Value> {
+    let text = ctx.string_arg(0)?;
+    let len = text.chars().count();
+    let start = ctx.number_arg(1)?;
+    let end = ctx.number_arg(2)?;
+    let (start, end) = (position(ctx, start, len)?, position(ctx, end, len)?);
+    if start > end {
+        let msg = Message::SubstringReversed;
+        return Err(ctx.error(msg, &[&start, &end]));
+    }
+    let text: String = text.chars().skip(start).take(end - start).collect();
+    ctx.string(&text)
+}
+
+fn position(ctx: &NativeContext, n: f64, len: usize) -> Result<usize> {
+    if n.fract() != 0.0 {
+        return Err(ctx.error(Message::PositionNotWhole, &[]));
+    }
+    if n < 0.0 || n > len as f64 {
+        return Err(ctx.error(Message::PositionOutOfBounds, &[&n, &len]));
+    }
+    Ok(n as usize)
+}
+
+// The position, in characters, of the first place `part` is found in the
+// string, or nil if it isn't.
+pub(super) fn index_of(ctx: &mut NativeContext) -> Result<Value> {
+    let text = ctx.string_arg(0)?;
+    let part = ctx.string_arg(1)?;
+    Ok(match text.find(&part) {
+        Some(at) => Value::Number(text[..at].chars().count() as f64),
+        None => Value::Nil,
+    })
+}
+
+// A list of the parts of the string between each `sep`; with an empty
+// `sep`, of each character.
+pub(super) fn split(ctx: &mut NativeContext) -> Result<Value> {
+    let text = ctx.string_arg(0)?;
+    let sep = ctx.string_arg(1)?;
+    let parts: Vec<String> = match sep.is_empty() {
+        true => text.chars().map(String::from).collect(),
+        false => text.split(&sep).map(String::from).collect(),
+    };
+    let items = parts.iter().map(|part| ctx.string(part));
+    let items = items.collect::<Result<_>>()?;
+    ctx.list(items)
+}
+
+// Rust's number formatting and parsing ignore the system locale, so these
+// always use '.' for the decimal point.
+pub(super) fn format_number(ctx: &mut NativeContext) -> Result<Value> {
+    let num = ctx.number_arg(0)?;
+    let places = ctx.number_arg(1)?;
+    let sep = ctx.string_arg(2)?;
+    if places.fract() != 0.0 || !(0.0..=100.0).contains(&places) {
+        return Err(ctx.error(Message::DecimalsRange, &[]));
     }
+    let text = match num.is_finite() {
+        true => format!("{:.*}", places as usize, num),
+        false => num.to_string(),
+    };
+    // Anything that rounds to zero shows as zero, without a sign.
+    let text = match text.strip_prefix('-') {
+        Some(digits) if digits.bytes().all(|b| b == b'0' || b == b'.') => {
+            digits.to_string()
+        }
+        _ => text,
+    };
+    let text = group_thousands(&text, &sep);
+    ctx.string(&text)
 }
 
-pub(super) fn clock(arg_count: usize, vm: &mut Vm) -> Result<Value> {
-    unsafe {
-        let mut tp = std::mem::MaybeUninit::<libc::timespec>::uninit();
-        if ffi::clock_gettime(libc::CLOCK_MONOTONIC, tp.as_mut_ptr()) == 0 {
-            let tp = tp.assume_init();
-            Ok(Value::Number(
-                Duration::new(tp.tv_sec as u64, tp.tv_nsec as u32)
-                    .as_secs_f64(),
-            ))
-        } else {
-            panic!("{}", std::io::Error::last_os_error());
+// Puts `sep` between each group of three digits before the decimal point.
+fn group_thousands(text: &str, sep: &str) -> String {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", text),
+    };
+    let whole_len = digits.find('.').unwrap_or(digits.len());
+    if !digits[..whole_len].bytes().all(|b| b.is_ascii_digit()) {
+        return text.to_string();
+    }
+    let mut grouped = sign.to_string();
+    for (i, digit) in digits.char_indices() {
+        if i > 0 && i < whole_len && (whole_len - i) % 3 == 0 {
+            grouped.push_str(sep);
         }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// Reads a decimal number, with an optional sign and exponent, and nothing
+// else apart from surrounding whitespace; anything else gives nil.
+pub(super) fn parse_number(ctx: &mut NativeContext) -> Result<Value> {
+    let text = ctx.string_arg(0)?;
+    let text = text.trim();
+    let digits =
+        |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let valid = match mantissa.split_once('.') {
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(mantissa),
+    } && exponent
+        .is_none_or(|e| digits(e.strip_prefix(['-', '+']).unwrap_or(e)));
+    Ok(match text.parse::<f64>() {
+        Ok(n) if valid => Value::Number(n),
+        _ => Value::Nil,
+    })
+}
+
+// The value as `print` would show it.
+pub(super) fn str(ctx: &mut NativeContext) -> Result<Value> {
+    match ctx.arg(0) {
+        Value::String(s) => Ok(Value::String(s)),
+        arg => ctx.string(&arg.to_string()),
+    }
+}
+
+// A number as it is, or a string read as by `parseNumber`.
+pub(super) fn num(ctx: &mut NativeContext) -> Result<Value> {
+    match ctx.arg(0) {
+        Value::Number(n) => Ok(Value::Number(n)),
+        _ => parse_number(ctx),
     }
 }
@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use super::LoxFunction;
+use crate::{
+    code::{Chunk, Instruction, Op},
+    Obj, Value,
+};
+
+/// The operand types seen by each arithmetic and comparison instruction
+/// that has run, from [`Vm::stats`](crate::Vm::stats).
+pub struct Stats {
+    pub sites: Vec<Site>,
+}
+
+pub struct Site {
+    pub function: String,
+    pub line: u32,
+    pub op: &'static str,
+    /// Operand type names and how many times each combination was seen,
+    /// most frequent first. Unary operators have an empty second type.
+    pub types: Vec<(&'static str, &'static str, u64)>,
+}
+
+// Sites are keyed by function and instruction offset. Functions are told
+// apart by an id the profile gives them, since a collected function's
+// address can be reused by a new one.
+#[derive(Default)]
+pub(super) struct Profile {
+    sites: HashMap<(u32, usize), Site>,
+    last_id: u32,
+}
+
+impl Profile {
+    pub(super) fn record(
+        &mut self,
+        func: &Obj<LoxFunction>,
+        chunk: &Chunk,
+        inst: Instruction,
+        offset: usize,
+        stack: &[Value],
+    ) {
+        let op = Op::generic(inst.opcode());
+        let operands = match op {
+            Op::Negate => 1,
+            Op::Add
+            | Op::Subtract
+            | Op::Multiply
+            | Op::Divide
+            | Op::Power
+            | Op::Greater
+            | Op::Less
+            | Op::Equal => 2,
+            _ => return,
+        };
+        let top = stack.len() - operands;
+        let a = stack[top].type_name();
+        let b = stack.get(top + 1).map_or("", Value::type_name);
+        self.count(func, chunk, op, offset, (a, b), 1);
+    }
+
+    // Counts `n` runs of the `op` at `offset` in `func`, with operands of
+    // the types in `types`.
+    pub(super) fn count(
+        &mut self,
+        func: &Obj<LoxFunction>,
+        chunk: &Chunk,
+        op: u8,
+        offset: usize,
+        (a, b): (&'static str, &'static str),
+        n: u64,
+    ) {
+        let id = match func.borrow().profile_id.get() {
+            0 => {
+                self.last_id += 1;
+                func.borrow().profile_id.set(self.last_id);
+                self.last_id
+            }
+            id => id,
+        };
+        let site = self.sites.entry((id, offset)).or_insert_with(|| Site {
+            function: func.borrow().name.clone(),
+            line: chunk.get_line(offset),
+            op: Op::name(op),
+            types: Vec::new(),
+        });
+        match site.types.iter_mut().find(|(x, y, _)| (*x, *y) == (a, b)) {
+            Some((_, _, count)) => *count += n,
+            None => site.types.push((a, b, n)),
+        }
+    }
+
+    pub(super) fn stats(&self) -> Stats {
+        let mut sites: Vec<_> = self
+            .sites
+            .values()
+            .map(|site| {
+                let mut types = site.types.clone();
+                types.sort_by_key(|&(_, _, count)| std::cmp::Reverse(count));
+                Site {
+                    function: site.function.clone(),
+                    types,
+                    ..*site
+                }
+            })
+            .collect();
+        sites.sort_by(|x, y| {
+            (&x.function, x.line, x.op).cmp(&(&y.function, y.line, y.op))
+        });
+        Stats { sites }
+    }
+}
+
+impl Site {
+    /// True if every execution saw the same operand types.
+    pub fn is_monomorphic(&self) -> bool {
+        self.types.len() == 1
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for site in &self.sites {
+            write!(f, "{} [line {}] {}:", site.function, site.line, site.op)?;
+            for (a, b, count) in &site.types {
+                match *b {
+                    "" => write!(f, " {}={}", a, count)?,
+                    _ => write!(f, " {},{}={}", a, b, count)?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
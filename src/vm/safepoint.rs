@@ -0,0 +1,145 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use super::{Result, RuntimeError};
+use crate::message::{Catalog, Message};
+
+/// What a safepoint hook is told about the running script.
+pub struct Safepoint {
+    /// Instructions executed by this vm so far.
+    pub instructions: u64,
+}
+
+/// A hook run at safepoints; returning an error stops the script with a
+/// runtime error carrying that message.
+pub type SafepointHook =
+    Box<dyn FnMut(&Safepoint) -> std::result::Result<(), String>>;
+
+/// Stops the script a vm is running, from another thread or a signal
+/// handler, with an "interrupted" error (see
+/// [`RuntimeError::is_interrupted`]). Interrupts sent while no script is
+/// running are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+// Everything that has to happen periodically while a script runs (limits,
+// interrupts, and the like) shares this one countdown, so the dispatch loop
+// makes a single check per instruction however many are in use.
+pub(super) struct Safepoints {
+    interval: u32,
+    // Compiled code counts down through a pointer to this, as `tick` does.
+    pub(super) countdown: u32,
+    // What the countdown started from: the interval, or less when fuel is
+    // about to run out.
+    period: u32,
+    executed: u64,
+    // The instruction count at which the fuel runs out, if there is any.
+    deadline: Option<u64>,
+    interrupt: InterruptHandle,
+    hooks: Vec<SafepointHook>,
+}
+
+impl Safepoints {
+    const DEFAULT_INTERVAL: u32 = 1024;
+
+    pub(super) fn new() -> Self {
+        Safepoints {
+            interval: Safepoints::DEFAULT_INTERVAL,
+            countdown: Safepoints::DEFAULT_INTERVAL,
+            period: Safepoints::DEFAULT_INTERVAL,
+            executed: 0,
+            deadline: None,
+            interrupt: InterruptHandle::default(),
+            hooks: Vec::new(),
+        }
+    }
+
+    pub(super) fn add_hook(&mut self, hook: SafepointHook) {
+        self.hooks.push(hook);
+    }
+
+    // Counts `n` instructions that weren't run, such as the work done by a
+    // native. Running out of fuel this way stops the script at the next
+    // instruction.
+    pub(super) fn charge(&mut self, n: u64) {
+        self.restart();
+        self.executed = self.executed.saturating_add(n);
+        self.restart();
+    }
+
+    pub(super) fn clear_interrupt(&mut self) {
+        self.interrupt.0.store(false, Ordering::Relaxed);
+    }
+
+    pub(super) fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    // Allow `fuel` more instructions, or any number with None.
+    pub(super) fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.restart();
+        self.deadline = fuel.map(|fuel| self.executed + fuel + 1);
+        self.restart();
+    }
+
+    pub(super) fn set_interval(&mut self, interval: u32) {
+        self.interval = interval.max(1);
+        self.restart();
+    }
+
+    // Starts a new countdown, keeping the instruction count exact.
+    fn restart(&mut self) {
+        self.executed += (self.period - self.countdown) as u64;
+        self.period = match self.deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_sub(self.executed).max(1);
+                left.min(self.interval as u64) as u32
+            }
+            None => self.interval,
+        };
+        self.countdown = self.period;
+    }
+
+    #[inline]
+    pub(super) fn tick(&mut self, messages: &Catalog) -> Result<()> {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return Ok(());
+        }
+        self.expired(messages)
+    }
+
+    // Everything a safepoint checks, once the countdown reaches zero.
+    #[cold]
+    pub(super) fn expired(&mut self, messages: &Catalog) -> Result<()> {
+        self.restart();
+        if self
+            .deadline
+            .is_some_and(|deadline| self.executed >= deadline)
+        {
+            // The instruction that would have used more doesn't run.
+            self.executed -= 1;
+            let msg = messages.format(Message::OutOfFuel, &[]);
+            return Err(RuntimeError::timeout(msg));
+        }
+        if self.interrupt.0.swap(false, Ordering::Relaxed) {
+            let msg = messages.format(Message::Interrupted, &[]);
+            return Err(RuntimeError::interrupted(msg));
+        }
+        let safepoint = Safepoint {
+            instructions: self.executed,
+        };
+        for hook in &mut self.hooks {
+            hook(&safepoint).map_err(RuntimeError::new)?;
+        }
+        Ok(())
+    }
+}
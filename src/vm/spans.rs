@@ -0,0 +1,55 @@
+use tracing::span::EnteredSpan;
+
+use super::LoxFunction;
+
+// A `tracing` span for each call that's sampled, entered while the call
+// runs, so that calls a script makes nest inside each other and inside
+// whatever the host was doing.
+pub(super) struct CallSpans {
+    every: u32,
+    max_depth: usize,
+    calls: u32,
+    // One for each frame, None for calls left out.
+    open: Vec<Option<EnteredSpan>>,
+}
+
+impl CallSpans {
+    pub(super) fn new() -> Self {
+        CallSpans {
+            every: 1,
+            max_depth: usize::MAX,
+            calls: 0,
+            open: Vec::new(),
+        }
+    }
+
+    pub(super) fn set_sampling(&mut self, every: u32, max_depth: usize) {
+        self.every = every;
+        self.max_depth = max_depth;
+        self.calls = 0;
+    }
+
+    // `depth` is the number of frames below the call's own.
+    pub(super) fn enter(&mut self, func: &LoxFunction, depth: usize) {
+        let sampled = self.every != 0
+            && depth <= self.max_depth
+            && self.calls.is_multiple_of(self.every);
+        if depth <= self.max_depth {
+            self.calls = self.calls.wrapping_add(1);
+        }
+        let span = sampled.then(|| {
+            let (name, line) = (func.name.as_str(), func.line);
+            tracing::trace_span!("call", function = name, line).entered()
+        });
+        self.open.push(span);
+    }
+
+    pub(super) fn exit(&mut self) {
+        self.open.pop();
+    }
+
+    // Exits every span, innermost first, when an error unwinds the stack.
+    pub(super) fn exit_all(&mut self) {
+        while self.open.pop().is_some() {}
+    }
+}
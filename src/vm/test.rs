@@ -1,37 +1,92 @@
-use std::io::Write;
 use std::{cell::RefCell, rc::Rc};
 
-use crate::Vm;
+use crate::testing::{assert_opcodes, interpret, interpret_with, opcodes};
+use crate::{Dialect, Vm, VmOptions};
 
+mod assert;
 mod assignment;
 mod block;
 mod bool;
 mod break_;
+mod bundle;
+mod cache;
+mod clock;
 mod comments;
+mod conformance;
+mod constant;
 mod continue_;
+mod defer;
+mod dialect;
+mod doc;
+mod embedding;
+mod error_line;
 mod for_;
+mod for_in;
 mod function;
+mod gc;
+mod increment;
+mod isolated;
+// Traced runs stay in the interpreter.
+#[cfg(all(feature = "jit", not(feature = "trace_execution")))]
+mod jit;
+mod list;
+#[cfg(feature = "log")]
+mod logging;
 mod logical_operator;
+mod long_jump;
+mod loop_else;
+mod map;
+mod multiple_assignment;
+mod namespace;
+mod nesting;
 mod nil;
 mod number;
 mod operator;
+mod optional_semicolons;
 mod print;
+#[cfg(feature = "profiling")]
+mod profiling;
+mod property;
+mod quicken;
+mod read_line;
+mod repl;
+mod return_;
+mod safepoint;
+mod shadowing;
+mod show_source;
+mod source_size;
+#[cfg(feature = "tracing")]
+mod spans;
+mod stack;
+mod strict;
 mod string;
+mod switch;
+#[cfg(any(feature = "trace_execution", feature = "print_code"))]
+mod trace;
+mod type_;
+mod unassigned;
+mod value_size;
 mod variable;
+mod verbose_errors;
 mod while_;
 
-fn interpret(source: &str) -> (String, String) {
-    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
-    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
-    let mut vm = Vm::new(stdout.clone(), stderr.clone());
-    if let Err(e) = vm.interpret(source.to_string()) {
-        let _ = writeln!(stderr.borrow_mut(), "{}", e);
+// A vm that prints its output and its errors to the buffer returned with it.
+fn vm() -> (Vm, Rc<RefCell<Vec<u8>>>) {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    (Vm::new(out.clone(), out.clone()), out)
+}
+
+// Takes what has been printed to `out` so far.
+fn output(out: &Rc<RefCell<Vec<u8>>>) -> String {
+    String::from_utf8(out.borrow_mut().split_off(0)).unwrap()
+}
+
+// The book's dialect, without the extensions to the language.
+fn book() -> VmOptions {
+    VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
     }
-    let ret = (
-        String::from_utf8(stdout.borrow().to_vec()).unwrap(),
-        String::from_utf8(stderr.borrow().to_vec()).unwrap(),
-    );
-    ret
 }
 
 #[test]
@@ -89,3 +144,28 @@ fn precedence() {
     assert_eq!(stdout, expected.join("\n"));
     assert_eq!(stderr, "");
 }
+
+#[test]
+fn opcodes_emitted() {
+    assert_opcodes(
+        "print 1 >= 2;",
+        &["ONE", "INT", "LESS", "NOT", "PRINT", "NIL", "RETURN"],
+    );
+    assert_opcodes(
+        "{ var a; a = a; }",
+        &["NIL", "GETLOCAL", "SETLOCAL", "POP", "POP", "NIL", "RETURN"],
+    );
+    assert_eq!(
+        opcodes("print;"),
+        Err("[line 1] Error at ';': expect expression\n".to_string())
+    );
+}
+
+#[test]
+fn runtime_error_resets_vm() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    assert!(vm.interpret("print -\"a\";".to_string()).is_err());
+    vm.interpret("print 1;".to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"1\n");
+}
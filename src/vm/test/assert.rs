@@ -0,0 +1,45 @@
+use super::interpret;
+
+#[test]
+fn passing() {
+    let source = r#"
+    assert(true, nil);
+    assert(true);
+    assert(1 + 1 == 2, "maths");
+    assert(0, nil);
+    assert("", "empty strings are truthy");
+    print "done";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "done\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn failing() {
+    let source = r#"
+    print "before";
+    assert(1 > 2, "1 is not more than 2");
+    print "after";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "before\n");
+    assert_eq!(stderr, "[line 3] assertion failed: 1 is not more than 2\n");
+
+    let (_, stderr) = interpret("\nassert(nil, nil);");
+    assert_eq!(stderr, "[line 2] assertion failed\n");
+    let (_, stderr) = interpret("assert(1 == 2);");
+    assert_eq!(stderr, "[line 1] assertion failed\n");
+    let (_, stderr) = interpret("fun f() {\n  assert(false, [1]);\n}\nf();");
+    assert_eq!(stderr, "[line 2] assertion failed: [1]\n");
+}
+
+#[test]
+fn argument_count() {
+    let (_, stderr) = interpret("assert();");
+    assert_eq!(stderr, "[line 1] expected 1 to 2 arguments but got 0\n");
+    let (_, stderr) = interpret("assert(true, nil, nil);");
+    assert_eq!(stderr, "[line 1] expected 1 to 2 arguments but got 3\n");
+}
@@ -1,10 +1,5 @@
-use std::{cell::RefCell, rc::Rc};
-
 use super::interpret;
 
-type Stdout = Rc<RefCell<Vec<u8>>>;
-type Stderr = Rc<RefCell<Vec<u8>>>;
-
 #[test]
 fn associativity() {
     let source = r#"
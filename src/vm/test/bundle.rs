@@ -0,0 +1,121 @@
+use std::{cell::RefCell, fs, path::PathBuf, rc::Rc};
+
+use crate::{bundle, bundled_program, Vm};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "redlox-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn compile(source: &str) -> Vec<u8> {
+    let sink = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(sink.clone(), sink);
+    vm.compile(source.to_string()).unwrap()
+}
+
+fn run(program: &[u8]) -> String {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(stdout.clone(), stdout.clone());
+    vm.run_compiled(program).unwrap();
+    let ret = String::from_utf8(stdout.borrow().to_vec()).unwrap();
+    ret
+}
+
+#[test]
+fn compile_and_run() {
+    let program = compile(
+        r#"
+fun sq(x) { return x * x; }
+var s = "ok";
+print sq(7);
+print s;
+print clock() >= 0;
+"#,
+    );
+    assert_eq!(run(&program), "49\nok\ntrue\n");
+}
+
+#[test]
+fn compile_errors() {
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(stderr.clone(), stderr.clone());
+    assert!(vm.compile("print ;".to_string()).is_none());
+    assert_eq!(
+        String::from_utf8(stderr.borrow().to_vec()).unwrap(),
+        "[line 1] Error at ';': expect expression\n"
+    );
+}
+
+#[test]
+fn bundle_round_trip() {
+    let dir = temp_dir("bundle");
+    let runtime = dir.join("runtime");
+    let (first, second) = (dir.join("first"), dir.join("second"));
+    fs::write(&runtime, b"not really an executable").unwrap();
+    assert_eq!(bundled_program(&runtime).unwrap(), None);
+
+    let program = compile("print 1;");
+    bundle(&program, &runtime, &first).unwrap();
+    assert_eq!(bundled_program(&first).unwrap(), Some(program));
+
+    // Bundling from a bundle replaces the program.
+    let program = compile("print 2;");
+    bundle(&program, &first, &second).unwrap();
+    let bundled = bundled_program(&second).unwrap().unwrap();
+    assert_eq!(run(&bundled), "2\n");
+    assert_eq!(
+        fs::metadata(&second).unwrap().len(),
+        fs::metadata(&runtime).unwrap().len() + program.len() as u64 + 16
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn symbol_ids_are_relinked() {
+    // The compiling vm gives `a` and `b` different ids than a fresh one.
+    let sink = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(sink.clone(), sink);
+    vm.interpret("var z = 0; var b = 1;".to_string()).unwrap();
+    let program = vm
+        .compile(
+            r#"
+var a = "a";
+var b = "b";
+fun f() { return a + b; }
+print f();
+"#
+            .to_string(),
+        )
+        .unwrap();
+    assert_eq!(run(&program), "ab\n");
+
+    // And the running vm can have seen other names first.
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(stdout.clone(), stdout.clone());
+    vm.interpret("var b; var y; var a;".to_string()).unwrap();
+    vm.run_compiled(&program).unwrap();
+    assert_eq!(String::from_utf8(stdout.borrow().to_vec()).unwrap(), "ab\n");
+}
+
+#[test]
+fn format_mismatch() {
+    let mut program = compile("print 1;");
+    program[4..8].copy_from_slice(&99u32.to_le_bytes());
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(stdout.clone(), stdout);
+    let version = env!("CARGO_PKG_VERSION");
+    assert_eq!(
+        vm.run_compiled(&program).unwrap_err().to_string(),
+        format!(
+            "program was compiled by redlox {} (format 99), but this is \
+             redlox {} (format 11)",
+            version, version
+        )
+    );
+}
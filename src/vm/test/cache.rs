@@ -0,0 +1,238 @@
+use std::{
+    cell::RefCell, fs, io::Write, path::PathBuf, rc::Rc, time::SystemTime,
+};
+
+use crate::{BytecodeCache, Vm, VmOptions};
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "redlox-test-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn cached_files(dir: &PathBuf) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.map(|e| e.unwrap().path()).collect())
+        .unwrap_or_default()
+}
+
+// Runs each source in turn on one vm, returning stdout and stderr.
+fn run_cached(sources: &[&str], cache: &BytecodeCache) -> (String, String) {
+    run_cached_with(sources, cache, VmOptions::default())
+}
+
+fn run_cached_with(
+    sources: &[&str],
+    cache: &BytecodeCache,
+    options: VmOptions,
+) -> (String, String) {
+    let stdout = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let stderr = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::with_options(stdout.clone(), stderr.clone(), options);
+    for source in sources {
+        if let Err(e) = vm.interpret_cached(source.to_string(), cache) {
+            let _ = writeln!(stderr.borrow_mut(), "{}", e);
+        }
+    }
+    let ret = (
+        String::from_utf8(stdout.borrow().to_vec()).unwrap(),
+        String::from_utf8(stderr.borrow().to_vec()).unwrap(),
+    );
+    ret
+}
+
+const SOURCE: &str = r#"
+fun greet(name) {
+  return "hi " + name;
+}
+var n = 1.5;
+for (var i = 0; i < 2; i = i + 1) {
+  print greet("there");
+  print n;
+}
+"#;
+
+#[test]
+fn round_trip() {
+    let dir = cache_dir("round_trip");
+    let cache = BytecodeCache::in_dir(&dir);
+
+    let first = run_cached(&[SOURCE], &cache);
+    assert_eq!(
+        first,
+        ("hi there\n1.5\nhi there\n1.5\n".to_string(), "".into())
+    );
+    let files = cached_files(&dir);
+    assert_eq!(files.len(), 1);
+    let saved = fs::read(&files[0]).unwrap();
+
+    assert_eq!(run_cached(&[SOURCE], &cache), first);
+    assert_eq!(fs::read(&files[0]).unwrap(), saved);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn runtime_errors_keep_lines() {
+    let dir = cache_dir("runtime_errors");
+    let cache = BytecodeCache::in_dir(&dir);
+    let source = "print 1;\n\nprint -\"a\";";
+
+    let first = run_cached(&[source], &cache);
+    assert_eq!(first.1, "[line 3] operand must be a number\n");
+    assert_eq!(run_cached(&[source], &cache), first);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn runtime_errors_show_source() {
+    let dir = cache_dir("show_source");
+    let cache = BytecodeCache::in_dir(&dir);
+    let source = "print 1;\nprint -\"a\";";
+    let options = VmOptions {
+        show_source: true,
+        ..Default::default()
+    };
+
+    let first = run_cached_with(&[source], &cache, options.clone());
+    assert_eq!(
+        first.1,
+        "[line 2] operand must be a number\n    print -\"a\";\n"
+    );
+    assert_eq!(run_cached_with(&[source], &cache, options), first);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn compile_errors_are_not_cached() {
+    let dir = cache_dir("compile_errors");
+    let cache = BytecodeCache::in_dir(&dir);
+
+    let (_, stderr) = run_cached(&["print ;"], &cache);
+    assert_eq!(stderr, "[line 1] Error at ';': expect expression\n");
+    assert!(cached_files(&dir).is_empty());
+}
+
+#[test]
+fn programs_with_warnings_are_not_cached() {
+    let dir = cache_dir("warnings");
+    let cache = BytecodeCache::in_dir(&dir);
+    let options = VmOptions {
+        warn_shadowing: true,
+        ..Default::default()
+    };
+    let source = "var a = 1;\n{ var a = 2; print a; }";
+
+    let first = run_cached_with(&[source], &cache, options.clone());
+    assert!(
+        first.1.starts_with("[line 2] Warning at 'a': "),
+        "{}",
+        first.1
+    );
+    assert!(cached_files(&dir).is_empty());
+    assert_eq!(run_cached_with(&[source], &cache, options), first);
+}
+
+#[test]
+fn corrupt_entry_is_recompiled() {
+    let dir = cache_dir("corrupt");
+    let cache = BytecodeCache::in_dir(&dir);
+    run_cached(&[SOURCE], &cache);
+    let path = cached_files(&dir).remove(0);
+    let saved = fs::read(&path).unwrap();
+
+    fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+    let (stdout, stderr) = run_cached(&[SOURCE], &cache);
+    assert_eq!(stdout, "hi there\n1.5\nhi there\n1.5\n");
+    assert_eq!(stderr, "");
+    assert_eq!(fs::read(&path).unwrap(), saved);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn symbol_mismatch_is_relinked() {
+    let dir = cache_dir("symbols");
+    let cache = BytecodeCache::in_dir(&dir);
+    run_cached(&[SOURCE], &cache);
+
+    // A vm that has already seen other globals assigns different ids.
+    let (stdout, stderr) = run_cached(&["var other = 2;", SOURCE], &cache);
+    assert_eq!(stdout, "hi there\n1.5\nhi there\n1.5\n");
+    assert_eq!(stderr, "");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn options_are_part_of_the_key() {
+    let dir = cache_dir("options");
+    let cache = BytecodeCache::in_dir(&dir);
+    let source = "var x = 1;\nvar x = 2;\nprint(x);";
+    let strict = VmOptions {
+        strict: true,
+        ..Default::default()
+    };
+
+    let (stdout, _) = run_cached(&[source], &cache);
+    assert_eq!(stdout, "2\n");
+    let (stdout, stderr) = run_cached_with(&[source], &cache, strict);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 2] Error at 'x': already a global with this name\n"
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn option_sets_are_cached_apart() {
+    let dir = cache_dir("option_sets");
+    let cache = BytecodeCache::in_dir(&dir);
+    let source = "print(1);";
+    let strict = VmOptions {
+        strict: true,
+        ..Default::default()
+    };
+
+    run_cached(&[source], &cache);
+    run_cached_with(&[source], &cache, strict.clone());
+    let files = cached_files(&dir);
+    assert_eq!(files.len(), 2);
+    // Storing a file again would give it a new modification time.
+    for path in &files {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+    }
+    for _ in 0..2 {
+        assert_eq!(run_cached(&[source], &cache), ("1\n".into(), "".into()));
+        let strict = run_cached_with(&[source], &cache, strict.clone());
+        assert_eq!(strict, ("1\n".into(), "".into()));
+    }
+    assert_eq!(cached_files(&dir).len(), 2);
+    for path in &files {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(modified, SystemTime::UNIX_EPOCH);
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn hash_collisions_are_recompiled() {
+    let dir = cache_dir("collisions");
+    let cache = BytecodeCache::in_dir(&dir);
+    run_cached(&["print 1;"], &cache);
+
+    // Pretend "print 2;" hashes the same as "print 1;", which it is the
+    // same length as.
+    let path = cached_files(&dir).remove(0);
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let (_, key) = name.split_once('-').unwrap();
+    let other = dir.join(format!("{:016x}-{}", crate::fnv1a(b"print 2;"), key));
+    fs::rename(&path, &other).unwrap();
+    let (stdout, stderr) = run_cached(&["print 2;"], &cache);
+    assert_eq!(stdout, "2\n");
+    assert_eq!(stderr, "");
+    let _ = fs::remove_dir_all(&dir);
+}
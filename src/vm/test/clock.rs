@@ -0,0 +1,38 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use super::interpret;
+use crate::Vm;
+
+#[test]
+fn monotonic() {
+    let source = r#"
+    var start = clock();
+    var i = 0;
+    while (i < 100) i = i + 1;
+    print clock() >= start;
+    print start >= 0;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "true\ntrue\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn stubbed() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let now = Rc::new(Cell::new(10.0));
+    let time = now.clone();
+    vm.set_clock(move || {
+        time.set(time.get() + 0.5);
+        time.get()
+    });
+    vm.interpret("var t = clock(); print clock() - t;".to_string())
+        .unwrap();
+    assert_eq!(*out.borrow(), b"0.5\n");
+    assert_eq!(now.get(), 11.0);
+}
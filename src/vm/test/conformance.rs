@@ -0,0 +1,103 @@
+// Runs the programs in tests/lox, checking their output against the
+// `// expect: <line>` and `// expect runtime error: <msg>` comments in them.
+// With REDLOX_REFERENCE set to the path of another Lox interpreter (such as
+// jlox or clox), also runs each program through it and reports any program
+// whose output or success differs between the two.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+use super::interpret;
+
+const REFERENCE: &str = "REDLOX_REFERENCE";
+
+struct Expected {
+    stdout: String,
+    runtime_error: Option<String>,
+}
+
+fn programs() -> Vec<PathBuf> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn expected(source: &str) -> Expected {
+    let mut stdout = String::new();
+    let mut runtime_error = None;
+    for line in source.lines() {
+        if let Some((_, text)) = line.split_once("// expect: ") {
+            stdout.push_str(text);
+            stdout.push('\n');
+        } else if line.trim_end().ends_with("// expect:") {
+            stdout.push('\n');
+        } else if let Some((_, msg)) =
+            line.split_once("// expect runtime error: ")
+        {
+            runtime_error = Some(msg.to_string());
+        }
+    }
+    Expected {
+        stdout,
+        runtime_error,
+    }
+}
+
+#[test]
+fn expectations() {
+    for path in programs() {
+        let source = fs::read_to_string(&path).unwrap();
+        let expected = expected(&source);
+        let (stdout, stderr) = interpret(&source);
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(stdout, expected.stdout, "stdout of {}", name);
+        match expected.runtime_error {
+            Some(msg) => assert!(
+                stderr.contains(&msg),
+                "{}: expected runtime error '{}', got '{}'",
+                name,
+                msg,
+                stderr
+            ),
+            None => assert_eq!(stderr, "", "stderr of {}", name),
+        }
+    }
+}
+
+#[test]
+fn matches_reference() {
+    let Some(reference) = env::var_os(REFERENCE) else {
+        return;
+    };
+    let mut deviations = Vec::new();
+    for path in programs() {
+        let source = fs::read_to_string(&path).unwrap();
+        let (stdout, stderr) = interpret(&source);
+        let output = Command::new(&reference).arg(&path).output().unwrap();
+        let their_stdout = String::from_utf8_lossy(&output.stdout);
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if stdout != their_stdout {
+            deviations.push(format!(
+                "{}: stdout differs\n--- redlox\n{}--- reference\n{}",
+                name, stdout, their_stdout
+            ));
+        }
+        if stderr.is_empty() != output.status.success() {
+            deviations.push(format!(
+                "{}: redlox {}, reference exited with {}",
+                name,
+                if stderr.is_empty() {
+                    "succeeded"
+                } else {
+                    "failed"
+                },
+                output.status
+            ));
+        }
+    }
+    assert!(deviations.is_empty(), "\n{}", deviations.join("\n"));
+}
@@ -0,0 +1,66 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{assert_opcodes, interpret};
+use crate::Vm;
+
+#[test]
+fn common_constants_have_opcodes() {
+    assert_opcodes(
+        r#"print 0 + 1; print "";"#,
+        &[
+            "ZERO",
+            "ONE",
+            "ADD",
+            "PRINT",
+            "EMPTYSTRING",
+            "PRINT",
+            "NIL",
+            "RETURN",
+        ],
+    );
+    assert_opcodes(
+        "print 2; print 255; print 256; print 1.5;",
+        &[
+            "INT", "PRINT", "INT", "PRINT", "CONSTANT", "PRINT", "CONSTANT",
+            "PRINT", "NIL", "RETURN",
+        ],
+    );
+}
+
+#[test]
+fn values() {
+    let source = r#"
+    print 0;
+    print -0;
+    print 1;
+    print 1 + 1 == 2;
+    print 2 * 255;
+    print "" + "a" + "";
+    print "" == "";
+    var a = "b";
+    print a + "" == "b";
+    "#;
+
+    let expected = ["0", "-0", "1", "true", "510", "a", "true", "true", ""];
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, expected.join("\n"));
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn repeats_share_a_slot() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let source = r#"
+    var x = "a";
+    switch (x) {
+        case "a": print 2.5;
+        case "b": print 2.5;
+        case "a": print x + "b";
+    }
+    "#;
+    assert!(vm.disassemble(source.to_string()));
+    let listing = String::from_utf8(out.borrow().to_vec()).unwrap();
+    assert!(listing.ends_with("constants: 1 number, 2 strings\n"));
+}
@@ -0,0 +1,112 @@
+use super::interpret;
+
+#[test]
+fn at_block_end() {
+    let source = r#"
+    {
+        var a = "a";
+        defer print a;
+        defer {
+            var b = "b";
+            print b;
+        }
+        print "body";
+    }
+    print "after";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "body\nb\na\nafter\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn at_script_end() {
+    let source = r#"
+    defer print "last";
+    print "first";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "first\nlast\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn on_return() {
+    let source = r#"
+    fun f(x) {
+        var a = "a";
+        defer print a;
+        if (x) {
+            var b = "b";
+            defer print b;
+            return x + 1;
+        }
+        defer print "c";
+        return;
+    }
+    print f(1);
+    print f(false);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "b\na\n2\nc\na\nnil\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn on_break_and_continue() {
+    let source = r#"
+    for (var i = 0; i < 4; i = i + 1) {
+        defer print i;
+        if (i == 1) continue;
+        if (i == 2) break;
+        print "body";
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "body\n0\n1\n2\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn sees_only_earlier_locals() {
+    let source = r#"
+    var a = "global";
+    {
+        defer print a;
+        var a = "local";
+        print a;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "local\nglobal\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn errors() {
+    let (_, stderr) = interpret("fun f() { defer return; }");
+    assert!(stderr.starts_with(
+        "[line 1] Error at 'return': can't return from 'defer'\n"
+    ));
+
+    let (_, stderr) = interpret("while (true) { defer break; }");
+    assert!(
+        stderr.starts_with("[line 1] Error at ';': 'break' outside of loop\n")
+    );
+
+    let (_, stderr) = interpret("defer { defer print 1; }");
+    assert!(stderr.starts_with(
+        "[line 1] Error at 'defer': can't defer inside 'defer'\n"
+    ));
+
+    let (_, stderr) = interpret("if (true) defer print 1;");
+    assert!(stderr.starts_with(
+        "[line 1] Error at 'defer': 'defer' can't be the body of another \
+         statement\n"
+    ));
+}
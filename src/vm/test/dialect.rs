@@ -0,0 +1,30 @@
+use super::{book, interpret, interpret_with};
+
+#[test]
+fn extensions_are_identifiers() {
+    let source = r#"
+    var switch = 1;
+    var case = 2;
+    fun default(break) { return break + 1; }
+    var continue = default(switch + case);
+    print continue;
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, book());
+    assert_eq!(stdout, "4\n");
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret(source);
+    assert!(stderr
+        .starts_with("[line 2] Error at 'switch': expect variable name\n"));
+}
+
+#[test]
+fn book_keywords_remain() {
+    let source = "var while = 1;";
+
+    let (_, stderr) = interpret_with(source, book());
+    assert!(
+        stderr.starts_with("[line 1] Error at 'while': expect variable name\n")
+    );
+}
@@ -0,0 +1,81 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::interpret;
+use crate::{program::Program, vm::Heap, Vm};
+
+#[test]
+fn doc_native() {
+    let source = r#"
+    /// Adds two numbers.
+    ///
+    ///   a + b
+    fun add(a, b) { return a + b; }
+
+    fun undocumented() {}
+
+    /// Not a doc comment for f,
+    // because this comment comes between.
+    fun f() {}
+
+    ////Four slashes still count.
+    fun g() {}
+
+    print doc(add);
+    print doc(undocumented);
+    print doc(f);
+    print doc(g);
+    print doc(clock);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "Adds two numbers.\n\n  a + b\nnil\nnil\n/Four slashes still count.\nnil\n"
+    );
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn nested_functions() {
+    let source = r#"
+    fun outer() {
+        /// Inner docs.
+        fun inner() {}
+        return inner;
+    }
+    print doc(outer());
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "Inner docs.\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn not_a_function() {
+    let (stdout, stderr) = interpret("print doc(1);");
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "[line 1] argument must be a function\n");
+}
+
+#[test]
+fn vm_doc() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out);
+    let source = "/// Says hi.\nfun hi() {}\nvar x = 1;";
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(vm.doc("hi"), Some("Says hi.".to_string()));
+    assert_eq!(vm.doc("x"), None);
+    assert_eq!(vm.doc("missing"), None);
+}
+
+#[test]
+fn docs_are_serialized() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let source = "/// Says hi.\nfun hi() {}\nprint doc(hi);";
+    let bytes = vm.compile(source.to_string()).unwrap();
+    Program::deserialize(&bytes, &mut Heap::new()).unwrap();
+    vm.run_compiled(&bytes).unwrap();
+    assert_eq!(*out.borrow(), b"Says hi.\n");
+}
@@ -0,0 +1,294 @@
+use std::{cell::RefCell, io, rc::Rc};
+
+use super::vm;
+use crate::{testing::FakeEnv, HostValue, Vm};
+
+#[test]
+fn define_globals() {
+    let (mut vm, out) = vm();
+    vm.define_global("limit", 3).unwrap();
+    vm.define_global("name", "lox").unwrap();
+    vm.define_global("verbose", true).unwrap();
+    vm.define_global("missing", None::<f64>).unwrap();
+    let entries = vec![("a".into(), 1.into()), (HostValue::Nil, 2.into())];
+    vm.define_global("map", HostValue::Map(entries)).unwrap();
+    let source = "print limit * 2; print name; print verbose; print missing;";
+    vm.interpret(source.to_string()).unwrap();
+    vm.interpret("print map;".to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"6\nlox\ntrue\nnil\n{a: 1}\n");
+}
+
+#[test]
+fn get_globals() {
+    let (mut vm, _) = vm();
+    assert_eq!(vm.get_global("result"), None);
+    let source = r#"
+    var result = 1 + 2;
+    var greeting = "hi";
+    var nothing;
+    var list = [1, [nil]];
+    var map = {"k": list};
+    fun f() {}
+    "#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(vm.get_global("result"), Some(HostValue::Number(3.0)));
+    assert_eq!(vm.get_global("greeting"), Some("hi".into()));
+    assert_eq!(vm.get_global("nothing"), Some(HostValue::Nil));
+    assert_eq!(
+        vm.get_global("list"),
+        Some(HostValue::List(vec![
+            HostValue::Number(1.0),
+            HostValue::List(vec![HostValue::Nil]),
+        ]))
+    );
+    let map = vm.get_global("map").unwrap();
+    assert_eq!(map.as_map().unwrap()[0].0, "k".into());
+    assert_eq!(map.to_string(), "{k: [1, [nil]]}");
+    assert_eq!(vm.get_global("f"), Some(HostValue::Function("f".into())));
+    assert_eq!(
+        vm.get_global("clock"),
+        Some(HostValue::Function("clock".into()))
+    );
+}
+
+#[test]
+fn functions_cannot_be_defined() {
+    let (mut vm, _) = vm();
+    let err = vm
+        .define_global("f", HostValue::Function("g".into()))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "can't define function 'g' from the host");
+    assert_eq!(vm.get_global("f"), None);
+}
+
+#[test]
+fn natives_can_capture_state() {
+    let (mut vm, out) = vm();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let log = calls.clone();
+    vm.register_native("record", 2, move |_, args| {
+        log.borrow_mut().push(args.to_vec());
+        Ok(HostValue::Number(log.borrow().len() as f64))
+    });
+    let source = r#"print record("a", 1); print record(nil, true);"#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"1\n2\n");
+    assert_eq!(
+        *calls.borrow(),
+        [
+            vec!["a".into(), HostValue::Number(1.0)],
+            vec![HostValue::Nil, HostValue::Bool(true)],
+        ]
+    );
+}
+
+#[test]
+fn native_errors() {
+    let (mut vm, _) = vm();
+    vm.register_native("fail", 1, |_, args| Err(format!("bad {}", args[0])));
+    vm.register_native("f", 0, |_, _| Ok(HostValue::Function("g".into())));
+    let run = |vm: &mut Vm, source: &str| {
+        vm.interpret(source.to_string()).unwrap_err().to_string()
+    };
+    assert_eq!(
+        run(&mut vm, "fail();"),
+        "[line 1] expected 1 arguments but got 0"
+    );
+    assert_eq!(run(&mut vm, "fail(2);"), "[line 1] bad 2");
+    assert_eq!(
+        run(&mut vm, "f();"),
+        "[line 1] native function 'f' can't return function 'g'"
+    );
+}
+
+#[test]
+fn natives_can_write_output() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let err = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), err.clone());
+    vm.register_native("say", 1, |ctx, args| {
+        ctx.print(&format!("<{}>", args[0]))
+            .map_err(|e| e.to_string())?;
+        ctx.eprint("said\n").map_err(|e| e.to_string())?;
+        Ok(HostValue::Nil)
+    });
+    vm.interpret("say(1); print 2;".to_string()).unwrap();
+    vm.flush().unwrap();
+    assert_eq!(*out.borrow(), b"<1>2\n");
+    assert_eq!(*err.borrow(), b"said\n");
+}
+
+#[test]
+fn native_argument_helpers() {
+    let (mut vm, out) = vm();
+    vm.register_native("substring", 3, |ctx, _| {
+        let s = ctx.string_arg(0)?;
+        let start = ctx.number_arg(1)? as usize;
+        let end = match ctx.opt_arg(2) {
+            Some(_) => ctx.number_arg(2)? as usize,
+            None => s.len(),
+        };
+        Ok(s.get(start..end).unwrap_or("").into())
+    });
+    let source =
+        r#"print substring("hello", 1, 3); print substring("hi", 1, nil);"#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"el\ni\n");
+
+    let err = vm.interpret(r#"substring("a", nil, 1);"#.to_string());
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "[line 1] argument 2 to 'substring' must be a number, got nil"
+    );
+    let err = vm.interpret(r#"substring("a", 0, "b");"#.to_string());
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "[line 1] argument 3 to 'substring' must be a number, got string"
+    );
+}
+
+#[test]
+fn conversions() {
+    let (mut vm, _) = vm();
+    let source = r#"var n = 2.5; var s = "lox"; var b = false; var x;"#;
+    vm.interpret(source.to_string()).unwrap();
+    let get = |name| vm.get_global(name).unwrap();
+
+    assert_eq!(get("n").as_number(), Some(2.5));
+    assert_eq!(get("s").as_str(), Some("lox"));
+    assert_eq!(get("b").as_bool(), Some(false));
+    assert!(get("x").is_nil());
+    assert_eq!(get("n").as_str(), None);
+
+    assert_eq!(f64::try_from(get("n")), Ok(2.5));
+    assert_eq!(String::try_from(get("s")), Ok("lox".to_string()));
+    assert_eq!(bool::try_from(get("b")), Ok(false));
+    assert_eq!(f64::try_from(get("s")), Err("lox".into()));
+    assert_eq!(bool::try_from(get("x")), Err(HostValue::Nil));
+}
+
+#[test]
+fn call_lox_functions() {
+    let (mut vm, out) = vm();
+    let source = r#"
+    var calls = 0;
+    fun greet(name) {
+        calls = calls + 1;
+        print "hello " + name;
+        return calls;
+    }
+    fun fib(n) {
+        if (n < 2) return n;
+        return fib(n - 1) + fib(n - 2);
+    }
+    fun nothing() {}
+    "#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(vm.call("greet", &["a".into()]).unwrap(), 1.into());
+    assert_eq!(vm.call("greet", &["b".into()]).unwrap(), 2.into());
+    assert_eq!(*out.borrow(), b"hello a\nhello b\n");
+    assert_eq!(vm.call("fib", &[10.into()]).unwrap(), 55.into());
+    assert_eq!(vm.call("nothing", &[]).unwrap(), HostValue::Nil);
+    assert_eq!(vm.get_global("calls"), Some(2.into()));
+    vm.register_native("twice", 1, |_, args| {
+        Ok((args[0].as_number().unwrap_or(0.0) * 2.0).into())
+    });
+    assert_eq!(vm.call("twice", &[4.into()]).unwrap(), 8.into());
+}
+
+#[test]
+fn call_errors() {
+    let (mut vm, _) = vm();
+    let source = r#"
+    var x = 1;
+    fun f(a) { return -a; }
+    "#;
+    vm.interpret(source.to_string()).unwrap();
+    let mut call =
+        |name, args: &[HostValue]| vm.call(name, args).unwrap_err().to_string();
+    assert_eq!(call("g", &[]), "undefined variable 'g'");
+    assert_eq!(call("x", &[]), "'x' is not a function");
+    assert_eq!(
+        call("f", &[]),
+        "expected 1 arguments but got 0 ('f' declared on line 3)"
+    );
+    assert_eq!(
+        call("f", &[HostValue::Function("f".into())]),
+        "can't pass function 'f' from the host"
+    );
+    assert!(call("f", &["a".into()]).ends_with("operand must be a number"));
+    assert_eq!(vm.call("f", &[1.into()]).unwrap(), (-1).into());
+}
+
+#[test]
+fn compile_once_run_many() {
+    let (mut vm, out) = vm();
+    vm.define_global("n", 0).unwrap();
+    let script = vm
+        .compile_script("n = n + 1; print \"run \" + name;".to_string())
+        .unwrap();
+    for name in ["a", "b", "c"] {
+        vm.define_global("name", name).unwrap();
+        vm.run_script(&script).unwrap();
+    }
+    assert_eq!(*out.borrow(), b"run a\nrun b\nrun c\n");
+    assert_eq!(vm.get_global("n"), Some(3.into()));
+
+    let (mut other, _) = self::vm();
+    let err = other.run_script(&script).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "script was compiled by another vm, or released"
+    );
+    assert!(vm.compile_script("print;".to_string()).is_none());
+}
+
+#[test]
+fn release_script() {
+    let (mut vm, out) = vm();
+    let script = vm.compile_script("print \"kept\";".to_string()).unwrap();
+    let objects = vm.heap.len();
+    for _ in 0..100 {
+        let script = vm.compile_script("print 1;".to_string()).unwrap();
+        vm.run_script(&script).unwrap();
+        vm.release_script(&script);
+        assert!(vm.run_script(&script).is_err());
+    }
+    vm.collect_garbage();
+    assert_eq!(vm.heap.len(), objects);
+    vm.run_script(&script).unwrap();
+    assert_eq!(out.take().len(), 100 * 2 + 5);
+}
+
+#[test]
+fn fake_env() {
+    let env = FakeEnv::new(42);
+    env.push_input(["first", "second"]);
+    let sink = || Rc::new(RefCell::new(io::sink()));
+    let mut vm = Vm::new(sink(), sink());
+    env.install(&mut vm);
+    let rng = env.clone();
+    vm.register_native("random", 0, move |_, _| Ok(rng.random().into()));
+    let source = r#"
+    print readLine() + " " + readLine();
+    print readLine();
+    var r = random();
+    print r >= 0 and r < 1;
+    print clock();
+    "#;
+    env.advance(1.5);
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(env.take_stdout(), "first second\nnil\ntrue\n1.5\n");
+    assert_eq!(env.take_stdout(), "");
+
+    // The same seed gives the same numbers.
+    let (a, b) = (FakeEnv::new(7), FakeEnv::new(7));
+    let numbers = |env: &FakeEnv| (0..3).map(|_| env.random()).collect();
+    let first: Vec<f64> = numbers(&a);
+    assert_eq!(first, numbers(&b));
+    assert_ne!(first[0], first[1]);
+
+    let mut vm = env.vm(Default::default());
+    vm.interpret("print 1;".to_string()).unwrap();
+    assert_eq!(env.take_stdout(), "1\n");
+}
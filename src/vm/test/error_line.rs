@@ -0,0 +1,60 @@
+use super::interpret;
+
+// Runtime errors in code that spans lines are on the line of the token the
+// failing op stands for, not of wherever parsing had got to.
+
+#[test]
+fn binary_operator() {
+    let source = "print 1 +\n  nil\n;";
+    let (_, stderr) = interpret(source);
+    assert_eq!(stderr, "[line 1] operands must be numbers or strings\n");
+
+    let source = "var a = 1;\nprint a\n  <\n  nil;";
+    let (_, stderr) = interpret(source);
+    assert_eq!(stderr, "[line 3] operands must be numbers\n");
+}
+
+#[test]
+fn increment() {
+    let (_, stderr) = interpret("var x;\nx++\n;");
+    assert_eq!(stderr, "[line 2] operand must be a number\n");
+
+    let (_, stderr) = interpret("var x;\n--\nx;");
+    assert_eq!(stderr, "[line 2] operand must be a number\n");
+}
+
+#[test]
+fn index() {
+    let (_, stderr) = interpret("print [1][\n  \"a\"\n];");
+    assert_eq!(stderr, "[line 1] index must be a number\n");
+
+    let (_, stderr) = interpret("var l = [];\nl[\n  0] =\n  1;");
+    assert_eq!(
+        stderr,
+        "[line 2] index 0 is out of bounds for a list of length 0\n"
+    );
+}
+
+#[test]
+fn for_increment() {
+    let source = r#"
+    var x;
+    for (var i = 0;
+      i < 1;
+      i = i + x)
+      print i;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "0\n");
+    assert_eq!(stderr, "[line 5] operands must be numbers or strings\n");
+}
+
+#[test]
+fn for_in() {
+    let (_, stderr) = interpret("for (var i in\n  nil)\n  print i;");
+    assert_eq!(stderr, "[line 2] can only loop over lists and maps\n");
+
+    let (_, stderr) = interpret("for (var i in 0..\n  nil\n)\n  print i;");
+    assert_eq!(stderr, "[line 1] range endpoints must be numbers\n");
+}
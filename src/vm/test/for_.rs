@@ -1,4 +1,4 @@
-use super::interpret;
+use super::{book, interpret, interpret_with};
 
 #[test]
 fn class_in_body() {
@@ -96,7 +96,7 @@ fn statement_condition() {
         "",
     ];
 
-    let (stdout, stderr) = interpret(source);
+    let (stdout, stderr) = interpret_with(source, book());
     assert_eq!(stdout, "");
     assert_eq!(stderr, expected.join("\n"));
 }
@@ -108,9 +108,14 @@ fn statement_increment() {
     for (var a = 1; a < 2; {}) {}
     "#;
 
-    let (stdout, stderr) = interpret(source);
+    let (stdout, stderr) = interpret_with(source, book());
     assert_eq!(stdout, "");
     assert_eq!(stderr, "[line 3] Error at '{': expect expression\n");
+
+    let source = "for (var a = 0; a < 2; {}) print a = a + 1;";
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "1\n2\n");
+    assert_eq!(stderr, "");
 }
 
 #[test]
@@ -127,7 +132,7 @@ fn statement_initializer() {
         "",
     ];
 
-    let (stdout, stderr) = interpret(source);
+    let (stdout, stderr) = interpret_with(source, book());
     assert_eq!(stdout, "");
     assert_eq!(stderr, expected.join("\n"));
 }
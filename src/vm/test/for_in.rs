@@ -0,0 +1,126 @@
+use super::{interpret, interpret_with};
+use crate::{Dialect, VmOptions};
+
+#[test]
+fn lists() {
+    let source = r#"
+    for (var x in [1, "two", nil]) print x;
+    for (var x in []) print x;
+
+    // Items added while looping are reached; the body can shadow the name.
+    var l = [1];
+    for (var x in l) {
+      if (x < 3) l[0] = x + 1;
+      if (x < 3) l = [x + 1];
+      var x = "body";
+    }
+    print l;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "1\ntwo\nnil\n[2]\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn maps() {
+    let source = r#"
+    var m = {"a": 1, 2: "b"};
+    for (var k in m) {
+      print k;
+      print m[k];
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "a\n1\n2\nb\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn ranges() {
+    let source = r#"
+    for (var i in 0..3) print i;
+    for (var i in 3..=4) print i;
+    for (var i in 0.5..=2) print i;
+    for (var i in 1..1) print i; else print "empty";
+
+    // The end is evaluated once, and assigning the loop variable doesn't
+    // change where the loop is.
+    var n = 2;
+    for (var i in n - 1..n + 1) {
+      n = 10;
+      i = i * 10;
+      print i;
+    }
+    for (var i in 0..5) {
+      if (i == 1) continue;
+      if (i == 3) break;
+      print i;
+    }
+    "#;
+
+    let expected = [
+        "0", "1", "2", "3", "4", "0.5", "1.5", "empty", "10", "20", "0", "2",
+        "",
+    ];
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, expected.join("\n"));
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("for (var i in 0..\"3\") print i;");
+    assert_eq!(stderr, "[line 1] range endpoints must be numbers\n");
+    let (_, stderr) = interpret("print 0..3;");
+    assert_eq!(stderr, "[line 1] Error at '..': expect ';' after value\n");
+}
+
+#[test]
+fn break_continue_else() {
+    let source = r#"
+    for (var x in [1, 2, 3]) {
+      var y = x * 10;
+      if (x == 2) continue;
+      if (x == 3) break;
+      print y;
+    } else print "not reached";
+
+    for (var x in [1]) print x; else print "done";
+
+    fun first(l) {
+      for (var x in l) {
+        var found = x;
+        return found;
+      }
+      return "empty";
+    }
+    print first([4, 5]);
+    print first([]);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "10\n1\ndone\n4\nempty\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn errors() {
+    let (_, stderr) = interpret("for (var x in 3) print x;");
+    assert_eq!(stderr, "[line 1] can only loop over lists and maps\n");
+
+    let (_, stderr) = interpret("for (var x in [1] print x;");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at 'print': expect ')' after for-in sequence\n"
+    );
+
+    // `in` isn't reserved, and Book Lox has no for-in.
+    let (stdout, _) = interpret("for (var in = 0; in < 1; in++) print in;");
+    assert_eq!(stdout, "0\n");
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let (_, stderr) = interpret_with("for (var x in y) {}", options);
+    assert!(stderr.starts_with("[line 1] Error at 'in': expect ';'"));
+}
@@ -44,7 +44,10 @@ fn extra_arguments() {
 
     let (stdout, stderr) = interpret(source);
     assert_eq!(stdout, "");
-    assert_eq!(stderr, "[line 2] expected 2 arguments but got 4\n");
+    assert_eq!(
+        stderr,
+        "[line 7] expected 2 arguments but got 4 ('f' declared on line 2)\n"
+    );
 }
 
 #[test]
@@ -84,7 +87,10 @@ fn missing_arguments() {
 
     let (stdout, stderr) = interpret(source);
     assert_eq!(stdout, "");
-    assert_eq!(stderr, "[line 2] expected 2 arguments but got 1\n");
+    assert_eq!(
+        stderr,
+        "[line 4] expected 2 arguments but got 1 ('f' declared on line 2)\n"
+    );
 }
 
 #[test]
@@ -128,6 +134,17 @@ fn mutual_recursion() {
     assert_eq!(stderr, "");
 }
 
+#[test]
+fn native_call_in_expression() {
+    let source = r#"
+    print 1 + clock() * 0; // expect: 1
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "1\n");
+    assert_eq!(stderr, "");
+}
+
 #[test]
 fn nested_call_with_arguments() {
     let source = r#"
@@ -191,7 +208,41 @@ fn parameters() {
 
 #[test]
 fn print() {
-    panic!();
+    let source = r#"
+    fun foo() {}
+    print foo; // expect: <fn foo>
+    print clock; // expect: <native fn>
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "<fn foo>\n<native fn>\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn print_nested() {
+    let source = r#"
+    fun outer() {
+        fun middle() {
+            fun inner() {}
+            print inner;
+        }
+        middle();
+        print middle;
+    }
+    outer();
+    {
+        fun local() {}
+        print local;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "<fn outer.middle.inner>\n<fn outer.middle>\n<fn local>\n"
+    );
+    assert_eq!(stderr, "");
 }
 
 #[test]
@@ -753,3 +804,28 @@ fn too_many_parameters() {
         "[line 258] Error at 'a': can't have more than 255 parameters\n"
     );
 }
+
+#[test]
+fn lines_after_nested_function() {
+    let source = r#"
+    fun outer() {
+        fun inner(a) {
+            return a;
+        }
+
+        return inner(1, 2);
+    }
+
+    outer();
+    print -"a";
+    "#;
+
+    let (_, stderr) = interpret(source);
+    assert_eq!(
+        stderr,
+        "[line 7] expected 1 arguments but got 2 ('outer.inner' declared on line 3)\n"
+    );
+
+    let (_, stderr) = interpret("fun f() {\n}\nprint -\"a\";");
+    assert_eq!(stderr, "[line 3] operand must be a number\n");
+}
@@ -0,0 +1,142 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{output, vm};
+use crate::{Vm, VmOptions};
+
+#[test]
+fn garbage_is_freed() {
+    let (mut vm, out) = vm();
+    let source = r#"
+var s = "";
+for (var i = 0; i < 50000; i = i + 1) {
+    s = "garbage" + "!";
+}
+print s;
+"#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(output(&out), "garbage!\n");
+    assert!(vm.heap.len() < 50000, "{} objects", vm.heap.len());
+
+    vm.collect_garbage();
+    let live = vm.heap.len();
+    vm.collect_garbage();
+    assert_eq!(vm.heap.len(), live);
+}
+
+#[test]
+fn reachable_objects_survive() {
+    let (mut vm, out) = vm();
+    let source = r#"
+var greeting = "hello" + ", ";
+fun greet(name) {
+    fun punctuate(s) { return s + "!"; }
+    return punctuate(greeting + name);
+}
+"#;
+    vm.interpret(source.to_string()).unwrap();
+    vm.collect_garbage();
+    vm.interpret("print greet(\"world\");".to_string()).unwrap();
+    assert_eq!(output(&out), "hello, world!\n");
+}
+
+// A collection runs in the middle of a script when it has allocated enough,
+// with values on the stack that it hasn't finished with.
+#[test]
+fn collection_during_a_script() {
+    let (mut vm, out) = vm();
+    let source = r#"
+fun build(n) {
+    var s = "";
+    for (var i = 0; i < n; i = i + 1) s = s + "x";
+    return s;
+}
+var kept = "kept" + "!";
+var long = build(5000);
+print kept;
+print long == build(4999) + "x";
+"#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(output(&out), "kept!\ntrue\n");
+}
+
+#[test]
+fn heap_dump() {
+    let (mut vm, _) = vm();
+    let source = r#"
+fun greet() { print "hello, world"; }
+var name = "lox";
+var n = 1;
+"#;
+    vm.interpret(source.to_string()).unwrap();
+    let mut dot = Vec::new();
+    vm.dump_heap(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+
+    assert!(dot.starts_with("digraph heap {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("[shape=plaintext, label=\"global greet\"];"));
+    assert!(dot.contains("label=\"<fn greet>\\n"));
+    assert!(dot.contains("label=\"string \\\"hello, world\\\"\\n"));
+    assert!(dot.contains("label=\"string \\\"lox\\\"\\n"));
+    assert!(dot.contains("label=\"<native fn clock>\\n"));
+    // Numbers aren't objects.
+    assert!(!dot.contains("global n\""));
+    // The function refers to its string constant.
+    let id = |label: &str| {
+        let line = dot.lines().find(|line| line.contains(label)).unwrap();
+        line.trim().split(' ').next().unwrap().to_string()
+    };
+    let (greet, hello) = (id("<fn greet>"), id("hello, world"));
+    assert!(dot.contains(&format!("    {} -> {};\n", greet, hello)));
+}
+
+#[test]
+fn memory_limit() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = VmOptions {
+        max_heap: Some(64 * 1024),
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(out.clone(), out.clone(), options);
+    // Garbage alone never runs out.
+    let source = r#"
+var s;
+for (var i = 0; i < 10000; i = i + 1) {
+    s = "garbage" + "!";
+}
+print s;
+"#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(output(&out), "garbage!\n");
+
+    let source = r#"
+var s = "x";
+while (true) {
+    s = s + s;
+}
+"#;
+    let err = vm.interpret(source.to_string()).unwrap_err();
+    assert_eq!(err.to_string(), "[line 4] out of memory");
+    // The vm is still usable afterwards.
+    vm.interpret("s = nil; print 1;".to_string()).unwrap();
+    assert_eq!(output(&out), "1\n");
+}
+
+#[test]
+fn growing_map_counts_toward_limit() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = VmOptions {
+        max_heap: Some(64 * 1024),
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(out.clone(), out.clone(), options);
+    // Numbers as keys and values, so nothing but the map is allocated.
+    let source = r#"
+var m = {};
+for (var i = 0; i < 100000; i = i + 1) {
+    m[i] = i;
+}
+"#;
+    let err = vm.interpret(source.to_string()).unwrap_err();
+    assert!(err.to_string().ends_with("] out of memory"));
+}
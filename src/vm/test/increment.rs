@@ -0,0 +1,89 @@
+use super::interpret;
+
+#[test]
+fn prefix() {
+    let source = r#"
+    var a = 1;
+    print ++a;
+    print a;
+    {
+        var b = 1;
+        print --b;
+        print b;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "2\n2\n0\n0\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn postfix() {
+    let source = r#"
+    var a = 1;
+    print a++;
+    print a;
+    {
+        var b = 2;
+        print b--;
+        print -b--;
+        print b;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "1\n2\n2\n-1\n0\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn in_loop() {
+    let source = r#"
+    var n = 0;
+    for (var i = 0; i < 5; i++) n = n + i;
+    print n;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "10\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn minus_negate() {
+    let source = r#"
+    var a = 1;
+    var b = 1;
+    print 1--1;
+    print a--b;
+    print 1---1;
+    print --(3);
+    print a;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "2\n2\n0\n3\n1\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn non_number() {
+    let (_, stderr) = interpret("var s = \"s\"; s++;");
+    assert_eq!(stderr, "[line 1] operand must be a number\n");
+}
+
+#[test]
+fn invalid_target() {
+    for source in ["++1;", "(a)++;", "++f();", "f()--;"] {
+        let (_, stderr) = interpret(source);
+        assert!(
+            stderr.ends_with("invalid increment target\n"),
+            "{}: {}",
+            source,
+            stderr
+        );
+    }
+    let (_, stderr) = interpret("var a = 1; a++ = 2;");
+    assert!(stderr.ends_with("invalid assignment target\n"));
+}
@@ -0,0 +1,92 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::output;
+use crate::{Vm, VmOptions};
+
+#[test]
+fn globals_do_not_leak() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.interpret("var base = 1;".to_string()).unwrap();
+
+    vm.interpret_isolated("var a = 2; base = 3; print a + base;".to_string())
+        .unwrap();
+    assert_eq!(output(&out), "5\n");
+
+    vm.interpret_isolated("print base;".to_string()).unwrap();
+    assert_eq!(output(&out), "1\n");
+    let err = vm.interpret_isolated("print a;".to_string()).unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] undefined variable 'a'");
+    let err = vm.interpret_isolated("a = 1;".to_string()).unwrap_err();
+    assert_eq!(err.to_string(), "[line 1] undefined variable 'a'");
+}
+
+#[test]
+fn functions_see_child_globals() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.interpret("var n = 1; fun show() { print n; }".to_string())
+        .unwrap();
+
+    vm.interpret_isolated("n = 2; show();".to_string()).unwrap();
+    vm.interpret("show();".to_string()).unwrap();
+    assert_eq!(output(&out), "2\n1\n");
+}
+
+#[test]
+fn errors_drop_child_globals() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.interpret("var base = 1;".to_string()).unwrap();
+
+    assert!(vm
+        .interpret_isolated("base = 2; var x = 1; -nil;".to_string())
+        .is_err());
+    vm.interpret("print base;".to_string()).unwrap();
+    assert!(vm.interpret("print x;".to_string()).is_err());
+    assert_eq!(output(&out), "1\n");
+}
+
+#[test]
+fn strict_redeclaration() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let options = VmOptions {
+        strict: true,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(out.clone(), out.clone(), options);
+    vm.interpret("var base = 1;".to_string()).unwrap();
+
+    vm.interpret_isolated("var x = 1;".to_string()).unwrap();
+    vm.interpret_isolated("var x = 2; print(x);".to_string())
+        .unwrap();
+    vm.interpret_isolated("var base = 2;".to_string()).unwrap();
+    assert_eq!(
+        output(&out),
+        "2\n[line 1] Error at 'base': already a global with this name\n"
+    );
+}
+
+#[test]
+fn lists_and_maps_are_copied() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let source =
+        "var base = [1, [2, nil]]; base[1][1] = base; var m = {\"k\": 1};";
+    vm.interpret(source.to_string()).unwrap();
+
+    let source = r#"
+    base[0] = 99;
+    base[1][0] = 98;
+    m["k"] = 97;
+    print base[0];
+    print base[1][1][1][0];
+    print m["k"];
+    "#;
+    vm.interpret_isolated(source.to_string()).unwrap();
+    vm.interpret(
+        "print base[0]; print base[1][0]; print m[\"k\"];".to_string(),
+    )
+    .unwrap();
+    assert_eq!(output(&out), "99\n98\n97\n1\n2\n1\n");
+}
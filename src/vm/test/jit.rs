@@ -0,0 +1,206 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::interpret;
+use crate::{Value, Vm};
+
+// Runs `source`, then reports whether the global function `name` was
+// translated.
+fn compiled(source: &str, name: &str) -> bool {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.interpret(source.to_string()).unwrap();
+    let sym = vm.get_symbol(name);
+    match &vm.globals[&sym] {
+        Value::Function(f) => f.borrow().jit.is_compiled(),
+        _ => panic!("{} is not a function", name),
+    }
+}
+
+#[test]
+fn hot_numeric_function() {
+    let source = r#"
+fun triangle(n) {
+  var total = 0;
+  for (var i = 1; i <= n; i = i + 1) total = total + i;
+  return total;
+}
+var sum = 0;
+for (var i = 0; i < 1500; i = i + 1) sum = sum + triangle(4);
+print sum;
+print triangle(100);
+print triangle(-1);
+"#;
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "15000\n5050\n0\n");
+    assert_eq!(stderr, "");
+    assert!(compiled(source, "triangle"));
+}
+
+#[test]
+fn boolean_results() {
+    let source = r#"
+fun between(x, lo, hi) { return !(x < lo) and !(x > hi); }
+var count = 0;
+for (var i = 0; i < 1500; i = i + 1) {
+  if (between(i, 10, 19)) count = count + 1;
+}
+print count;
+print between(-1, 0, 1);
+"#;
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "10\nfalse\n");
+    assert_eq!(stderr, "");
+    assert!(compiled(source, "between"));
+}
+
+#[test]
+fn non_number_args_use_interpreter() {
+    let source = r#"
+fun add(a, b) { return a + b; }
+for (var i = 0; i < 1500; i = i + 1) add(i, i);
+print add(1, 2);
+print add("a", "b");
+print add(1, "b");
+"#;
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "3\nab\n");
+    assert!(stderr.ends_with("operands must be numbers or strings\n"));
+}
+
+#[test]
+fn unsupported_functions() {
+    let source = r#"
+fun show(n) { print n; return n; }
+fun maybe(n) { if (n > 0) return n; return nil; }
+fun name(n) { return "n"; }
+for (var i = 0; i < 1500; i = i + 1) {
+  show(i);
+  maybe(i);
+  name(i);
+}
+"#;
+    assert!(!compiled(source, "show"));
+    assert!(!compiled(source, "maybe"));
+    assert!(!compiled(source, "name"));
+}
+
+#[test]
+fn compiled_code_reaches_safepoints() {
+    let source = r#"
+fun spin(n) {
+  var i = 0;
+  while (i < n) i = i + 1;
+  return i;
+}
+for (var i = 0; i < 1500; i = i + 1) spin(1);
+print spin(100000000);
+"#;
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let err = vm
+        .interpret_with_fuel(source.to_string(), 100_000)
+        .unwrap_err();
+    assert!(err.to_string().ends_with("] out of fuel"));
+    let sym = vm.get_symbol("spin");
+    match &vm.globals[&sym] {
+        Value::Function(f) => assert!(f.borrow().jit.is_compiled()),
+        _ => panic!("spin is not a function"),
+    }
+
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.add_safepoint_hook(|safepoint| match safepoint.instructions {
+        n if n > 100_000 => Err("stopped".to_string()),
+        _ => Ok(()),
+    });
+    let err = vm.interpret(source.to_string()).unwrap_err();
+    assert!(err.to_string().ends_with("] stopped"));
+    assert_eq!(String::from_utf8(out.take()).unwrap(), "");
+}
+
+#[test]
+fn compiled_arithmetic() {
+    let source = r#"
+fun calc(a, b) {
+  var p = a ** b;
+  var q = -(a / b);
+  if (a == b or (a > b) == (b < a)) return p - q * 2;
+  return p;
+}
+for (var i = 0; i < 1500; i = i + 1) calc(i, 2);
+print calc(3, 2);
+print calc(2, 0.5);
+print calc(0, 0);
+"#;
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "12\n9.414213562373096\nNaN\n");
+    assert_eq!(stderr, "");
+    assert!(compiled(source, "calc"));
+}
+
+#[test]
+fn errors_are_located_in_compiled_code() {
+    let source = r#"
+fun spin(n) {
+  var i = 0;
+  while (i < n) i = i + 1;
+  return i;
+}
+for (var i = 0; i < 1500; i = i + 1) spin(1);
+spin(100000000);
+"#;
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let err = vm
+        .interpret_with_fuel(source.to_string(), 100_000)
+        .unwrap_err();
+    assert_eq!(err.line(), Some(4));
+}
+
+#[test]
+fn hook_panics_unwind_through_compiled_code() {
+    let source = r#"
+fun spin(n) {
+  var i = 0;
+  while (i < n) i = i + 1;
+  return i;
+}
+for (var i = 0; i < 1500; i = i + 1) spin(1);
+spin(100000000);
+"#;
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.add_safepoint_hook(|safepoint| {
+        assert!(safepoint.instructions < 100_000, "stopped");
+        Ok(())
+    });
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        vm.interpret(source.to_string())
+    }));
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"stopped"));
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn compiled_code_is_profiled() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out);
+    vm.interpret(
+        r#"
+fun half(n) {
+  return n / 2;
+}
+for (var i = 0; i < 1500; i = i + 1) half(i);
+"#
+        .to_string(),
+    )
+    .unwrap();
+    let stats = vm.stats();
+    let site = stats
+        .sites
+        .iter()
+        .find(|site| site.function == "half")
+        .unwrap();
+    assert_eq!((site.line, site.op), (3, "DIVIDE"));
+    assert_eq!(site.types, [("number", "number", 1500)]);
+}
@@ -0,0 +1,88 @@
+use super::{interpret, interpret_with};
+use crate::{Dialect, VmOptions};
+
+#[test]
+fn literals() {
+    let source = r#"
+    var a = [1, "two", [3, nil], 1 + 1];
+    print a;
+    print [];
+    print "${[true]}";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "[1, two, [3, nil], 2]\n[]\n[true]\n");
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("print [1, 2;");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at ';': expect ']' after list items\n"
+    );
+
+    // Book Lox has no lists.
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let (_, stderr) = interpret_with("print [];", options);
+    assert!(stderr.starts_with("[line 1] Error: unexpected character '['\n"));
+}
+
+#[test]
+fn index() {
+    let source = r#"
+    var a = [1, [2, 3]];
+    print a[0] + a[1][1];
+    a[0] = "x";
+    print a[1][0] = a[0];
+    print a;
+    fun f() { return a; }
+    f()[0] = 4;
+    print f()[0];
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "4\nx\n[x, [x, 3]]\n4\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn identity() {
+    let source = r#"
+    var a = [1];
+    var b = a;
+    b[0] = 2;
+    print a[0];
+    print a == b;
+    print [1] == [1];
+    a[0] = a;
+    print a;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "2\ntrue\nfalse\n[[...]]\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn index_errors() {
+    let errors = [
+        ("print 1[0];", "can only index lists and maps"),
+        ("var a = nil; a[0] = 1;", "can only index lists and maps"),
+        ("print [1][\"0\"];", "index must be a number"),
+        ("print [1][0.5];", "index must be a whole number"),
+        (
+            "print [1][1];",
+            "index 1 is out of bounds for a list of length 1",
+        ),
+        (
+            "[][-1] = 1;",
+            "index -1 is out of bounds for a list of length 0",
+        ),
+    ];
+    for (source, msg) in errors {
+        let (_, stderr) = interpret(source);
+        assert_eq!(stderr, format!("[line 1] {}\n", msg), "{}", source);
+    }
+}
@@ -0,0 +1,93 @@
+use std::{cell::RefCell, sync::Once};
+
+use log::{Level, Log, Metadata, Record};
+
+use super::interpret;
+
+// Keeps each thread's records apart, since tests run in parallel.
+thread_local! {
+    static RECORDS: RefCell<Vec<(Level, String, String)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+struct Recorder;
+
+impl Log for Recorder {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let target = record.target().to_string();
+        let text = record.args().to_string();
+        RECORDS.with(|r| r.borrow_mut().push((record.level(), target, text)));
+    }
+
+    fn flush(&self) {}
+}
+
+// The records logged by this thread while running `source`.
+fn logged(source: &str) -> Vec<(Level, String, String)> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&Recorder).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    RECORDS.with(|r| r.borrow_mut().clear());
+    interpret(source);
+    RECORDS.with(|r| r.take())
+}
+
+fn find<'a>(
+    records: &'a [(Level, String, String)],
+    prefix: &str,
+) -> Vec<&'a (Level, String, String)> {
+    records.iter().filter(|r| r.2.starts_with(prefix)).collect()
+}
+
+#[test]
+fn lifecycle() {
+    let source = r#"
+    fun f(n) { return n + "!"; }
+    var s = "";
+    for (var i = 0; i < 50000; i = i + 1) s = "garbage" + str(i);
+    for (var i = 0; i < 3; i = i + 1) s = f(s);
+    "#;
+
+    let records = logged(source);
+    let compiled = find(&records, "compiled ");
+    assert_eq!(compiled.len(), 1, "{:?}", records);
+    assert_eq!(compiled[0].0, Level::Debug);
+    assert_eq!(compiled[0].1, "redlox::parser");
+    assert!(compiled[0]
+        .2
+        .starts_with(&format!("compiled {} bytes in ", source.len())));
+
+    let natives = find(&records, "registered native ");
+    assert!(natives.iter().all(|r| r.0 == Level::Trace));
+    assert!(natives
+        .iter()
+        .any(|r| r.2 == "registered native str, arity 1"));
+    assert!(natives
+        .iter()
+        .any(|r| r.2 == "registered native assert, arity 1 to 2"));
+
+    let calls = find(&records, "call f at depth 1");
+    assert_eq!(calls.len(), 3);
+    assert_eq!(find(&records, "return from f").len(), 3);
+    assert_eq!(find(&records, "call <script> at depth 0").len(), 1);
+
+    let collections = find(&records, "collected ");
+    assert!(!collections.is_empty(), "{:?}", records);
+    assert_eq!(collections[0].1, "redlox::vm::gc");
+}
+
+#[test]
+fn errors() {
+    let records = logged("print 1 +;");
+    assert!(find(&records, "compiled ")[0].2.ends_with(", with errors"));
+    assert!(find(&records, "call ").is_empty());
+
+    let records = logged("fun f() { return nil + 1; }\nf();");
+    assert_eq!(find(&records, "error unwound 2 frames").len(), 1);
+}
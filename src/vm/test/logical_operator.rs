@@ -27,7 +27,7 @@ fn and() {
     let expected = ["false", "1", "false", "true", "3", "true", "false", ""];
 
     let (stdout, stderr) = interpret(source);
-    //assert_eq!(stdout, expected.join("\n"));
+    assert_eq!(stdout, expected.join("\n"));
     assert_eq!(stderr, "");
 }
 
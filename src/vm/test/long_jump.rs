@@ -0,0 +1,61 @@
+use super::interpret;
+
+// Enough statements to compile to more than 0xffff words of bytecode.
+fn big_body() -> String {
+    "n = n + 1;\n".repeat(14000)
+}
+
+#[test]
+fn if_over_64k() {
+    let source = format!(
+        "var n = 0;\nif (n == 0) {{\n{}}}\nprint n;\nif (n == 0) {{\n{}}}\nprint n;",
+        big_body(),
+        big_body()
+    );
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "14000\n14000\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn if_else_over_64k() {
+    let source = format!(
+        "var n = 0;\nfun f(c) {{\nif (c) {{\n{}}} else {{\n{}\nn = -n;}}\n}}\n\
+         f(true);\nprint n;\nf(false);\nprint n;",
+        big_body(),
+        big_body()
+    );
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "14000\n-28000\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn loops_over_64k() {
+    let source = format!(
+        "var n = 0;\nfor (var i = 0; i < 3; i = i + 1) {{\n{}}}\nprint n;\n\
+         while (n < 100000) {{\nif (n > 50000) break;\n{}\ncontinue;\n}}\n\
+         print n;",
+        big_body(),
+        big_body()
+    );
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "42000\n56000\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn logical_operators_over_64k() {
+    let sum = format!("0{}", " + 1".repeat(40000));
+    let source = format!(
+        "print false and {0};\nprint true and {0};\nprint nil or {0};",
+        sum
+    );
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "false\n40000\n40000\n");
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,64 @@
+use super::interpret;
+
+#[test]
+fn while_else() {
+    let source = r#"
+    fun find(n) {
+        var i = 0;
+        while (i < 5) {
+            if (i == n) {
+                print "found";
+                break;
+            }
+            i = i + 1;
+        } else print "not found";
+    }
+    find(2);
+    find(7);
+    while (false) {} else print "never ran";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "found\nnot found\nnever ran\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn for_else() {
+    let source = r#"
+    for (var i = 0; i < 3; i = i + 1) {
+        if (i == 1) continue;
+        print i;
+    } else {
+        print "done " + "with";
+        print i;
+    }
+    for (var i = 0; i < 3; i = i + 1) {
+        var a = "local";
+        if (i == 1) break;
+    } else print "skipped";
+    print "after";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "0\n2\ndone with\n3\nafter\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn break_in_else_leaves_outer_loop() {
+    let source = r#"
+    for (var i = 0; i < 3; i = i + 1) {
+        print i;
+        while (false) {} else {
+            var a = i;
+            if (a == 1) break;
+        }
+    } else print "not printed";
+    print "end";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "0\n1\nend\n");
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,108 @@
+use super::{interpret, interpret_with};
+use crate::{Dialect, VmOptions};
+
+#[test]
+fn literals() {
+    let source = r#"
+    var m = {"a": 1, 2: [true], "a": 3};
+    print m;
+    print {};
+    print "${ {"k": nil} }";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "{a: 3, 2: [true]}\n{}\n{k: nil}\n");
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("print {1 2};");
+    assert_eq!(stderr, "[line 1] Error at '2': expect ':' after map key\n");
+    let (_, stderr) = interpret("print {1: 2;");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at ';': expect '}' after map entries\n"
+    );
+
+    // Book Lox has no maps.
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let (_, stderr) = interpret_with("print {};", options);
+    assert!(stderr.starts_with("[line 1] Error at '{': expect expression\n"));
+}
+
+#[test]
+fn index() {
+    let source = r#"
+    var m = {};
+    m["x"] = 1;
+    m[2] = "two";
+    print m["x"] + m["x"];
+    print m[1 + 1];
+    print m["missing"];
+    print m["x"] = 5;
+    print m;
+    m[-0] = "zero";
+    print m[0];
+    print m;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "2\ntwo\nnil\n5\n{x: 5, 2: two}\nzero\n{x: 5, 2: two, 0: zero}\n"
+    );
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn keys_and_remove() {
+    let source = r#"
+    var m = {"a": 1, "b": 2, "c": 3};
+    print keys(m);
+    print remove(m, "b");
+    print remove(m, "b");
+    print keys(m);
+    m["b"] = 4;
+    var ks = keys(m);
+    for (var i = 0; i < 3; i = i + 1) print ks[i] + "=" + "${m[ks[i]]}";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "[a, b, c]\n2\nnil\n[a, c]\na=1\nc=3\nb=4\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn identity() {
+    let source = r#"
+    var a = {};
+    var b = a;
+    b["k"] = a;
+    print a;
+    print a == b;
+    print {} == {};
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "{k: {...}}\ntrue\nfalse\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn key_errors() {
+    let errors = [
+        ("print {nil: 1};", "map keys must be strings or numbers"),
+        ("print {}[[]];", "map keys must be strings or numbers"),
+        ("var m = {}; m[0 / 0] = 1;", "map key can't be NaN"),
+        (
+            "print remove({}, true);",
+            "map keys must be strings or numbers",
+        ),
+        ("print keys([]);", "argument must be a map"),
+    ];
+    for (source, msg) in errors {
+        let (_, stderr) = interpret(source);
+        assert_eq!(stderr, format!("[line 1] {}\n", msg), "{}", source);
+    }
+}
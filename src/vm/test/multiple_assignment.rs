@@ -0,0 +1,91 @@
+use super::{assert_opcodes, interpret, interpret_with};
+use crate::VmOptions;
+
+#[test]
+fn swap() {
+    let source = r#"
+    var a = 1;
+    var b = 2;
+    a, b = b, a;
+    print a;
+    print b;
+    {
+        var c = "c";
+        var d = "d";
+        c, d, a = d, c, a + b;
+        print c + d;
+        print a;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "2\n1\ndc\n3\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn values_before_assignment() {
+    let source = r#"
+    var a = 1;
+    var b = 2;
+    fun show(x) {
+        print "show";
+        return x;
+    }
+    a, b = a + b, show(a);
+    print a;
+    print b;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "show\n3\n1\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn stores_in_reverse() {
+    assert_opcodes(
+        "{ var a; var b; a, b = b, a; }",
+        &[
+            "NIL", "NIL", "GETLOCAL", "GETLOCAL", "SETLOCAL", "POP",
+            "SETLOCAL", "POP", "POPN", "NIL", "RETURN",
+        ],
+    );
+}
+
+#[test]
+fn value_count_mismatch() {
+    let (stdout, stderr) = interpret("var a; var b; a, b = 1;");
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at '1': expect 2 values to assign, not 1\n"
+    );
+
+    let (_, stderr) = interpret("var a; var b; a, b = 1, 2, 3;");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at '3': expect 2 values to assign, not 3\n"
+    );
+}
+
+#[test]
+fn invalid_target() {
+    let (stdout, stderr) = interpret("var a; a, 1 = 1, 2;");
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "[line 1] Error at '1': expect variable name\n");
+}
+
+#[test]
+fn strict_undeclared() {
+    let options = VmOptions {
+        strict: true,
+        ..Default::default()
+    };
+    let (stdout, stderr) = interpret_with("var a; a, b = 1, 2;", options);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at 'b': can't assign to undeclared variable\n"
+    );
+}
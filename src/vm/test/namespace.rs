@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+
+use super::vm;
+use crate::{HostValue, Vm};
+
+// Runs `source` in `namespace`, returning what it printed.
+fn run(vm: &mut Vm, out: &RefCell<Vec<u8>>, namespace: &str, source: &str) {
+    vm.set_namespace(Some(namespace));
+    if let Err(e) = vm.interpret(source.to_string()) {
+        out.borrow_mut().extend(format!("{}\n", e).bytes());
+    }
+}
+
+fn take(out: &RefCell<Vec<u8>>) -> String {
+    String::from_utf8(out.take()).unwrap()
+}
+
+#[test]
+fn globals_are_separate() {
+    let (mut vm, out) = vm();
+    vm.define_global("host", "shared").unwrap();
+    let source = r#"
+    var count = 0;
+    fun bump() { count = count + 1; return count; }
+    bump();
+    print bump();
+    print host;
+    print clock() >= 0;
+    "#;
+    run(&mut vm, &out, "a", source);
+    run(&mut vm, &out, "b", "var count = 10;\nprint count;");
+    run(&mut vm, &out, "a", "print count;");
+    assert_eq!(take(&out), "2\nshared\ntrue\n10\n2\n");
+
+    assert_eq!(vm.get_global("a::count"), Some(HostValue::Number(2.0)));
+    assert_eq!(vm.get_global("b::count"), Some(HostValue::Number(10.0)));
+    assert_eq!(vm.get_global("count"), None);
+}
+
+#[test]
+fn assignment_stays_in_namespace() {
+    let (mut vm, out) = vm();
+    vm.define_global("config", 1).unwrap();
+    vm.register_native("hostFn", 0, |_, _| Ok(HostValue::Nil));
+    run(
+        &mut vm,
+        &out,
+        "a",
+        "config = 0; hostFn = nil; print config;",
+    );
+    run(&mut vm, &out, "b", "print config; print hostFn == nil;");
+    run(&mut vm, &out, "a", "missing = 1;");
+    assert_eq!(
+        take(&out),
+        "0\n1\nfalse\n[line 1] undefined variable 'missing'\n"
+    );
+    assert_eq!(vm.get_global("config"), Some(HostValue::Number(1.0)));
+    assert_eq!(vm.get_global("a::config"), Some(HostValue::Number(0.0)));
+}
+
+#[test]
+fn forward_references() {
+    let (mut vm, out) = vm();
+    let source = r#"
+    fun isEven(n) { if (n == 0) return true; return isOdd(n - 1); }
+    fun isOdd(n) { if (n == 0) return false; return isEven(n - 1); }
+    print isEven(4);
+    "#;
+    run(&mut vm, &out, "a", source);
+    assert_eq!(take(&out), "true\n");
+}
+
+#[test]
+fn imports() {
+    let (mut vm, out) = vm();
+    let source = r#"
+    export fun greet(name) { return "hi " + name; }
+    export var version = 2;
+    var secret = "s";
+    "#;
+    run(&mut vm, &out, "lib", source);
+    let source = r#"
+    import lib::greet;
+    import lib::version;
+    print greet("there");
+    version = version + 1;
+    "#;
+    run(&mut vm, &out, "app", source);
+    run(&mut vm, &out, "lib", "print version;");
+    assert_eq!(take(&out), "hi there\n3\n");
+
+    run(&mut vm, &out, "app", "import lib::secret;");
+    assert!(take(&out).starts_with(
+        "[line 1] Error at 'secret': 'lib::secret' isn't exported\n"
+    ));
+    run(&mut vm, &out, "app", "print secret;");
+    assert_eq!(take(&out), "[line 1] undefined variable 'secret'\n");
+}
+
+#[test]
+fn import_errors() {
+    let (mut vm, out) = vm();
+    run(&mut vm, &out, "lib", "export var x = 1;");
+    run(&mut vm, &out, "app", "import lib::x; var x = 2;");
+    assert!(take(&out).starts_with(
+        "[line 1] Error at 'x': already imported a global with this name\n"
+    ));
+    run(&mut vm, &out, "app", "{ import lib::x; }");
+    assert!(take(&out).starts_with(
+        "[line 1] Error at 'import': can only import at the top level\n"
+    ));
+    run(&mut vm, &out, "app", "fun f() { export var y; }");
+    assert!(take(&out).starts_with(
+        "[line 1] Error at 'export': can only export at the top level\n"
+    ));
+    run(&mut vm, &out, "app", "export print 1;");
+    assert!(take(&out).starts_with(
+        "[line 1] Error at 'print': expect 'var' or 'fun' after 'export'\n"
+    ));
+}
+
+#[test]
+fn natives_in_namespace() {
+    let (mut vm, out) = vm();
+    vm.register_native_in("a", "secret", 0, |_, _| Ok("a's".into()));
+    vm.register_native_in("b", "clock", 0, |_, _| Ok(HostValue::Number(-1.0)));
+    run(&mut vm, &out, "a", "print secret();");
+    run(&mut vm, &out, "b", "print clock();");
+    run(&mut vm, &out, "c", "print clock() >= 0;");
+    run(&mut vm, &out, "c", "print secret();");
+    assert_eq!(
+        take(&out),
+        "a's\n-1\ntrue\n[line 1] undefined variable 'secret'\n"
+    );
+}
+
+#[test]
+fn restricted() {
+    let (mut vm, out) = vm();
+    vm.define_global("config", 1).unwrap();
+    vm.register_native("log", 1, |_, _| Ok(HostValue::Nil));
+    vm.restrict_namespace("tenant", &["log"]);
+    run(&mut vm, &out, "tenant", "log(1); print \"logged\";");
+    run(&mut vm, &out, "tenant", "print clock();");
+    run(&mut vm, &out, "tenant", "config = 2;");
+    run(&mut vm, &out, "tenant", "var config = 3; print config;");
+    assert_eq!(
+        take(&out),
+        "logged\n[line 1] undefined variable 'clock'\n\
+         [line 1] undefined variable 'config'\n3\n"
+    );
+    assert_eq!(vm.get_global("config"), Some(HostValue::Number(1.0)));
+
+    // The globals it may see, it can't assign.
+    run(&mut vm, &out, "tenant", "log = nil;");
+    run(&mut vm, &out, "tenant", "log(2); print \"still logged\";");
+    assert_eq!(
+        take(&out),
+        "[line 1] 'log' is read-only in a restricted namespace\n\
+         still logged\n"
+    );
+
+    // Other namespaces, and scripts in none, still see everything.
+    vm.set_namespace(None);
+    vm.interpret("print config;".to_string()).unwrap();
+    assert_eq!(take(&out), "1\n");
+}
+
+#[test]
+fn restricted_copies_lists_and_maps() {
+    let (mut vm, out) = vm();
+    vm.interpret("var config = [1, {\"k\": 2}];".to_string())
+        .unwrap();
+    vm.restrict_namespace("tenant", &["config"]);
+    let source = "config[0] = 3; config[1][\"k\"] = 4; print config;";
+    run(&mut vm, &out, "tenant", source);
+    run(&mut vm, &out, "tenant", "print config;");
+    assert_eq!(take(&out), "[3, {k: 4}]\n[3, {k: 4}]\n");
+
+    vm.set_namespace(None);
+    vm.interpret("print config;".to_string()).unwrap();
+    assert_eq!(take(&out), "[1, {k: 2}]\n");
+
+    // A new value replaces the copy.
+    vm.interpret("config = [5];".to_string()).unwrap();
+    run(&mut vm, &out, "tenant", "print config;");
+    assert_eq!(take(&out), "[5]\n");
+}
+
+#[test]
+fn functions_keep_their_own_names() {
+    let (mut vm, out) = vm();
+    let source = r#"
+    fun foo(a) {
+        fun bar() {}
+        print bar;
+    }
+    print foo;
+    foo(1);
+    foo();
+    "#;
+    run(&mut vm, &out, "plug", source);
+    assert_eq!(
+        take(&out),
+        "<fn foo>\n<fn foo.bar>\n[line 8] expected 1 arguments but got 0 \
+         ('foo' declared on line 2)\n"
+    );
+}
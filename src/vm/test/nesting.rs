@@ -0,0 +1,101 @@
+use super::{interpret, interpret_with};
+use crate::{ast, VmOptions};
+
+const DEEP: usize = 100_000;
+
+fn nested(open: &str, inner: &str, close: &str, depth: usize) -> String {
+    format!("{}{}{}", open.repeat(depth), inner, close.repeat(depth))
+}
+
+fn limit(max_nesting: usize) -> VmOptions {
+    VmOptions {
+        max_nesting,
+        ..Default::default()
+    }
+}
+
+fn ast_error(source: &str, options: &VmOptions) -> String {
+    ast::parse(source.to_string(), options)
+        .unwrap_err()
+        .to_string()
+}
+
+#[test]
+fn deep_grouping() {
+    let source = format!("print {};", nested("(", "1", ")", DEEP));
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at '(': expression too deeply nested\n"
+    );
+    assert_eq!(
+        ast_error(&source, &VmOptions::default()),
+        "[line 1] Error at '(': expression too deeply nested"
+    );
+}
+
+#[test]
+fn deep_unary() {
+    let source = format!("print {};", nested("-", "1", "", DEEP));
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at '-': expression too deeply nested\n"
+    );
+    assert!(ast::parse(source, &VmOptions::default()).is_err());
+}
+
+#[test]
+fn deep_blocks() {
+    let source = nested("{", "print 1;", "}", DEEP);
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "");
+    assert!(stderr
+        .starts_with("[line 1] Error at '{': statement too deeply nested\n"));
+    assert!(ast::parse(source, &VmOptions::default()).is_err());
+}
+
+#[test]
+fn deep_functions() {
+    let source = nested("fun f() {", "", "}", DEEP);
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "");
+    assert!(stderr.contains("too deeply nested"), "{}", stderr);
+    assert!(ast::parse(source, &VmOptions::default()).is_err());
+}
+
+#[test]
+fn within_limit() {
+    let source = format!("print {};", nested("(", "1", ")", 200));
+
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "1\n");
+    assert_eq!(stderr, "");
+    assert!(ast::parse(source, &VmOptions::default()).is_ok());
+}
+
+#[test]
+fn custom_limit() {
+    let source = "print -(1);\nprint --(1);";
+
+    let (stdout, stderr) = interpret_with(source, limit(4));
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 2] Error at '1': expression too deeply nested\n"
+    );
+    assert_eq!(
+        ast_error(source, &limit(4)),
+        "[line 2] Error at '1': expression too deeply nested"
+    );
+
+    let (stdout, stderr) = interpret_with(source, limit(5));
+    assert_eq!(stdout, "-1\n1\n");
+    assert_eq!(stderr, "");
+}
@@ -58,3 +58,105 @@ fn nan_equality() {
 fn trailing_dot() {
     panic!();
 }
+
+#[test]
+fn format_number() {
+    let source = r#"
+    print formatNumber(1234567.891, 2, ",");
+    print formatNumber(-1234.5, 0, " ");
+    print formatNumber(999, 1, ",");
+    print formatNumber(-0.001, 2, ",");
+    print formatNumber(-0.5, 0, ",");
+    print formatNumber(-0, 1, ",");
+    print formatNumber(1000000000000000000000, 0, "_");
+    print formatNumber(0.000000123, 9, ",");
+    print formatNumber(1/0, 2, ",");
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "1,234,567.89\n-1 234\n999.0\n0.00\n0\n0.0\n\
+         1_000_000_000_000_000_000_000\n0.000000123\ninf\n"
+    );
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("formatNumber(1, 1.5, \",\");");
+    assert_eq!(
+        stderr,
+        "[line 1] decimals must be a whole number from 0 to 100\n"
+    );
+    let (_, stderr) = interpret("formatNumber(1, nil, \",\");");
+    assert_eq!(
+        stderr,
+        "[line 1] argument 2 to 'formatNumber' must be a number, got nil\n"
+    );
+}
+
+#[test]
+fn num() {
+    let source = r#"
+    print num("12.5") + 1;
+    print num(7);
+    print num(str(-0.1)) == -0.1;
+    print num("12px");
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "13.5\n7\ntrue\nnil\n");
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("num(nil);");
+    assert_eq!(
+        stderr,
+        "[line 1] argument 1 to 'num' must be a string, got nil\n"
+    );
+}
+
+#[test]
+fn parse_number() {
+    let source = r#"
+    print parseNumber("12.5");
+    print parseNumber(" -3e2 ");
+    print parseNumber("1.5e-7");
+    print parseNumber("2E+21");
+    print parseNumber("1,5");
+    print parseNumber(".5");
+    print parseNumber("inf");
+    print parseNumber("");
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "12.5\n-300\n0.00000015\n2000000000000000000000\nnil\nnil\nnil\nnil\n"
+    );
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("parseNumber(12);");
+    assert_eq!(
+        stderr,
+        "[line 1] argument 1 to 'parseNumber' must be a string, got number\n"
+    );
+}
+
+#[test]
+fn round_trips() {
+    let source = r#"
+    fun check(n, decimals) {
+        var text = formatNumber(n, decimals, "");
+        if (parseNumber(text) != n) print text;
+    }
+    check(0, 0);
+    check(-2.5, 1);
+    check(123456.125, 3);
+    check(1000000000000000, 0);
+    check(-0.000001, 6);
+    check(0.1, 17);
+    print "done";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "done\n");
+    assert_eq!(stderr, "");
+}
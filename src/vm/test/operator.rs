@@ -1,4 +1,5 @@
-use super::interpret;
+use super::{interpret, interpret_with};
+use crate::{Dialect, VmOptions};
 
 #[test]
 fn add_bool_nil() {
@@ -432,3 +433,35 @@ fn subtract_num_nonnum() {
     assert_eq!(stdout, "");
     assert_eq!(stderr, "[line 2] operands must be numbers\n");
 }
+
+#[test]
+fn power() {
+    let source = r#"
+    print 2 ** 10;
+    print 2 ** 3 ** 2;
+    print -2 ** 2;
+    print (-2) ** 2;
+    print 2 * 3 ** 2;
+    print 4 ** 0.5;
+    print 2 ** -1;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "1024\n512\n-4\n4\n18\n2\n0.5\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn power_nonnum() {
+    let (stdout, stderr) = interpret("print \"2\" ** 2;");
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "[line 1] operands must be numbers\n");
+
+    // In book Lox, `**` is two stars.
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let (_, stderr) = interpret_with("print 2 ** 2;", options);
+    assert!(stderr.starts_with("[line 1] Error at '*': expect expression\n"));
+}
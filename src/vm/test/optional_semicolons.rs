@@ -0,0 +1,117 @@
+use super::{interpret, interpret_with};
+use crate::VmOptions;
+
+fn options() -> VmOptions {
+    VmOptions {
+        optional_semicolons: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn newline_ends_statement() {
+    let source = r#"
+    var a = 1
+    var b = a +
+        2
+    print a
+    print b; print "c"
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "1\n3\nc\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn brace_and_eof_end_statement() {
+    let source = r#"
+    fun f(n) { if (n) { return "yes" } return }
+    print f(true)
+    { print f(false) }
+    print "end""#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "yes\nnil\nend\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn bare_return_before_newline() {
+    let source = r#"
+    fun f() {
+        return
+        print "unreachable"
+    }
+    print f()
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "nil\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn same_line_still_needs_semicolon() {
+    let source = r#"
+    print 1 print 2
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 2] Error at 'print': expect ';' after value\n"
+    );
+}
+
+#[test]
+fn for_clauses_need_semicolons() {
+    let source = r#"
+    for (var i = 0
+         i < 2; i = i + 1) print i
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 3] Error at 'i': expect ';' after variable declaration\n"
+    );
+
+    let source = r#"
+    var i
+    for (i = 0
+         i < 2; i = i + 1) print i
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 4] Error at 'i': expect ';' after expression\n"
+    );
+
+    let source = r#"
+    for (var i = 0; i < 2
+         i = i + 1) print i
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 3] Error at 'i': expect ';' after loop condition\n"
+    );
+}
+
+#[test]
+fn off_by_default() {
+    let source = r#"
+    print 1
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "[line 3] Error at end: expect ';' after value\n");
+}
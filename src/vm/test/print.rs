@@ -1,4 +1,7 @@
+use std::{cell::RefCell, io, rc::Rc};
+
 use super::interpret;
+use crate::{FlushPolicy, Vm, VmOptions};
 
 #[test]
 fn missing_argument() {
@@ -10,3 +13,65 @@ fn missing_argument() {
     assert_eq!(stdout, "");
     assert_eq!(stderr, "[line 2] Error at ';': expect expression\n");
 }
+
+// Records the writes and flushes made to it.
+#[derive(Default)]
+struct Sink {
+    writes: Vec<Vec<u8>>,
+    flushes: usize,
+}
+
+impl io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+fn print_to_sink(flush: FlushPolicy, source: &str) -> Rc<RefCell<Sink>> {
+    let sink = Rc::new(RefCell::new(Sink::default()));
+    let options = VmOptions {
+        flush,
+        ..Default::default()
+    };
+    let mut vm = Vm::with_options(sink.clone(), sink.clone(), options);
+    vm.interpret(source.to_string()).unwrap();
+    sink
+}
+
+const TWO_PRINTS: &str = r#"print "one"; print 2;"#;
+
+#[test]
+fn output_is_buffered_until_the_end() {
+    let sink = print_to_sink(FlushPolicy::Buffered, TWO_PRINTS);
+    assert_eq!(sink.borrow().writes, [b"one\n2\n".to_vec()]);
+    assert_eq!(sink.borrow().flushes, 1);
+}
+
+#[test]
+fn line_flush_policy() {
+    let sink = print_to_sink(FlushPolicy::Line, TWO_PRINTS);
+    assert_eq!(sink.borrow().writes, [b"one\n".to_vec(), b"2\n".to_vec()]);
+    assert_eq!(sink.borrow().flushes, 3);
+}
+
+#[test]
+fn flush_native() {
+    let source = r#"print "one"; flush(); print 2;"#;
+    let sink = print_to_sink(FlushPolicy::Buffered, source);
+    assert_eq!(sink.borrow().writes, [b"one\n".to_vec(), b"2\n".to_vec()]);
+    assert_eq!(sink.borrow().flushes, 2);
+}
+
+#[test]
+fn flushed_on_runtime_error() {
+    let sink = Rc::new(RefCell::new(Sink::default()));
+    let mut vm = Vm::new(sink.clone(), sink.clone());
+    assert!(vm.interpret(r#"print 1; print -"a";"#.to_string()).is_err());
+    assert_eq!(sink.borrow().writes, [b"1\n".to_vec()]);
+}
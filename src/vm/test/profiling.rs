@@ -0,0 +1,83 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{Stats, Vm};
+
+fn stats(source: &str) -> Stats {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out);
+    let _ = vm.interpret(source.to_string());
+    vm.stats()
+}
+
+#[test]
+fn operand_types_per_site() {
+    let stats = stats(
+        r#"
+fun add(a, b) {
+  return a + b;
+}
+for (var i = 0; i < 3; i = i + 1) add(i, 1);
+add("a", "b");
+print -1 == nil;
+"#,
+    );
+    let sites: Vec<_> = stats
+        .sites
+        .iter()
+        .map(|s| (s.function.as_str(), s.op, s.types.clone()))
+        .collect();
+    assert_eq!(
+        sites,
+        [
+            ("<script>", "ADD", vec![("number", "number", 3)]),
+            ("<script>", "LESS", vec![("number", "number", 4)]),
+            ("<script>", "EQUAL", vec![("number", "nil", 1)]),
+            ("<script>", "NEGATE", vec![("number", "", 1)]),
+            (
+                "add",
+                "ADD",
+                vec![("number", "number", 3), ("string", "string", 1)]
+            ),
+        ]
+    );
+    assert!(stats.sites[0].is_monomorphic());
+    assert!(!stats.sites[4].is_monomorphic());
+}
+
+#[test]
+fn failed_operations_are_recorded() {
+    let stats = stats("print 1 < true;");
+    assert_eq!(stats.sites[0].types, [("number", "boolean", 1)]);
+    assert_eq!(
+        stats.to_string(),
+        "<script> [line 1] LESS: number,boolean=1\n"
+    );
+}
+
+#[test]
+fn freed_functions_keep_their_own_sites() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out);
+    // Each script replaces `f`, and makes enough garbage for the one before
+    // to be collected, so its memory can go to the next.
+    for i in 0..20 {
+        let source = format!(
+            r#"
+var f;
+fun f{i}(a) {{ return -a; }}
+f = f{i};
+f{i} = nil;
+f(1);
+var s = "";
+for (var j = 0; j < 2000; j = j + 1) s = s + "x";
+"#
+        );
+        vm.interpret(source).unwrap();
+    }
+    let stats = vm.stats();
+    let negates = stats.sites.iter().filter(|s| s.op == "NEGATE");
+    let mut names: Vec<_> = negates.map(|s| s.function.clone()).collect();
+    names.sort_by_key(|name| name[1..].parse::<u32>().unwrap());
+    let expected: Vec<_> = (0..20).map(|i| format!("f{}", i)).collect();
+    assert_eq!(names, expected);
+}
@@ -0,0 +1,378 @@
+// Property tests over random programs. Programs are built by `Gen` from a
+// list of choices that proptest picks, and shrinks on failure: fewer
+// choices, and smaller ones, make for shorter and simpler programs, since
+// choice 0 is always the simplest production.
+
+use proptest::{collection::vec, prelude::*, sample::select};
+
+use super::interpret;
+use crate::{ast, testing::opcodes, VmOptions};
+
+const CASES: u32 = 300;
+
+// The choices a program is built from; once they run out, every choice is
+// 0.
+struct Choices {
+    choices: Vec<u32>,
+    next: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Ty {
+    Num,
+    Str,
+    Bool,
+}
+
+struct Var {
+    name: String,
+    ty: Ty,
+    // Loop counters are left alone, so that every loop ends.
+    assignable: bool,
+}
+
+// Builds programs that are well typed and always finish, so that they
+// compile and run without errors.
+struct Gen {
+    choices: Choices,
+    scopes: Vec<Vec<Var>>,
+    names: usize,
+    loops: usize,
+}
+
+impl Choices {
+    fn below(&mut self, n: usize) -> usize {
+        let choice = self.choices.get(self.next).copied().unwrap_or(0);
+        self.next += 1;
+        choice as usize % n
+    }
+
+    // True when the choice is 0, so that it shrinks towards true.
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+impl Gen {
+    fn new(choices: Vec<u32>) -> Self {
+        Gen {
+            choices: Choices { choices, next: 0 },
+            scopes: vec![Vec::new()],
+            names: 0,
+            loops: 0,
+        }
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.names += 1;
+        format!("{}{}", prefix, self.names)
+    }
+
+    // A variable in scope of type `ty`, if there is one.
+    fn pick_var(&mut self, ty: Ty, assignable: bool) -> Option<String> {
+        let vars: Vec<_> = self
+            .scopes
+            .iter()
+            .flatten()
+            .filter(|v| v.ty == ty && (v.assignable || !assignable))
+            .collect();
+        if vars.is_empty() {
+            return None;
+        }
+        Some(vars[self.choices.below(vars.len())].name.clone())
+    }
+
+    fn any_ty(&mut self) -> Ty {
+        [Ty::Num, Ty::Str, Ty::Bool][self.choices.below(3)]
+    }
+
+    fn expr(&mut self, ty: Ty, depth: usize) -> String {
+        if self.choices.chance(30) {
+            if let Some(name) = self.pick_var(ty, false) {
+                return name;
+            }
+        }
+        let leaf = depth == 0 || self.choices.chance(30);
+        match ty {
+            Ty::Num if leaf => match self.choices.below(3) {
+                0 => format!(
+                    "{}.{}",
+                    self.choices.below(100),
+                    self.choices.below(10)
+                ),
+                _ => self.choices.below(100).to_string(),
+            },
+            Ty::Num => match self.choices.below(4) {
+                0 => format!("- {}", self.expr(Ty::Num, depth - 1)),
+                1 => format!("({})", self.expr(Ty::Num, depth - 1)),
+                _ => {
+                    let op = ["+", "-", "*", "/"][self.choices.below(4)];
+                    let left = self.expr(Ty::Num, depth - 1);
+                    let right = self.expr(Ty::Num, depth - 1);
+                    format!("{} {} {}", left, op, right)
+                }
+            },
+            Ty::Str if leaf => {
+                let len = self.choices.below(4);
+                let s: String = (0..len)
+                    .map(|_| {
+                        "ab c"[self.choices.below(4)..].chars().next().unwrap()
+                    })
+                    .collect();
+                format!("\"{}\"", s)
+            }
+            Ty::Str => {
+                let left = self.expr(Ty::Str, depth - 1);
+                let right = self.expr(Ty::Str, depth - 1);
+                format!("{} + {}", left, right)
+            }
+            Ty::Bool if leaf => ["true", "false"][self.choices.below(2)].into(),
+            Ty::Bool => match self.choices.below(5) {
+                0 => format!("!{}", self.expr(Ty::Bool, depth - 1)),
+                1 => {
+                    let op = ["<", "<=", ">", ">="][self.choices.below(4)];
+                    let left = self.expr(Ty::Num, depth - 1);
+                    let right = self.expr(Ty::Num, depth - 1);
+                    format!("({} {} {})", left, op, right)
+                }
+                2 => {
+                    let ty = self.any_ty();
+                    let op = ["==", "!="][self.choices.below(2)];
+                    let left = self.expr(ty, depth - 1);
+                    let right = self.expr(ty, depth - 1);
+                    format!("({} {} {})", left, op, right)
+                }
+                _ => {
+                    let op = ["and", "or"][self.choices.below(2)];
+                    let left = self.expr(Ty::Bool, depth - 1);
+                    let right = self.expr(Ty::Bool, depth - 1);
+                    format!("({} {} {})", left, op, right)
+                }
+            },
+        }
+    }
+
+    fn block(&mut self, depth: usize) -> String {
+        self.scopes.push(Vec::new());
+        let count = self.choices.below(4);
+        let body: Vec<_> = (0..count).map(|_| self.stmt(depth)).collect();
+        self.scopes.pop();
+        format!("{{\n{}}}\n", body.concat())
+    }
+
+    fn stmt(&mut self, depth: usize) -> String {
+        let choice = if depth == 0 {
+            self.choices.below(3)
+        } else {
+            self.choices.below(9)
+        };
+        match choice {
+            0 => {
+                let ty = self.any_ty();
+                format!("print {};\n", self.expr(ty, 3))
+            }
+            1 => {
+                let ty = self.any_ty();
+                let init = self.expr(ty, 3);
+                let name = self.fresh("v");
+                self.scopes.last_mut().unwrap().push(Var {
+                    name: name.clone(),
+                    ty,
+                    assignable: true,
+                });
+                format!("var {} = {};\n", name, init)
+            }
+            2 => {
+                let ty = self.any_ty();
+                let Some(name) = self.pick_var(ty, true) else {
+                    return "print nil;\n".into();
+                };
+                format!("{} = {};\n", name, self.expr(ty, 2))
+            }
+            3 => {
+                let cond = self.expr(Ty::Bool, 2);
+                let then = self.nested(depth - 1);
+                match self.choices.chance(50) {
+                    true => {
+                        let else_ = self.nested(depth - 1);
+                        format!("if ({}) {}else {}", cond, then, else_)
+                    }
+                    false => format!("if ({}) {}", cond, then),
+                }
+            }
+            4 => {
+                let counter = self.fresh("i");
+                let bound = self.choices.below(4);
+                self.scopes.push(vec![Var {
+                    name: counter.clone(),
+                    ty: Ty::Num,
+                    assignable: false,
+                }]);
+                self.loops += 1;
+                let body = self.nested(depth - 1);
+                self.loops -= 1;
+                self.scopes.pop();
+                format!(
+                    "for (var {0} = 0; {0} < {1}; {0} = {0} + 1) {2}",
+                    counter, bound, body
+                )
+            }
+            5 if self.loops > 0 => {
+                let cond = self.expr(Ty::Bool, 1);
+                let jump = ["break", "continue"][self.choices.below(2)];
+                format!("if ({}) {};\n", cond, jump)
+            }
+            6 => {
+                let subject = self.expr(Ty::Num, 1);
+                let mut cases = String::new();
+                for _ in 0..self.choices.below(3) {
+                    let test = self.choices.below(5);
+                    let ty = self.any_ty();
+                    cases.push_str(&format!(
+                        "case {}: print {};\n",
+                        test,
+                        self.expr(ty, 2)
+                    ));
+                }
+                if self.choices.chance(50) {
+                    cases.push_str("default: print \"default\";\n");
+                }
+                format!("switch ({}) {{\n{}}}\n", subject, cases)
+            }
+            _ => self.block(depth - 1),
+        }
+    }
+
+    // A statement that gets a scope of its own, as the body of an if or a
+    // loop; a declaration there would be a compile error.
+    fn nested(&mut self, depth: usize) -> String {
+        match self.choices.chance(70) {
+            true => self.block(depth),
+            false => {
+                self.scopes.push(Vec::new());
+                let stmt = self.stmt(depth);
+                self.scopes.pop();
+                match stmt.starts_with("var ") {
+                    true => format!("{{\n{}}}\n", stmt),
+                    false => stmt,
+                }
+            }
+        }
+    }
+
+    // Top-level statements, all of them inside a function so that every
+    // variable is a local.
+    fn program(&mut self) -> String {
+        let count = 1 + self.choices.below(6);
+        let body: Vec<_> = (0..count).map(|_| self.stmt(3)).collect();
+        body.concat()
+    }
+}
+
+// The JSON for a tree, without the spans, which formatting changes.
+fn shape(stmts: &[ast::Stmt]) -> String {
+    let json = ast::to_json(stmts);
+    let mut out = String::new();
+    let mut in_span = false;
+    for line in json.lines() {
+        if line.trim_start().starts_with("\"span\"") {
+            in_span = true;
+        } else if in_span {
+            in_span = !line.trim_start().starts_with('}');
+        } else {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn parse(source: &str) -> Vec<ast::Stmt> {
+    ast::parse(source.to_string(), &VmOptions::default())
+        .unwrap_or_else(|e| panic!("{}\n{}", e, source))
+}
+
+// A program's top-level statements, from up to 400 choices.
+fn program() -> impl Strategy<Value = String> {
+    vec(any::<u32>(), 0..400).prop_map(|choices| Gen::new(choices).program())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn generated_programs_compile(body in program()) {
+        if let Err(e) = opcodes(&body) {
+            panic!("{}\n{}", e, body);
+        }
+    }
+
+    #[test]
+    fn format_round_trips(source in program()) {
+        let tree = parse(&source);
+        let formatted = ast::format(&tree);
+        let reparsed = parse(&formatted);
+        prop_assert_eq!(shape(&tree), shape(&reparsed), "{}", formatted);
+        prop_assert_eq!(
+            ast::format(&reparsed),
+            formatted,
+            "formatting isn't stable"
+        );
+    }
+
+    // The first call of a function runs its generic instructions, and the
+    // second runs whatever the vm rewrote them into on the way.
+    #[test]
+    fn quickened_code_agrees(body in program()) {
+        let source =
+            format!("fun f() {{\n{}}}\nf();\nprint \"--\";\nf();\n", body);
+        let (stdout, stderr) = interpret(&source);
+        prop_assert_eq!(stderr, "", "{}", source);
+        let (first, second) = stdout.split_once("--\n").unwrap();
+        prop_assert_eq!(first, second, "{}", source);
+    }
+
+    // Random token sequences, mostly not valid Lox, must produce errors
+    // rather than panics.
+    #[test]
+    fn token_soup_never_panics(tokens in vec(select(TOKENS), 0..40)) {
+        let source = tokens.join(" ");
+        let _ = opcodes(&source);
+        let _ = ast::parse(source, &VmOptions::default());
+    }
+}
+
+const TOKENS: &[&str] = &[
+    "(", ")", "{", "}", ";", ",", ".", ":", "-", "+", "/", "*", "!", "!=", "=",
+    "==", "<", "<=", ">", ">=", "x", "y", "1", "2.5", "\"s\"", "and", "or",
+    "if", "else", "while", "for", "fun", "return", "var", "print", "nil",
+    "true", "false", "break", "continue", "switch", "case", "default", "class",
+    "this", "super", "\n", "$", "\"open",
+];
+
+const OPENERS: &[&str] = &[
+    "(",
+    "-",
+    "!",
+    "{",
+    "fun f() {",
+    "if (x) ",
+    "while (x) ",
+    "a = ",
+];
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(20))]
+
+    // Random mixes of nesting constructs, far deeper than the limit, must
+    // give an error rather than overflow the stack.
+    #[test]
+    fn deep_nesting_never_overflows(
+        openers in vec(select(OPENERS), 20_000)
+    ) {
+        let source = openers.concat();
+        let (_, stderr) = interpret(&source);
+        prop_assert!(stderr.contains("too deeply nested"));
+        prop_assert!(ast::parse(source, &VmOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    code::Op,
+    program::Program,
+    vm::{Heap, LoxFunction},
+    Obj, Value, Vm,
+};
+
+// Runs `source` on a new vm, returning the vm and what it printed.
+fn run_vm(source: &str) -> (Vm, String) {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.interpret(source.to_string()).unwrap();
+    let printed = String::from_utf8(out.borrow().to_vec()).unwrap();
+    (vm, printed)
+}
+
+// The global function `name`, which lives as long as `vm` does.
+fn function(vm: &mut Vm, name: &str) -> Obj<LoxFunction> {
+    let sym = vm.get_symbol(name);
+    match &vm.globals[&sym] {
+        Value::Function(f) => f.clone(),
+        _ => panic!("{} is not a function", name),
+    }
+}
+
+// Runs `source`, returning what it printed and the opcodes of the global
+// function `name`.
+fn run(source: &str, name: &str) -> (String, Vec<&'static str>) {
+    let (mut vm, printed) = run_vm(source);
+    (printed, opcodes(&function(&mut vm, name)))
+}
+
+fn opcodes(func: &Obj<LoxFunction>) -> Vec<&'static str> {
+    let func = func.borrow();
+    func.chunk
+        .instructions(0)
+        .map(|inst| Op::name(inst.opcode()))
+        .collect()
+}
+
+#[test]
+fn add_is_quickened() {
+    let (_, add) = run("fun add(a, b) { return a + b; }", "add");
+    assert!(add.contains(&"ADD"));
+
+    let (_, add) = run("fun add(a, b) { return a + b; } add(1, 2);", "add");
+    assert!(add.contains(&"ADDNUMBER"));
+
+    let source = r#"fun add(a, b) { return a + b; } add("a", "b");"#;
+    let (_, add) = run(source, "add");
+    assert!(add.contains(&"ADDSTRING"));
+}
+
+#[test]
+fn deopt_on_new_types() {
+    let source = r#"
+fun add(a, b) { return a + b; }
+print add(1, 2);
+print add("a", "b");
+print add(3, 4);
+print add("c", "d");
+"#;
+    let (printed, add) = run(source, "add");
+    assert_eq!(printed, "3\nab\n7\ncd\n");
+    // The last call deoptimized the site, and it hasn't run since.
+    assert!(add.contains(&"ADD"));
+
+    let source = r#"
+fun add(a, b) { return a + b; }
+add(1, 2);
+print add(1, "b");
+"#;
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let err = vm.interpret(source.to_string()).unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with("operands must be numbers or strings"));
+}
+
+#[test]
+fn serialized_code_is_generic() {
+    let (mut vm, _) = run_vm("fun add(a, b) { return a + b; } add(1, 2);");
+    let add = function(&mut vm, "add");
+    let program = Program::new(std::mem::take(&mut *add.borrow_mut()), &[]);
+    let mut heap = Heap::new();
+    let copy = Program::deserialize(&program.serialize(), &mut heap).unwrap();
+    assert!(opcodes(&heap.alloc(copy.script)).contains(&"ADD"));
+}
+
+#[test]
+fn native_calls_are_quickened() {
+    let source = "fun poll() { return clock(); } poll();";
+    let (_, poll) = run(source, "poll");
+    assert!(poll.contains(&"CALLNATIVE"));
+
+    let source = r#"
+fun call(f) { return f(); }
+print call(clock) >= 0;
+fun one() { return 1; }
+print call(one);
+print call(clock) >= 0;
+print call(one);
+"#;
+    let (printed, call) = run(source, "call");
+    assert_eq!(printed, "true\n1\ntrue\n1\n");
+    assert!(call.contains(&"CALL"));
+    assert!(!call.contains(&"CALLNATIVE"));
+
+    let source = r#"
+fun call(f, arg) { return f(arg); }
+call(doc, call);
+call(clock, 1);
+"#;
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let err = vm.interpret(source.to_string()).unwrap_err();
+    assert!(err.to_string().ends_with("expected 0 arguments but got 1"));
+}
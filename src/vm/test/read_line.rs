@@ -0,0 +1,68 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufRead, Read},
+    rc::Rc,
+};
+
+use crate::Vm;
+
+fn vm(input: &'static str) -> (Vm, Rc<RefCell<Vec<u8>>>) {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.set_input(Rc::new(RefCell::new(io::Cursor::new(input))));
+    (vm, out)
+}
+
+#[test]
+fn lines() {
+    let (mut vm, out) = vm("one\ntwo\r\n\nlast");
+    let source = r#"
+    var line = readLine();
+    while (line != nil) {
+      print "[" + line + "]";
+      line = readLine();
+    }
+    print readLine();
+    "#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"[one]\n[two]\n[]\n[last]\nnil\n");
+}
+
+// Input that keeps what had been written to `out` when it was read.
+struct Watched {
+    out: Rc<RefCell<Vec<u8>>>,
+    seen: Rc<RefCell<Vec<u8>>>,
+    input: io::Cursor<&'static str>,
+}
+
+impl Read for Watched {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl BufRead for Watched {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        *self.seen.borrow_mut() = self.out.borrow().clone();
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.input.consume(amt)
+    }
+}
+
+#[test]
+fn prompt_is_flushed() {
+    let (mut vm, out) = vm("");
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    vm.set_input(Rc::new(RefCell::new(Watched {
+        out: out.clone(),
+        seen: seen.clone(),
+        input: io::Cursor::new("lox\n"),
+    })));
+    let source = r#"print "name?"; print "hi " + readLine();"#;
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(*seen.borrow(), b"name?\n");
+    assert_eq!(*out.borrow(), b"name?\nhi lox\n");
+}
@@ -0,0 +1,63 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{Dialect, Vm, VmOptions};
+
+fn repl(entries: &[&str]) -> String {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    for entry in entries {
+        let _ = vm.interpret_repl(entry.to_string());
+    }
+    let out = out.borrow();
+    String::from_utf8(out.to_vec()).unwrap()
+}
+
+#[test]
+fn bare_expressions_print() {
+    assert_eq!(repl(&["1 + 2", "\"a\" + \"b\";", "nil"]), "3\nab\nnil\n");
+    assert_eq!(repl(&["var a = 1;", "a = a + 1", "a"]), "2\n2\n");
+    assert_eq!(repl(&["fun f() { return 3; }", "f()"]), "3\n");
+}
+
+#[test]
+fn statements_do_not_print() {
+    assert_eq!(repl(&["var a = 1;", "print a;", "{ a; }", "a; a;"]), "1\n");
+    assert_eq!(repl(&["if (true) 1;", "a, b = 1, 2;"]), "");
+}
+
+#[test]
+fn only_whole_entries() {
+    assert_eq!(
+        repl(&["1 2"]),
+        "[line 1] Error at '2': expect ';' after expression\n"
+    );
+}
+
+#[test]
+fn interpret_is_unchanged() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    vm.interpret("1 + 2;".to_string()).unwrap();
+    assert!(out.borrow().is_empty());
+}
+
+#[test]
+fn completions() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::new(out.clone(), out.clone());
+    let source = "var counter = 0; fun count() {} var cat; { var cow; }";
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(
+        vm.completions("c"),
+        ["case", "cat", "class", "clock", "continue", "count", "counter"]
+    );
+    assert_eq!(vm.completions("flu"), ["flush"]);
+    assert!(vm.completions("x").is_empty());
+
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let vm = Vm::with_options(out.clone(), out, options);
+    assert_eq!(vm.completions("c"), ["class", "clock"]);
+}
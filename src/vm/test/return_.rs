@@ -0,0 +1,61 @@
+use super::interpret;
+
+#[test]
+fn at_top_level() {
+    let source = r#"
+    return "wat"; // Error at 'return': Can't return from top-level code.
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "");
+    assert!(stderr.starts_with(
+        "[line 2] Error at 'return': can't return from top-level code\n"
+    ));
+}
+
+#[test]
+fn in_function() {
+    let source = r#"
+    fun f() {
+        return "ok";
+        print "bad";
+    }
+
+    print f(); // expect: ok
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "ok\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn return_nil_if_no_value() {
+    let source = r#"
+    fun f() {
+        return;
+        print "bad";
+    }
+
+    print f(); // expect: nil
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "nil\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn after_while() {
+    let source = r#"
+    fun f() {
+        while (true) return "ok";
+    }
+
+    print f(); // expect: ok
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "ok\n");
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,143 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::vm;
+use crate::HostValue;
+
+#[test]
+fn hooks_see_instruction_counts() {
+    let (mut vm, _) = vm();
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    vm.set_safepoint_interval(1);
+    vm.add_safepoint_hook(move |sp| {
+        log.borrow_mut().push(sp.instructions);
+        Ok(())
+    });
+    // CONSTANT, CONSTANT, ADD, PRINT, NIL, RETURN
+    vm.interpret("print 1 + 2;".to_string()).unwrap();
+    assert_eq!(*seen.borrow(), [1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn hook_errors_stop_the_script() {
+    let (mut vm, out) = vm();
+    vm.set_safepoint_interval(10);
+    vm.add_safepoint_hook(|sp| match sp.instructions {
+        n if n >= 100 => Err(format!("stopped after {} instructions", n)),
+        _ => Ok(()),
+    });
+    let source = "var i = 0;\nwhile (true) {\n  i = i + 1;\n}";
+    let err = vm.interpret(source.to_string()).unwrap_err();
+    assert!(err.to_string().starts_with("[line "));
+    assert!(err
+        .to_string()
+        .ends_with("] stopped after 100 instructions"));
+    assert_eq!(out.borrow().len(), 0);
+
+    // The vm is still usable afterwards.
+    vm.interpret("print 1;".to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"1\n");
+}
+
+#[test]
+fn changing_the_interval_keeps_the_count() {
+    let (mut vm, _) = vm();
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    vm.add_safepoint_hook(move |sp| {
+        log.borrow_mut().push(sp.instructions);
+        Ok(())
+    });
+    vm.set_safepoint_interval(4);
+    vm.interpret("print 1 + 2;".to_string()).unwrap();
+    vm.set_safepoint_interval(1);
+    vm.interpret("print 1;".to_string()).unwrap();
+    assert_eq!(*seen.borrow(), [4, 7, 8, 9, 10]);
+}
+
+#[test]
+fn fuel_stops_runaway_scripts() {
+    let (mut vm, out) = vm();
+    let source = "var i = 0;\nwhile (true) {\n  i = i + 1;\n}";
+    let err = vm
+        .interpret_with_fuel(source.to_string(), 10_000)
+        .unwrap_err();
+    assert!(err.is_timeout());
+    assert!(err.to_string().ends_with("] out of fuel"));
+
+    // Other errors aren't timeouts, and the fuel is gone afterwards.
+    let err = vm.interpret("print -nil;".to_string()).unwrap_err();
+    assert!(!err.is_timeout());
+    let source = "for (var i = 0; i < 100000; i = i + 1) {} print 1;";
+    vm.interpret(source.to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"1\n");
+}
+
+#[test]
+fn fuel_is_exact() {
+    let (mut vm, out) = vm();
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    vm.set_safepoint_interval(4);
+    vm.add_safepoint_hook(move |sp| {
+        log.borrow_mut().push(sp.instructions);
+        Ok(())
+    });
+    // ONE, PRINT, NIL, RETURN
+    vm.interpret_with_fuel("print 1;".to_string(), 4).unwrap();
+    let err = vm
+        .interpret_with_fuel("print 2;".to_string(), 3)
+        .unwrap_err();
+    assert!(err.is_timeout());
+    assert_eq!(*out.borrow(), b"1\n2\n");
+    // The RETURN that ran out doesn't count, and hooks still run every
+    // four instructions.
+    vm.interpret("print 3; print 4;".to_string()).unwrap();
+    assert_eq!(*seen.borrow(), [4, 11]);
+}
+
+#[test]
+fn natives_can_charge_fuel() {
+    let (mut vm, out) = vm();
+    vm.register_native("expensive", 0, |ctx, _| {
+        ctx.charge(100);
+        Ok(HostValue::Nil)
+    });
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    vm.set_safepoint_interval(1);
+    vm.add_safepoint_hook(move |sp| {
+        log.borrow_mut().push(sp.instructions);
+        Ok(())
+    });
+    // GET_GLOBAL, CALL, POP, NIL, RETURN, with the call counting as 101.
+    vm.interpret("expensive();".to_string()).unwrap();
+    assert_eq!(seen.borrow().last(), Some(&105));
+
+    let source = "print 1; expensive(); print 2;";
+    let err = vm.interpret_with_fuel(source.to_string(), 50).unwrap_err();
+    assert!(err.is_timeout());
+    assert_eq!(*out.borrow(), b"1\n");
+}
+
+#[test]
+fn interrupt_from_another_thread() {
+    let (mut vm, out) = vm();
+    let handle = vm.interrupt_handle();
+    // Left over from before the script started, so ignored.
+    handle.interrupt();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+    let err = vm
+        .interpret("print 1; while (true) {}".to_string())
+        .unwrap_err();
+    interrupter.join().unwrap();
+    assert!(err.is_interrupted());
+    assert_eq!(err.to_string(), "[line 1] interrupted");
+    assert_eq!(*out.borrow(), b"1\n");
+
+    vm.interpret("print 2;".to_string()).unwrap();
+    assert_eq!(*out.borrow(), b"1\n2\n");
+}
@@ -0,0 +1,68 @@
+use super::{interpret, interpret_with};
+use crate::VmOptions;
+
+fn warn() -> VmOptions {
+    VmOptions {
+        warn_shadowing: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn shadowed_local() {
+    let source = r#"
+    {
+        var a = 1;
+        {
+            var a = 2;
+            print a;
+        }
+    }
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, warn());
+    assert_eq!(stdout, "2\n");
+    assert_eq!(
+        stderr,
+        "[line 5] Warning at 'a': shadows a local declared on line 3\n"
+    );
+}
+
+#[test]
+fn shadowed_global() {
+    let source = r#"
+    var a = 1;
+    fun f(a) {
+        var clock = a;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, warn());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        concat!(
+            "[line 3] Warning at 'a': shadows a global declared on line 2\n",
+            "[line 4] Warning at 'clock': shadows a global\n",
+        )
+    );
+}
+
+#[test]
+fn off_by_default() {
+    let (_, stderr) = interpret("var a; { var a = 1; { var a = 2; } }");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn strict_makes_it_an_error() {
+    let options = VmOptions {
+        strict: true,
+        ..warn()
+    };
+    let (_, stderr) = interpret_with("{ var a = 1; { var a = 2; } }", options);
+    assert_eq!(
+        stderr,
+        "[line 1] Error at 'a': shadows a local declared on line 1\n"
+    );
+}
@@ -0,0 +1,91 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::interpret_with;
+use crate::{Vm, VmOptions};
+
+fn show_source() -> VmOptions {
+    VmOptions {
+        show_source: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn compile_error() {
+    let source = "var a = 1;\n  print a +;\nprint (1;";
+    let (_, stderr) = interpret_with(source, show_source());
+    assert_eq!(
+        stderr,
+        "[line 2] Error at ';': expect expression\n\
+         \x20     print a +;\n\
+         \x20              ^\n\
+         [line 3] Error at ';': expect ')' after expression\n\
+         \x20   print (1;\n\
+         \x20           ^\n"
+    );
+}
+
+#[test]
+fn token_width_and_tabs() {
+    let (_, stderr) = interpret_with("\tvar nil = 1;", show_source());
+    assert_eq!(
+        stderr,
+        "[line 1] Error at 'nil': expect variable name\n\
+         \x20   \tvar nil = 1;\n\
+         \x20   \t    ^^^\n"
+    );
+}
+
+#[test]
+fn error_at_end() {
+    let (_, stderr) = interpret_with("print 1 +\n\n", show_source());
+    assert_eq!(
+        stderr,
+        "[line 3] Error at end: expect expression\n\
+         \x20   print 1 +\n\
+         \x20            ^\n"
+    );
+}
+
+#[test]
+fn scan_error() {
+    let (_, stderr) = interpret_with("print 1;\nprint $;", show_source());
+    assert_eq!(
+        stderr,
+        "[line 2] Error: unexpected character '$'\n\
+         \x20   print $;\n"
+    );
+}
+
+#[test]
+fn runtime_error() {
+    let source = r#"
+fun f(a) {
+    return -a;
+}
+f("x");
+"#;
+    let (_, stderr) = interpret_with(source, show_source());
+    assert_eq!(
+        stderr,
+        "[line 3] operand must be a number\n\
+         \x20       return -a;\n"
+    );
+
+    let (_, stderr) = interpret_with(source, VmOptions::default());
+    assert_eq!(stderr, "[line 3] operand must be a number\n");
+}
+
+#[test]
+fn error_accessors() {
+    let out = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut vm = Vm::with_options(out.clone(), out.clone(), show_source());
+    let e = vm.interpret("\nprint nil < 1;".to_string()).unwrap_err();
+    assert_eq!(e.line(), Some(2));
+    assert_eq!(e.source_line(), Some("print nil < 1;"));
+
+    let mut vm = Vm::new(out.clone(), out);
+    let e = vm.interpret("\nprint nil < 1;".to_string()).unwrap_err();
+    assert_eq!(e.line(), Some(2));
+    assert_eq!(e.source_line(), None);
+}
@@ -0,0 +1,57 @@
+use super::{interpret, interpret_with};
+use crate::{ast, VmOptions};
+
+#[test]
+fn long_lines() {
+    let source = format!(
+        "var s = \"{}\"; print s == nil; print x;",
+        "a".repeat(1 << 20)
+    );
+    let (stdout, stderr) = interpret(&source);
+    assert_eq!(stdout, "false\n");
+    assert_eq!(stderr, "[line 1] undefined variable 'x'\n");
+}
+
+#[test]
+fn many_lines() {
+    let source = format!("{}print x;", "\n".repeat(200_000));
+    let (_, stderr) = interpret(&source);
+    assert_eq!(stderr, "[line 200001] undefined variable 'x'\n");
+}
+
+#[test]
+fn max_source_bytes() {
+    let options = VmOptions {
+        max_source_bytes: Some(8),
+        ..Default::default()
+    };
+    let (stdout, _) = interpret_with("print 1;", options.clone());
+    assert_eq!(stdout, "1\n");
+    let (stdout, stderr) = interpret_with("print 10;", options.clone());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] Error: source too large (9 bytes, but the limit is 8)\n"
+    );
+    let err = ast::parse("print 10;".to_string(), &options).unwrap_err();
+    assert_eq!(err.to_string(), stderr.trim_end());
+}
+
+#[test]
+fn max_source_lines() {
+    let options = VmOptions {
+        max_source_lines: 2,
+        // Not quoted, however long it is.
+        show_source: true,
+        ..Default::default()
+    };
+    let (stdout, _) = interpret_with("print 1;\nprint 2;", options.clone());
+    assert_eq!(stdout, "1\n2\n");
+    // The line after a final newline counts.
+    let (stdout, stderr) = interpret_with("print 1;\nprint 2;\n", options);
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] Error: source too large (3 lines, but the limit is 2)\n"
+    );
+}
@@ -0,0 +1,133 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Metadata, Subscriber,
+};
+
+use super::vm;
+use crate::Vm;
+
+// Records each span as it's entered and exited, as "+name" and "-name",
+// with a call's function for its name.
+#[derive(Clone, Default)]
+struct Recorder {
+    names: Arc<Mutex<Vec<String>>>,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+struct FunctionName(Option<String>);
+
+impl Visit for FunctionName {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "function" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn Debug) {}
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes) -> Id {
+        let mut function = FunctionName(None);
+        span.record(&mut function);
+        let name = function.0.unwrap_or(span.metadata().name().to_string());
+        let mut names = self.names.lock().unwrap();
+        names.push(name);
+        Id::from_u64(names.len() as u64)
+    }
+
+    fn record(&self, _: &Id, _: &Record) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event) {}
+
+    fn enter(&self, span: &Id) {
+        self.mark('+', span);
+    }
+
+    fn exit(&self, span: &Id) {
+        self.mark('-', span);
+    }
+}
+
+impl Recorder {
+    fn mark(&self, sign: char, span: &Id) {
+        let name =
+            self.names.lock().unwrap()[span.into_u64() as usize - 1].clone();
+        self.log.lock().unwrap().push(format!("{}{}", sign, name));
+    }
+}
+
+fn traced(vm: &mut Vm, source: &str) -> Vec<String> {
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let _ = vm.interpret(source.to_string());
+    });
+    let log = recorder.log.lock().unwrap().clone();
+    log
+}
+
+const SOURCE: &str = r#"
+fun leaf() {}
+fun branch() { leaf(); leaf(); }
+branch();
+branch();
+"#;
+
+#[test]
+fn calls_nest() {
+    let log = traced(&mut vm().0, SOURCE);
+    assert_eq!(
+        log.join(" "),
+        "+interpret +<script> \
+         +branch +leaf -leaf +leaf -leaf -branch \
+         +branch +leaf -leaf +leaf -leaf -branch \
+         -<script> -interpret"
+    );
+}
+
+#[test]
+fn errors_exit_spans() {
+    let source = "fun f() { g(); }\nfun g() { nil + 1; }\nf();";
+    let log = traced(&mut vm().0, source);
+    assert_eq!(
+        log.join(" "),
+        "+interpret +<script> +f +g -g -f -<script> -interpret"
+    );
+}
+
+#[test]
+fn sampling() {
+    let (mut vm, _) = vm();
+    vm.set_call_spans(2, usize::MAX);
+    let log = traced(&mut vm, SOURCE);
+    // Calls are counted in the order they're made: the script, a branch,
+    // a leaf, a leaf, a branch, and so on.
+    assert_eq!(
+        log.join(" "),
+        "+interpret +<script> +leaf -leaf \
+         +branch +leaf -leaf -branch -<script> -interpret"
+    );
+
+    vm.set_call_spans(1, 1);
+    let log = traced(&mut vm, SOURCE);
+    assert_eq!(
+        log.join(" "),
+        "+interpret +<script> +branch -branch +branch -branch \
+         -<script> -interpret"
+    );
+
+    vm.set_call_spans(0, usize::MAX);
+    assert_eq!(traced(&mut vm, SOURCE).join(" "), "+interpret -interpret");
+}
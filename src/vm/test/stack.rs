@@ -0,0 +1,110 @@
+use super::interpret;
+use crate::{
+    code::Op,
+    program::Program,
+    vm::{Heap, LoxFunction},
+};
+
+fn function(ops: &[(u8, Option<u32>)], arity: usize) -> LoxFunction {
+    let mut func = LoxFunction::new("f");
+    func.arity = arity;
+    for &(op, arg) in ops {
+        match arg {
+            Some(arg) => func.chunk.write_op_arg(op, arg),
+            None => func.chunk.write_op(op),
+        }
+    }
+    func
+}
+
+#[test]
+fn max_slots() {
+    // The callee, a and b, then c, then c again for the return.
+    let mut func = function(
+        &[
+            (Op::GetLocal, Some(1)),
+            (Op::GetLocal, Some(2)),
+            (Op::Add, None),
+            (Op::GetLocal, Some(3)),
+            (Op::Return, None),
+        ],
+        2,
+    );
+    func.check_stack().unwrap();
+    assert_eq!(func.max_slots, 5);
+}
+
+#[test]
+fn underflow() {
+    let mut func = function(&[(Op::Pop, None), (Op::Return, None)], 0);
+    let e = func.check_stack().unwrap_err();
+    assert_eq!(e.to_string(), "stack underflow at offset 0");
+}
+
+#[test]
+fn missing_local() {
+    let ops = [(Op::GetLocal, Some(2)), (Op::Return, None)];
+    let mut func = function(&ops, 1);
+    let e = func.check_stack().unwrap_err();
+    assert_eq!(e.to_string(), "no local slot 2 at offset 0");
+}
+
+#[test]
+fn mismatched_heights() {
+    // One branch leaves an extra value behind.
+    let mut func = function(
+        &[
+            (Op::True, None),
+            (Op::JumpIfFalse, Some(1)),
+            (Op::Nil, None),
+            (Op::Pop, None),
+            (Op::Nil, None),
+            (Op::Return, None),
+        ],
+        0,
+    );
+    let e = func.check_stack().unwrap_err();
+    assert!(e.to_string().starts_with("stack height at offset"));
+}
+
+#[test]
+fn rejected_when_loaded() {
+    let script = function(&[(Op::Pop, None), (Op::Return, None)], 0);
+    let bytes = Program::new(script, &[]).serialize();
+    assert!(Program::deserialize(&bytes, &mut Heap::new()).is_err());
+}
+
+#[test]
+fn compiled_functions_are_checked() {
+    let source = r#"
+    fun f(a, b) {
+        var c = a + b;
+        {
+            var d = c;
+            print d;
+        }
+        return c;
+    }
+    print f(1, 2);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "3\n3\n");
+    assert_eq!(stderr, "");
+}
+
+// Tracing prints the whole stack on every instruction.
+#[cfg(not(feature = "trace_execution"))]
+#[test]
+fn deep_recursion() {
+    let source = r#"
+    fun f(n) {
+        return f(n + 1);
+    }
+    f(0);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "");
+    assert!(stderr.starts_with("[line 3] stack overflow"), "{}", stderr);
+}
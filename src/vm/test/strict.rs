@@ -0,0 +1,127 @@
+use super::{interpret, interpret_with};
+use crate::VmOptions;
+
+fn strict() -> VmOptions {
+    VmOptions {
+        strict: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn assign_undeclared() {
+    let source = r#"
+    var a = 1;
+    a = 2;
+    fun f() {
+        b = 3;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, strict());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 5] Error at 'b': can't assign to undeclared variable\n"
+    );
+}
+
+#[test]
+fn assign_declared_and_native() {
+    let source = r#"
+    var a = 1;
+    fun f() {
+        a = 2;
+    }
+    f();
+    clock = nil;
+    print(a);
+    print(clock);
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, strict());
+    assert_eq!(stdout, "2\nnil\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn assign_declared_later() {
+    let source = r#"
+    fun reset() {
+        count = 0;
+    }
+    var count = 5;
+    reset();
+    print(count);
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, strict());
+    assert_eq!(stdout, "0\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn redeclare_global() {
+    let source = r#"
+    var a = 1;
+    fun a() {}
+    var clock;
+    "#;
+
+    let expected = [
+        "[line 3] Error at 'a': already a global with this name",
+        "[line 4] Error at 'clock': already a global with this name",
+        "",
+    ];
+
+    let (stdout, stderr) = interpret_with(source, strict());
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, expected.join("\n"));
+}
+
+#[test]
+fn local_shadowing_global() {
+    let source = r#"
+    var a = "global";
+    {
+        var a = "local";
+        print(a);
+    }
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, strict());
+    assert_eq!(stdout, "local\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn print_needs_parens() {
+    let source = r#"
+    print(1 + 2);
+    print 3;
+    print(4) + 5;
+    "#;
+
+    let expected = [
+        "[line 3] Error at '3': expect '(' after 'print'",
+        "[line 4] Error at '+': expect ';' after value",
+        "",
+    ];
+
+    let (stdout, stderr) = interpret_with(source, strict());
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, expected.join("\n"));
+}
+
+#[test]
+fn not_strict_by_default() {
+    let source = r#"
+    var a = 1;
+    var a = 2;
+    print a;
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "2\n");
+    assert_eq!(stderr, "");
+}
@@ -1,4 +1,5 @@
-use super::interpret;
+use super::{interpret, interpret_with};
+use crate::{Dialect, VmOptions};
 
 #[test]
 fn error_after_multiline() {
@@ -17,6 +18,83 @@ fn error_after_multiline() {
     assert_eq!(stderr, "[line 8] undefined variable 'err'\n");
 }
 
+#[test]
+fn escapes() {
+    let source = r#"
+    print "a\tb\nc";
+    print "say \"hi\" \\ bye";
+    print "\u{48}\u{1F600}" + "\0" == "H😀" + "\u{0}";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "a\tb\nc\nsay \"hi\" \\ bye\ntrue\n");
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("print \"a\\qb\";");
+    assert_eq!(stderr, "[line 1] Error: invalid escape sequence '\\q'\n");
+    for bad in ["\\u41", "\\u{}", "\\u{1234567}", "\\u{D800}", "\\u{4g}"] {
+        let (_, stderr) = interpret(&format!("print \"{}\";", bad));
+        assert_eq!(stderr, "[line 1] Error: invalid unicode escape\n");
+    }
+
+    // Book Lox has no escape sequences.
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let (stdout, _) = interpret_with(r#"print "a\n\q\";"#, options);
+    assert_eq!(stdout, "a\\n\\q\\\n");
+}
+
+#[test]
+fn interpolation() {
+    let source = r#"
+    var x = 2;
+    print "x = ${x + 1}!";
+    print "${x}${x}";
+    print "a${"b${x}c"}d ${nil} ${true} ${clock}";
+    print "\${x} costs $${x}";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "x = 3!\n22\nab2cd nil true <native fn>\n${x} costs $2\n"
+    );
+    assert_eq!(stderr, "");
+
+    let (_, stderr) = interpret("print \"${1 2}\";");
+    assert_eq!(
+        stderr,
+        "[line 1] Error at '2': expect '}' after expression in string\n"
+    );
+
+    // Book Lox has no interpolation.
+    let options = VmOptions {
+        dialect: Dialect::Book,
+        ..Default::default()
+    };
+    let (stdout, _) = interpret_with(r#"print "${1}";"#, options);
+    assert_eq!(stdout, "${1}\n");
+}
+
+#[test]
+fn interpolation_in_defer() {
+    // Deferred statements are scanned again, in the middle of whatever
+    // comes after the block.
+    let source = r#"
+    fun f() {
+        defer print "d${"in${1}"}";
+        return "r";
+    }
+    { defer print "b${2}"; } "${f()}"; print "e${3}";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "b2\ndin1\ne3\n");
+    assert_eq!(stderr, "");
+}
+
 #[test]
 fn literals() {
     let source = r#"
@@ -49,6 +127,22 @@ var a = "1
     assert_eq!(stderr, "");
 }
 
+#[test]
+fn to_string() {
+    let source = r#"
+    fun f() {}
+    print str(1.5) + str(nil) + str(true);
+    print str("a") == "a";
+    print str([1, "b", {"c": nil}]);
+    print str(f);
+    print len(str(-0.25));
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "1.5niltrue\ntrue\n[1, b, {c: nil}]\n<fn f>\n5\n");
+    assert_eq!(stderr, "");
+}
+
 #[test]
 fn unterminated() {
     let source = r#"
@@ -60,3 +154,49 @@ fn unterminated() {
     assert_eq!(stdout, "");
     assert_eq!(stderr, "[line 3] Error: unterminated string\n");
 }
+
+#[test]
+fn natives() {
+    let source = r#"
+    print len("héllo");
+    print len([1, 2]) + len({});
+    print substr("héllo", 1, 3);
+    print substr("abc", 3, 3) == "";
+    print indexOf("héllo", "l");
+    print indexOf("abc", "z");
+    print split("a,b,,c", ",");
+    print len(split("hé", ""));
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "5\n2\nél\ntrue\n2\nnil\n[a, b, , c]\n2\n");
+    assert_eq!(stderr, "");
+
+    let errors = [
+        ("len(1);", "argument must be a string, list or map"),
+        (
+            "substr(\"abc\", 2, 1);",
+            "substring start 2 is after its end 1",
+        ),
+        (
+            "substr(\"abc\", 0, 4);",
+            "position 4 is out of bounds for a string of length 3",
+        ),
+        (
+            "substr(\"abc\", 0.5, 1);",
+            "position must be a whole number",
+        ),
+        (
+            "indexOf(\"a\", nil);",
+            "argument 2 to 'indexOf' must be a string, got nil",
+        ),
+        (
+            "split(1, \",\");",
+            "argument 1 to 'split' must be a string, got number",
+        ),
+    ];
+    for (source, msg) in errors {
+        let (_, stderr) = interpret(source);
+        assert_eq!(stderr, format!("[line 1] {}\n", msg), "{}", source);
+    }
+}
@@ -0,0 +1,64 @@
+use super::interpret;
+
+#[test]
+fn no_matching_case() {
+    let source = r#"
+    switch (2) {
+        case 1: print "one";
+    }
+    switch (3) {
+        case 1: print "one";
+        case 2: print "two";
+    }
+    print "after";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "after\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn last_case_matches() {
+    let source = r#"
+    switch (2) {
+        case 1: print "one";
+        case 2: print "two";
+    }
+    print "after";
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "two\nafter\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn default_case() {
+    let source = r#"
+    switch (3) {
+        case 1: print "one";
+        default: print "other";
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "other\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn break_in_loop() {
+    let source = r#"
+    for (var i = 0; i < 3; i = i + 1) {
+        switch (i) {
+            case 1: break;
+        }
+        print i;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "0\n");
+    assert_eq!(stderr, "");
+}
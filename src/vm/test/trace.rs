@@ -0,0 +1,30 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{output, vm};
+
+// Runs `source`, returning what it printed, its errors included, and the
+// trace.
+fn traced(source: &str) -> (String, String) {
+    let (mut vm, out) = vm();
+    let trace = Rc::new(RefCell::new(Vec::<u8>::new()));
+    vm.set_trace_output(trace.clone());
+    vm.interpret(source.to_string()).unwrap();
+    (output(&out), output(&trace))
+}
+
+#[cfg(feature = "trace_execution")]
+#[test]
+fn execution_trace_goes_to_trace_output() {
+    let (out, trace) = traced("print 1 + 2;");
+    assert_eq!(out, "3\n");
+    assert!(trace.contains("          [ nil ][ 1 ][ 2 ]\n"), "{}", trace);
+    assert!(trace.contains("PRINT\n"), "{}", trace);
+}
+
+#[cfg(feature = "print_code")]
+#[test]
+fn listing_goes_to_trace_output() {
+    let (out, trace) = traced("print 1;");
+    assert_eq!(out, "1\n");
+    assert!(trace.starts_with("== <script> ==\n"), "{}", trace);
+}
@@ -0,0 +1,42 @@
+use super::interpret;
+
+#[test]
+fn names() {
+    let source = r#"
+    fun f() {}
+    print type(nil);
+    print type(false);
+    print type(1.5);
+    print type("");
+    print type(f);
+    print type(len);
+    print type([]);
+    print type({});
+    print type(type(nil));
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(
+        stdout,
+        "nil\nboolean\nnumber\nstring\nfunction\nfunction\nlist\nmap\nstring\n"
+    );
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn branching() {
+    let source = r#"
+    fun describe(v) {
+        if (type(v) == "number") return "number ${v}";
+        if (type(v) == "list") return "list of ${len(v)}";
+        return type(v);
+    }
+    print describe(3);
+    print describe([1, 2]);
+    print describe(nil);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "number 3\nlist of 2\nnil\n");
+    assert_eq!(stderr, "");
+}
@@ -0,0 +1,89 @@
+use super::{interpret, interpret_with};
+use crate::VmOptions;
+
+const WARNING: &str = "local variable may be read before it is assigned";
+
+fn warnings(source: &str) -> Vec<String> {
+    let (_, stderr) = interpret(source);
+    stderr.lines().map(String::from).collect()
+}
+
+#[test]
+fn never_assigned() {
+    let source = r#"
+    {
+        var a;
+        var b = nil;
+        print a;
+        print b;
+        print a;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "nil\nnil\nnil\n");
+    assert_eq!(
+        stderr,
+        format!(
+            "[line 5] Warning at 'a': {0}\n[line 6] Warning at 'b': {0}\n",
+            WARNING
+        )
+    );
+}
+
+#[test]
+fn assigned_on_every_path() {
+    let source = r#"
+    fun f(x) {
+        var a;
+        var b;
+        var c;
+        if (x) a = 1; else a = 2;
+        if (x) b = 1; else return;
+        while (true) {
+            c = 1;
+            break;
+        }
+        c = 2;
+        print a + b + c;
+        var d = 1;
+        print d;
+    }
+    f(true);
+    "#;
+
+    let (stdout, stderr) = interpret(source);
+    assert_eq!(stdout, "4\n1\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn assigned_on_some_paths() {
+    let cases = [
+        "{ var a; if (true) a = 1; print a; }",
+        "{ var a; while (false) a = 1; print a; }",
+        "{ var a; for (;false; a = 1) {} print a; }",
+        "{ var a; true or (a = 1); print a; }",
+        "{ var a; switch (1) { case 1: a = 1; default: print a; } }",
+        "{ var a; while (false) {} else a = 1; print a; }",
+    ];
+    for source in cases {
+        assert_eq!(
+            warnings(source),
+            [format!("[line 1] Warning at 'a': {}", WARNING)],
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn strict_makes_it_an_error() {
+    let options = VmOptions {
+        strict: true,
+        ..Default::default()
+    };
+    let (stdout, stderr) = interpret_with("{ var a; print(a); }", options);
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, format!("[line 1] Error at 'a': {}\n", WARNING));
+}
@@ -0,0 +1,70 @@
+use super::interpret_with;
+use crate::VmOptions;
+
+#[test]
+fn max_string_bytes() {
+    let options = VmOptions {
+        max_string_bytes: Some(8),
+        ..Default::default()
+    };
+    let source = r#"
+    var s = "ab";
+    for (var i in 0..10) {
+      s = s + s;
+      print s;
+    }
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, options.clone());
+    assert_eq!(stdout, "abab\nabababab\n");
+    assert_eq!(
+        stderr,
+        "[line 4] string too long (16 bytes, but the limit is 8)\n"
+    );
+
+    // Interpolation, and strings made by natives.
+    let (_, stderr) = interpret_with("print \"${1234}${5678}!\";", options);
+    assert_eq!(
+        stderr,
+        "[line 1] string too long (9 bytes, but the limit is 8)\n"
+    );
+    let options = VmOptions {
+        max_string_bytes: Some(4),
+        ..Default::default()
+    };
+    let (_, stderr) =
+        interpret_with("print \"${[1, 2, 3]}\";", options.clone());
+    assert_eq!(
+        stderr,
+        "[line 1] string too long (9 bytes, but the limit is 4)\n"
+    );
+    let (_, stderr) =
+        interpret_with("formatNumber(1000000, 0, \",\");", options);
+    assert_eq!(
+        stderr,
+        "[line 1] string too long (9 bytes, but the limit is 4)\n"
+    );
+}
+
+#[test]
+fn max_list_items() {
+    let options = VmOptions {
+        max_list_items: Some(3),
+        ..Default::default()
+    };
+    let (stdout, stderr) = interpret_with(
+        "print [1, 2, 3];\nprint [1, 2, 3, 4];",
+        options.clone(),
+    );
+    assert_eq!(stdout, "[1, 2, 3]\n");
+    assert_eq!(
+        stderr,
+        "[line 2] list too long (4 items, but the limit is 3)\n"
+    );
+
+    let (_, stderr) = interpret_with("split(\"a,b,c,d\", \",\");", options);
+    assert_eq!(
+        stderr,
+        "[line 1] list too long (4 items, but the limit is 3)\n"
+    );
+}
@@ -0,0 +1,69 @@
+use super::interpret_with;
+use crate::VmOptions;
+
+fn verbose() -> VmOptions {
+    VmOptions {
+        verbose_errors: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn arithmetic_operands() {
+    let (stdout, stderr) = interpret_with(r#"print "a" * nil;"#, verbose());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] operands must be numbers, got string and nil\n"
+    );
+
+    let (_, stderr) = interpret_with("print 1 < true;", verbose());
+    assert_eq!(
+        stderr,
+        "[line 1] operands must be numbers, got number and boolean\n"
+    );
+}
+
+#[test]
+fn add_operands() {
+    let (stdout, stderr) = interpret_with(r#"print "a" + 1;"#, verbose());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] operands must be numbers or strings, got string and number\n"
+    );
+}
+
+#[test]
+fn negate_operand() {
+    let (stdout, stderr) = interpret_with(r#"print -"s";"#, verbose());
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "[line 1] operand must be a number, got string\n");
+}
+
+#[test]
+fn call_non_function() {
+    let (stdout, stderr) = interpret_with("nil();", verbose());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 1] can only call functions or classes, got nil\n"
+    );
+}
+
+#[test]
+fn arity() {
+    let source = r#"
+    fun f(a, b) {} f(1);
+    "#;
+
+    let (stdout, stderr) = interpret_with(source, verbose());
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        "[line 2] 'f' expected 2 arguments but got 1 (declared on line 2)\n"
+    );
+
+    let (_, stderr) = interpret_with("clock(1);", verbose());
+    assert_eq!(stderr, "[line 1] 'clock' expected 0 arguments but got 1\n");
+}